@@ -0,0 +1,26 @@
+use std::path::{Path, PathBuf};
+
+use crate::file_kind::FileKind;
+
+/// Most messages Telegram puts in one album
+pub const MAX_ITEMS: usize = 10;
+
+/// Whether `path` is a photo or video, which are sent as albums
+pub fn is_media(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    matches!(FileKind::from_filename(name), FileKind::Image | FileKind::Video)
+}
+
+/// Split files uploaded together into albums of at most `MAX_ITEMS` photos
+/// and videos, in order, and the files sent on their own: everything else,
+/// files over `max_size` (which are split) and a photo or video left alone
+pub fn group(paths: Vec<PathBuf>, max_size: u64) -> (Vec<Vec<PathBuf>>, Vec<PathBuf>) {
+    let (media, mut single): (Vec<PathBuf>, Vec<PathBuf>) = paths.into_iter().partition(|path| {
+        is_media(path) && std::fs::metadata(path).is_ok_and(|m| m.len() <= max_size)
+    });
+    let mut albums: Vec<Vec<PathBuf>> = media.chunks(MAX_ITEMS).map(<[PathBuf]>::to_vec).collect();
+    if albums.last().is_some_and(|album| album.len() == 1) {
+        single.extend(albums.pop().unwrap_or_default());
+    }
+    (albums, single)
+}
@@ -0,0 +1,120 @@
+use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::slint_generatedAppWindow::StatBar as SlintStatBar;
+use crate::{format_size, stats, AppWindow};
+
+/// Requests are counted per minute over this window
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Flood waits this recent count as a sign of being close to the limits
+const FLOOD_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// How often the diagnostics panel is brought up to date
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Methods shown in the chart, busiest first
+const METHODS_SHOWN: usize = 10;
+
+/// What was asked of Telegram since the app started. Telegram doesn't say
+/// how close an account is to its limits; the request rate and recent
+/// flood waits are the best hint there is
+struct Usage {
+    started: DateTime<Local>,
+    requests: BTreeMap<&'static str, u64>,
+    /// When each request of the last `RATE_WINDOW` was made
+    recent: VecDeque<Instant>,
+    uploaded: u64,
+    downloaded: u64,
+    /// When each flood wait was imposed, and for how many seconds
+    flood_waits: Vec<(Instant, u32)>,
+}
+
+static USAGE: Mutex<Option<Usage>> = Mutex::new(None);
+
+fn with_usage<T>(f: impl FnOnce(&mut Usage) -> T) -> T {
+    let mut usage = USAGE.lock().unwrap();
+    let usage = usage.get_or_insert_with(|| Usage {
+        started: Local::now(),
+        requests: BTreeMap::new(),
+        recent: VecDeque::new(),
+        uploaded: 0,
+        downloaded: 0,
+        flood_waits: Vec::new(),
+    });
+    f(usage)
+}
+
+/// Count a request to `method`, named as in the Telegram API, e.g. "upload.getFile"
+pub fn request(method: &'static str) {
+    with_usage(|usage| {
+        *usage.requests.entry(method).or_default() += 1;
+        let now = Instant::now();
+        usage.recent.push_back(now);
+        while usage.recent.front().is_some_and(|t| now.duration_since(*t) > RATE_WINDOW) {
+            usage.recent.pop_front();
+        }
+    });
+}
+
+/// Count `bytes` of file content sent to Telegram
+pub fn uploaded(bytes: usize) {
+    with_usage(|usage| usage.uploaded += bytes as u64);
+}
+
+/// Count `bytes` of file content fetched from Telegram
+pub fn downloaded(bytes: usize) {
+    with_usage(|usage| usage.downloaded += bytes as u64);
+}
+
+/// Note that Telegram made the app wait `secs` seconds
+pub fn flood_wait(secs: u32) {
+    with_usage(|usage| usage.flood_waits.push((Instant::now(), secs)));
+}
+
+/// Bring the diagnostics panel up to date every few seconds
+pub async fn run_reporter(ui_handle: slint::Weak<AppWindow>) {
+    loop {
+        let (summary, flood_summary, methods) = with_usage(|usage| {
+            let now = Instant::now();
+            let per_minute = usage.recent.iter().filter(|t| now.duration_since(**t) <= RATE_WINDOW).count();
+            let total: u64 = usage.requests.values().sum();
+            let summary = tr!(
+                "Since {since}: {total} requests, {rate} in the last minute; {up} uploaded, {down} downloaded",
+                since = usage.started.format("%H:%M"),
+                total = total,
+                rate = per_minute,
+                up = format_size(usage.uploaded),
+                down = format_size(usage.downloaded),
+            );
+            
+            let recent: Vec<u32> = usage.flood_waits.iter()
+                .filter(|(at, _)| now.duration_since(*at) <= FLOOD_WINDOW)
+                .map(|(_, secs)| *secs)
+                .collect();
+            let flood_summary = match recent.iter().max() {
+                Some(longest) => tr!(
+                    "Telegram asked to slow down {count} time(s) in the last hour, for up to {secs}s",
+                    count = recent.len(),
+                    secs = longest,
+                ),
+                None if usage.flood_waits.is_empty() => String::new(),
+                None => tr!("{count} flood wait(s) since startup, none in the last hour", count = usage.flood_waits.len()),
+            };
+            
+            let mut methods: Vec<(String, u64)> = usage.requests.iter()
+                .map(|(method, count)| (method.to_string(), *count))
+                .collect();
+            methods.sort_by(|a, b| b.1.cmp(&a.1));
+            methods.truncate(METHODS_SHOWN);
+            (summary, flood_summary, methods)
+        });
+        
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            let bars: Vec<SlintStatBar> = stats::bars(methods, |count| count.to_string());
+            ui.set_api_usage_summary(summary.into());
+            ui.set_api_flood_summary(flood_summary.into());
+            ui.set_api_requests(slint::ModelRc::new(slint::VecModel::from(bars)));
+        });
+        tokio::time::sleep(REPORT_INTERVAL).await;
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{anyhow, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::session_crypt::SessionKey;
+
+/// Mirrors the window's locked state, so what serves stored files outside
+/// the window can refuse while it is locked
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_locked(locked: bool) {
+    LOCKED.store(locked, Ordering::Relaxed);
+}
+
+pub fn is_locked() -> bool {
+    LOCKED.load(Ordering::Relaxed)
+}
+
+/// Passphrase required before the library or session can be used
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppLock {
+    /// Argon2 hash of the passphrase, PHC string format
+    pub hash: String,
+    /// Lock again after this many minutes without activity; 0 only locks at startup
+    #[serde(default)]
+    pub idle_minutes: u32,
+    /// Salt for deriving the session encryption key from the passphrase
+    #[serde(default)]
+    pub key_salt: [u8; 16],
+}
+
+impl AppLock {
+    pub fn new(passphrase: &str, idle_minutes: u32) -> Result<Self> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(passphrase.as_bytes(), &salt)
+            .map_err(|e| anyhow!("Failed to hash passphrase: {}", e))?
+            .to_string();
+        let mut key_salt = [0u8; 16];
+        OsRng.fill_bytes(&mut key_salt);
+        Ok(Self { hash, idle_minutes, key_salt })
+    }
+    
+    pub fn verify(&self, passphrase: &str) -> bool {
+        PasswordHash::new(&self.hash)
+            .map(|hash| Argon2::default().verify_password(passphrase.as_bytes(), &hash).is_ok())
+            .unwrap_or(false)
+    }
+    
+    /// Key for the session file when it is protected by the passphrase
+    pub fn session_key(&self, passphrase: &str) -> Result<SessionKey> {
+        let mut key = SessionKey::new([0u8; 32]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &self.key_salt, key.as_mut_slice())
+            .map_err(|e| anyhow!("Failed to derive session key: {}", e))?;
+        Ok(key)
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::database::ArchiveMember;
+use crate::folder::FolderFile;
+use crate::paths;
+
+/// Pack `files` (as returned by `folder::collect_files`) into a `.tar.zst`
+/// compressed at zstd `level` in the temp dir, returning the archive path and
+/// the member list.
+pub fn pack_folder(root: &Path, files: &[FolderFile], level: i32) -> Result<(PathBuf, Vec<ArchiveMember>)> {
+    let dir = paths::cache_dir("teledrive-archives");
+    std::fs::create_dir_all(&dir)?;
+    let archive_path = dir.join(archive_name(root)?);
+    write_archive(files, level, File::create(&archive_path)?)?;
+    Ok((archive_path, members(files)?))
+}
+
+/// Name for an archive of `root` made now, e.g. "photos_2024-05-01_12-00-00.tar.zst"
+pub fn archive_name(root: &Path) -> Result<String> {
+    let root_name = root
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("Invalid folder name")?;
+    Ok(format!("{}_{}.tar.zst", root_name, chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")))
+}
+
+/// Write `files` to `out` as a `.tar.zst` compressed at zstd `level`.
+/// Blocking; run off the async runtime
+pub fn write_archive(files: &[FolderFile], level: i32, out: impl Write) -> Result<()> {
+    let encoder = zstd::stream::write::Encoder::new(out, level)?;
+    let mut builder = tar::Builder::new(encoder);
+    for file in files {
+        builder
+            .append_path_with_name(&file.path, member_name(file)?)
+            .with_context(|| format!("Failed to add {:?} to archive", file.path))?;
+    }
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// What an archive of `files` holds, for browsing without downloading it
+pub fn members(files: &[FolderFile]) -> Result<Vec<ArchiveMember>> {
+    files.iter()
+        .map(|file| Ok(ArchiveMember { path: member_name(file)?, size: file.size }))
+        .collect()
+}
+
+/// Path of a file inside the archive: its virtual folder plus its name
+fn member_name(file: &FolderFile) -> Result<String> {
+    let filename = file
+        .path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("Invalid filename")?;
+    Ok(format!("{}/{}", file.folder, filename))
+}
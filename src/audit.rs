@@ -0,0 +1,112 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::database::DATE_FORMAT;
+use crate::slint_generatedAppWindow::ActivityEntry as SlintActivityEntry;
+
+/// Activity log in the data folder, one JSON entry per line. Entries are
+/// only ever appended; nothing in the app rewrites or trims it
+const LOG_FILE: &str = "activity.jsonl";
+/// Newest entries shown in the window; the file keeps the rest
+const SHOWN_ENTRIES: usize = 500;
+
+/// Keeps lines from concurrent tasks whole
+static WRITING: Mutex<()> = Mutex::new(());
+
+/// Something done to the stored files or the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Login,
+    Upload,
+    Download,
+    Open,
+    Delete,
+    Rename,
+    Move,
+    /// A link copied, or the file forwarded to a chat
+    Share,
+}
+
+impl Action {
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Login => "Login",
+            Action::Upload => "Upload",
+            Action::Download => "Download",
+            Action::Open => "Open",
+            Action::Delete => "Delete",
+            Action::Rename => "Rename",
+            Action::Move => "Move",
+            Action::Share => "Share",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub action: Action,
+    /// File (folder and name) or account acted on
+    pub subject: String,
+    /// What else is worth knowing, e.g. the new name of a renamed file
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+/// Add an entry to the log. Failures are only logged: the log must never
+/// stand in the way of the operation itself
+pub fn record(action: Action, subject: impl Into<String>, detail: impl Into<String>) {
+    let entry = Entry {
+        time: Local::now(),
+        action,
+        subject: subject.into(),
+        detail: detail.into(),
+    };
+    if let Err(e) = append(&entry) {
+        warn!("Failed to write {:?} to the activity log: {:?}", entry, e);
+    }
+}
+
+fn append(entry: &Entry) -> Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let _guard = WRITING.lock().unwrap();
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(LOG_FILE)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// The newest `limit` entries, newest first. Lines that can't be read, say
+/// one cut short by a crash, are skipped
+pub fn recent(limit: usize) -> Vec<Entry> {
+    let Ok(file) = std::fs::File::open(LOG_FILE) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<Entry> = BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect();
+    let excess = entries.len().saturating_sub(limit);
+    entries.drain(..excess);
+    entries.reverse();
+    entries
+}
+
+/// Entries for the activity list in the window
+pub fn shown_entries() -> Vec<SlintActivityEntry> {
+    recent(SHOWN_ENTRIES)
+        .into_iter()
+        .map(|e| SlintActivityEntry {
+            time: e.time.format(DATE_FORMAT).to_string().into(),
+            action: e.action.label().into(),
+            subject: e.subject.into(),
+            detail: e.detail.into(),
+        })
+        .collect()
+}
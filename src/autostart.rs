@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+/// Name the app registers itself under
+const AUTOSTART_NAME: &str = "TeleDrive";
+/// Passed on login launches so the app starts hidden in the tray
+pub const MINIMIZED_FLAG: &str = "--minimized";
+
+fn current_exe() -> Result<PathBuf> {
+    std::env::current_exe().context("Failed to locate the running executable")
+}
+
+/// Register or unregister launching at login for the current user
+pub fn set_enabled(enabled: bool) -> Result<()> {
+    if enabled {
+        platform::enable(&current_exe()?)
+    } else {
+        platform::disable()
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+    
+    const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+    
+    pub fn enable(exe: &std::path::Path) -> Result<()> {
+        let (run, _) = RegKey::predef(HKEY_CURRENT_USER).create_subkey(RUN_KEY)?;
+        run.set_value(AUTOSTART_NAME, &format!("\"{}\" {}", exe.display(), MINIMIZED_FLAG))?;
+        Ok(())
+    }
+    
+    pub fn disable() -> Result<()> {
+        let run = RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(RUN_KEY, winreg::enums::KEY_WRITE)?;
+        match run.delete_value(AUTOSTART_NAME) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    
+    const LABEL: &str = "com.teledrive.app";
+    
+    fn plist_path() -> Result<PathBuf> {
+        let home = std::env::var_os("HOME").context("HOME is not set")?;
+        Ok(PathBuf::from(home)
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", LABEL)))
+    }
+    
+    pub fn enable(exe: &std::path::Path) -> Result<()> {
+        let path = plist_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        
+        let plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+        <string>{}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+            LABEL,
+            exe.display(),
+            MINIMIZED_FLAG
+        );
+        std::fs::write(path, plist)?;
+        Ok(())
+    }
+    
+    pub fn disable() -> Result<()> {
+        match std::fs::remove_file(plist_path()?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::*;
+    
+    /// `$XDG_CONFIG_HOME/autostart/teledrive.desktop`
+    fn desktop_entry_path() -> Result<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .context("Neither XDG_CONFIG_HOME nor HOME is set")?;
+        Ok(config.join("autostart").join("teledrive.desktop"))
+    }
+    
+    pub fn enable(exe: &std::path::Path) -> Result<()> {
+        let path = desktop_entry_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec=\"{}\" {}\nX-GNOME-Autostart-enabled=true\n",
+            AUTOSTART_NAME,
+            exe.display(),
+            MINIMIZED_FLAG
+        );
+        std::fs::write(path, entry)?;
+        Ok(())
+    }
+    
+    pub fn disable() -> Result<()> {
+        match std::fs::remove_file(desktop_entry_path()?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
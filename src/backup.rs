@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+use crate::config::Settings;
+use crate::cron::CronSchedule;
+use crate::database::Database;
+use crate::file_attrs::FileAttrs;
+use crate::folder;
+use crate::transfer::{TransferKind, TransferQueue, UploadOptions};
+
+/// Missed runs further back than this are not caught up on
+const MAX_CATCH_UP_DAYS: i64 = 7;
+
+/// A folder backed up on a recurring schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupJob {
+    pub source: PathBuf,
+    /// Cron-style schedule, see `CronSchedule`
+    pub schedule: String,
+    /// Last time the job ran, seconds since the Unix epoch
+    #[serde(default)]
+    pub last_run: Option<i64>,
+}
+
+impl BackupJob {
+    pub fn name(&self) -> String {
+        self.source.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("Backup")
+            .to_string()
+    }
+    
+    /// Virtual folder the job stores into: the folder's name and a short
+    /// hash of its full path, so sources with the same name stay apart
+    pub fn folder_root(&self) -> String {
+        let hash = Sha256::digest(self.source.to_string_lossy().as_bytes());
+        let short: String = hash[..4].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}", self.name(), short)
+    }
+    
+    pub fn last_run_label(&self) -> String {
+        self.last_run
+            .and_then(|t| Local.timestamp_opt(t, 0).single())
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "never".to_string())
+    }
+}
+
+/// Tracks which minutes have already been checked for each job
+#[derive(Default)]
+pub struct BackupClock {
+    checked_until: HashMap<PathBuf, DateTime<Local>>,
+}
+
+impl BackupClock {
+    /// Indices of the jobs whose schedule fired since they were last checked
+    pub fn due_jobs(&mut self, jobs: &[BackupJob], now: DateTime<Local>) -> Vec<usize> {
+        let now = truncate_to_minute(now);
+        let oldest = now - ChronoDuration::days(MAX_CATCH_UP_DAYS);
+        let mut due = Vec::new();
+        
+        for (index, job) in jobs.iter().enumerate() {
+            let schedule: CronSchedule = match job.schedule.parse() {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    warn!("Skipping backup of {:?} with invalid schedule {:?}: {:#}", job.source, job.schedule, e);
+                    continue;
+                }
+            };
+            
+            // Catch up on runs missed while the app was closed, otherwise start from now
+            let since = self.checked_until.get(&job.source).copied()
+                .or_else(|| job.last_run.and_then(|t| Local.timestamp_opt(t, 0).single()))
+                .map(truncate_to_minute)
+                .unwrap_or(now)
+                .max(oldest);
+            
+            let mut minute = since + ChronoDuration::minutes(1);
+            while minute <= now {
+                if schedule.matches(&minute) {
+                    due.push(index);
+                    break;
+                }
+                minute += ChronoDuration::minutes(1);
+            }
+            self.checked_until.insert(job.source.clone(), now);
+        }
+        
+        due
+    }
+}
+
+fn truncate_to_minute(time: DateTime<Local>) -> DateTime<Local> {
+    time.with_second(0)
+        .and_then(|t| t.with_nanosecond(0))
+        .unwrap_or(time)
+}
+
+/// Queue every new or changed file under the job's folder; returns how many were queued
+pub async fn run_backup_job(job: &BackupJob, db: &Database, queue: &TransferQueue) -> Result<usize> {
+    let (root, virtual_root) = (job.source.clone(), job.folder_root());
+    let files = tokio::task::spawn_blocking(move || folder::collect_files_into(&root, &virtual_root))
+        .await
+        .context("Folder scan panicked")??;
+    
+    // Files already stored with the same size and modification time are
+    // skipped; changed ones replace the stored copy
+    let changed: Vec<_> = files.into_iter()
+        .filter_map(|file| {
+            let filename = file.path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            match db.find_in_folder(&file.folder, filename) {
+                Some(record) => {
+                    let changed = record.file_size != file.size
+                        || record.mtime.is_none()
+                        || record.mtime != FileAttrs::read(&file.path).mtime;
+                    changed.then(|| (file, record.key()))
+                }
+                None => Some((file, None)),
+            }
+        })
+        .collect();
+    
+    if changed.is_empty() {
+        return Ok(0);
+    }
+    
+    let total_bytes = changed.iter().map(|(f, _)| f.size).sum();
+    let batch = queue.start_batch(tr!("Backup of {name}", name = job.name()), changed.len(), total_bytes);
+    let count = changed.len();
+    for (file, replaces) in changed {
+        queue.push(TransferKind::Upload {
+            path: file.path,
+            options: UploadOptions {
+                folder: file.folder,
+                batch: Some(batch),
+                replaces,
+                ..Default::default()
+            },
+        });
+    }
+    
+    Ok(count)
+}
+
+/// Run the backup jobs that are due and remember when they ran
+pub async fn run_due_backups(
+    clock: &mut BackupClock,
+    settings: &Arc<Mutex<Settings>>,
+    db: &Database,
+    queue: &TransferQueue,
+) -> usize {
+    let now = Local::now();
+    let jobs = settings.lock().unwrap().backup_jobs.clone();
+    let mut queued = 0;
+    
+    for index in clock.due_jobs(&jobs, now) {
+        let job = &jobs[index];
+        info!("Running scheduled backup of {:?}", job.source);
+        match run_backup_job(job, db, queue).await {
+            Ok(count) => {
+                info!("Backup of {:?} queued {} file(s)", job.source, count);
+                queued += count;
+            }
+            Err(e) => error!("Backup of {:?} failed: {:?}", job.source, e),
+        }
+        
+        let mut settings = settings.lock().unwrap();
+        if let Some(stored) = settings.backup_jobs.iter_mut().find(|j| j.source == job.source) {
+            stored.last_run = Some(now.timestamp());
+        }
+        if let Err(e) = settings.save(crate::SETTINGS_FILE) {
+            error!("Failed to save settings: {:?}", e);
+        }
+    }
+    
+    queued
+}
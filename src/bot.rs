@@ -0,0 +1,373 @@
+use anyhow::{bail, Context, Result};
+use grammers_client::types::chat::PackedType;
+use grammers_client::types::PackedChat;
+use grammers_client::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::audit::{self, Action};
+use crate::caption::{self, CaptionMeta};
+use crate::config::Settings;
+use crate::database::{ChatRef, Database, FileRecord};
+use crate::file_attrs::FileAttrs;
+use crate::transfer::TransferQueue;
+use crate::{api_usage, app_lock, encryption, format_size, forward_stored_file, queued_status, read_only};
+
+const API_URL: &str = "https://api.telegram.org/bot";
+/// Seconds a getUpdates call waits for new messages
+const POLL_TIMEOUT_SECS: u64 = 50;
+const RETRY_DELAY: Duration = Duration::from_secs(15);
+/// Files listed per `/list` reply
+const LIST_LIMIT: usize = 30;
+/// Largest file a bot may upload
+pub const UPLOAD_LIMIT: u64 = 50 * 1024 * 1024;
+/// Sending `UPLOAD_LIMIT` bytes can take a while on a slow line
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const HELP: &str = "TeleDrive remote control\n\n\
+/list [text] - newest files, or those matching text\n\
+/get <name> - send me the file\n\
+/status - transfers and library size";
+
+#[derive(Deserialize)]
+struct Reply<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+impl<T> Reply<T> {
+    fn into_result(self, method: &str) -> Result<T> {
+        if !self.ok {
+            bail!("{} failed: {}", method, self.description.unwrap_or_default());
+        }
+        self.result.with_context(|| format!("{} returned nothing", method))
+    }
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    from: Option<User>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: i64,
+    username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SentMessage {
+    message_id: i32,
+    document: Option<Document>,
+}
+
+#[derive(Deserialize)]
+struct Document {
+    file_id: String,
+}
+
+/// Bot API connection for one token
+struct Bot {
+    http: reqwest::Client,
+    /// API URL including the token, so it is stripped from every error
+    base: String,
+}
+
+impl Bot {
+    fn new(token: &str) -> Result<Self> {
+        Self::with_timeout(token, Duration::from_secs(POLL_TIMEOUT_SECS + 10))
+    }
+    
+    fn with_timeout(token: &str, timeout: Duration) -> Result<Self> {
+        let http = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self { http, base: format!("{}{}", API_URL, token) })
+    }
+    
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<T> {
+        let reply: Reply<T> = self.http.post(format!("{}/{}", self.base, method))
+            .json(&params)
+            .send().await
+            .map_err(|e| e.without_url())?
+            .json().await
+            .map_err(|e| e.without_url())
+            .with_context(|| format!("Unexpected answer to {}", method))?;
+        reply.into_result(method)
+    }
+    
+    async fn send_document(&self, chat_id: &str, name: &str, content: Vec<u8>, caption: String) -> Result<SentMessage> {
+        let form = reqwest::multipart::Form::new()
+            .text("chat_id", chat_id.to_string())
+            .text("caption", caption)
+            // Keep it a document, whatever the content looks like
+            .text("disable_content_type_detection", "true")
+            .part("document", reqwest::multipart::Part::bytes(content).file_name(name.to_string()));
+        let reply: Reply<SentMessage> = self.http.post(format!("{}/sendDocument", self.base))
+            .multipart(form)
+            .send().await
+            .map_err(|e| e.without_url())?
+            .json().await
+            .map_err(|e| e.without_url())
+            .context("Unexpected answer to sendDocument")?;
+        reply.into_result("sendDocument")
+    }
+    
+    async fn send_text(&self, chat_id: i64, text: &str) -> Result<()> {
+        self.call::<serde_json::Value>("sendMessage", json!({ "chat_id": chat_id, "text": text })).await?;
+        Ok(())
+    }
+}
+
+/// Everything a command may look at
+pub struct BotContext {
+    pub client: Arc<Mutex<Option<Client>>>,
+    pub db: Arc<Database>,
+    pub queue: Arc<TransferQueue>,
+}
+
+/// Answer commands sent to the companion bot while `token` holds a token.
+///
+/// Only messages from the signed-in account are answered. Files are sent by
+/// forwarding the stored message from the account into its chat with the bot
+pub async fn run_bot(context: BotContext, mut token: watch::Receiver<String>) {
+    loop {
+        let current = token.borrow_and_update().clone();
+        if current.is_empty() {
+            if token.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+        
+        tokio::select! {
+            result = serve(&context, &current) => {
+                if let Err(e) = result {
+                    warn!("Bot stopped: {:?}; restarting in {:?}", e, RETRY_DELAY);
+                }
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            changed = token.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                info!("Bot token changed");
+            }
+        }
+    }
+}
+
+async fn serve(context: &BotContext, token: &str) -> Result<()> {
+    let bot = Bot::new(token)?;
+    let me: User = bot.call("getMe", json!({})).await?;
+    let bot_username = me.username.context("The bot has no username")?;
+    info!("Bot @{} is listening", bot_username);
+    
+    // The signed-in account, the only one answered; looked up once
+    let mut owner = None;
+    let mut offset = 0;
+    loop {
+        let updates: Vec<Update> = bot.call(
+            "getUpdates",
+            json!({ "offset": offset, "timeout": POLL_TIMEOUT_SECS, "allowed_updates": ["message"] }),
+        ).await?;
+        for update in updates {
+            offset = update.update_id + 1;
+            let Some(message) = update.message else { continue };
+            let chat_id = message.chat.id;
+            // One message failing must not stop the bot answering the next
+            if let Err(e) = answer(context, &bot, &bot_username, &mut owner, message).await {
+                warn!("Failed to answer bot message in chat {}: {:?}", chat_id, e);
+            }
+        }
+    }
+}
+
+/// Run the command in `message` and send back the result, when it comes
+/// from the signed-in account
+async fn answer(context: &BotContext, bot: &Bot, bot_username: &str, owner: &mut Option<i64>, message: Message) -> Result<()> {
+    let (Some(from), Some(text)) = (message.from, message.text) else {
+        return Ok(());
+    };
+    
+    // Commands only make sense once signed in, and only for the owner
+    let Some(client) = context.client.lock().unwrap().clone() else {
+        return bot.send_text(message.chat.id, "TeleDrive is not signed in right now").await;
+    };
+    let owner = match *owner {
+        Some(id) => id,
+        None => {
+            api_usage::request("users.getUsers");
+            *owner.insert(client.get_me().await?.id())
+        }
+    };
+    if from.id != owner {
+        warn!("Ignoring bot message from user {}", from.id);
+        return Ok(());
+    }
+    if app_lock::is_locked() {
+        return bot.send_text(message.chat.id, "TeleDrive is locked; unlock it to use the bot").await;
+    }
+    
+    let answer = match handle(context, &client, bot_username, &text).await {
+        Ok(answer) => answer,
+        Err(e) => format!("Failed: {:#}", e),
+    };
+    bot.send_text(message.chat.id, &answer).await
+}
+
+async fn handle(context: &BotContext, client: &Client, bot_username: &str, text: &str) -> Result<String> {
+    let (command, argument) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+    // Commands can be addressed as /list@SomeBot
+    let command = command.split('@').next().unwrap_or_default();
+    let argument = argument.trim();
+    match command {
+        "/list" => Ok(list(&context.db, argument)),
+        "/get" => {
+            if argument.is_empty() {
+                return Ok("Usage: /get <name>".to_string());
+            }
+            let Some(record) = context.db.find_by_name(argument) else {
+                return Ok(format!("No file matches \"{}\"", argument));
+            };
+            api_usage::request("contacts.resolveUsername");
+            let chat = client.resolve_username(bot_username).await?
+                .context("Could not find the chat with the bot")?;
+            forward_stored_file(client, &record, chat.pack()).await?;
+            Ok(format!("{} ({})", record.filename, format_size(record.file_size)))
+        }
+        "/status" => {
+            let records = context.db.all_records();
+            let total: u64 = records.iter().map(|r| r.file_size).sum();
+            Ok(format!("{}\n{} files, {}", queued_status(&context.queue), records.len(), format_size(total)))
+        }
+        _ => Ok(HELP.to_string()),
+    }
+}
+
+/// Newest records matching `query` in name or folder, one per line
+fn list(db: &Database, query: &str) -> String {
+    let query = query.to_lowercase();
+    let records = db.all_records();
+    let lines: Vec<String> = records.iter()
+        .rev()
+        .filter(|r| r.filename.to_lowercase().contains(&query) || r.folder.to_lowercase().contains(&query))
+        .take(LIST_LIMIT)
+        .map(|r| match r.folder.as_str() {
+            "" => format!("{} ({})", r.filename, format_size(r.file_size)),
+            folder => format!("{}/{} ({})", folder, r.filename, format_size(r.file_size)),
+        })
+        .collect();
+    if lines.is_empty() {
+        "No files".to_string()
+    } else {
+        lines.join("\n")
+    }
+}
+
+/// Whether a bot can store files in `chat` for the account to read back: a
+/// channel or supergroup, where everyone sees the same message ids. Basic
+/// groups number messages per member
+pub fn can_store_in(chat: PackedChat) -> bool {
+    matches!(chat.ty, PackedType::Broadcast | PackedType::Megagroup | PackedType::Gigagroup)
+}
+
+/// Store a file by having the bot with `token` send it to `chat` through the
+/// HTTP Bot API, with no user session involved. The bot must be allowed to
+/// post there; the record points at `chat`, so the app downloads it from there
+/// like any other file
+pub async fn upload_file(
+    db: &Database,
+    settings: &Settings,
+    token: &str,
+    chat: &ChatRef,
+    path: &Path,
+    folder: &str,
+) -> Result<FileRecord> {
+    read_only::check("uploading")?;
+    let filename = path.file_name().and_then(|n| n.to_str()).context("Invalid filename")?.to_string();
+    let file_size = tokio::fs::metadata(path).await?.len();
+    // Checked before hashing or encrypting, which would be wasted on it
+    let sent_size = if settings.encrypt_uploads { encryption::encrypted_size(file_size) } else { file_size };
+    if sent_size > UPLOAD_LIMIT {
+        bail!(
+            "{} is {}; bots can only upload files up to {}",
+            filename,
+            format_size(sent_size),
+            format_size(UPLOAD_LIMIT),
+        );
+    }
+    let hash_path = path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || caption::hash_file(&hash_path)).await??;
+    let attrs = FileAttrs::read(path);
+    
+    let encrypted = if settings.encrypt_uploads {
+        let (source, hash) = (path.to_path_buf(), sha256.clone());
+        Some(tokio::task::spawn_blocking(move || encryption::encrypt(&source, &hash)).await??)
+    } else {
+        None
+    };
+    let content = tokio::fs::read(encrypted.as_ref().map_or(path, |(copy, _)| copy.as_path())).await;
+    // Read whole, so the copy has served its purpose either way
+    if let Some((copy, _)) = &encrypted {
+        encryption::remove(copy);
+    }
+    let content = content?;
+    
+    let key_id = encrypted.map(|(_, id)| id);
+    let meta = CaptionMeta {
+        version: CaptionMeta::VERSION,
+        path: path.to_string_lossy().into_owned(),
+        folder: folder.to_string(),
+        size: file_size,
+        sha256: sha256.clone(),
+        mtime: attrs.mtime,
+        mode: attrs.mode,
+        encrypted: key_id.is_some(),
+        key_id: key_id.clone(),
+        ..Default::default()
+    };
+    // With names hidden Telegram sees a random name and no caption, as with
+    // uploads from the account
+    let hidden_name = settings.hide_names.then(|| format!("{:032x}", rand::random::<u128>()));
+    let document_name = hidden_name.as_deref().unwrap_or(&filename);
+    let caption = if hidden_name.is_some() { String::new() } else { meta.to_caption() };
+    let bot = Bot::with_timeout(token, UPLOAD_TIMEOUT)?;
+    let sent_bytes = content.len();
+    api_usage::request("bot.sendDocument");
+    // The Bot API writes channel and supergroup ids with a -100 prefix
+    let sent = bot.send_document(&format!("-100{}", chat.id), document_name, content, caption).await?;
+    api_usage::uploaded(sent_bytes);
+    let document = sent.document.context("Telegram did not store the file as a document")?;
+    
+    let mut record = FileRecord::new(&filename, &document.file_id, file_size, sent.message_id);
+    record.folder = folder.to_string();
+    record.sha256 = Some(sha256);
+    record.mtime = attrs.mtime;
+    record.mode = attrs.mode;
+    record.chat = Some(chat.clone());
+    record.key_id = key_id;
+    record.sent_by_bot = true;
+    db.insert_file(record.clone()).await?;
+    info!("Bot stored {} as message {} in {}", record.path(), sent.message_id, chat.name);
+    audit::record(Action::Upload, record.path(), format!("Sent by the bot to {}", chat.name));
+    Ok(record)
+}
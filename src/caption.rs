@@ -0,0 +1,121 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+use crate::database::FileRecord;
+use crate::folder;
+
+/// Telegram's caption limit for regular accounts
+const MAX_CAPTION_LEN: usize = 1024;
+/// Caption of documents sent while the rest of a stream is still uploading,
+/// replaced by their metadata once it is done
+pub const PENDING: &str = "TeleDrive: uploading…";
+
+/// Metadata stored as the caption of every uploaded document, so the index
+/// can be rebuilt from the storage chat alone.
+///
+/// Keys are kept short because captions are limited to 1024 characters.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CaptionMeta {
+    /// Format version, bumped on incompatible changes
+    #[serde(rename = "v")]
+    pub version: u32,
+    /// Original local path
+    #[serde(rename = "p")]
+    pub path: String,
+    /// Virtual folder
+    #[serde(rename = "f", default, skip_serializing_if = "String::is_empty")]
+    pub folder: String,
+    /// Size of the whole file in bytes
+    #[serde(rename = "s")]
+    pub size: u64,
+    /// SHA-256 of the whole file, hex encoded
+    #[serde(rename = "h")]
+    pub sha256: String,
+    /// Modification time, seconds since the Unix epoch
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Unix permission bits
+    #[serde(rename = "o", default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(rename = "t", default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Whether the document content is encrypted
+    #[serde(rename = "e", default)]
+    pub encrypted: bool,
+    /// Fingerprint of the key, when encrypted
+    #[serde(rename = "k", default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Whether the document is a zstd copy of the file
+    #[serde(rename = "z", default, skip_serializing_if = "std::ops::Not::not")]
+    pub compressed: bool,
+    /// Piece number and piece count, for files split across messages
+    #[serde(rename = "x", default, skip_serializing_if = "Option::is_none")]
+    pub piece: Option<(u32, u32)>,
+    /// Whether the image was sent as a photo, so size and hash are those of
+    /// the original rather than of the JPEG Telegram keeps
+    #[serde(rename = "i", default, skip_serializing_if = "std::ops::Not::not")]
+    pub photo: bool,
+    /// Whether the document is a resized copy of the image, so the original
+    /// is not what a download gives back
+    #[serde(rename = "d", default, skip_serializing_if = "std::ops::Not::not")]
+    pub downscaled: bool,
+}
+
+impl CaptionMeta {
+    pub const VERSION: u32 = 1;
+    
+    /// Compact JSON caption, dropping the directory part of the path if needed to fit
+    pub fn to_caption(&self) -> String {
+        let caption = serde_json::to_string(self).unwrap_or_default();
+        if caption.chars().count() <= MAX_CAPTION_LEN {
+            return caption;
+        }
+        
+        let mut short = self.clone();
+        short.path = Path::new(&self.path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        serde_json::to_string(&short).unwrap_or_default()
+    }
+    
+    /// Fill in what the caption tells about `record`; its size is left as
+    /// the document's
+    pub fn apply(&self, record: &mut FileRecord) {
+        // Anyone in the chat can write a caption, so the folder is cleaned like typed input
+        record.folder = folder::normalize(&self.folder);
+        record.sha256 = Some(self.sha256.clone()).filter(|h| !h.is_empty() && !self.photo);
+        record.tags = self.tags.clone();
+        record.mtime = self.mtime;
+        record.mode = self.mode;
+        record.compressed = self.compressed;
+        record.key_id = self.key_id.clone();
+        record.photo = self.photo;
+        record.downscaled = self.downscaled;
+    }
+    
+    /// Parse a caption written by `to_caption`; `None` for anything else
+    pub fn parse(caption: &str) -> Option<Self> {
+        let meta: Self = serde_json::from_str(caption.trim()).ok()?;
+        (meta.version >= 1).then_some(meta)
+    }
+}
+
+/// SHA-256 of a file, hex encoded. Blocking; run off the async runtime
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
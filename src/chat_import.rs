@@ -0,0 +1,81 @@
+use anyhow::Result;
+use chrono::Local;
+use grammers_client::types::media::{Document, Photo};
+use grammers_client::types::{Media, Message};
+use grammers_client::Client;
+use grammers_session::PackedChat;
+use std::path::Path;
+
+use crate::api_usage;
+use crate::caption::CaptionMeta;
+use crate::database::{ChatRef, FileRecord};
+use crate::photo;
+
+/// Report scan progress every this many messages
+const PROGRESS_EVERY: usize = 200;
+
+/// Records for every document in the history of `chat`, newest first.
+/// `progress` gets the number of messages scanned and documents found so far
+pub async fn scan(
+    client: &Client,
+    chat: PackedChat,
+    chat_name: &str,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<Vec<FileRecord>> {
+    let chat_ref = ChatRef::new(chat_name, chat);
+    api_usage::request("messages.getHistory");
+    let mut messages = client.iter_messages(chat);
+    let mut records = Vec::new();
+    let mut scanned = 0;
+    while let Some(message) = messages.next().await? {
+        scanned += 1;
+        if scanned % PROGRESS_EVERY == 0 {
+            progress(scanned, records.len());
+        }
+        let Some(Media::Document(document)) = message.media() else {
+            continue;
+        };
+        records.push(document_record(&message, &document, Some(chat_ref.clone())));
+    }
+    progress(scanned, records.len());
+    Ok(records)
+}
+
+/// Record for `document`, sent in `message`, dated when it was sent
+pub fn document_record(message: &Message, document: &Document, chat: Option<ChatRef>) -> FileRecord {
+    // Voice notes, round videos and GIFs often come without a name
+    let filename = match document.name() {
+        "" => format!("document_{}", message.id()),
+        name => name.to_string(),
+    };
+    let mut record = FileRecord::new(
+        &filename,
+        &format!("tg_file_{}", filename),
+        document.size().max(0) as u64,
+        message.id(),
+    );
+    record.upload_date = message.date().with_timezone(&Local);
+    record.chat = chat;
+    record.album = message.grouped_id();
+    record
+}
+
+/// Record for an image TeleDrive sent as `photo`, named after the original
+/// its caption `meta` describes
+pub fn photo_record(message: &Message, photo: &Photo, meta: &CaptionMeta, chat: Option<ChatRef>) -> FileRecord {
+    let name = Path::new(&meta.path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("photo");
+    let mut record = FileRecord::new(
+        &photo::stored_name(name),
+        &format!("tg_file_{}", name),
+        photo.size().max(0) as u64,
+        message.id(),
+    );
+    record.upload_date = message.date().with_timezone(&Local);
+    record.chat = chat;
+    record.album = message.grouped_id();
+    meta.apply(&mut record);
+    record
+}
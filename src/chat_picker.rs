@@ -0,0 +1,55 @@
+use anyhow::Result;
+use grammers_client::Client;
+use grammers_client::types::Chat;
+use grammers_session::PackedChat;
+
+use crate::api_usage;
+use crate::slint_generatedAppWindow::ChatEntry as SlintChatEntry;
+
+/// Most recent dialogs offered in the picker
+const MAX_DIALOGS: usize = 500;
+
+/// A chat or contact files can be sent to
+pub struct DialogChoice {
+    pub name: String,
+    /// "User", "Bot", "Group" or "Channel", translated for display
+    pub kind: &'static str,
+    pub chat: PackedChat,
+}
+
+/// The account's dialogs, most recently active first
+pub async fn load_dialogs(client: &Client) -> Result<Vec<DialogChoice>> {
+    api_usage::request("messages.getDialogs");
+    let mut dialogs = client.iter_dialogs().limit(MAX_DIALOGS);
+    let mut choices = Vec::new();
+    while let Some(dialog) = dialogs.next().await? {
+        let chat = dialog.chat();
+        let kind = match chat {
+            Chat::User(user) if user.is_bot() => "Bot",
+            Chat::User(_) => "User",
+            Chat::Group(_) => "Group",
+            Chat::Channel(_) => "Channel",
+        };
+        choices.push(DialogChoice {
+            name: chat.name().to_string(),
+            kind,
+            chat: chat.pack(),
+        });
+    }
+    Ok(choices)
+}
+
+/// Dialogs whose name contains `query`, case-insensitively
+pub fn filter(dialogs: &[DialogChoice], query: &str) -> Vec<SlintChatEntry> {
+    let query = query.trim().to_lowercase();
+    dialogs
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| d.name.to_lowercase().contains(&query))
+        .map(|(index, d)| SlintChatEntry {
+            name: d.name.clone().into(),
+            kind: d.kind.into(),
+            index: index as i32,
+        })
+        .collect()
+}
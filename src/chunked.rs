@@ -0,0 +1,405 @@
+use anyhow::{bail, Context, Result};
+use grammers_client::types::{Downloadable, Media, Uploaded};
+use grammers_client::Client;
+use grammers_mtsender::InvocationError;
+use grammers_tl_types as tl;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::task::JoinSet;
+
+use crate::api_usage;
+use crate::transfer::{Cancelled, ResumeState, TransferControl};
+
+/// Size of each uploaded part (Telegram's maximum)
+pub const UPLOAD_PART_SIZE: usize = 512 * 1024;
+/// Size of each downloaded chunk (Telegram's maximum)
+pub const DOWNLOAD_CHUNK_SIZE: i32 = 512 * 1024;
+/// Files above this must be sent with `saveBigFilePart`
+const BIG_FILE_THRESHOLD: u64 = 10 * 1024 * 1024;
+/// Documents smaller than this are downloaded over a single stream
+const PARALLEL_THRESHOLD: u64 = 32 * 1024 * 1024;
+/// Chunks requested at once by a parallel download
+const PARALLEL_CHUNKS: usize = 4;
+
+/// Fill `buf` from `reader`, returning fewer bytes only at end of input
+async fn read_part<R: AsyncReadExt + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Upload the `range` bytes of `path` part by part as a file called `name`,
+/// continuing from `resume`.
+///
+/// `resume` is updated after every part, so a transfer stopped by pause, a
+/// dropped connection or a restart can carry on from the last acknowledged part.
+pub async fn upload_parts(
+    client: &Client,
+    path: &Path,
+    name: &str,
+    range: Range<u64>,
+    resume: &mut ResumeState,
+    control: &TransferControl,
+    mut on_progress: impl FnMut(u64, u64),
+) -> Result<Uploaded> {
+    let name = name.to_string();
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = range.end - range.start;
+    let total_parts = size.div_ceil(UPLOAD_PART_SIZE as u64).max(1) as i32;
+    let big = size > BIG_FILE_THRESHOLD;
+    
+    if resume.upload_file_id == 0 {
+        resume.upload_file_id = rand::random::<i64>();
+        resume.parts_done = 0;
+    }
+    let file_id = resume.upload_file_id;
+    
+    file.seek(SeekFrom::Start(range.start + resume.parts_done as u64 * UPLOAD_PART_SIZE as u64)).await?;
+    let mut buf = vec![0u8; UPLOAD_PART_SIZE];
+    
+    while (resume.parts_done as i32) < total_parts {
+        control.check()?;
+        
+        let remaining = size - resume.parts_done as u64 * UPLOAD_PART_SIZE as u64;
+        let n = read_part(&mut file, &mut buf[..remaining.min(UPLOAD_PART_SIZE as u64) as usize]).await?;
+        let bytes = buf[..n].to_vec();
+        let file_part = resume.parts_done as i32;
+        
+        api_usage::request(if big { "upload.saveBigFilePart" } else { "upload.saveFilePart" });
+        api_usage::uploaded(bytes.len());
+        let request = async {
+            if big {
+                client.invoke(&tl::functions::upload::SaveBigFilePart {
+                    file_id,
+                    file_part,
+                    file_total_parts: total_parts,
+                    bytes,
+                }).await
+            } else {
+                client.invoke(&tl::functions::upload::SaveFilePart {
+                    file_id,
+                    file_part,
+                    bytes,
+                }).await
+            }
+        };
+        
+        let accepted = tokio::select! {
+            result = request => result?,
+            _ = control.cancelled() => return Err(Cancelled.into()),
+        };
+        if !accepted {
+            bail!("Telegram rejected part {} of {}", file_part, name);
+        }
+        
+        resume.parts_done += 1;
+        on_progress((resume.parts_done as u64 * UPLOAD_PART_SIZE as u64).min(size), size);
+    }
+    
+    let input_file = if big {
+        tl::enums::InputFile::Big(tl::types::InputFileBig {
+            id: file_id,
+            parts: total_parts,
+            name,
+        })
+    } else {
+        tl::enums::InputFile::File(tl::types::InputFile {
+            id: file_id,
+            parts: total_parts,
+            name,
+            md5_checksum: String::new(),
+        })
+    };
+    
+    Ok(Uploaded::from_raw(input_file))
+}
+
+/// Upload what `reader` yields, up to `limit` bytes, as a file called `name`
+/// whose size isn't known in advance. Parts are sent as they are read, and
+/// only the last one tells Telegram how many there are.
+///
+/// Returns the file and its size, or `None` if `reader` had nothing left.
+/// Nothing is kept, so a stream can't be resumed
+pub async fn upload_stream<R: AsyncReadExt + Unpin>(
+    client: &Client,
+    reader: &mut R,
+    name: &str,
+    limit: u64,
+    control: &TransferControl,
+) -> Result<Option<(Uploaded, u64)>> {
+    let file_id = rand::random::<i64>();
+    let mut pending: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut size = 0u64;
+    let mut ended = false;
+    
+    // Small files can't be sent as big ones, so hold back that much first
+    while !ended && size <= BIG_FILE_THRESHOLD {
+        let part = next_part(reader, limit - size).await?;
+        ended = part.len() < UPLOAD_PART_SIZE || size + part.len() as u64 == limit;
+        size += part.len() as u64;
+        if !part.is_empty() {
+            pending.push_back(part);
+        }
+    }
+    if pending.is_empty() {
+        return Ok(None);
+    }
+    
+    let big = size > BIG_FILE_THRESHOLD;
+    let mut file_part = 0;
+    loop {
+        control.check()?;
+        
+        // One part read ahead tells whether the current one is the last
+        if pending.len() < 2 && !ended {
+            let part = next_part(reader, limit - size).await?;
+            ended = part.len() < UPLOAD_PART_SIZE || size + part.len() as u64 == limit;
+            size += part.len() as u64;
+            if !part.is_empty() {
+                pending.push_back(part);
+            }
+        }
+        let Some(bytes) = pending.pop_front() else {
+            break;
+        };
+        let last = ended && pending.is_empty();
+        
+        api_usage::request(if big { "upload.saveBigFilePart" } else { "upload.saveFilePart" });
+        api_usage::uploaded(bytes.len());
+        let request = async {
+            if big {
+                client.invoke(&tl::functions::upload::SaveBigFilePart {
+                    file_id,
+                    file_part,
+                    file_total_parts: if last { file_part + 1 } else { -1 },
+                    bytes,
+                }).await
+            } else {
+                client.invoke(&tl::functions::upload::SaveFilePart {
+                    file_id,
+                    file_part,
+                    bytes,
+                }).await
+            }
+        };
+        let accepted = tokio::select! {
+            result = request => result?,
+            _ = control.cancelled() => return Err(Cancelled.into()),
+        };
+        if !accepted {
+            bail!("Telegram rejected part {} of {}", file_part, name);
+        }
+        file_part += 1;
+    }
+    
+    let input_file = if big {
+        tl::enums::InputFile::Big(tl::types::InputFileBig {
+            id: file_id,
+            parts: file_part,
+            name: name.to_string(),
+        })
+    } else {
+        tl::enums::InputFile::File(tl::types::InputFile {
+            id: file_id,
+            parts: file_part,
+            name: name.to_string(),
+            md5_checksum: String::new(),
+        })
+    };
+    Ok(Some((Uploaded::from_raw(input_file), size)))
+}
+
+/// Read the next part of a stream, at most `remaining` bytes; empty at the end
+async fn next_part<R: AsyncReadExt + Unpin>(reader: &mut R, remaining: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; remaining.min(UPLOAD_PART_SIZE as u64) as usize];
+    let n = read_part(reader, &mut buf).await?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+/// Download `media` into `dest` at byte `start` chunk by chunk, continuing from `resume`.
+///
+/// When resuming, the existing partial file is trimmed to the last complete
+/// chunk and appended to. Progress is reported relative to the media alone.
+pub async fn download_parts(
+    client: &Client,
+    media: &Media,
+    dest: &Path,
+    start: u64,
+    total: u64,
+    resume: &mut ResumeState,
+    control: &TransferControl,
+    mut on_progress: impl FnMut(u64, u64),
+) -> Result<()> {
+    let mut file = open_partial(dest, start, resume).await?;
+    
+    let mut download = client
+        .iter_download(media)
+        .chunk_size(DOWNLOAD_CHUNK_SIZE)
+        .skip_chunks(resume.parts_done as i32);
+    let mut received = resume.parts_done as u64 * DOWNLOAD_CHUNK_SIZE as u64;
+    
+    // Always flush what was written, so a paused download keeps every complete chunk
+    let result = async {
+        loop {
+            control.check()?;
+            
+            let chunk = tokio::select! {
+                result = download.next() => result?,
+                _ = control.cancelled() => return Err(Cancelled.into()),
+            };
+            let Some(chunk) = chunk else {
+                return Ok(());
+            };
+            api_usage::request("upload.getFile");
+            api_usage::downloaded(chunk.len());
+            
+            file.write_all(&chunk).await?;
+            resume.parts_done += 1;
+            received += chunk.len() as u64;
+            on_progress(received.min(total), total);
+        }
+    }.await;
+    
+    file.flush().await?;
+    result
+}
+
+/// Open `dest` for writing at the first chunk `resume` has not completed,
+/// trimming anything after it
+async fn open_partial(dest: &Path, start: u64, resume: &mut ResumeState) -> Result<tokio::fs::File> {
+    let offset = start + resume.parts_done as u64 * DOWNLOAD_CHUNK_SIZE as u64;
+    
+    if offset > 0 && dest.exists() {
+        let mut file = tokio::fs::OpenOptions::new().write(true).open(dest).await?;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        Ok(file)
+    } else if start > 0 {
+        bail!("Earlier pieces of {:?} are missing; download it again", dest);
+    } else {
+        resume.parts_done = 0;
+        Ok(tokio::fs::File::create(dest).await?)
+    }
+}
+
+/// Like `download_parts`, but large documents are fetched with several
+/// byte-range requests in flight at once.
+///
+/// Chunks are still written in order, so `resume` means the same thing for
+/// both and a download can switch between them when resumed.
+pub async fn download_parts_parallel(
+    client: &Client,
+    media: &Media,
+    dest: &Path,
+    start: u64,
+    total: u64,
+    resume: &mut ResumeState,
+    control: &TransferControl,
+    mut on_progress: impl FnMut(u64, u64),
+) -> Result<()> {
+    let location = match file_location(media) {
+        Some(location) if total >= PARALLEL_THRESHOLD => location,
+        _ => return download_parts(client, media, dest, start, total, resume, control, on_progress).await,
+    };
+    
+    let mut file = open_partial(dest, start, resume).await?;
+    let total_chunks = total.div_ceil(DOWNLOAD_CHUNK_SIZE as u64) as u32;
+    let mut next_chunk = resume.parts_done;
+    let mut finished: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut requests = JoinSet::new();
+    
+    let result = async {
+        loop {
+            control.check()?;
+            
+            // Keep the window full, counting chunks that arrived out of order
+            while next_chunk < total_chunks && requests.len() + finished.len() < PARALLEL_CHUNKS {
+                requests.spawn(fetch_chunk(client.clone(), location.clone(), next_chunk));
+                next_chunk += 1;
+            }
+            
+            let joined = tokio::select! {
+                joined = requests.join_next() => joined,
+                _ = control.cancelled() => return Err(Cancelled.into()),
+            };
+            let Some(joined) = joined else {
+                return Ok(());
+            };
+            let (index, bytes) = joined.context("Chunk download panicked")??;
+            finished.insert(index, bytes);
+            
+            while let Some(bytes) = finished.remove(&resume.parts_done) {
+                file.write_all(&bytes).await?;
+                resume.parts_done += 1;
+                on_progress((resume.parts_done as u64 * DOWNLOAD_CHUNK_SIZE as u64).min(total), total);
+            }
+        }
+    }.await;
+    
+    requests.abort_all();
+    file.flush().await?;
+    result
+}
+
+/// Location of a document's content, or a photo's largest size, for `upload.getFile`
+pub fn file_location(media: &Media) -> Option<tl::enums::InputFileLocation> {
+    let document = match media {
+        Media::Document(document) => document,
+        Media::Photo(photo) => return photo.to_raw_input_location(),
+        _ => return None,
+    };
+    let Some(tl::enums::Document::Document(raw)) = &document.raw.document else {
+        return None;
+    };
+    
+    Some(tl::types::InputDocumentFileLocation {
+        id: raw.id,
+        access_hash: raw.access_hash,
+        file_reference: raw.file_reference.clone(),
+        thumb_size: String::new(),
+    }.into())
+}
+
+/// Size of what `file_location` points at; `None` for other media
+pub fn media_size(media: &Media) -> Option<u64> {
+    match media {
+        Media::Document(document) => Some(document.size().max(0) as u64),
+        Media::Photo(photo) => Some(photo.size().max(0) as u64),
+        _ => None,
+    }
+}
+
+/// Fetch one chunk of `DOWNLOAD_CHUNK_SIZE` bytes, following Telegram to the
+/// DC that stores the file
+pub async fn fetch_chunk(client: Client, location: tl::enums::InputFileLocation, index: u32) -> Result<(u32, Vec<u8>)> {
+    let request = tl::functions::upload::GetFile {
+        precise: false,
+        cdn_supported: false,
+        location,
+        offset: index as i64 * DOWNLOAD_CHUNK_SIZE as i64,
+        limit: DOWNLOAD_CHUNK_SIZE,
+    };
+    
+    api_usage::request("upload.getFile");
+    let response = match client.invoke(&request).await {
+        Err(InvocationError::Rpc(rpc)) if rpc.name == "FILE_MIGRATE" && rpc.value.is_some() => {
+            client.invoke_in_dc(&request, rpc.value.unwrap() as i32).await?
+        }
+        other => other?,
+    };
+    let tl::enums::upload::File::File(file) = response else {
+        bail!("Telegram redirected the download to a CDN");
+    };
+    api_usage::downloaded(file.bytes.len());
+    Ok((index, file.bytes))
+}
@@ -0,0 +1,208 @@
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Telegram-backed cloud storage. Without a command the desktop app starts
+#[derive(Parser)]
+#[command(name = "teledrive", version)]
+pub struct Cli {
+    /// Start hidden in the tray (used when launched at login)
+    #[arg(long)]
+    pub minimized: bool,
+    /// Named profile with its own session, database and settings
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Keep all files next to the executable (same as a portable.flag file there)
+    #[arg(long, global = true)]
+    pub portable: bool,
+    /// Print the results of a command as JSON
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Print the results of a command as tab-separated lines meant for scripts
+    #[arg(long, global = true, conflicts_with = "json")]
+    pub porcelain: bool,
+    /// Files to upload; handed to the running window if there is one
+    #[arg(value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List stored files, newest first
+    List {
+        /// Only files whose name or folder contains this
+        query: Option<String>,
+        /// Only starred files
+        #[arg(long)]
+        starred: bool,
+    },
+    /// Show the number of stored files and the transfers waiting to run
+    Status,
+    /// Upload files, waiting until they are stored. `-` uploads standard
+    /// input as it is read, e.g. `pg_dump db | teledrive upload --name db.sql -`
+    Upload {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Virtual folder to store them in
+        #[arg(long, default_value = "")]
+        folder: String,
+        /// Name to store standard input under
+        #[arg(long)]
+        name: Option<String>,
+        /// Send images as Telegram photos, compressed and previewable
+        #[arg(long, conflicts_with = "as_document")]
+        as_photo: bool,
+        /// Send images as documents, keeping the originals
+        #[arg(long)]
+        as_document: bool,
+        /// Send large JPEG and PNG images as smaller copies, as set under
+        /// Smaller images
+        #[arg(long, conflicts_with = "full_size")]
+        downscale: bool,
+        /// Send images at full size whatever the settings say
+        #[arg(long)]
+        full_size: bool,
+        /// Have the companion bot send them to the chat set for bot uploads,
+        /// without a user session; files up to 50 MB
+        #[arg(long, conflicts_with_all = ["name", "as_photo", "downscale"])]
+        via_bot: bool,
+    },
+    /// Back up a folder as one .tar.zst archive, packed while it uploads
+    Backup {
+        dir: PathBuf,
+        /// Virtual folder to store the archive in
+        #[arg(long, default_value = "")]
+        folder: String,
+        /// Name of the archive; the folder's name and the time by default
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Incremental backups of a folder, restorable one by one
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Write a stored file to standard output as it downloads, e.g.
+    /// `teledrive cat backup.tar | tar -x`
+    Cat {
+        /// Stored file ("folder/name" works too)
+        name: String,
+    },
+    /// Download stored files by name ("folder/name" works too)
+    Download {
+        #[arg(required = true)]
+        names: Vec<String>,
+        /// Folder to save them in
+        #[arg(long, default_value = ".")]
+        dest: PathBuf,
+    },
+    /// Compare the storage chat with the index: report documents no file
+    /// points at and files whose message was deleted
+    Gc {
+        /// Delete those documents and drop those files from the index
+        #[arg(long)]
+        clean: bool,
+    },
+    /// Copy every stored file to the account signed in with another profile,
+    /// forwarding through the chat between the two accounts and uploading
+    /// again what can't be forwarded
+    Migrate {
+        /// Profile signed in to the account to copy to; "default" for the default profile
+        #[arg(long)]
+        to: String,
+        /// Delete each file from this account once the other has it
+        #[arg(long = "move")]
+        move_files: bool,
+    },
+    /// Check the index for unreadable, duplicate or inconsistent records and
+    /// whether their messages still exist
+    Check {
+        /// Put right what can be, using what Telegram still has
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Expose the stored files to other programs
+    Serve {
+        #[command(subcommand)]
+        protocol: ServeProtocol,
+    },
+}
+
+impl Command {
+    /// Resolve the paths given against `base`, where the app was started,
+    /// before the working directory changes
+    pub fn make_absolute(&mut self, base: &Path) {
+        match self {
+            Command::Upload { files, .. } => {
+                for file in files.iter_mut().filter(|f| !is_stdin(f)) {
+                    *file = base.join(&*file);
+                }
+            }
+            Command::Download { dest, .. } => *dest = base.join(&*dest),
+            Command::Backup { dir, .. } => *dir = base.join(&*dir),
+            Command::Snapshot { action: SnapshotAction::Create { dir } } => *dir = base.join(&*dir),
+            Command::Snapshot { action: SnapshotAction::Restore { dest, .. } } => *dest = base.join(&*dest),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Take a snapshot of a folder, sending only data no earlier snapshot stored
+    Create {
+        dir: PathBuf,
+    },
+    /// List the snapshots taken, oldest first
+    List,
+    /// Restore a snapshot into a folder
+    Restore {
+        /// Snapshot id, or enough of its start to tell it apart
+        id: String,
+        /// Folder to restore into
+        #[arg(long)]
+        dest: PathBuf,
+    },
+    /// Drop the snapshots the rules don't keep. Each folder's snapshots are
+    /// counted apart, and a snapshot kept by any rule stays
+    Forget {
+        /// Keep the newest N snapshots
+        #[arg(long, value_name = "N")]
+        keep_last: Option<usize>,
+        /// Keep the newest snapshot of each of the last N days with one
+        #[arg(long, value_name = "N")]
+        keep_daily: Option<usize>,
+        /// Keep the newest snapshot of each of the last N weeks with one
+        #[arg(long, value_name = "N")]
+        keep_weekly: Option<usize>,
+        /// Keep the newest snapshot of each of the last N months with one
+        #[arg(long, value_name = "N")]
+        keep_monthly: Option<usize>,
+        /// Prune right after
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Delete stored chunks no snapshot needs any more
+    Prune,
+}
+
+/// `-`, standing for standard input
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+#[derive(Subcommand)]
+pub enum ServeProtocol {
+    /// SFTP for FileZilla, `sftp`, rclone and the like. The password is read
+    /// from SFTP_PASSWORD
+    Sftp {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:2222")]
+        listen: SocketAddr,
+        /// User name clients log in with
+        #[arg(long, default_value = "teledrive")]
+        user: String,
+    },
+}
@@ -0,0 +1,91 @@
+use anyhow::{bail, Context, Result};
+use arboard::Clipboard;
+use std::path::PathBuf;
+
+use crate::paths;
+
+/// A file taken from the clipboard, ready to queue for upload
+pub struct PastedFile {
+    pub path: PathBuf,
+    /// Written by us (pasted image), so it can be removed after upload
+    pub temporary: bool,
+}
+
+/// Collect uploadable files from the clipboard.
+///
+/// Copied files arrive as paths or `file://` URIs, one per line; a bitmap
+/// (e.g. a screenshot) is encoded to a PNG with a timestamped name in the temp dir.
+pub fn paste_files() -> Result<Vec<PastedFile>> {
+    let mut clipboard = Clipboard::new().context("Clipboard unavailable")?;
+    
+    if let Ok(text) = clipboard.get_text() {
+        let files: Vec<PastedFile> = text
+            .lines()
+            .filter_map(parse_path)
+            .filter(|path| path.is_file())
+            .map(|path| PastedFile { path, temporary: false })
+            .collect();
+        if !files.is_empty() {
+            return Ok(files);
+        }
+    }
+    
+    if let Ok(image) = clipboard.get_image() {
+        let path = save_pasted_image(image)?;
+        return Ok(vec![PastedFile { path, temporary: true }]);
+    }
+    
+    bail!("Clipboard holds no image or file path")
+}
+
+/// Accept plain paths and `file://` URIs
+fn parse_path(line: &str) -> Option<PathBuf> {
+    let line = line.trim().trim_matches('"');
+    if line.is_empty() {
+        return None;
+    }
+    
+    match line.strip_prefix("file://") {
+        Some(uri) => Some(PathBuf::from(percent_decode(uri))),
+        None => Some(PathBuf::from(line)),
+    }
+}
+
+/// Decode `%XX` escapes in a file URI
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&input[i + 1..i + 3], 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn save_pasted_image(image: arboard::ImageData) -> Result<PathBuf> {
+    let buffer = image::RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .context("Clipboard image has an unexpected size")?;
+    
+    let dir = paths::cache_dir("teledrive-clipboard");
+    std::fs::create_dir_all(&dir)?;
+    
+    let name = format!("Pasted_{}.png", chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
+    let path = dir.join(name);
+    buffer.save_with_format(&path, image::ImageFormat::Png)?;
+    
+    Ok(path)
+}
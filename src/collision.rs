@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+/// What to do with a download whose destination already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    Overwrite,
+    Skip,
+    /// Save next to it as "name (1).ext"
+    KeepBoth,
+}
+
+/// `path` with " (1)", " (2)"... before the extension, whichever is free first
+pub fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// Asks what to do about existing destinations while queueing downloads,
+/// and stops asking once the user applies a choice to all of them
+pub struct CollisionPrompt {
+    /// Destinations that exist and haven't been asked about yet
+    remaining: usize,
+    apply_to_all: Option<Collision>,
+}
+
+impl CollisionPrompt {
+    /// Prompt for downloads into `dests`, counting those already present
+    pub fn new<'a>(dests: impl IntoIterator<Item = &'a Path>) -> Self {
+        Self {
+            remaining: dests.into_iter().filter(|d| d.exists()).count(),
+            apply_to_all: None,
+        }
+    }
+    
+    /// Where to save a download meant for `dest`; `None` skips it
+    pub fn resolve(&mut self, dest: PathBuf) -> Option<PathBuf> {
+        if !dest.exists() {
+            return Some(dest);
+        }
+        let choice = match self.apply_to_all {
+            Some(choice) => choice,
+            None => {
+                let choice = ask(&dest);
+                self.remaining = self.remaining.saturating_sub(1);
+                if self.remaining > 0 && ask_apply_to_all(self.remaining) {
+                    self.apply_to_all = Some(choice);
+                }
+                choice
+            }
+        };
+        match choice {
+            Collision::Overwrite => Some(dest),
+            Collision::Skip => None,
+            Collision::KeepBoth => Some(free_name(&dest)),
+        }
+    }
+}
+
+fn ask(dest: &Path) -> Collision {
+    let overwrite = tr!("Overwrite");
+    let keep_both = tr!("Keep both");
+    let answer = rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Warning)
+        .set_title(tr!("File already exists"))
+        .set_description(tr!(
+            "{name} already exists in {folder}. Overwrite it, keep both, or skip this download?",
+            name = dest.file_name().unwrap_or_default().to_string_lossy(),
+            folder = dest.parent().unwrap_or(dest).display(),
+        ))
+        .set_buttons(rfd::MessageButtons::YesNoCancelCustom(overwrite.clone(), keep_both.clone(), tr!("Skip")))
+        .show();
+    // Some platforms report custom buttons by label, others by position
+    match answer {
+        rfd::MessageDialogResult::Yes => Collision::Overwrite,
+        rfd::MessageDialogResult::No => Collision::KeepBoth,
+        rfd::MessageDialogResult::Custom(label) if label == overwrite => Collision::Overwrite,
+        rfd::MessageDialogResult::Custom(label) if label == keep_both => Collision::KeepBoth,
+        _ => Collision::Skip,
+    }
+}
+
+fn ask_apply_to_all(remaining: usize) -> bool {
+    rfd::MessageDialog::new()
+        .set_title(tr!("File already exists"))
+        .set_description(tr!("Do the same for the other {count} files that already exist?", count = remaining))
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show() == rfd::MessageDialogResult::Yes
+}
@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+/// Folder compressed copies wait in until they are uploaded
+const COMPRESSED_DIR: &str = "teledrive-compressed";
+/// A compressed copy has to save at least this share of the size to be used
+const MIN_SAVING: f64 = 0.05;
+
+/// When uploads are compressed with zstd before they are sent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionRules {
+    pub enabled: bool,
+    /// zstd level from 1 (fastest) to 19 (smallest); also used for folder archives
+    pub level: i32,
+    /// Smaller files are sent as they are
+    pub min_size: u64,
+    /// Extensions, without the dot, of formats that are compressed already
+    pub skip_extensions: Vec<String>,
+}
+
+impl Default for CompressionRules {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: 3,
+            min_size: 1024 * 1024,
+            skip_extensions: [
+                "zip", "gz", "xz", "bz2", "7z", "rar", "zst",
+                "jpg", "jpeg", "png", "gif", "webp", "heic",
+                "mp4", "mkv", "mov", "avi", "webm", "mp3", "m4a", "aac", "ogg", "opus", "flac",
+                "docx", "xlsx", "pptx", "odt", "epub", "apk",
+            ].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl CompressionRules {
+    /// Whether a `size` byte file at `path` should be compressed
+    pub fn applies_to(&self, path: &Path, size: u64) -> bool {
+        let extension = path.extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        self.enabled
+            && size >= self.min_size
+            && !self.skip_extensions.iter().any(|skip| skip.eq_ignore_ascii_case(&extension))
+    }
+    
+    /// Extensions as typed in the settings: separated by commas or spaces,
+    /// with or without the dot
+    pub fn parse_extensions(text: &str) -> Vec<String> {
+        text.split(|c: char| c == ',' || c.is_whitespace())
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect()
+    }
+}
+
+/// Compressed copy of `path` named "<name>.zst", in a folder named after
+/// `sha256` so a resumed upload finds the same bytes again. `None` when
+/// compressing would not save enough to be worth it
+pub fn compress(path: &Path, sha256: &str, level: i32) -> Result<Option<PathBuf>> {
+    let filename = path.file_name().context("Invalid filename")?;
+    let dir = paths::cache_dir(COMPRESSED_DIR).join(sha256);
+    let mut name = filename.to_os_string();
+    name.push(".zst");
+    let dest = dir.join(name);
+    
+    if !dest.exists() {
+        std::fs::create_dir_all(&dir)?;
+        // Written under another name first so an interrupted run leaves no
+        // truncated copy to be picked up on resume
+        let partial = dest.with_extension("zst.partial");
+        let mut encoder = zstd::stream::write::Encoder::new(File::create(&partial)?, level)?;
+        std::io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?;
+        std::fs::rename(&partial, &dest)?;
+    }
+    
+    let original = std::fs::metadata(path)?.len();
+    let compressed = std::fs::metadata(&dest)?.len();
+    if compressed as f64 > original as f64 * (1.0 - MIN_SAVING) {
+        remove(&dest);
+        return Ok(None);
+    }
+    Ok(Some(dest))
+}
+
+/// Delete a compressed copy once it is no longer needed
+pub fn remove(copy: &Path) {
+    let _ = std::fs::remove_file(copy);
+    if let Some(dir) = copy.parent() {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+/// Replace the zstd file at `path` with what it holds
+pub fn decompress_in_place(path: &Path) -> Result<()> {
+    let mut name = path.file_name().context("Invalid filename")?.to_os_string();
+    name.push(".decompressing");
+    let partial = path.with_file_name(name);
+    let mut decoder = zstd::stream::read::Decoder::new(File::open(path)?)?;
+    std::io::copy(&mut decoder, &mut File::create(&partial)?)
+        .with_context(|| format!("Failed to decompress {:?}", path))?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
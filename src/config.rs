@@ -0,0 +1,138 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::app_lock::AppLock;
+use crate::backup::BackupJob;
+use crate::compression::CompressionRules;
+use crate::downscale::DownscaleRules;
+use crate::paths;
+use crate::photo::SendAs;
+use crate::database::ChatRef;
+use crate::hooks::Hooks;
+use crate::logging::LogLevel;
+use crate::retry::RetryPolicy;
+use crate::session_crypt::KeySource;
+use crate::sync::SyncPair;
+
+/// User settings persisted as JSON
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Launch (minimized to tray) when the user logs in
+    pub auto_start: bool,
+    /// Only browse and download: uploads, deletes and renames are refused
+    pub read_only: bool,
+    /// Refuse files over the account's size limit instead of splitting them
+    pub reject_oversized: bool,
+    /// Encrypt uploads with a key kept in the OS keyring
+    pub encrypt_uploads: bool,
+    /// Send uploads under a random name with no caption, so Telegram sees
+    /// nothing meaningful; the real names are only in the local index
+    pub hide_names: bool,
+    /// Which uploads are compressed, and how hard
+    pub compression: CompressionRules,
+    /// Cut large uploads into content-defined pieces and reuse pieces
+    /// already stored instead of sending them again
+    pub dedup_chunks: bool,
+    /// Send photos and videos uploaded together as Telegram albums
+    pub album_uploads: bool,
+    /// How images are sent unless an upload says otherwise
+    pub send_images_as: SendAs,
+    /// Which images are made smaller before they are sent
+    pub downscale: DownscaleRules,
+    /// Index the text of uploaded documents and code so search finds what's inside
+    pub index_contents: bool,
+    /// Recognise text in uploaded images so screenshots can be searched
+    pub ocr_images: bool,
+    /// Fetch a few stored files back now and then to catch damaged ones early
+    pub verify_in_background: bool,
+    /// Look for new releases daily and show a banner when there is one
+    pub check_updates: bool,
+    /// Release whose banner was dismissed, so it isn't shown again
+    pub dismissed_update: String,
+    /// UI language code; empty follows the system locale
+    pub language: String,
+    pub theme: Theme,
+    /// Show the library as a grid of thumbnails instead of a list
+    pub grid_view: bool,
+    /// Folder downloads are saved to without asking; `None` asks every time
+    pub download_dir: Option<PathBuf>,
+    pub log_level: LogLevel,
+    /// ISO code of the country picked on the login screen
+    pub phone_country: String,
+    /// Passphrase asked for at startup and after idle time; `None` when off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_lock: Option<AppLock>,
+    /// Keep the session file encrypted with a key from this source; `None` stores it in plain text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_encryption: Option<KeySource>,
+    /// Group or supergroup used as a drive shared by its members; `None`
+    /// stores files in Saved Messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_drive: Option<ChatRef>,
+    /// Channel or supergroup `upload --via-bot` has the companion bot send files to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot_upload_chat: Option<ChatRef>,
+    /// How often and how patiently failed transfers are tried again
+    pub retry: RetryPolicy,
+    /// Commands run before uploads and after finished transfers
+    pub hooks: Hooks,
+    /// Receives a JSON POST when transfers finish or a sync fails; empty for none
+    pub webhook_url: String,
+    /// Folders backed up on a recurring schedule
+    pub backup_jobs: Vec<BackupJob>,
+    /// Local folders kept in lockstep with virtual folders
+    pub sync_pairs: Vec<SyncPair>,
+}
+
+/// Colour scheme of the window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Follow the OS dark mode preference
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    /// Order of the entries in the theme picker
+    const ALL: [Theme; 3] = [Theme::System, Theme::Light, Theme::Dark];
+    
+    pub fn index(self) -> i32 {
+        Self::ALL.iter().position(|t| *t == self).unwrap_or(0) as i32
+    }
+    
+    pub fn from_index(index: i32) -> Self {
+        usize::try_from(index).ok()
+            .and_then(|i| Self::ALL.get(i).copied())
+            .unwrap_or_default()
+    }
+}
+
+impl Settings {
+    /// Load settings, falling back to defaults if the file is missing or unreadable
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = &paths::config_file(path);
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid settings file {:?}: {:?}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+    
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = paths::config_file(path);
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use grammers_client::{Client, InitParams};
+use grammers_mtsender::InvocationError;
+use grammers_tl_types as tl;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::errors;
+use crate::retry::Backoff;
+use crate::session_crypt::{self, SharedSessionKey};
+use crate::transfer::TransferQueue;
+use crate::{set_status, AppWindow};
+
+const CONNECT_MAX_ATTEMPTS: u32 = 8;
+const CONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+const CONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// A health-check tick arriving this much later than scheduled means the machine slept
+const SLEEP_DETECT_SLACK: Duration = Duration::from_secs(15);
+/// Telegram's test data centers by number; accounts there are separate
+/// from real ones and can be made up freely
+const TEST_DCS: [(i32, &str); 3] = [
+    (1, "149.154.175.10:443"),
+    (2, "149.154.167.40:443"),
+    (3, "149.154.175.117:443"),
+];
+
+/// Health of the link to Telegram, shown next to the status line. Flood
+/// waits are shown there too, from the transfer worker's countdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    SignedOut,
+    Connecting,
+    Connected,
+    /// The connection dropped and a new one is being made
+    Reconnecting,
+    /// Reconnecting failed; tried again on the next health check
+    Offline,
+}
+
+impl ConnectionState {
+    /// Name the UI picks the label and colour by
+    fn key(self) -> &'static str {
+        match self {
+            ConnectionState::SignedOut => "signed-out",
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Offline => "offline",
+        }
+    }
+}
+
+/// Show `state` in the window
+pub fn show_state(ui_handle: &slint::Weak<AppWindow>, state: ConnectionState) {
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| ui.set_connection_state(state.key().into()));
+}
+
+/// Credentials needed to (re)connect
+#[derive(Clone)]
+pub struct ConnectParams {
+    pub api_id: i32,
+    pub api_hash: String,
+    pub session_file: String,
+    /// Key of the encrypted session file, if it is encrypted
+    pub session_key: SharedSessionKey,
+    /// Server to connect to instead of Telegram's production DCs, e.g. a test DC
+    pub server: Option<SocketAddr>,
+}
+
+/// Server picked in the environment: `TELEGRAM_TEST_DC` names a test data
+/// center (1 to 3), `TELEGRAM_SERVER` any `ip:port`. `None` for production
+pub fn server_from_env() -> Result<Option<SocketAddr>> {
+    if let Ok(address) = std::env::var("TELEGRAM_SERVER") {
+        let server = address.trim().parse()
+            .with_context(|| format!("TELEGRAM_SERVER must be an ip:port, not {:?}", address))?;
+        return Ok(Some(server));
+    }
+    let Ok(dc) = std::env::var("TELEGRAM_TEST_DC") else {
+        return Ok(None);
+    };
+    let address = dc.trim().parse::<i32>().ok()
+        .and_then(|dc| TEST_DCS.iter().find(|(id, _)| *id == dc))
+        .map(|(_, address)| *address)
+        .with_context(|| format!("TELEGRAM_TEST_DC must be 1, 2 or 3, not {:?}", dc))?;
+    Ok(Some(address.parse()?))
+}
+
+/// Initialize Telegram client, retrying transient connection failures
+pub async fn init_telegram_client(
+    params: &ConnectParams,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<Client> {
+    info!("Initializing Telegram client...");
+    
+    let mut backoff = Backoff::new(CONNECT_BASE_DELAY, CONNECT_MAX_DELAY, CONNECT_MAX_ATTEMPTS);
+    
+    loop {
+        // Load session (consumed by each connection attempt)
+        let session = session_crypt::load(&params.session_file, params.session_key.lock().unwrap().as_ref())?;
+        
+        // Connect to Telegram
+        let result = Client::connect(grammers_client::Config {
+            session,
+            api_id: params.api_id,
+            api_hash: params.api_hash.clone(),
+            params: InitParams {
+                server_addr: params.server,
+                ..Default::default()
+            },
+        }).await;
+        
+        match result {
+            Ok(client) => {
+                info!("Client connected");
+                return Ok(client);
+            }
+            Err(e) => {
+                let Some(delay) = backoff.next_delay() else {
+                    return Err(e).context(format!(
+                        "Giving up after {} attempts",
+                        backoff.max_attempts() + 1
+                    ));
+                };
+                
+                warn!("Connection attempt failed: {:?}; retrying in {:?}", e, delay);
+                set_status(
+                    &ui_handle,
+                    tr!(
+                        "Connection failed, retrying in {secs}s (attempt {attempt}/{max})...",
+                        secs = delay.as_secs().max(1),
+                        attempt = backoff.attempt(),
+                        max = backoff.max_attempts(),
+                    ),
+                );
+                tokio::time::sleep(delay).await;
+                set_status(&ui_handle, tr!("Connecting to Telegram..."));
+            }
+        }
+    }
+}
+
+/// Cheap round trip used to check that the connection is still alive
+async fn is_alive(client: &Client) -> bool {
+    api_usage::request("updates.getState");
+    let ping = client.invoke(&tl::functions::updates::GetState {});
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, ping).await {
+        Err(_) => false,
+        Ok(Err(InvocationError::Io(_) | InvocationError::Transport(_) | InvocationError::Dropped)) => false,
+        // Any answer, even a FLOOD_WAIT, means the connection works; a new
+        // one would only make a flood wait worse
+        Ok(_) => true,
+    }
+}
+
+/// Watch the shared client and transparently replace it when the connection dies.
+///
+/// Runs a periodic health check, and an immediate one when `reconnect` is notified
+/// (a transfer hit a connection error) or the machine appears to have been asleep.
+/// While reconnecting the queue is held offline, so in-flight jobs that were put
+/// back are retried on the new client.
+pub async fn run_connection_monitor(
+    client: Arc<Mutex<Option<Client>>>,
+    queue: Arc<TransferQueue>,
+    reconnect: Arc<Notify>,
+    params: ConnectParams,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    loop {
+        let started = SystemTime::now();
+        tokio::select! {
+            _ = tokio::time::sleep(HEALTH_CHECK_INTERVAL) => {
+                let elapsed = started.elapsed().unwrap_or_default();
+                if elapsed > HEALTH_CHECK_INTERVAL + SLEEP_DETECT_SLACK {
+                    info!("Woke up after {:?}; checking connection", elapsed);
+                }
+            }
+            _ = reconnect.notified() => {
+                info!("Reconnect requested");
+            }
+        }
+        
+        // Nothing to monitor until the user has signed in
+        let Some(current) = client.lock().unwrap().clone() else {
+            continue;
+        };
+        
+        if is_alive(&current).await {
+            queue.set_online(true);
+            show_state(&ui_handle, ConnectionState::Connected);
+            continue;
+        }
+        
+        warn!("Telegram connection lost; reconnecting");
+        queue.set_online(false);
+        show_state(&ui_handle, ConnectionState::Reconnecting);
+        set_status(&ui_handle, tr!("Connection lost, reconnecting..."));
+        
+        match init_telegram_client(&params, ui_handle.clone()).await {
+            Ok(new_client) => match new_client.is_authorized().await {
+                Ok(true) => {
+                    *client.lock().unwrap() = Some(new_client);
+                    queue.set_online(true);
+                    show_state(&ui_handle, ConnectionState::Connected);
+                    info!("Reconnected");
+                    set_status(&ui_handle, tr!("Reconnected to Telegram"));
+                }
+                Ok(false) => {
+                    warn!("Session is no longer authorized");
+                    *client.lock().unwrap() = None;
+                    show_state(&ui_handle, ConnectionState::SignedOut);
+                    let _ = ui_handle.upgrade_in_event_loop(|ui| {
+                        ui.set_is_authenticated(false);
+                        ui.set_status_text(tr!("Session expired, please sign in again").into());
+                    });
+                }
+                Err(e) => {
+                    warn!("Authorization check failed after reconnect: {:?}", e);
+                    show_state(&ui_handle, ConnectionState::Offline);
+                    set_status(&ui_handle, tr!("Offline, will retry shortly"));
+                }
+            },
+            Err(e) => {
+                warn!("Reconnect failed: {:?}", e);
+                show_state(&ui_handle, ConnectionState::Offline);
+                set_status(&ui_handle, tr!("Offline: {error}. Will keep retrying.", error = errors::describe(&e)));
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::error;
+
+use crate::database::{FileRecord, RecordKey};
+use crate::file_kind::FileKind;
+
+/// Text beyond this many bytes of a file is not indexed
+const MAX_TEXT_BYTES: u64 = 10 * 1024 * 1024;
+/// Shorter words are too common to be worth indexing
+const MIN_TERM_LEN: usize = 2;
+
+/// Inverted index from words to the stored files containing them, the
+/// files written as `RecordKey`s
+#[derive(Default, Serialize, Deserialize)]
+struct IndexData {
+    terms: BTreeMap<String, BTreeSet<String>>,
+    /// Terms of each document, so it can be dropped again
+    documents: HashMap<String, Vec<String>>,
+}
+
+/// Index as written before files were told apart by chat, keyed by message id
+#[derive(Deserialize)]
+struct LegacyIndexData {
+    terms: BTreeMap<String, BTreeSet<i32>>,
+    documents: HashMap<i32, Vec<String>>,
+}
+
+/// Full-text index over the contents of stored files, keyed by chat and
+/// message, persisted as JSON next to the database
+pub struct ContentIndex {
+    data: Mutex<IndexData>,
+    file: PathBuf,
+}
+
+impl ContentIndex {
+    /// Load the index at `file`. One keyed by message id alone is carried
+    /// over for the messages only one of `records` is stored in
+    pub fn new(file: impl AsRef<Path>, records: &[FileRecord]) -> Self {
+        let file = file.as_ref().to_path_buf();
+        let content = std::fs::read_to_string(&file).unwrap_or_default();
+        let data = serde_json::from_str(&content)
+            .ok()
+            .or_else(|| serde_json::from_str(&content).ok().map(|legacy| upgrade(legacy, records)))
+            .unwrap_or_default();
+        Self { data: Mutex::new(data), file }
+    }
+    
+    /// Index `text` as the contents of file `key`, replacing what was there
+    pub fn add(&self, key: RecordKey, text: &str) {
+        let key = key.to_string();
+        {
+            let mut data = self.data.lock().unwrap();
+            remove_document(&mut data, &key);
+            let terms: BTreeSet<String> = tokenize(text).collect();
+            for term in &terms {
+                data.terms.entry(term.clone()).or_default().insert(key.clone());
+            }
+            data.documents.insert(key, terms.into_iter().collect());
+        }
+        self.save();
+    }
+    
+    pub fn remove(&self, key: RecordKey) {
+        let removed = remove_document(&mut self.data.lock().unwrap(), &key.to_string());
+        if removed {
+            self.save();
+        }
+    }
+    
+    /// Documents containing every word of `query`; the last word may be a prefix
+    /// since it is usually still being typed. `None` for a query without words
+    pub fn search(&self, query: &str) -> Option<HashSet<RecordKey>> {
+        let words: Vec<String> = tokenize(query).collect();
+        let (last, rest) = words.split_last()?;
+        let data = self.data.lock().unwrap();
+        
+        let mut hits: HashSet<&String> = data.terms
+            .range(last.clone()..)
+            .take_while(|(term, _)| term.starts_with(last.as_str()))
+            .flat_map(|(_, keys)| keys.iter())
+            .collect();
+        for word in rest {
+            let keys = data.terms.get(word);
+            hits.retain(|key| keys.map_or(false, |keys| keys.contains(*key)));
+        }
+        Some(hits.into_iter().filter_map(|key| key.parse().ok()).collect())
+    }
+    
+    fn save(&self) {
+        let result = serde_json::to_string(&*self.data.lock().unwrap())
+            .map_err(anyhow::Error::from)
+            .and_then(|json| std::fs::write(&self.file, json).map_err(Into::into));
+        if let Err(e) = result {
+            error!("Failed to save content index: {:?}", e);
+        }
+    }
+}
+
+fn remove_document(data: &mut IndexData, key: &str) -> bool {
+    let Some(terms) = data.documents.remove(key) else {
+        return false;
+    };
+    for term in terms {
+        if let Some(keys) = data.terms.get_mut(&term) {
+            keys.remove(key);
+            if keys.is_empty() {
+                data.terms.remove(&term);
+            }
+        }
+    }
+    true
+}
+
+/// Rekey a legacy index by chat and message. A message id several records
+/// share can't be told apart any more, so its contents are dropped
+fn upgrade(legacy: LegacyIndexData, records: &[FileRecord]) -> IndexData {
+    let mut owners: HashMap<i32, Option<RecordKey>> = HashMap::new();
+    for key in records.iter().filter_map(FileRecord::key) {
+        owners.entry(key.message_id)
+            .and_modify(|owner| *owner = None)
+            .or_insert(Some(key));
+    }
+    let key_of = |id: &i32| owners.get(id).copied().flatten().map(|key| key.to_string());
+    
+    let mut data = IndexData::default();
+    for (id, terms) in &legacy.documents {
+        if let Some(key) = key_of(id) {
+            data.documents.insert(key, terms.clone());
+        }
+    }
+    for (term, ids) in legacy.terms {
+        let keys: BTreeSet<String> = ids.iter().filter_map(key_of).collect();
+        if !keys.is_empty() {
+            data.terms.insert(term, keys);
+        }
+    }
+    data
+}
+
+/// Lowercased words of `text`
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_TERM_LEN)
+        .map(str::to_lowercase)
+}
+
+/// Whether `extract_text` can read anything from a file of this name
+pub fn is_indexable(filename: &str) -> bool {
+    let ext = extension(filename);
+    ext == "pdf"
+        || FileKind::from_filename(filename) == FileKind::Code
+        || matches!(ext.as_str(), "txt" | "md" | "csv" | "log" | "ini" | "cfg" | "tex" | "rst")
+}
+
+/// Plain text of a text, markdown, code or PDF file; `None` for other kinds.
+/// Blocking, run it off the async runtime
+pub fn extract_text(path: &Path) -> Result<Option<String>> {
+    let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    if !is_indexable(filename) {
+        return Ok(None);
+    }
+    
+    if extension(filename) == "pdf" {
+        let text = pdf_extract::extract_text(path)
+            .with_context(|| format!("Failed to extract text from {:?}", path))?;
+        return Ok(Some(text));
+    }
+    
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?
+        .take(MAX_TEXT_BYTES)
+        .read_to_end(&mut bytes)?;
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn extension(filename: &str) -> String {
+    filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default()
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Folder crash reports are written to
+pub const CRASH_DIR: &str = "crashes";
+/// Where a report can be filed once the user agrees
+const ISSUE_URL: &str = "https://github.com/DarkFeed2005/TeleDrive-RS/issues/new";
+/// Recent log lines kept for the report
+const BREADCRUMBS: usize = 50;
+/// Keep the issue link within what browsers accept
+const MAX_ISSUE_BODY: usize = 6000;
+/// Suffix of reports the user has already been asked about
+const SEEN_SUFFIX: &str = ".seen.txt";
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Remembers the last log lines so a crash report can show what led up to it
+pub struct BreadcrumbLayer;
+
+impl<S: Subscriber> Layer<S> for BreadcrumbLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() > Level::INFO {
+            return;
+        }
+        let mut line = format!("{} {}:", event.metadata().level(), event.metadata().target());
+        event.record(&mut LineVisitor(&mut line));
+        let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == BREADCRUMBS {
+            recent.pop_front();
+        }
+        recent.push_back(redact(&line));
+    }
+}
+
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {:?}", value);
+        } else {
+            let _ = write!(self.0, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Mask long digit runs (phone numbers, ids) so reports can be shared
+fn redact(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut digits = String::new();
+    for c in line.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        if digits.len() >= 7 {
+            out.push_str(&"#".repeat(digits.len()));
+        } else {
+            out.push_str(&digits);
+        }
+        digits.clear();
+        out.push(c);
+    }
+    out.pop();
+    out
+}
+
+/// Write a report for every panic, including ones inside spawned tasks that
+/// would otherwise only end that task
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let report = format_report(&info.to_string());
+        match write_report(&report) {
+            Ok(path) => tracing::error!("Panic: {}; crash report saved to {:?}", info, path),
+            Err(e) => tracing::error!("Panic: {}; could not save crash report: {:?}", info, e),
+        }
+        default_hook(info);
+    }));
+}
+
+fn format_report(panic: &str) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "TeleDrive {} crash report", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "Time: {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z"));
+    let _ = writeln!(report, "OS: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let _ = writeln!(report, "Thread: {}", std::thread::current().name().unwrap_or("unnamed"));
+    let _ = writeln!(report, "\n{}\n", redact(panic));
+    let _ = writeln!(report, "Recent activity:");
+    for line in RECENT.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let _ = writeln!(report, "  {}", line);
+    }
+    let _ = writeln!(report, "\nBacktrace:\n{}", std::backtrace::Backtrace::force_capture());
+    report
+}
+
+fn write_report(report: &str) -> Result<PathBuf> {
+    std::fs::create_dir_all(CRASH_DIR)?;
+    let name = format!("crash-{}.txt", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    let path = Path::new(CRASH_DIR).join(name);
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// Reports written since the user was last asked about them
+fn pending_reports() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(CRASH_DIR) else {
+        return Vec::new();
+    };
+    let mut reports: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            name.starts_with("crash-") && name.ends_with(".txt") && !name.ends_with(SEEN_SUFFIX)
+        })
+        .collect();
+    reports.sort();
+    reports
+}
+
+/// Don't ask about `report` again
+fn mark_seen(report: &Path) -> Result<()> {
+    let name = report.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let seen = report.with_file_name(name.replace(".txt", SEEN_SUFFIX));
+    std::fs::rename(report, seen)?;
+    Ok(())
+}
+
+/// Ask whether to send reports of earlier crashes; each is asked about once
+pub fn offer_pending_reports() {
+    for report in pending_reports() {
+        let answer = rfd::MessageDialog::new()
+            .set_level(rfd::MessageLevel::Warning)
+            .set_title(tr!("TeleDrive crashed"))
+            .set_description(tr!(
+                "TeleDrive stopped unexpectedly. A report without your files or credentials was saved to {path}.\n\nOpen a GitHub issue with this report? You can review it before anything is sent.",
+                path = report.display(),
+            ))
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show();
+        if answer == rfd::MessageDialogResult::Yes {
+            if let Err(e) = submit(&report) {
+                tracing::warn!("Failed to open crash report issue: {:?}", e);
+            }
+        }
+        if let Err(e) = mark_seen(&report) {
+            tracing::warn!("Failed to mark crash report {:?} as seen: {:?}", report, e);
+        }
+    }
+}
+
+/// Open a new issue in the browser with the report filled in. Nothing is
+/// sent until the user submits the form
+fn submit(report: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(report)?;
+    let body: String = text.chars().take(MAX_ISSUE_BODY).collect();
+    let title = text.lines().nth(5).unwrap_or("Crash report");
+    let url = format!(
+        "{}?title={}&body={}",
+        ISSUE_URL,
+        percent_encode(&format!("Crash: {}", title)),
+        percent_encode(&format!("```\n{}\n```", body)),
+    );
+    open::that(url)?;
+    Ok(())
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
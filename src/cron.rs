@@ -0,0 +1,216 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::str::FromStr;
+
+/// A cron-style schedule: "minute hour day-of-month month day-of-week".
+///
+/// Each field accepts `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps
+/// (`*/15`, `0-30/10`). Day-of-week runs 0-6 from Sunday (7 is also Sunday).
+/// `@hourly`, `@daily`, `@weekly` and `@monthly` are accepted as shorthands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// Whether day-of-month / day-of-week were restricted (`*` otherwise)
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    /// Whether the schedule fires during the minute of `time`
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        if !self.minutes[time.minute() as usize]
+            || !self.hours[time.hour() as usize]
+            || !self.months[time.month() as usize]
+        {
+            return false;
+        }
+        
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        // As in cron, when both day fields are restricted either one may match
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+    
+    fn from_str(text: &str) -> Result<Self> {
+        let text = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("Expected 5 fields (minute hour day month weekday), got {}", fields.len());
+        };
+        
+        let mut weekdays = parse_field(weekday, 0, 7).context("Invalid day of week")?;
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59).context("Invalid minute")?,
+            hours: parse_field(hour, 0, 23).context("Invalid hour")?,
+            days: parse_field(day, 1, 31).context("Invalid day of month")?,
+            months: parse_field(month, 1, 12).context("Invalid month")?,
+            weekdays,
+            days_restricted: day != "*",
+            weekdays_restricted: weekday != "*",
+        })
+    }
+}
+
+/// Parse one field into a table indexed by value, `true` where it matches
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let mut table = vec![false; max as usize + 1];
+    
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().context("Invalid step")?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("Step must be at least 1");
+        }
+        
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let value: u32 = range.parse()?;
+            // "5/15" means from 5 to the end in steps of 15
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            bail!("{} is outside {}-{}", range, min, max);
+        }
+        
+        for value in (start..=end).step_by(step as usize) {
+            table[value as usize] = true;
+        }
+    }
+    
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    
+    fn at(date: (i32, u32, u32), hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(date.0, date.1, date.2, hour, minute, 0).unwrap()
+    }
+    
+    fn schedule(text: &str) -> CronSchedule {
+        text.parse().unwrap()
+    }
+    
+    // 2024-03-01 is a Friday, 2024-03-03 a Sunday
+    const FRIDAY: (i32, u32, u32) = (2024, 3, 1);
+    const SATURDAY: (i32, u32, u32) = (2024, 3, 2);
+    const SUNDAY: (i32, u32, u32) = (2024, 3, 3);
+    
+    #[test]
+    fn star_matches_every_minute() {
+        let every = schedule("* * * * *");
+        assert!(every.matches(&at(FRIDAY, 0, 0)));
+        assert!(every.matches(&at(SUNDAY, 23, 59)));
+    }
+    
+    #[test]
+    fn fixed_minute_and_hour() {
+        let daily = schedule("30 14 * * *");
+        assert!(daily.matches(&at(FRIDAY, 14, 30)));
+        assert!(!daily.matches(&at(FRIDAY, 14, 31)));
+        assert!(!daily.matches(&at(FRIDAY, 15, 30)));
+    }
+    
+    #[test]
+    fn steps_ranges_and_lists() {
+        let quarter = schedule("*/15 * * * *");
+        assert!([0, 15, 30, 45].iter().all(|&m| quarter.matches(&at(FRIDAY, 9, m))));
+        assert!(!quarter.matches(&at(FRIDAY, 9, 10)));
+        
+        let from_five = schedule("5/20 * * * *");
+        assert!([5, 25, 45].iter().all(|&m| from_five.matches(&at(FRIDAY, 9, m))));
+        assert!(!from_five.matches(&at(FRIDAY, 9, 0)));
+        
+        let stepped_range = schedule("0-30/10 * * * *");
+        assert!(stepped_range.matches(&at(FRIDAY, 9, 30)));
+        assert!(!stepped_range.matches(&at(FRIDAY, 9, 40)));
+        
+        let office = schedule("0 9-17 * * 1-5");
+        assert!(office.matches(&at(FRIDAY, 9, 0)));
+        assert!(office.matches(&at(FRIDAY, 17, 0)));
+        assert!(!office.matches(&at(FRIDAY, 18, 0)));
+        assert!(!office.matches(&at(SATURDAY, 9, 0)));
+        
+        let twice = schedule("0 8,20 * * *");
+        assert!(twice.matches(&at(FRIDAY, 8, 0)));
+        assert!(twice.matches(&at(FRIDAY, 20, 0)));
+        assert!(!twice.matches(&at(FRIDAY, 12, 0)));
+    }
+    
+    #[test]
+    fn seven_is_sunday() {
+        assert!(schedule("0 0 * * 7").matches(&at(SUNDAY, 0, 0)));
+        assert!(schedule("0 0 * * 0").matches(&at(SUNDAY, 0, 0)));
+        assert!(!schedule("0 0 * * 7").matches(&at(SATURDAY, 0, 0)));
+    }
+    
+    #[test]
+    fn shorthands_expand_to_fields() {
+        assert_eq!(schedule("@hourly"), schedule("0 * * * *"));
+        assert_eq!(schedule("@daily"), schedule("0 0 * * *"));
+        assert_eq!(schedule("@weekly"), schedule("0 0 * * 0"));
+        assert_eq!(schedule("@monthly"), schedule("0 0 1 * *"));
+    }
+    
+    #[test]
+    fn either_day_field_matches_when_both_are_restricted() {
+        // The 13th, or any Friday
+        let both = schedule("0 0 13 * 5");
+        assert!(both.matches(&at(FRIDAY, 0, 0)));
+        assert!(both.matches(&at((2024, 3, 13), 0, 0)));
+        assert!(!both.matches(&at(SATURDAY, 0, 0)));
+        
+        // Only the day of month is restricted, so it has to match
+        let thirteenth = schedule("0 0 13 * *");
+        assert!(!thirteenth.matches(&at(FRIDAY, 0, 0)));
+        assert!(thirteenth.matches(&at((2024, 3, 13), 0, 0)));
+    }
+    
+    #[test]
+    fn rejects_invalid_schedules() {
+        for text in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "30-10 * * * *",
+            "a * * * *",
+            "@yearly",
+        ] {
+            assert!(text.parse::<CronSchedule>().is_err(), "{:?} parsed", text);
+        }
+    }
+}
@@ -0,0 +1,1103 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use grammers_session::{PackedChat, PackedType};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::content_index::ContentIndex;
+use crate::date_range::DateRange;
+use crate::file_attrs::FileAttrs;
+use crate::file_kind::FileKind;
+use crate::folder;
+use crate::format_size;
+use crate::media_info::MediaInfo;
+use crate::thumbnails;
+use crate::slint_generatedAppWindow::{FileEntry as SlintFileEntry, HistoryEntry as SlintHistoryEntry};
+
+/// Oldest history entries are dropped beyond this
+const MAX_HISTORY_ENTRIES: usize = 1000;
+/// Files the Recent listing shows
+const RECENT_LIMIT: usize = 50;
+/// Full-text index of file contents, kept next to the database file
+const CONTENT_INDEX_FILE: &str = "content_index.json";
+/// Rotated copies of the index kept next to it, newest first as `.1`, `.2`, ...
+const BACKUP_COUNT: usize = 5;
+/// A new copy is rotated in at most this often
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How dates are shown in the UI, and how older databases stored upload dates
+pub const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// File record structure for JSON storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileRecord {
+    pub filename: String,
+    pub file_id: String,
+    /// Stored as RFC 3339; databases from older versions hold `DATE_FORMAT` local times
+    #[serde(deserialize_with = "deserialize_date")]
+    pub upload_date: DateTime<Local>,
+    pub file_size: u64,
+    /// Message in Saved Messages holding the document (absent for old records)
+    #[serde(default)]
+    pub message_id: Option<i32>,
+    /// Virtual folder the file lives in, `/`-separated; empty for the root
+    #[serde(default)]
+    pub folder: String,
+    /// Contents, when the stored file is an archive packed before upload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archive_members: Vec<ArchiveMember>,
+    /// Documents holding consecutive pieces of a file too large for one message,
+    /// in order; empty when the file was sent whole. `message_id` is the first piece
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub split_pieces: Vec<SplitPiece>,
+    /// SHA-256 of the uploaded content, hex encoded (absent for old records)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Modification time of the original, seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Unix permission bits of the original
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Text recognised in the image, when OCR was enabled at upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocr_text: Option<String>,
+    /// Capture date, camera and dimensions of photos and videos
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaInfo>,
+    /// Chat holding the document when it was imported from somewhere other
+    /// than Saved Messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat: Option<ChatRef>,
+    /// Stored as a zstd copy, decompressed when downloaded
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compressed: bool,
+    /// Fingerprint of the key the contents are encrypted with; `None` when
+    /// stored as they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Telegram album (grouped messages) the file was sent in along with others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<i64>,
+    /// Sent as a Telegram photo: what is stored is the JPEG Telegram made of
+    /// the image, not the original
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub photo: bool,
+    /// A smaller copy of the image was stored instead of the full-size original
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub downscaled: bool,
+    /// Its message was deleted in Telegram, so it can't be downloaded
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub missing: bool,
+    /// Starred by the user: listed under Favorites and ahead of other files
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub starred: bool,
+    /// Last time it was downloaded, by hand or with the CLI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_downloaded: Option<DateTime<Local>>,
+    /// Last time it was opened from the app
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_opened: Option<DateTime<Local>>,
+    /// Last time the background check fetched it back from Telegram
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<DateTime<Local>>,
+    /// The background check found its content differs from what was uploaded
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub corrupt: bool,
+    /// Stored file this one is a newer version of, uploaded by folder sync.
+    /// That one is deleted once this one is recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<RecordKey>,
+    /// Sent by the bot into `chat`; deleted there when deleted from the app,
+    /// unlike files imported from a chat
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sent_by_bot: bool,
+}
+
+/// A chat other than Saved Messages, stored so its messages can be fetched again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatRef {
+    pub name: String,
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_hash: Option<i64>,
+    /// "user", "bot", "chat", "megagroup", "broadcast" or "gigagroup"
+    pub kind: String,
+}
+
+impl ChatRef {
+    pub fn new(name: &str, chat: PackedChat) -> Self {
+        let kind = match chat.ty {
+            PackedType::User => "user",
+            PackedType::Bot => "bot",
+            PackedType::Chat => "chat",
+            PackedType::Megagroup => "megagroup",
+            PackedType::Broadcast => "broadcast",
+            PackedType::Gigagroup => "gigagroup",
+        };
+        Self {
+            name: name.to_string(),
+            id: chat.id,
+            access_hash: chat.access_hash,
+            kind: kind.to_string(),
+        }
+    }
+    
+    pub fn packed(&self) -> Result<PackedChat> {
+        let ty = match self.kind.as_str() {
+            "user" => PackedType::User,
+            "bot" => PackedType::Bot,
+            "chat" => PackedType::Chat,
+            "megagroup" => PackedType::Megagroup,
+            "broadcast" => PackedType::Broadcast,
+            "gigagroup" => PackedType::Gigagroup,
+            other => anyhow::bail!("Unknown chat type {:?}", other),
+        };
+        Ok(PackedChat { ty, id: self.id, access_hash: self.access_hash })
+    }
+}
+
+/// What identifies a stored file: its message, in the chat holding it
+/// (`None` for Saved Messages). Channels and supergroups number their
+/// messages separately, so the id alone may match files in other chats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "StoredKey")]
+pub struct RecordKey {
+    pub chat: Option<i64>,
+    pub message_id: i32,
+}
+
+/// A `RecordKey` as saved, or the bare message id older versions saved
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredKey {
+    Key { chat: Option<i64>, message_id: i32 },
+    MessageId(i32),
+}
+
+impl From<StoredKey> for RecordKey {
+    fn from(stored: StoredKey) -> Self {
+        match stored {
+            StoredKey::Key { chat, message_id } => Self { chat, message_id },
+            StoredKey::MessageId(message_id) => Self { chat: None, message_id },
+        }
+    }
+}
+
+impl RecordKey {
+    pub fn new(chat: Option<&ChatRef>, message_id: i32) -> Self {
+        Self { chat: chat.map(|c| c.id), message_id }
+    }
+}
+
+/// `chat:message`, with 0 for Saved Messages as in the merge key; how the
+/// UI and the content index refer to a file
+impl Display for RecordKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.chat.unwrap_or(0), self.message_id)
+    }
+}
+
+impl FromStr for RecordKey {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let (chat, message_id) = s.split_once(':').with_context(|| format!("Invalid file key {:?}", s))?;
+        let chat: i64 = chat.parse()?;
+        Ok(Self { chat: (chat != 0).then_some(chat), message_id: message_id.parse()? })
+    }
+}
+
+/// One document of a file split across several messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitPiece {
+    pub message_id: i32,
+    pub size: u64,
+    /// Hash of the piece when it was cut by content, so other files can share it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// A file packed inside an uploaded archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveMember {
+    pub path: String,
+    pub size: u64,
+}
+
+impl FileRecord {
+    /// Record for a document just stored in `message_id`, dated now
+    pub fn new(filename: &str, file_id: &str, file_size: u64, message_id: i32) -> Self {
+        Self {
+            filename: filename.to_string(),
+            file_id: file_id.to_string(),
+            upload_date: Local::now(),
+            file_size,
+            message_id: Some(message_id),
+            folder: String::new(),
+            archive_members: Vec::new(),
+            split_pieces: Vec::new(),
+            sha256: None,
+            tags: Vec::new(),
+            mtime: None,
+            mode: None,
+            ocr_text: None,
+            media: None,
+            chat: None,
+            compressed: false,
+            key_id: None,
+            album: None,
+            photo: false,
+            downscaled: false,
+            missing: false,
+            starred: false,
+            last_downloaded: None,
+            last_opened: None,
+            verified: None,
+            corrupt: false,
+            previous: None,
+            sent_by_bot: false,
+        }
+    }
+    
+    /// Attributes to restore on the downloaded copy
+    pub fn attrs(&self) -> FileAttrs {
+        FileAttrs { mtime: self.mtime, mode: self.mode }
+    }
+    
+    /// Folder and name, `/`-separated
+    pub fn path(&self) -> String {
+        match self.folder.as_str() {
+            "" => self.filename.clone(),
+            folder => format!("{}/{}", folder, self.filename),
+        }
+    }
+    
+    /// Its message and chat; `None` for old records without a message
+    pub fn key(&self) -> Option<RecordKey> {
+        self.message_id.map(|id| RecordKey::new(self.chat.as_ref(), id))
+    }
+    
+    /// Every message holding part of the file
+    pub fn message_ids(&self) -> Vec<i32> {
+        if self.split_pieces.is_empty() {
+            self.message_id.into_iter().collect()
+        } else {
+            self.split_pieces.iter().map(|p| p.message_id).collect()
+        }
+    }
+    
+    /// When a photo or video was taken, falling back to when it was uploaded
+    pub fn capture_date(&self) -> DateTime<Local> {
+        self.media.as_ref()
+            .and_then(|m| m.captured_at)
+            .unwrap_or(self.upload_date)
+    }
+    
+    /// When it was last downloaded or opened, if ever
+    pub fn last_used(&self) -> Option<DateTime<Local>> {
+        self.last_downloaded.max(self.last_opened)
+    }
+    
+    /// Name of the chat holding the document
+    pub fn chat_label(&self) -> String {
+        match &self.chat {
+            Some(chat) => chat.name.clone(),
+            None => "Saved Messages".to_string(),
+        }
+    }
+}
+
+/// Order of the library listing, newest first either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    UploadDate,
+    /// Capture date for photos and videos that have one
+    CaptureDate,
+}
+
+/// Which records the library lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Listing {
+    #[default]
+    All,
+    /// Starred files only
+    Favorites,
+    /// Files downloaded or opened lately, last used first
+    Recent,
+}
+
+impl Listing {
+    /// Order of the entries in the "Show" picker
+    const ALL: [Listing; 3] = [Listing::All, Listing::Favorites, Listing::Recent];
+    
+    pub fn from_index(index: i32) -> Self {
+        usize::try_from(index).ok()
+            .and_then(|i| Self::ALL.get(i).copied())
+            .unwrap_or_default()
+    }
+    
+    fn includes(self, record: &FileRecord) -> bool {
+        match self {
+            Listing::All => true,
+            Listing::Favorites => record.starred,
+            Listing::Recent => record.last_used().is_some(),
+        }
+    }
+}
+
+/// How a transfer ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferOutcome {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl TransferOutcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransferOutcome::Completed => "Completed",
+            TransferOutcome::Failed => "Failed",
+            TransferOutcome::Cancelled => "Cancelled",
+        }
+    }
+}
+
+/// A finished transfer, kept for the activity panel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub filename: String,
+    /// "Upload" or "Download"
+    pub direction: String,
+    pub outcome: TransferOutcome,
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_secs: u64,
+    pub file_size: u64,
+    pub error: Option<String>,
+    /// Where a completed download was saved
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+/// On-disk layout of the database file. Records are read one by one, so a
+/// damaged one doesn't take the others with it
+#[derive(Default, Serialize, Deserialize)]
+#[serde(from = "StoredDbCompat")]
+struct StoredDb {
+    files: Vec<serde_json::Value>,
+    history: Vec<HistoryEntry>,
+    /// Bumped by every save, so an instance can tell another one wrote since
+    generation: u64,
+}
+
+/// Older versions stored a bare array of file records
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredDbCompat {
+    Legacy(Vec<serde_json::Value>),
+    Current {
+        files: Vec<serde_json::Value>,
+        #[serde(default)]
+        history: Vec<HistoryEntry>,
+        #[serde(default)]
+        generation: u64,
+    },
+}
+
+impl From<StoredDbCompat> for StoredDb {
+    fn from(compat: StoredDbCompat) -> Self {
+        match compat {
+            StoredDbCompat::Legacy(files) => Self { files, history: Vec::new(), generation: 0 },
+            StoredDbCompat::Current { files, history, generation } => Self { files, history, generation },
+        }
+    }
+}
+
+/// The file as this instance last read or wrote it, what another
+/// instance's changes are told apart from ours by
+#[derive(Default)]
+struct Synced {
+    generation: u64,
+    files: HashMap<String, serde_json::Value>,
+    history: HashSet<String>,
+}
+
+impl Synced {
+    /// From `stored` with its files as this version writes them, so they
+    /// compare equal to records that didn't change
+    fn new(stored: &StoredDb) -> Self {
+        Self {
+            generation: stored.generation,
+            files: stored.files.iter().map(|f| (merge_key(f), f.clone())).collect(),
+            history: stored.history.iter().filter_map(|h| serde_json::to_string(h).ok()).collect(),
+        }
+    }
+}
+
+/// What identifies a stored record across instances: its chat and message,
+/// or where it is for a record that has none
+fn merge_key(file: &serde_json::Value) -> String {
+    let chat = file.get("chat").and_then(|c| c.get("id")).and_then(serde_json::Value::as_i64);
+    match file.get("message_id").and_then(serde_json::Value::as_i64) {
+        Some(id) => format!("{}:{}", chat.unwrap_or(0), id),
+        None => format!("{}/{}", file.get("folder").unwrap_or(&serde_json::Value::Null), file.get("filename").unwrap_or(&serde_json::Value::Null)),
+    }
+}
+
+/// Read `files` into records, keeping those that can't be read as they are
+fn read_records(files: Vec<serde_json::Value>, path: &Path) -> (Vec<FileRecord>, Vec<serde_json::Value>) {
+    let mut records = Vec::with_capacity(files.len());
+    let mut unreadable = Vec::new();
+    for file in files {
+        match serde_json::from_value(file.clone()) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                warn!("Unreadable record in {:?}: {}", path, e);
+                unreadable.push(file);
+            }
+        }
+    }
+    (records, unreadable)
+}
+
+/// `path` with `.{suffix}` added to its file name
+fn with_suffix(path: &Path, suffix: impl Display) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", suffix));
+    path.with_file_name(name)
+}
+
+async fn read_stored(path: &Path) -> Result<StoredDb> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Newest rotated copy of the index at `path` that can still be read
+async fn newest_backup(path: &Path) -> Option<StoredDb> {
+    for n in 1..=BACKUP_COUNT {
+        let backup = with_suffix(path, n);
+        match read_stored(&backup).await {
+            Ok(stored) => {
+                warn!("Restored the index from {:?}", backup);
+                return Some(stored);
+            }
+            Err(e) if backup.exists() => warn!("The backup {:?} is unreadable too: {}", backup, e),
+            Err(_) => {}
+        }
+    }
+    None
+}
+
+/// Database management using JSON file storage
+pub struct Database {
+    file_path: PathBuf,
+    /// Held while the file is written, so saves never interleave
+    saving: tokio::sync::Mutex<()>,
+    synced: Mutex<Synced>,
+    records: Arc<Mutex<Vec<FileRecord>>>,
+    history: Arc<Mutex<Vec<HistoryEntry>>>,
+    /// Records that could not be read, kept as they were until `fsck`
+    /// repairs or drops them
+    unreadable: Arc<Mutex<Vec<serde_json::Value>>>,
+    contents: ContentIndex,
+}
+
+impl Database {
+    pub async fn new(db_path: &str) -> Result<Self> {
+        let file_path = PathBuf::from(db_path);
+        let stored = if file_path.exists() {
+            match read_stored(&file_path).await {
+                Ok(stored) => stored,
+                Err(e) => {
+                    warn!("The index {:?} is unreadable: {}", file_path, e);
+                    // Set aside rather than overwritten by the next save
+                    let corrupt = with_suffix(&file_path, "corrupt");
+                    if let Err(e) = tokio::fs::rename(&file_path, &corrupt).await {
+                        warn!("Failed to move the unreadable index to {:?}: {:?}", corrupt, e);
+                    }
+                    newest_backup(&file_path).await.unwrap_or_default()
+                }
+            }
+        } else {
+            StoredDb::default()
+        };
+        let (records, unreadable) = read_records(stored.files, &file_path);
+        let files = records.iter()
+            .filter_map(|r| serde_json::to_value(r).ok())
+            .chain(unreadable.iter().cloned())
+            .collect();
+        let synced = Synced::new(&StoredDb { files, history: stored.history.clone(), generation: stored.generation });
+        let history = stored.history;
+        let contents = ContentIndex::new(file_path.with_file_name(CONTENT_INDEX_FILE), &records);
+        
+        Ok(Self {
+            file_path,
+            saving: tokio::sync::Mutex::new(()),
+            synced: Mutex::new(synced),
+            records: Arc::new(Mutex::new(records)),
+            history: Arc::new(Mutex::new(history)),
+            unreadable: Arc::new(Mutex::new(unreadable)),
+            contents,
+        })
+    }
+    
+    /// Write the index. Another instance (the app and a command, say) may
+    /// have saved since this one last read or wrote it; its changes are
+    /// merged in first, ours winning where both changed the same record
+    pub async fn save(&self) -> Result<()> {
+        let _saving = self.saving.lock().await;
+        let _locked = self.lock_file().await?;
+        if let Ok(theirs) = read_stored(&self.file_path).await {
+            if theirs.generation != self.synced.lock().unwrap().generation {
+                info!("The index was changed by another instance; merging");
+                self.merge(theirs)?;
+            }
+        }
+        
+        let mut files = self.records.lock().unwrap()
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        files.extend(self.unreadable.lock().unwrap().iter().cloned());
+        let stored = StoredDb {
+            files,
+            history: self.history.lock().unwrap().clone(),
+            generation: self.synced.lock().unwrap().generation + 1,
+        };
+        let json = serde_json::to_string_pretty(&stored)?;
+        
+        // Written next to the index and renamed over it, so a crash
+        // mid-write leaves the previous version whole
+        self.rotate_backups().await;
+        let partial = with_suffix(&self.file_path, "partial");
+        let mut file = tokio::fs::File::create(&partial).await?;
+        file.write_all(json.as_bytes()).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&partial, &self.file_path).await?;
+        *self.synced.lock().unwrap() = Synced::new(&stored);
+        
+        Ok(())
+    }
+    
+    /// Take the lock other instances wait on before writing the index,
+    /// released when the file is dropped
+    async fn lock_file(&self) -> Result<std::fs::File> {
+        let path = with_suffix(&self.file_path, "lock");
+        let file = tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::options().create(true).truncate(false).write(true).open(&path)?;
+            file.lock()?;
+            Ok::<_, std::io::Error>(file)
+        }).await??;
+        Ok(file)
+    }
+    
+    /// Fold what another instance saved into memory: a record only one side
+    /// changed, added or removed since the last sync takes that side's
+    /// version; one both changed keeps ours. History from both is kept
+    fn merge(&self, theirs: StoredDb) -> Result<()> {
+        let mut synced = self.synced.lock().unwrap();
+        let mut records = self.records.lock().unwrap();
+        let mut unreadable = self.unreadable.lock().unwrap();
+        let mut ours: HashMap<String, serde_json::Value> = HashMap::new();
+        for file in records.iter().map(serde_json::to_value).chain(unreadable.iter().cloned().map(Ok)) {
+            let file = file?;
+            ours.insert(merge_key(&file), file);
+        }
+        
+        let mut merged = Vec::with_capacity(theirs.files.len());
+        let mut seen = HashSet::new();
+        for their in theirs.files {
+            let key = merge_key(&their);
+            seen.insert(key.clone());
+            match (synced.files.get(&key), ours.remove(&key)) {
+                // Changed by us since, or added on both sides
+                (Some(base), Some(our)) if our != *base => merged.push(our),
+                (None, Some(our)) => merged.push(our),
+                // Removed by us, and not changed by them since
+                (Some(base), None) if their == *base => {}
+                _ => merged.push(their),
+            }
+        }
+        // Only here: added by us, or removed by them
+        for (key, our) in ours {
+            if !seen.contains(&key) && synced.files.get(&key).map_or(true, |base| *base != our) {
+                merged.push(our);
+            }
+        }
+        (*records, *unreadable) = read_records(merged, &self.file_path);
+        
+        let mut history = self.history.lock().unwrap();
+        let ours: Vec<HistoryEntry> = history.drain(..)
+            .filter(|h| serde_json::to_string(h).map_or(true, |json| !synced.history.contains(&json)))
+            .collect();
+        *history = theirs.history;
+        history.extend(ours);
+        if history.len() > MAX_HISTORY_ENTRIES {
+            let excess = history.len() - MAX_HISTORY_ENTRIES;
+            history.drain(..excess);
+        }
+        synced.generation = theirs.generation;
+        Ok(())
+    }
+    
+    /// Copy the index into the rotated backups, dropping the oldest, when
+    /// the newest copy is older than `BACKUP_INTERVAL`
+    async fn rotate_backups(&self) {
+        let newest = with_suffix(&self.file_path, 1);
+        let due = std::fs::metadata(&newest)
+            .and_then(|m| m.modified())
+            .map_or(true, |modified| modified.elapsed().map_or(true, |age| age >= BACKUP_INTERVAL));
+        if !due || !self.file_path.exists() {
+            return;
+        }
+        for n in (1..BACKUP_COUNT).rev() {
+            let _ = tokio::fs::rename(with_suffix(&self.file_path, n), with_suffix(&self.file_path, n + 1)).await;
+        }
+        // Dated now, whether or not the copy keeps the original's time
+        let copied = tokio::fs::copy(&self.file_path, &newest).await
+            .and_then(|_| std::fs::File::options().write(true).open(&newest))
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(e) = copied {
+            warn!("Failed to back up the index to {:?}: {:?}", newest, e);
+        }
+    }
+    
+    pub async fn insert_file(&self, record: FileRecord) -> Result<()> {
+        self.records.lock().unwrap().push(record);
+        self.save().await?;
+        
+        Ok(())
+    }
+    
+    /// Add records from another index or chat, skipping messages already
+    /// known (chat and message identify records); returns how many were added
+    pub async fn import_records(&self, imported: Vec<FileRecord>) -> Result<usize> {
+        let added = {
+            let mut records = self.records.lock().unwrap();
+            let before = records.len();
+            for record in imported {
+                let known = record.key().is_some_and(|key| records.iter().any(|r| r.key() == Some(key)));
+                if !known {
+                    records.push(record);
+                }
+            }
+            records.len() - before
+        };
+        if added > 0 {
+            self.save().await?;
+        }
+        Ok(added)
+    }
+    
+    /// Make the records held in `chat` match `scanned` (newest first): add
+    /// documents not yet indexed and drop those whose messages are gone.
+    /// Records already known keep their local changes; returns how many were
+    /// added and removed
+    pub async fn sync_chat(&self, chat: &ChatRef, scanned: Vec<FileRecord>) -> Result<(usize, usize)> {
+        let in_chat = |r: &FileRecord| r.chat.as_ref().map_or(false, |c| c.id == chat.id);
+        let present: HashSet<i32> = scanned.iter().filter_map(|r| r.message_id).collect();
+        let (added, removed) = {
+            let mut records = self.records.lock().unwrap();
+            let mut removed = Vec::new();
+            records.retain(|r| {
+                let gone = in_chat(r) && !r.message_id.map_or(false, |id| present.contains(&id));
+                if gone {
+                    removed.extend(r.message_id);
+                }
+                !gone
+            });
+            let known: HashSet<i32> = records.iter().filter(|r| in_chat(r)).filter_map(|r| r.message_id).collect();
+            let mut added = 0;
+            for record in scanned.into_iter().rev() {
+                if !record.message_id.map_or(false, |id| known.contains(&id)) {
+                    records.push(record);
+                    added += 1;
+                }
+            }
+            (added, removed)
+        };
+        for message_id in &removed {
+            self.contents.remove(RecordKey::new(Some(chat), *message_id));
+        }
+        if added > 0 || !removed.is_empty() {
+            self.save().await?;
+        }
+        Ok((added, removed.len()))
+    }
+    
+    /// Copy of every record, e.g. for computing statistics
+    pub fn all_records(&self) -> Vec<FileRecord> {
+        self.records.lock().unwrap().clone()
+    }
+    
+    /// Records in the file that could not be read, as they are stored
+    pub fn unreadable_records(&self) -> Vec<serde_json::Value> {
+        self.unreadable.lock().unwrap().clone()
+    }
+    
+    /// Change the records and the unreadable ones together, then save
+    pub async fn repair(&self, change: impl FnOnce(&mut Vec<FileRecord>, &mut Vec<serde_json::Value>)) -> Result<()> {
+        {
+            let mut records = self.records.lock().unwrap();
+            let mut unreadable = self.unreadable.lock().unwrap();
+            change(&mut records, &mut unreadable);
+        }
+        self.save().await
+    }
+    
+    /// Messages of `record` that other files in the same chat share through
+    /// deduplicated pieces, which must outlive `record`
+    pub fn shared_message_ids(&self, record: &FileRecord) -> HashSet<i32> {
+        let own: HashSet<i32> = record.message_ids().into_iter().collect();
+        let chat = record.chat.as_ref().map(|c| c.id);
+        self.records.lock().unwrap()
+            .iter()
+            .filter(|r| r.message_id != record.message_id && r.chat.as_ref().map(|c| c.id) == chat)
+            .flat_map(|r| r.message_ids())
+            .filter(|id| own.contains(id))
+            .collect()
+    }
+    
+    pub fn find(&self, key: RecordKey) -> Option<FileRecord> {
+        self.records.lock().unwrap()
+            .iter()
+            .find(|r| r.key() == Some(key))
+            .cloned()
+    }
+    
+    /// Note that the file `key` was just opened, or downloaded when `opened`
+    /// is false, for the Recent listing
+    pub async fn mark_used(&self, key: RecordKey, opened: bool) -> Result<()> {
+        let now = Local::now();
+        self.update_records(&[key], |r| {
+            if opened {
+                r.last_opened = Some(now);
+            } else {
+                r.last_downloaded = Some(now);
+            }
+        }).await?;
+        Ok(())
+    }
+    
+    /// Mark the records with a message among `ids` as missing, returning how
+    /// many there were. Deletions outside channels and supergroups don't say
+    /// the chat, but such message ids are unique to the account
+    pub async fn mark_missing(&self, channel: Option<i64>, ids: &[i32]) -> Result<usize> {
+        let ids: HashSet<i32> = ids.iter().copied().collect();
+        let marked = {
+            let mut records = self.records.lock().unwrap();
+            let mut marked = 0;
+            for record in records.iter_mut().filter(|r| !r.missing) {
+                let in_chat = match (channel, &record.chat) {
+                    (Some(channel), Some(chat)) => chat.id == channel,
+                    (Some(_), None) => false,
+                    (None, Some(chat)) => matches!(chat.kind.as_str(), "user" | "bot" | "chat"),
+                    (None, None) => true,
+                };
+                if in_chat && record.message_ids().iter().any(|id| ids.contains(id)) {
+                    record.missing = true;
+                    marked += 1;
+                }
+            }
+            marked
+        };
+        if marked > 0 {
+            self.save().await?;
+        }
+        Ok(marked)
+    }
+    
+    /// Drop the record of file `key` from the index
+    pub async fn remove_file(&self, key: RecordKey) -> Result<Option<FileRecord>> {
+        let removed = {
+            let mut records = self.records.lock().unwrap();
+            let index = records.iter().position(|r| r.key() == Some(key));
+            index.map(|i| records.remove(i))
+        };
+        if removed.is_some() {
+            self.contents.remove(key);
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+    
+    /// Apply `change` to the records of the files in `keys` and save;
+    /// returns how many were changed
+    pub async fn update_records(&self, keys: &[RecordKey], mut change: impl FnMut(&mut FileRecord)) -> Result<usize> {
+        let changed = {
+            let mut records = self.records.lock().unwrap();
+            let mut changed = 0;
+            for record in records.iter_mut() {
+                if record.key().is_some_and(|key| keys.contains(&key)) {
+                    change(record);
+                    changed += 1;
+                }
+            }
+            changed
+        };
+        if changed > 0 {
+            self.save().await?;
+        }
+        Ok(changed)
+    }
+    
+    /// Move virtual folder `from` with everything below it to `to`; returns
+    /// how many records were moved
+    pub async fn move_folder(&self, from: &str, to: &str) -> Result<usize> {
+        let moved = {
+            let mut records = self.records.lock().unwrap();
+            let mut moved = 0;
+            for record in records.iter_mut() {
+                if let Some(new_folder) = folder::moved_path(&record.folder, from, to) {
+                    record.folder = new_folder;
+                    moved += 1;
+                }
+            }
+            moved
+        };
+        if moved > 0 {
+            self.save().await?;
+        }
+        Ok(moved)
+    }
+    
+    /// Make the file `key` findable by the words of `text`
+    pub fn index_contents(&self, key: RecordKey, text: &str) {
+        self.contents.add(key, text);
+    }
+    
+    /// Latest record stored as `filename` in virtual folder `folder`
+    pub fn find_in_folder(&self, folder: &str, filename: &str) -> Option<FileRecord> {
+        self.records.lock().unwrap()
+            .iter()
+            .rev()
+            .find(|r| r.folder == folder && r.filename == filename)
+            .cloned()
+    }
+    
+    /// Stored file called `name` ("folder/name" works too): an exact match
+    /// first, then the newest name containing it
+    pub fn find_by_name(&self, name: &str) -> Option<FileRecord> {
+        let records = self.records.lock().unwrap();
+        let lower = name.to_lowercase();
+        let path = |r: &FileRecord| match r.folder.as_str() {
+            "" => r.filename.clone(),
+            folder => format!("{}/{}", folder, r.filename),
+        };
+        // Only files that can still be fetched
+        let stored = records.iter().rev().filter(|r| r.message_id.is_some());
+        stored.clone().find(|r| r.filename == name || path(r) == name)
+            .or_else(|| stored.clone().find(|r| r.filename.to_lowercase().contains(&lower)))
+            .cloned()
+    }
+    
+    /// List the records of `listing`, starred then the rest, newest first,
+    /// keeping only those uploaded within `range` whose name, folder, tags or
+    /// indexed contents match `query`
+    pub fn get_all_files(&self, query: &str, range: &DateRange, order: SortOrder, listing: Listing) -> Result<Vec<SlintFileEntry>> {
+        let content_hits = self.contents.search(query).unwrap_or_default();
+        let query = query.to_lowercase();
+        let records = self.records.lock().unwrap();
+        let mut matches: Vec<&FileRecord> = records
+            .iter()
+            .filter(|r| listing.includes(r))
+            .filter(|r| range.contains(&r.upload_date))
+            .filter(|r| r.filename.to_lowercase().contains(&query)
+                || r.key().is_some_and(|key| content_hits.contains(&key))
+                || r.folder.to_lowercase().contains(&query)
+                || r.tags.iter().any(|t| t.to_lowercase().contains(&query))
+                || r.ocr_text.as_ref().map_or(false, |t| t.to_lowercase().contains(&query))
+                || r.archive_members.iter().any(|m| m.path.to_lowercase().contains(&query)))
+            .collect();
+        
+        // Records are stored oldest first
+        matches.reverse();
+        if order == SortOrder::CaptureDate {
+            matches.sort_by_key(|r| std::cmp::Reverse(r.capture_date()));
+        }
+        if listing == Listing::Recent {
+            matches.sort_by_key(|r| std::cmp::Reverse(r.last_used()));
+            matches.truncate(RECENT_LIMIT);
+        } else {
+            // Stable, so each group keeps the date order
+            matches.sort_by_key(|r| !r.starred);
+        }
+        
+        let files = matches
+            .into_iter()
+            .map(|r| {
+                let kind = FileKind::from_filename(&r.filename);
+                SlintFileEntry {
+                    filename: r.filename.clone().into(),
+                    kind: kind.label().into(),
+                    icon: kind.icon().into(),
+                    folder: r.folder.clone().into(),
+                    archive_files: r.archive_members.len() as i32,
+                    split_pieces: r.split_pieces.len() as i32,
+                    file_id: r.file_id.clone().into(),
+                    upload_date: r.upload_date.format(DATE_FORMAT).to_string().into(),
+                    size: format_size(r.file_size).into(),
+                    message_id: r.message_id.unwrap_or(0),
+                    key: r.key().map(|key| key.to_string()).unwrap_or_default().into(),
+                    media_info: r.media.as_ref().map(MediaInfo::describe).unwrap_or_default().into(),
+                    missing: r.missing,
+                    starred: r.starred,
+                    corrupt: r.corrupt,
+                    last_used: r.last_used()
+                        .map(|d| d.format(DATE_FORMAT).to_string())
+                        .unwrap_or_default()
+                        .into(),
+                    thumbnail: thumbnails::cached(r)
+                        .and_then(|path| slint::Image::load_from_path(&path).ok())
+                        .unwrap_or_default(),
+                    selected: false,
+                }
+            })
+            .collect();
+        Ok(files)
+    }
+    
+    /// Record a finished, failed or cancelled transfer
+    pub async fn add_history(&self, entry: HistoryEntry) -> Result<()> {
+        {
+            let mut history = self.history.lock().unwrap();
+            history.push(entry);
+            if history.len() > MAX_HISTORY_ENTRIES {
+                let excess = history.len() - MAX_HISTORY_ENTRIES;
+                history.drain(..excess);
+            }
+        }
+        self.save().await
+    }
+    
+    pub async fn clear_history(&self) -> Result<()> {
+        self.history.lock().unwrap().clear();
+        self.save().await
+    }
+    
+    /// History entries, newest first
+    pub fn get_history(&self) -> Vec<SlintHistoryEntry> {
+        self.history.lock().unwrap()
+            .iter()
+            .rev()
+            .map(|h| SlintHistoryEntry {
+                filename: h.filename.clone().into(),
+                direction: h.direction.clone().into(),
+                outcome: h.outcome.label().into(),
+                finished_at: h.finished_at.clone().into(),
+                duration: format_duration(h.duration_secs).into(),
+                size: format_size(h.file_size).into(),
+                error: h.error.clone().unwrap_or_default().into(),
+                path: h.path.as_ref().map(|p| p.to_string_lossy().to_string()).unwrap_or_default().into(),
+            })
+            .collect()
+    }
+}
+
+/// Accept RFC 3339 as well as the local `DATE_FORMAT` times older versions wrote
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Local>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    if let Ok(date) = DateTime::parse_from_rfc3339(&text) {
+        return Ok(date.with_timezone(&Local));
+    }
+    NaiveDateTime::parse_from_str(&text, DATE_FORMAT)
+        .ok()
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid upload date {:?}", text)))
+}
+
+/// Human readable duration, e.g. "1h 02m 03s"
+pub fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn record_key_parses_chat_and_message() {
+        let key: RecordKey = "1234567:89".parse().unwrap();
+        assert_eq!(key, RecordKey { chat: Some(1234567), message_id: 89 });
+        assert_eq!(key.to_string(), "1234567:89");
+    }
+    
+    #[test]
+    fn record_key_with_chat_zero_is_saved_messages() {
+        let key: RecordKey = "0:89".parse().unwrap();
+        assert_eq!(key, RecordKey { chat: None, message_id: 89 });
+        assert_eq!(key.to_string(), "0:89");
+    }
+    
+    #[test]
+    fn record_key_rejects_malformed_text() {
+        for text in ["89", "", ":89", "0:", "chat:89", "0:89:1"] {
+            assert!(text.parse::<RecordKey>().is_err(), "{:?} parsed", text);
+        }
+    }
+    
+    #[test]
+    fn record_key_round_trips_through_json() {
+        let key = RecordKey { chat: Some(1234567), message_id: 89 };
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(serde_json::from_str::<RecordKey>(&json).unwrap(), key);
+        
+        let saved_messages = RecordKey { chat: None, message_id: 89 };
+        let json = serde_json::to_string(&saved_messages).unwrap();
+        assert_eq!(serde_json::from_str::<RecordKey>(&json).unwrap(), saved_messages);
+    }
+    
+    #[test]
+    fn bare_message_id_loads_as_saved_messages_key() {
+        assert_eq!(serde_json::from_str::<RecordKey>("89").unwrap(), RecordKey { chat: None, message_id: 89 });
+    }
+    
+    #[test]
+    fn legacy_record_loads_with_bare_previous_id() {
+        let record: FileRecord = serde_json::from_value(serde_json::json!({
+            "filename": "notes.txt",
+            "file_id": "tg_file_notes.txt",
+            "upload_date": "2023-04-05 06:07:08",
+            "file_size": 120,
+            "message_id": 90,
+            "previous": 89,
+        })).unwrap();
+        assert_eq!(record.previous, Some(RecordKey { chat: None, message_id: 89 }));
+        assert_eq!(record.key(), Some(RecordKey { chat: None, message_id: 90 }));
+        assert!(record.chat.is_none());
+    }
+    
+    #[tokio::test]
+    async fn find_by_name_skips_records_without_a_message() {
+        let dir = std::env::temp_dir().join(format!("teledrive-find-by-name-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(dir.join("db.json").to_str().unwrap()).await.unwrap();
+        
+        let mut lost = FileRecord::new("report.pdf", "tg_file_report.pdf", 10, 1);
+        lost.message_id = None;
+        db.insert_file(lost).await.unwrap();
+        db.insert_file(FileRecord::new("old report.pdf", "tg_file_old_report.pdf", 10, 2)).await.unwrap();
+        
+        let found = db.find_by_name("report.pdf");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found.map(|r| r.filename), Some("old report.pdf".to_string()));
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
+
+/// Quick ranges offered next to the library search, in picker order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatePreset {
+    AllTime,
+    Today,
+    ThisWeek,
+    ThisMonth,
+    ThisYear,
+    /// Bounds typed by the user
+    Custom,
+}
+
+impl DatePreset {
+    const ALL: [DatePreset; 6] = [
+        DatePreset::AllTime,
+        DatePreset::Today,
+        DatePreset::ThisWeek,
+        DatePreset::ThisMonth,
+        DatePreset::ThisYear,
+        DatePreset::Custom,
+    ];
+    
+    pub fn from_index(index: i32) -> Self {
+        usize::try_from(index).ok()
+            .and_then(|i| Self::ALL.get(i).copied())
+            .unwrap_or(DatePreset::AllTime)
+    }
+}
+
+/// Upload dates to keep: `start` inclusive, `end` exclusive, either may be open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub start: Option<DateTime<Local>>,
+    pub end: Option<DateTime<Local>>,
+}
+
+impl DateRange {
+    /// Range for a preset relative to `now`; weeks start on Monday.
+    /// `Custom` yields the open range, see [`DateRange::custom`]
+    pub fn preset(preset: DatePreset, now: DateTime<Local>) -> Self {
+        let today = now.date_naive();
+        let start = match preset {
+            DatePreset::AllTime | DatePreset::Custom => return Self::default(),
+            DatePreset::Today => today,
+            DatePreset::ThisWeek => today - Duration::days(today.weekday().num_days_from_monday() as i64),
+            DatePreset::ThisMonth => today.with_day(1).unwrap_or(today),
+            DatePreset::ThisYear => today.with_ordinal(1).unwrap_or(today),
+        };
+        Self { start: start_of_day(start), end: None }
+    }
+    
+    /// Range from two "YYYY-MM-DD" dates, both days included; empty leaves that side open
+    pub fn custom(from: &str, to: &str) -> Result<Self> {
+        let from = parse_day(from).context("Invalid start date")?;
+        let to = parse_day(to).context("Invalid end date")?;
+        Ok(Self {
+            start: from.and_then(start_of_day),
+            end: to.and_then(|day| day.succ_opt()).and_then(start_of_day),
+        })
+    }
+    
+    pub fn contains(&self, time: &DateTime<Local>) -> bool {
+        self.start.map_or(true, |start| *time >= start) && self.end.map_or(true, |end| *time < end)
+    }
+}
+
+fn parse_day(text: &str) -> Result<Option<NaiveDate>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(NaiveDate::parse_from_str(text, "%Y-%m-%d")?))
+}
+
+/// Local midnight of `day`; the earliest one if a DST change makes it ambiguous
+fn start_of_day(day: NaiveDate) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&day.and_hms_opt(0, 0, 0)?).earliest()
+}
@@ -0,0 +1,218 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::database::{ChatRef, FileRecord, SplitPiece};
+
+const MB: u64 = 1024 * 1024;
+/// Files above this are cut into content-defined pieces when deduplicating
+pub const MIN_FILE_SIZE: u64 = 128 * MB;
+/// Synced files above this are cut into the smaller delta pieces
+pub const DELTA_MIN_FILE_SIZE: u64 = 16 * MB;
+
+/// Sizes of the pieces a file is cut into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chunking {
+    pub min: u64,
+    /// A piece ends where this many high bits of the rolling hash are all
+    /// zero, about 2^`bits` bytes past the minimum on average
+    pub bits: u32,
+    pub max: u64,
+}
+
+impl Chunking {
+    /// Deduplication across files: pieces of about 64 MB past 16 MB, at most
+    /// 256 MB, smaller than Telegram's limit so shared data is found more often
+    pub const SHARED: Self = Self { min: 16 * MB, bits: 26, max: 256 * MB };
+    /// New versions of synced files: pieces of about 4 MB past 1 MB, at most
+    /// 16 MB, so a small change only sends a few megabytes
+    pub const DELTA: Self = Self { min: MB, bits: 22, max: 16 * MB };
+}
+
+/// Random value per byte for the rolling hash. Fixed, so the same content
+/// always gets the same boundaries
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5445_4c45_4452_4956;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Byte range of a file that becomes one stored piece
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub start: u64,
+    pub end: u64,
+    pub sha256: String,
+}
+
+/// Finds where content-defined pieces end in a stream of bytes (gear hash
+/// content-defined chunking). Data inserted or removed early in a file only
+/// moves the boundaries near it, so the other pieces stay the same
+pub struct Chunker {
+    min: u64,
+    max: u64,
+    mask: u64,
+    len: u64,
+    hash: u64,
+}
+
+impl Chunker {
+    /// Pieces of `min` to `max` bytes, about 2^`bits` past the minimum on average
+    pub fn new(min: u64, bits: u32, max: u64) -> Self {
+        Self { min, max, mask: !(u64::MAX >> bits), len: 0, hash: 0 }
+    }
+    
+    /// Feed the next bytes; returns how many of them complete the current
+    /// piece, if it ends within `data`. Feed the rest again afterwards
+    pub fn next_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, &byte) in data.iter().enumerate() {
+            self.len += 1;
+            if self.len < self.min {
+                continue;
+            }
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+            if self.hash & self.mask == 0 || self.len >= self.max {
+                self.len = 0;
+                self.hash = 0;
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Cut the file at `path` where its content says so, into pieces sized by
+/// `chunking` and of at most `max_chunk` bytes, so the pieces still match
+/// earlier uploads of similar files
+pub fn split(path: &Path, chunking: Chunking, max_chunk: u64) -> Result<Vec<Chunk>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0u8; MB as usize];
+    let mut chunker = Chunker::new(chunking.min, chunking.bits, chunking.max.min(max_chunk));
+    let mut chunks = Vec::new();
+    let mut start = 0u64;
+    let mut offset = 0u64;
+    let mut hasher = Sha256::new();
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut from = 0;
+        while let Some(cut) = chunker.next_boundary(&buf[from..n]) {
+            hasher.update(&buf[from..from + cut]);
+            from += cut;
+            let end = offset + from as u64;
+            chunks.push(Chunk { start, end, sha256: format!("{:x}", hasher.finalize_reset()) });
+            start = end;
+        }
+        hasher.update(&buf[from..n]);
+        offset += n as u64;
+    }
+    if offset > start {
+        chunks.push(Chunk { start, end: offset, sha256: format!("{:x}", hasher.finalize()) });
+    }
+    Ok(chunks)
+}
+
+/// Pieces already stored in `chat` (Saved Messages when `None`) by hash,
+/// which a new upload can point at instead of sending them again
+pub fn known_pieces(records: &[FileRecord], chat: Option<&ChatRef>) -> HashMap<String, SplitPiece> {
+    records.iter()
+        .filter(|r| r.chat.as_ref().map(|c| c.id) == chat.map(|c| c.id))
+        .flat_map(|r| &r.split_pieces)
+        .filter_map(|piece| Some((piece.sha256.clone()?, piece.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const MIN: u64 = 1024;
+    const BITS: u32 = 10;
+    const MAX: u64 = 8 * 1024;
+    
+    /// Reproducible bytes with no repeating pattern
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+    
+    /// End offsets of the pieces `data` is cut into, fed in blocks of `block` bytes
+    fn cuts(data: &[u8], block: usize) -> Vec<usize> {
+        let mut chunker = Chunker::new(MIN, BITS, MAX);
+        let mut cuts = Vec::new();
+        let mut offset = 0;
+        for block in data.chunks(block) {
+            let mut from = 0;
+            while let Some(cut) = chunker.next_boundary(&block[from..]) {
+                from += cut;
+                cuts.push(offset + from);
+            }
+            offset += block.len();
+        }
+        cuts
+    }
+    
+    #[test]
+    fn pieces_stay_within_min_and_max() {
+        let data = noise(1024 * 1024, 1);
+        let cuts = cuts(&data, 4096);
+        assert!(cuts.len() > 10);
+        let mut start = 0;
+        for end in cuts {
+            let len = (end - start) as u64;
+            assert!((MIN..=MAX).contains(&len), "piece of {} bytes", len);
+            start = end;
+        }
+    }
+    
+    #[test]
+    fn boundaries_do_not_depend_on_how_data_is_fed() {
+        let data = noise(256 * 1024, 2);
+        assert_eq!(cuts(&data, 1), cuts(&data, 1000));
+        assert_eq!(cuts(&data, 1000), cuts(&data, data.len()));
+    }
+    
+    #[test]
+    fn constant_data_is_cut_at_max() {
+        let data = vec![0u8; 10 * MAX as usize];
+        let cuts = cuts(&data, 4096);
+        let expected: Vec<usize> = (1..=10).map(|i| i * MAX as usize).collect();
+        assert_eq!(cuts, expected);
+    }
+    
+    #[test]
+    fn insertion_only_moves_nearby_boundaries() {
+        let data = noise(512 * 1024, 3);
+        let inserted = 100;
+        let mut changed = noise(inserted, 4);
+        changed.extend_from_slice(&data);
+        
+        let before = cuts(&data, 4096);
+        let after: Vec<usize> = cuts(&changed, 4096).into_iter().map(|cut| cut - inserted).collect();
+        // Past the first few pieces both files are cut in the same places
+        let tail = &before[before.len() / 2..];
+        assert!(tail.iter().all(|cut| after.contains(cut)));
+    }
+}
@@ -0,0 +1,139 @@
+use grammers_client::types::{Attribute, InputMedia, InputMessage, Uploaded};
+use lofty::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::file_kind::FileKind;
+use crate::media_info::MediaInfo;
+use crate::video_thumb;
+
+/// What Telegram is told about a document besides its name: the MIME type
+/// and, for audio and video, what players need, so it can be played and
+/// previewed in Telegram's apps rather than only downloaded
+#[derive(Clone, Default)]
+pub struct DocumentInfo {
+    /// `None` leaves it to Telegram's guess from the name
+    pub mime_type: Option<String>,
+    pub attributes: Vec<Attribute>,
+    /// Frame picked from a video, to upload as its thumbnail
+    pub thumbnail: Option<PathBuf>,
+}
+
+impl DocumentInfo {
+    /// Inspect the file at `path`, to be sent under `name`. Blocking, run it
+    /// off the async runtime
+    pub fn read(path: &Path, name: &str) -> Self {
+        let mime_type = match mime_guess::from_path(name).first() {
+            Some(mime) => Some(mime.essence_str().to_string()),
+            // No extension, or one nobody knows: look at the content
+            None => infer::get_from_path(path).ok().flatten().map(|kind| kind.mime_type().to_string()),
+        };
+        let kind = mime_type.as_deref().map_or_else(|| FileKind::from_filename(name), kind_of_mime);
+        let mut info = Self { mime_type, ..Default::default() };
+        match kind {
+            FileKind::Video => {
+                let media = MediaInfo::read(path);
+                info.attributes.extend(media.as_ref().and_then(|m| video_attribute(path, m)));
+                info.thumbnail = video_thumb::generate(path, media.and_then(|m| m.duration_secs));
+            }
+            FileKind::Audio => info.attributes.extend(audio_attribute(path)),
+            _ => {}
+        }
+        info
+    }
+    
+    /// `message` with `uploaded` attached as a document described by this,
+    /// and `thumbnail` as its thumbnail
+    pub fn message(self, message: InputMessage, uploaded: Uploaded, thumbnail: Option<Uploaded>) -> InputMessage {
+        let message = match &self.mime_type {
+            Some(mime) => message.mime_type(mime),
+            None => message,
+        };
+        let message = self.attributes.into_iter().fold(message.document(uploaded), |message, attr| message.attribute(attr));
+        match thumbnail {
+            Some(thumbnail) => message.thumbnail(thumbnail),
+            None => message,
+        }
+    }
+    
+    /// Album item `media` with `uploaded` attached as a document described by
+    /// this, and `thumbnail` as its thumbnail
+    pub fn media(self, media: InputMedia, uploaded: Uploaded, thumbnail: Option<Uploaded>) -> InputMedia {
+        let media = match &self.mime_type {
+            Some(mime) => media.mime_type(mime),
+            None => media,
+        };
+        let media = self.attributes.into_iter().fold(media.document(uploaded), |media, attr| media.attribute(attr));
+        match thumbnail {
+            Some(thumbnail) => media.thumbnail(thumbnail),
+            None => media,
+        }
+    }
+}
+
+fn kind_of_mime(mime: &str) -> FileKind {
+    match mime.split('/').next() {
+        Some("video") => FileKind::Video,
+        Some("audio") => FileKind::Audio,
+        Some("image") => FileKind::Image,
+        _ => FileKind::Other,
+    }
+}
+
+/// Size and length from the MP4/MOV header; other containers are left to
+/// Telegram. Streaming is only offered when the index comes before the data,
+/// or players would have to fetch the whole file first
+fn video_attribute(path: &Path, info: &MediaInfo) -> Option<Attribute> {
+    let (w, h) = (info.width?, info.height?);
+    Some(Attribute::Video {
+        round_message: false,
+        supports_streaming: moov_first(path).unwrap_or(false),
+        duration: Duration::from_secs_f64(info.duration_secs.unwrap_or(0.0).max(0.0)),
+        w: w as i32,
+        h: h as i32,
+    })
+}
+
+/// Length, title and artist from the tags
+fn audio_attribute(path: &Path) -> Option<Attribute> {
+    let tagged = lofty::read_from_path(path)
+        .map_err(|e| debug!("No audio metadata in {:?}: {:?}", path, e))
+        .ok()?;
+    let tag = tagged.primary_tag().or_else(|| tagged.first_tag());
+    Some(Attribute::Audio {
+        duration: tagged.properties().duration(),
+        title: tag.and_then(|t| t.title()).map(|t| t.into_owned()),
+        performer: tag.and_then(|t| t.artist()).map(|a| a.into_owned()),
+    })
+}
+
+/// Whether the MP4 index (`moov`) comes before the media data (`mdat`),
+/// found by walking the top-level boxes
+fn moov_first(path: &Path) -> std::io::Result<bool> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 8];
+    loop {
+        file.read_exact(&mut header)?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        match &header[4..] {
+            b"moov" => return Ok(true),
+            b"mdat" => return Ok(false),
+            _ => {}
+        }
+        let skip = match size {
+            // Runs to the end of the file, so neither comes after it
+            0 => return Ok(false),
+            // A 64-bit size follows the type
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                u64::from_be_bytes(large).saturating_sub(16)
+            }
+            size => size.saturating_sub(8),
+        };
+        file.seek(SeekFrom::Current(skip as i64))?;
+    }
+}
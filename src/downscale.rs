@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+/// Folder downscaled copies wait in until they are uploaded
+const DOWNSCALED_DIR: &str = "teledrive-downscaled";
+/// Virtual subfolder the full-size originals are kept in, when they are
+pub const ORIGINALS_FOLDER: &str = "Originals";
+
+/// When images are made smaller before they are sent, for archives of many
+/// photos where full resolution isn't needed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownscaleRules {
+    pub enabled: bool,
+    /// Images with a longer side than this many pixels are resized to it
+    pub max_side: u32,
+    /// JPEG quality from 1 to 100 the resized copies are saved with
+    pub quality: u8,
+    /// Upload the full-size original as well, into `ORIGINALS_FOLDER`
+    pub keep_originals: bool,
+}
+
+impl Default for DownscaleRules {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_side: 2560,
+            quality: 85,
+            keep_originals: false,
+        }
+    }
+}
+
+/// Formats that can be resized; others (RAW, HEIC, WebP) go as they are
+pub fn is_candidate(path: &Path) -> bool {
+    matches!(ImageFormat::from_path(path), Ok(ImageFormat::Jpeg | ImageFormat::Png))
+}
+
+/// Copy of the image at `path` resized to fit `rules.max_side`, saved in its
+/// own format under its own name, in a folder named after `sha256` so a
+/// resumed upload finds the same bytes again. `None` when the image is small
+/// enough already, or resizing would not make it smaller
+pub fn downscale(path: &Path, sha256: &str, rules: &DownscaleRules) -> Result<Option<PathBuf>> {
+    let filename = path.file_name().context("Invalid filename")?;
+    let dest = paths::cache_dir(DOWNSCALED_DIR).join(sha256).join(filename);
+    if dest.exists() {
+        return Ok(Some(dest));
+    }
+    let (width, height) = image::image_dimensions(path)?;
+    if width.max(height) <= rules.max_side {
+        return Ok(None);
+    }
+    
+    let format = ImageFormat::from_path(path)?;
+    let image = image::open(path)?.resize(rules.max_side, rules.max_side, FilterType::Lanczos3);
+    std::fs::create_dir_all(dest.parent().context("Invalid filename")?)?;
+    // Written under another name first so an interrupted run leaves no
+    // truncated copy to be picked up on resume
+    let partial = dest.with_extension("partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    match format {
+        ImageFormat::Jpeg => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut out, rules.quality.clamp(1, 100)))?,
+        _ => image.write_to(&mut out, format)?,
+    }
+    out.flush()?;
+    drop(out);
+    
+    if std::fs::metadata(&partial)?.len() >= std::fs::metadata(path)?.len() {
+        let _ = std::fs::remove_file(&partial);
+        return Ok(None);
+    }
+    std::fs::rename(&partial, &dest)?;
+    Ok(Some(dest))
+}
+
+/// Delete a downscaled copy once it is uploaded
+pub fn remove(copy: &Path) {
+    let _ = std::fs::remove_file(copy);
+    if let Some(dir) = copy.parent() {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
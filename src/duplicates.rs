@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use crate::database::FileRecord;
+use crate::format_size;
+use crate::slint_generatedAppWindow::DuplicateEntry as SlintDuplicateEntry;
+
+/// Stored files with identical content
+pub struct DuplicateSet {
+    pub sha256: String,
+    pub size: u64,
+    /// Oldest upload first
+    pub records: Vec<FileRecord>,
+}
+
+impl DuplicateSet {
+    /// Space taken by every copy but the first
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.records.len() as u64 - 1)
+    }
+    
+    /// Copies that can go, keeping the oldest upload
+    pub fn redundant(&self) -> &[FileRecord] {
+        &self.records[1..]
+    }
+    
+    pub fn to_entry(&self) -> SlintDuplicateEntry {
+        let mut names: Vec<String> = self.records.iter()
+            .map(|r| if r.folder.is_empty() { r.filename.clone() } else { format!("{}/{}", r.folder, r.filename) })
+            .collect();
+        names.sort();
+        names.dedup();
+        SlintDuplicateEntry {
+            hash: self.sha256.clone().into(),
+            names: names.join(", ").into(),
+            copies: self.records.len() as i32,
+            size: format_size(self.size).into(),
+            wasted: format_size(self.wasted()).into(),
+        }
+    }
+}
+
+/// Records sharing a content hash and size, most wasted space first.
+/// Records uploaded before hashes were stored are never reported
+pub fn find_duplicates(records: &[FileRecord]) -> Vec<DuplicateSet> {
+    let mut groups: HashMap<(&str, u64), Vec<FileRecord>> = HashMap::new();
+    for record in records {
+        if let (Some(sha256), Some(_)) = (&record.sha256, record.message_id) {
+            groups.entry((sha256.as_str(), record.file_size)).or_default().push(record.clone());
+        }
+    }
+    
+    let mut sets: Vec<DuplicateSet> = groups
+        .into_iter()
+        .filter(|(_, records)| records.len() > 1)
+        .map(|((sha256, size), mut records)| {
+            records.sort_by_key(|r| r.upload_date);
+            DuplicateSet { sha256: sha256.to_string(), size, records }
+        })
+        .collect();
+    sets.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.sha256.cmp(&b.sha256)));
+    sets
+}
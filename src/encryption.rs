@@ -0,0 +1,465 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use crate::{paths, secrets};
+
+/// Marks an encrypted upload
+const MAGIC: &[u8] = b"TDENC1\0\0";
+const ID_LEN: usize = 8;
+const PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+/// Plain bytes sealed at a time, so large files never sit in memory whole
+const SEGMENT: usize = 1024 * 1024;
+/// Folder encrypted copies wait in until they are uploaded
+const ENCRYPTED_DIR: &str = "teledrive-encrypted";
+
+pub type FileKey = Zeroizing<[u8; 32]>;
+
+/// Short fingerprint of a key, recorded with every file it encrypted
+pub fn key_id(key: &FileKey) -> String {
+    to_hex(&id_bytes(key)).to_string()
+}
+
+fn id_bytes(key: &FileKey) -> [u8; ID_LEN] {
+    let mut id = [0u8; ID_LEN];
+    id.copy_from_slice(&Sha256::digest(key.as_slice())[..ID_LEN]);
+    id
+}
+
+fn to_hex(bytes: &[u8]) -> Zeroizing<String> {
+    Zeroizing::new(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn key_from_hex(hex: &str) -> Result<FileKey> {
+    secrets::decode_key(hex).context("Malformed encryption key")
+}
+
+/// How the keys are filed in the OS keyring
+#[derive(Serialize, Deserialize)]
+struct StoredKeys {
+    current: Zeroizing<String>,
+    #[serde(default)]
+    retired: Vec<Zeroizing<String>>,
+}
+
+/// Key file written by "Export key...", to carry the key to another
+/// computer or keep it somewhere safe
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    id: String,
+    key: Zeroizing<String>,
+}
+
+/// Keys for file contents, kept in the OS keyring. New uploads use the
+/// current key; retired ones stay so files encrypted before a rotation or
+/// an import still open
+pub struct Keys {
+    current: FileKey,
+    retired: Vec<FileKey>,
+}
+
+impl Keys {
+    /// Whether a key was created or imported on this computer yet
+    pub fn exist() -> Result<bool> {
+        Ok(secrets::get(secrets::FILE_KEYS)?.is_some())
+    }
+    
+    /// Keys from the keyring; a first key is created when there are none
+    pub fn load() -> Result<Self> {
+        let Some(json) = secrets::get(secrets::FILE_KEYS)? else {
+            let keys = Self { current: random_key(), retired: Vec::new() };
+            keys.save()?;
+            return Ok(keys);
+        };
+        let stored: StoredKeys = serde_json::from_str(&json).context("Malformed file keys in the keyring")?;
+        Ok(Self {
+            current: key_from_hex(&stored.current)?,
+            retired: stored.retired.iter().map(|hex| key_from_hex(hex)).collect::<Result<_>>()?,
+        })
+    }
+    
+    fn save(&self) -> Result<()> {
+        let stored = StoredKeys {
+            current: to_hex(self.current.as_slice()),
+            retired: self.retired.iter().map(|key| to_hex(key.as_slice())).collect(),
+        };
+        let json = Zeroizing::new(serde_json::to_string(&stored)?);
+        secrets::set(secrets::FILE_KEYS, &json)
+    }
+    
+    pub fn current(&self) -> &FileKey {
+        &self.current
+    }
+    
+    pub fn current_id(&self) -> String {
+        key_id(&self.current)
+    }
+    
+    /// Key with fingerprint `id`, current or retired
+    pub fn find(&self, id: &str) -> Option<&FileKey> {
+        std::iter::once(&self.current)
+            .chain(&self.retired)
+            .find(|key| key_id(key) == id)
+    }
+    
+    /// Switch new uploads to a fresh key, keeping the old one for the files
+    /// it encrypted; returns the new key's fingerprint
+    pub fn rotate(&mut self) -> Result<String> {
+        let old = std::mem::replace(&mut self.current, random_key());
+        self.retired.push(old);
+        self.save()?;
+        Ok(self.current_id())
+    }
+    
+    /// Make `key` the current one, e.g. the key of another computer sharing
+    /// the same files. The previous key is retired, not forgotten
+    pub fn adopt(&mut self, key: FileKey) -> Result<()> {
+        if key_id(&key) == self.current_id() {
+            return Ok(());
+        }
+        self.retired.retain(|k| key_id(k) != key_id(&key));
+        let old = std::mem::replace(&mut self.current, key);
+        self.retired.push(old);
+        self.save()
+    }
+    
+    /// The current key as 24 BIP39 words, to write down in case the key
+    /// file and this computer are both lost
+    pub fn phrase(&self) -> Result<Zeroizing<String>> {
+        let mnemonic = bip39::Mnemonic::from_entropy(self.current.as_slice())?;
+        Ok(Zeroizing::new(mnemonic.to_string()))
+    }
+    
+    /// Write the current key to `path`. Whoever has the file can read
+    /// everything encrypted with it
+    pub fn export(&self, path: &Path) -> Result<()> {
+        let file = KeyFile { id: self.current_id(), key: to_hex(self.current.as_slice()) };
+        let json = Zeroizing::new(serde_json::to_string_pretty(&file)?);
+        std::fs::write(path, json.as_bytes()).with_context(|| format!("Failed to write {:?}", path))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+}
+
+fn random_key() -> FileKey {
+    let mut key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(key.as_mut_slice());
+    key
+}
+
+/// Read a key file written by `Keys::export`
+pub fn import(path: &Path) -> Result<FileKey> {
+    let json = Zeroizing::new(std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?);
+    let file: KeyFile = serde_json::from_str(&json).context("Not a TeleDrive key file")?;
+    let key = key_from_hex(&file.key)?;
+    if key_id(&key) != file.id {
+        bail!("The key file is damaged");
+    }
+    Ok(key)
+}
+
+/// Key written down as a recovery phrase by `Keys::phrase`. Case, extra
+/// spaces and line breaks don't matter; a mistyped word fails the checksum
+pub fn from_phrase(phrase: &str) -> Result<FileKey> {
+    let words = Zeroizing::new(phrase.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase());
+    let mnemonic = bip39::Mnemonic::parse_normalized(&words).map_err(|e| anyhow!("Invalid recovery phrase: {}", e))?;
+    let entropy = Zeroizing::new(mnemonic.to_entropy());
+    if entropy.len() != 32 {
+        bail!("A recovery phrase has 24 words");
+    }
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&entropy);
+    Ok(key)
+}
+
+/// `phrase` numbered four words to a line, for writing down
+pub fn numbered(phrase: &str) -> Zeroizing<String> {
+    let words: Vec<String> = phrase.split_whitespace()
+        .enumerate()
+        .map(|(i, word)| format!("{:>2}. {:<10}", i + 1, word))
+        .collect();
+    Zeroizing::new(words.chunks(4).map(|line| line.concat().trim_end().to_string()).collect::<Vec<_>>().join("\n"))
+}
+
+/// Where the encrypted copy of `path` goes: "<name>.enc" in a folder named
+/// after the file's hash and the key, so a resumed upload finds the same
+/// bytes again
+pub fn copy_path(path: &Path, sha256: &str, id: &str) -> Result<PathBuf> {
+    let mut name = path.file_name().context("Invalid filename")?.to_os_string();
+    name.push(".enc");
+    Ok(paths::cache_dir(ENCRYPTED_DIR).join(format!("{}-{}", sha256, id)).join(name))
+}
+
+/// Size of the encrypted copy of a file of `plain` bytes: the header, and a
+/// tag for each segment (there is always one, if only an empty one)
+pub fn encrypted_size(plain: u64) -> u64 {
+    let segments = plain.div_ceil(SEGMENT as u64).max(1);
+    (MAGIC.len() + ID_LEN + PREFIX_LEN) as u64 + plain + segments * TAG_LEN as u64
+}
+
+/// Encrypted copy of `path` made with the current key, and that key's
+/// fingerprint. Blocking; run off the async runtime
+pub fn encrypt(path: &Path, sha256: &str) -> Result<(PathBuf, String)> {
+    let keys = Keys::load()?;
+    let id = keys.current_id();
+    let dest = copy_path(path, sha256, &id)?;
+    if dest.exists() {
+        return Ok((dest, id));
+    }
+    
+    std::fs::create_dir_all(dest.parent().context("Invalid filename")?)?;
+    // Written under another name first so an interrupted run leaves no
+    // truncated copy to be picked up on resume
+    let partial = dest.with_extension("enc.partial");
+    let mut encryptor = Encryptor::new(std::io::BufWriter::new(File::create(&partial)?), keys.current())?;
+    std::io::copy(&mut File::open(path)?, &mut encryptor)
+        .with_context(|| format!("Failed to encrypt {:?}", path))?;
+    encryptor.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&partial, &dest)?;
+    Ok((dest, id))
+}
+
+/// Encrypts what is written to it into `out`, in the format `decrypt`
+/// reads, so data that never is a file of its own can be encrypted as it
+/// goes. The last segment is only sealed by `finish`
+pub struct Encryptor<W: Write> {
+    out: W,
+    cipher: ChaCha20Poly1305,
+    prefix: [u8; PREFIX_LEN],
+    counter: u32,
+    block: Vec<u8>,
+}
+
+impl<W: Write> Encryptor<W> {
+    /// Start encrypting with `key`, writing the header to `out`
+    pub fn new(mut out: W, key: &FileKey) -> Result<Self> {
+        let mut prefix = [0u8; PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        out.write_all(MAGIC)?;
+        out.write_all(&id_bytes(key))?;
+        out.write_all(&prefix)?;
+        Ok(Self {
+            out,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key.as_slice())),
+            prefix,
+            counter: 0,
+            block: Vec::with_capacity(SEGMENT),
+        })
+    }
+    
+    fn seal(&mut self, last: bool) -> std::io::Result<()> {
+        let sealed = self.cipher.encrypt(&nonce(&self.prefix, self.counter, last), self.block.as_slice())
+            .map_err(|_| std::io::Error::other("Failed to encrypt"))?;
+        self.out.write_all(&sealed)?;
+        self.block.clear();
+        self.counter = self.counter.checked_add(1)
+            .ok_or_else(|| std::io::Error::other("File too large to encrypt"))?;
+        Ok(())
+    }
+    
+    /// Seal what is left as the last segment and hand back `out`
+    pub fn finish(mut self) -> Result<W> {
+        self.seal(true)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // A full block is sealed once more follows; until then it may be the last
+        if self.block.len() == SEGMENT && !buf.is_empty() {
+            self.seal(false)?;
+        }
+        let n = buf.len().min(SEGMENT - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Delete an encrypted copy once it is no longer needed
+pub fn remove(copy: &Path) {
+    let _ = std::fs::remove_file(copy);
+    if let Some(dir) = copy.parent() {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+/// Replace the encrypted file at `path` with what it holds, using the key
+/// with fingerprint `id`. Blocking; run off the async runtime
+pub fn decrypt_in_place(path: &Path, id: &str) -> Result<()> {
+    let mut input = std::io::BufReader::new(File::open(path)?);
+    let mut name = path.file_name().context("Invalid filename")?.to_os_string();
+    name.push(".decrypting");
+    let partial = path.with_file_name(name);
+    let mut out = std::io::BufWriter::new(File::create(&partial)?);
+    let result = decrypt(&mut input, id, &mut out)
+        .with_context(|| format!("Failed to decrypt {:?}", path))
+        .and_then(|()| Ok(out.into_inner().map_err(|e| e.into_error())?.sync_all()?));
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Write what the encrypted `input` holds to `out` segment by segment, using
+/// the key with fingerprint `id`. Each segment is checked before it is
+/// written, and a stream cut short fails at its end. Blocking
+pub fn decrypt(input: &mut impl Read, id: &str, out: &mut impl Write) -> Result<()> {
+    let keys = Keys::load()?;
+    let key = keys.find(id).with_context(|| {
+        format!("This file was encrypted with key {}, which is not on this computer; import it first", id)
+    })?;
+    decrypt_with(input, key, out)
+}
+
+/// `decrypt` with the key already at hand
+fn decrypt_with(input: &mut impl Read, key: &FileKey, out: &mut impl Write) -> Result<()> {
+    let mut header = [0u8; MAGIC.len() + ID_LEN + PREFIX_LEN];
+    input.read_exact(&mut header).context("Not an encrypted file")?;
+    if &header[..MAGIC.len()] != MAGIC || header[MAGIC.len()..MAGIC.len() + ID_LEN] != id_bytes(key) {
+        bail!("Not encrypted with key {}", key_id(key));
+    }
+    let prefix: [u8; PREFIX_LEN] = header[MAGIC.len() + ID_LEN..].try_into().unwrap();
+    
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_slice()));
+    segments(input, SEGMENT + TAG_LEN, |sealed, counter, last| {
+        let plain = Zeroizing::new(
+            cipher.decrypt(&nonce(&prefix, counter, last), sealed)
+                .map_err(|_| anyhow!("The file was changed or damaged after it was encrypted"))?,
+        );
+        Ok(out.write_all(&plain)?)
+    })
+}
+
+/// Nonce of segment `counter`; the last segment is marked so a file cut
+/// short is noticed
+fn nonce(prefix: &[u8; PREFIX_LEN], counter: u32, last: bool) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..PREFIX_LEN + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    Nonce::clone_from_slice(&nonce)
+}
+
+/// Feed `input` to `each` in blocks of `size` bytes with their number and
+/// whether it is the last one. An empty input is one empty last block
+fn segments(input: &mut impl Read, size: usize, mut each: impl FnMut(&[u8], u32, bool) -> Result<()>) -> Result<()> {
+    let mut block = vec![0u8; size];
+    let mut next = vec![0u8; size];
+    let mut len = read_full(input, &mut block)?;
+    let mut counter = 0u32;
+    loop {
+        let next_len = if len == size { read_full(input, &mut next)? } else { 0 };
+        let last = next_len == 0;
+        each(&block[..len], counter, last)?;
+        if last {
+            return Ok(());
+        }
+        std::mem::swap(&mut block, &mut next);
+        len = next_len;
+        counter = counter.checked_add(1).context("File too large to encrypt")?;
+    }
+}
+
+/// Fill `buf` as far as the input allows; returns how much was read
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const HEADER_LEN: usize = MAGIC.len() + ID_LEN + PREFIX_LEN;
+    
+    fn test_key() -> FileKey {
+        Zeroizing::new([7u8; 32])
+    }
+    
+    fn plain(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+    
+    fn encrypt_bytes(plain: &[u8]) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(Vec::new(), &test_key()).unwrap();
+        encryptor.write_all(plain).unwrap();
+        encryptor.finish().unwrap()
+    }
+    
+    fn decrypt_bytes(sealed: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decrypt_with(&mut &sealed[..], &test_key(), &mut out)?;
+        Ok(out)
+    }
+    
+    #[test]
+    fn round_trips_around_segment_sizes() {
+        for len in [0, 1, SEGMENT - 1, SEGMENT, SEGMENT + 1, 2 * SEGMENT] {
+            let data = plain(len);
+            let sealed = encrypt_bytes(&data);
+            assert_eq!(decrypt_bytes(&sealed).unwrap(), data, "{} bytes", len);
+        }
+    }
+    
+    #[test]
+    fn encrypted_size_matches_the_output() {
+        for len in [0, 1, SEGMENT - 1, SEGMENT, SEGMENT + 1, 2 * SEGMENT, 2 * SEGMENT + 3] {
+            assert_eq!(encrypt_bytes(&plain(len)).len() as u64, encrypted_size(len as u64), "{} bytes", len);
+        }
+    }
+    
+    #[test]
+    fn rejects_a_stream_cut_at_a_segment_boundary() {
+        for len in [SEGMENT + 1, 2 * SEGMENT, 2 * SEGMENT + 1] {
+            let sealed = encrypt_bytes(&plain(len));
+            let whole_segments = (sealed.len() - HEADER_LEN - 1) / (SEGMENT + TAG_LEN);
+            for kept in 1..=whole_segments {
+                let cut = &sealed[..HEADER_LEN + kept * (SEGMENT + TAG_LEN)];
+                assert!(decrypt_bytes(cut).is_err(), "{} bytes cut after {} segments", len, kept);
+            }
+        }
+    }
+    
+    #[test]
+    fn rejects_a_flipped_byte() {
+        let sealed = encrypt_bytes(&plain(SEGMENT + 100));
+        for at in [HEADER_LEN, HEADER_LEN + SEGMENT / 2, HEADER_LEN + SEGMENT + TAG_LEN + 10, sealed.len() - 1] {
+            let mut damaged = sealed.clone();
+            damaged[at] ^= 1;
+            assert!(decrypt_bytes(&damaged).is_err(), "byte {} flipped", at);
+        }
+    }
+    
+    #[test]
+    fn rejects_another_key() {
+        let sealed = encrypt_bytes(&plain(10));
+        let mut out = Vec::new();
+        assert!(decrypt_with(&mut &sealed[..], &Zeroizing::new([8u8; 32]), &mut out).is_err());
+    }
+}
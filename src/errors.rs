@@ -0,0 +1,107 @@
+use grammers_client::SignInError;
+use grammers_mtsender::InvocationError;
+
+use crate::format_size;
+use crate::limits::TooLarge;
+use crate::read_only::ReadOnly;
+use crate::transfer::{flood_wait_seconds, invocation_error, is_connection_error};
+
+/// A downloaded file came out different from what was stored
+#[derive(Debug, thiserror::Error)]
+#[error("{name} is damaged: expected {}, got {}", format_size(*.expected), format_size(*.actual))]
+pub struct IntegrityError {
+    pub name: String,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Broad kinds of failure, each with a fix the user can try
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The connection to Telegram broke
+    Network,
+    /// Telegram rejected the login or the session is no longer valid
+    Auth,
+    /// Telegram asked to wait this many seconds
+    FloodWait(u32),
+    FileTooBig,
+    DiskFull,
+    Integrity,
+    ReadOnly,
+    Other,
+}
+
+/// RPC errors meaning the user has to sign in (again)
+const AUTH_ERRORS: &[&str] = &[
+    "AUTH_KEY_UNREGISTERED",
+    "AUTH_KEY_INVALID",
+    "SESSION_REVOKED",
+    "SESSION_EXPIRED",
+    "USER_DEACTIVATED",
+    "USER_DEACTIVATED_BAN",
+    "PHONE_CODE_INVALID",
+    "PHONE_CODE_EXPIRED",
+    "PHONE_NUMBER_INVALID",
+    "PHONE_NUMBER_BANNED",
+    "PASSWORD_HASH_INVALID",
+];
+
+/// Windows reports a full disk as ERROR_HANDLE_DISK_FULL or ERROR_DISK_FULL
+#[cfg(windows)]
+const DISK_FULL_CODES: &[i32] = &[39, 112];
+/// ENOSPC, plus EDQUOT for a full quota
+#[cfg(not(windows))]
+const DISK_FULL_CODES: &[i32] = &[28, 122];
+
+pub fn classify(err: &anyhow::Error) -> ErrorKind {
+    if let Some(secs) = flood_wait_seconds(err) {
+        return ErrorKind::FloodWait(secs);
+    }
+    for cause in err.chain() {
+        if cause.is::<TooLarge>() {
+            return ErrorKind::FileTooBig;
+        }
+        if cause.is::<IntegrityError>() {
+            return ErrorKind::Integrity;
+        }
+        if cause.is::<ReadOnly>() {
+            return ErrorKind::ReadOnly;
+        }
+        if cause.is::<SignInError>() {
+            return ErrorKind::Auth;
+        }
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            if io.raw_os_error().map_or(false, |code| DISK_FULL_CODES.contains(&code)) {
+                return ErrorKind::DiskFull;
+            }
+        }
+    }
+    match invocation_error(err) {
+        Some(InvocationError::Rpc(rpc)) if AUTH_ERRORS.contains(&rpc.name.as_str()) => ErrorKind::Auth,
+        Some(InvocationError::Rpc(rpc)) if rpc.name == "FILE_PARTS_INVALID" || rpc.name == "FILE_PART_TOO_BIG" => {
+            ErrorKind::FileTooBig
+        }
+        _ if is_connection_error(err) => ErrorKind::Network,
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Message for the status bar: what went wrong and what to do about it.
+/// Errors that fit no category keep their own text
+pub fn describe(err: &anyhow::Error) -> String {
+    match classify(err) {
+        ErrorKind::Network => tr!("No connection to Telegram. Check your internet connection; transfers resume on their own."),
+        ErrorKind::Auth => tr!("Telegram did not accept the sign-in. Check the code or password, or sign in again."),
+        ErrorKind::FloodWait(secs) => tr!("Telegram is limiting requests. Try again in {secs}s.", secs = secs),
+        ErrorKind::FileTooBig => tr!("The file is over Telegram's size limit. Untick \"Refuse files over the size limit\" to split it."),
+        ErrorKind::DiskFull => tr!("The disk is full. Free some space or save to another folder."),
+        ErrorKind::Integrity => tr!("{error}. Download it again.", error = format!("{:#}", err)),
+        ErrorKind::ReadOnly => read_only_message(),
+        ErrorKind::Other => format!("{:#}", err),
+    }
+}
+
+/// Why a change to stored files was refused in read-only mode
+pub fn read_only_message() -> String {
+    tr!("Read-only mode is on. Turn it off in Settings to change stored files.")
+}
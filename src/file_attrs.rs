@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Filesystem attributes kept with an upload and restored on download
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileAttrs {
+    /// Modification time, seconds since the Unix epoch
+    pub mtime: Option<i64>,
+    /// Unix permission bits, without setuid, setgid and sticky (always
+    /// `None` on other platforms)
+    pub mode: Option<u32>,
+}
+
+/// Permission bits kept and restored; setuid and the like are not carried
+/// over from a caption anyone in the chat could have written
+const MODE_MASK: u32 = 0o777;
+
+impl FileAttrs {
+    /// Read the attributes of a local file, leaving out any that are unavailable
+    pub fn read(path: &Path) -> Self {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return Self::default();
+        };
+        let mtime = metadata.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        
+        Self { mtime, mode: mode_of(&metadata) }
+    }
+    
+    /// Apply the recorded attributes to a downloaded file
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        if let Some(mtime) = self.mtime.filter(|t| *t >= 0) {
+            let file = std::fs::File::options().write(true).open(path)?;
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64))?;
+        }
+        if let Some(mode) = self.mode {
+            set_mode(path, mode & MODE_MASK)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & MODE_MASK)
+}
+
+#[cfg(not(unix))]
+fn mode_of(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+/// Unix permission bits have no equivalent here
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,62 @@
+/// Broad category of a stored file, used for icons and grouping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    Image,
+    Video,
+    Audio,
+    Archive,
+    Document,
+    Code,
+    Other,
+}
+
+impl FileKind {
+    /// Guess the kind from the file extension
+    pub fn from_filename(filename: &str) -> Self {
+        let ext = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        
+        match ext.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | "tif" | "tiff" | "heic" | "svg"
+            | "raw" | "cr2" | "nef" | "arw" | "dng" => FileKind::Image,
+            "mp4" | "mkv" | "mov" | "avi" | "webm" | "wmv" | "flv" | "m4v" | "mpg" | "mpeg" => {
+                FileKind::Video
+            }
+            "mp3" | "flac" | "wav" | "ogg" | "opus" | "m4a" | "aac" | "wma" => FileKind::Audio,
+            "zip" | "rar" | "7z" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "iso" => {
+                FileKind::Archive
+            }
+            "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "xls" | "xlsx" | "ods"
+            | "csv" | "ppt" | "pptx" | "odp" | "epub" => FileKind::Document,
+            "rs" | "py" | "js" | "ts" | "c" | "h" | "cpp" | "hpp" | "java" | "go" | "rb" | "sh"
+            | "json" | "toml" | "yaml" | "yml" | "xml" | "html" | "css" | "sql" => FileKind::Code,
+            _ => FileKind::Other,
+        }
+    }
+    
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileKind::Image => "Image",
+            FileKind::Video => "Video",
+            FileKind::Audio => "Audio",
+            FileKind::Archive => "Archive",
+            FileKind::Document => "Document",
+            FileKind::Code => "Code",
+            FileKind::Other => "File",
+        }
+    }
+    
+    pub fn icon(&self) -> &'static str {
+        match self {
+            FileKind::Image => "🖼",
+            FileKind::Video => "🎬",
+            FileKind::Audio => "🎵",
+            FileKind::Archive => "📦",
+            FileKind::Document => "📄",
+            FileKind::Code => "📝",
+            FileKind::Other => "📁",
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::ignore_file::IgnoreRules;
+
+/// A file found under a folder picked for upload
+pub struct FolderFile {
+    pub path: PathBuf,
+    /// Virtual folder mirroring the file's location, rooted at the picked folder's name
+    pub folder: String,
+    pub size: u64,
+}
+
+/// Recursively list regular files under `root`, sorted by path, leaving out
+/// what its `.teledriveignore` and the global ignore patterns name.
+///
+/// Symlinks are not followed, so links pointing back up the tree can't loop.
+pub fn collect_files(root: &Path) -> Result<Vec<FolderFile>> {
+    let root_name = root
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("Invalid folder name")?;
+    
+    collect_files_into(root, root_name)
+}
+
+/// Like `collect_files`, but with virtual folders rooted at `virtual_root`
+pub fn collect_files_into(root: &Path, virtual_root: &str) -> Result<Vec<FolderFile>> {
+    let rules = IgnoreRules::load(root);
+    let mut files = Vec::new();
+    let entries = WalkDir::new(root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !rules.ignores_entry(e.path(), e.file_type().is_dir()));
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping unreadable entry: {:?}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        
+        let relative_dir = entry
+            .path()
+            .parent()
+            .and_then(|parent| parent.strip_prefix(root).ok())
+            .unwrap_or_else(|| Path::new(""));
+        
+        files.push(FolderFile {
+            path: entry.path().to_path_buf(),
+            folder: join_virtual(virtual_root, relative_dir),
+            size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+        });
+    }
+    
+    Ok(files)
+}
+
+/// `root` plus the components of `relative`, joined with `/` on every platform
+pub fn join_virtual(root: &str, relative: &Path) -> String {
+    std::iter::once(root.to_string())
+        .chain(relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Virtual folder path as typed by the user or read from a caption, with
+/// empty segments, surrounding slashes or spaces, `.`, `..` and drive
+/// prefixes removed; "" is the root
+pub fn normalize(folder: &str) -> String {
+    folder
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|part| is_plain(part))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Local path for a `/`-separated key under `root`, refusing keys that would leave it
+pub fn local_path(root: &Path, key: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for part in key.split('/') {
+        if !is_plain(part) {
+            return None;
+        }
+        path.push(part);
+    }
+    Some(path)
+}
+
+/// Whether `part` names an entry of a folder and nothing else: not empty,
+/// not `.` or `..`, not absolute and without a separator or drive prefix
+fn is_plain(part: &str) -> bool {
+    let mut components = Path::new(part).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+        && !part.contains(['/', '\\'])
+}
+
+/// Every virtual folder holding `folders` (one entry per stored file),
+/// including intermediate ones, sorted by path, with the number of files at
+/// or below each
+pub fn tree<'a>(folders: impl Iterator<Item = &'a str>) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for folder in folders.filter(|f| !f.is_empty()) {
+        let mut end = 0;
+        for part in folder.split('/') {
+            end += part.len();
+            *counts.entry(folder[..end].to_string()).or_default() += 1;
+            end += 1;
+        }
+    }
+    counts.into_iter().collect()
+}
+
+/// Where `path` ends up when folder `from` moves to `to`; `None` if it is
+/// not inside `from`
+pub fn moved_path(path: &str, from: &str, to: &str) -> Option<String> {
+    if path == from {
+        return Some(to.to_string());
+    }
+    let rest = path.strip_prefix(from)?.strip_prefix('/')?;
+    Some(if to.is_empty() { rest.to_string() } else { format!("{}/{}", to, rest) })
+}
+
+/// Last segment of a virtual folder path
+pub fn name(folder: &str) -> &str {
+    folder.rsplit('/').next().unwrap_or(folder)
+}
@@ -0,0 +1,291 @@
+use anyhow::Result;
+use grammers_client::types::Media;
+use grammers_client::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::caption::CaptionMeta;
+use crate::database::{ChatRef, Database, FileRecord, RecordKey, SplitPiece};
+use crate::{chat_import, chunked, read_only, saved_messages, snapshot};
+
+/// Messages are looked up this many at a time
+const FETCH_BATCH: usize = 100;
+
+/// Something wrong with the index
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    /// "unreadable", "no-message", "duplicate", "first-piece", "gone", "size",
+    /// "dangling-chunks" or "broken-snapshot"
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i32>,
+    /// File, pack or snapshot concerned
+    pub subject: String,
+    pub detail: String,
+    pub repaired: bool,
+}
+
+/// What `check` found
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    pub records: usize,
+    pub issues: Vec<Issue>,
+}
+
+/// A document or photo as it is in Telegram
+struct Remote {
+    size: u64,
+    meta: Option<CaptionMeta>,
+}
+
+/// Identifies a message: chat id, `None` for Saved Messages, and message id
+type MessageKey = (Option<i64>, i32);
+
+fn chat_key(record: &FileRecord) -> Option<i64> {
+    record.chat.as_ref().map(|c| c.id)
+}
+
+/// Check the index: records that can't be read, have no message, point at
+/// the same messages as another, or whose size is off, and chunks of the
+/// snapshot store with no pack. Connected, also whether the messages still
+/// exist and what their captions say.
+///
+/// With `repair`, what can be is put right, using what Telegram still has:
+/// unreadable records are rebuilt from their message, records whose message
+/// is gone and duplicates are dropped, and sizes are corrected
+pub async fn check(client: Option<&Client>, db: &Database, repair: bool) -> Result<CheckReport> {
+    if repair {
+        read_only::check("repairing the index")?;
+    }
+    let records = db.all_records();
+    let mut report = CheckReport { records: records.len(), ..Default::default() };
+    
+    let mut recovered = Vec::new();
+    let mut still_unreadable = Vec::new();
+    for raw in db.unreadable_records() {
+        let error = serde_json::from_value::<FileRecord>(raw.clone()).err().map(|e| e.to_string()).unwrap_or_default();
+        let message_id = raw.get("message_id").and_then(Value::as_i64).map(|id| id as i32);
+        let mut issue = Issue {
+            kind: "unreadable",
+            message_id,
+            subject: raw.get("filename").and_then(Value::as_str).unwrap_or("?").to_string(),
+            detail: error,
+            repaired: false,
+        };
+        match (repair, client, message_id) {
+            (true, Some(client), Some(_)) => match recover(client, &raw).await {
+                Ok(Some(record)) => {
+                    recovered.push(record);
+                    issue.repaired = true;
+                }
+                Ok(None) => {
+                    issue.detail = format!("{}; its message is gone", issue.detail);
+                    issue.repaired = true;
+                }
+                Err(e) => {
+                    warn!("Failed to recover {}: {:?}", issue.subject, e);
+                    still_unreadable.push(raw);
+                }
+            },
+            _ => still_unreadable.push(raw),
+        }
+        report.issues.push(issue);
+    }
+    
+    // What Telegram holds, for the chats that could be looked at
+    let mut remote: HashMap<MessageKey, Remote> = HashMap::new();
+    let mut looked_at = HashSet::new();
+    if let Some(client) = client {
+        let mut by_chat: HashMap<Option<i64>, (Option<&ChatRef>, Vec<i32>)> = HashMap::new();
+        for record in &records {
+            by_chat.entry(chat_key(record))
+                .or_insert_with(|| (record.chat.as_ref(), Vec::new()))
+                .1
+                .extend(record.message_ids());
+        }
+        for (key, (chat, mut ids)) in by_chat {
+            ids.sort_unstable();
+            ids.dedup();
+            match fetch(client, chat, &ids).await {
+                Ok(found) => {
+                    remote.extend(found.into_iter().map(|(id, document)| ((key, id), document)));
+                    looked_at.insert(key);
+                }
+                Err(e) => warn!("Failed to look up the messages in {}: {:?}", chat.map_or("Saved Messages", |c| c.name.as_str()), e),
+            }
+        }
+    }
+    
+    let mut seen = HashSet::new();
+    let mut gone = HashSet::new();
+    let mut sizes: HashMap<MessageKey, u64> = HashMap::new();
+    for record in &records {
+        let issue = |kind, message_id, detail: String| Issue {
+            kind,
+            message_id,
+            subject: record.path(),
+            detail,
+            repaired: repair,
+        };
+        let Some(mut message_id) = record.message_id else {
+            report.issues.push(Issue { repaired: false, ..issue("no-message", None, "It has no message, so it can't be downloaded".to_string()) });
+            continue;
+        };
+        let chat = chat_key(record);
+        if !seen.insert((chat, record.message_ids())) {
+            report.issues.push(issue("duplicate", Some(message_id), "Another record points at the same messages".to_string()));
+            continue;
+        }
+        if let Some(first) = record.split_pieces.first().filter(|first| first.message_id != message_id) {
+            report.issues.push(issue("first-piece", Some(message_id), format!("Its first piece is message {}", first.message_id)));
+            message_id = first.message_id;
+        }
+        
+        let ids = record.message_ids();
+        let missing = ids.iter().filter(|id| !remote.contains_key(&(chat, **id))).count();
+        if looked_at.contains(&chat) && missing > 0 {
+            report.issues.push(issue("gone", Some(message_id), format!("{} of its {} message(s) were deleted", missing, ids.len())));
+            gone.insert((chat, message_id));
+            continue;
+        }
+        
+        let plain = !record.compressed && record.key_id.is_none();
+        let expected = match remote.get(&(chat, message_id)) {
+            // The caption of a photo describes the original, not Telegram's JPEG
+            Some(Remote { size, .. }) if record.photo => Some(*size),
+            Some(Remote { meta: Some(meta), .. }) => Some(meta.size),
+            Some(Remote { size, meta: None }) if plain && record.split_pieces.is_empty() => Some(*size),
+            _ if plain && !record.split_pieces.is_empty() => Some(record.split_pieces.iter().map(|p| p.size).sum()),
+            _ => None,
+        };
+        if let Some(expected) = expected.filter(|size| *size != record.file_size) {
+            report.issues.push(issue("size", Some(message_id), format!("Recorded as {} bytes, stored as {}", record.file_size, expected)));
+            sizes.insert((chat, message_id), expected);
+        }
+    }
+    
+    let mut repository = snapshot::Repository::load()?;
+    let mut dangling: HashMap<RecordKey, usize> = HashMap::new();
+    for location in repository.chunks.values().filter(|l| !repository.packs.contains_key(&l.pack)) {
+        *dangling.entry(location.pack).or_default() += 1;
+    }
+    for (pack, count) in &dangling {
+        report.issues.push(Issue {
+            kind: "dangling-chunks",
+            message_id: Some(pack.message_id),
+            subject: format!("pack {}", pack),
+            detail: format!("{} chunk(s) are said to be in a pack that isn't stored", count),
+            repaired: repair,
+        });
+    }
+    for taken in &repository.snapshots {
+        let missing = taken.files.iter()
+            .flat_map(|f| &f.chunks)
+            .filter(|hash| repository.chunks.get(*hash).map_or(true, |l| !repository.packs.contains_key(&l.pack)))
+            .count();
+        if missing > 0 {
+            report.issues.push(Issue {
+                kind: "broken-snapshot",
+                message_id: None,
+                subject: format!("snapshot {}", taken.id),
+                detail: format!("{} chunk(s) it needs are not stored", missing),
+                repaired: false,
+            });
+        }
+    }
+    
+    if repair {
+        if !dangling.is_empty() {
+            let packs = &repository.packs;
+            repository.chunks.retain(|_, l| packs.contains_key(&l.pack));
+            repository.save()?;
+        }
+        db.repair(|records, unreadable| {
+            *unreadable = still_unreadable;
+            records.extend(recovered);
+            for record in records.iter_mut() {
+                if let Some(first) = record.split_pieces.first() {
+                    record.message_id = Some(first.message_id);
+                }
+            }
+            records.retain(|r| !r.message_id.is_some_and(|id| gone.contains(&(chat_key(r), id))));
+            let mut seen = HashSet::new();
+            records.retain(|r| r.message_id.is_none() || seen.insert((chat_key(r), r.message_ids())));
+            for record in records.iter_mut() {
+                if let Some(size) = record.message_id.and_then(|id| sizes.get(&(chat_key(record), id))) {
+                    record.file_size = *size;
+                }
+            }
+        }).await?;
+    }
+    info!("Checked {} records: {} issue(s)", report.records, report.issues.len());
+    Ok(report)
+}
+
+/// The documents and photos among `ids` in `chat`, Saved Messages if `None`,
+/// that still exist
+async fn fetch(client: &Client, chat: Option<&ChatRef>, ids: &[i32]) -> Result<HashMap<i32, Remote>> {
+    let packed = match chat {
+        Some(chat) => chat.packed()?,
+        None => saved_messages(client).await?,
+    };
+    let mut found = HashMap::new();
+    for batch in ids.chunks(FETCH_BATCH) {
+        api_usage::request("messages.getMessages");
+        for message in client.get_messages_by_id(packed, batch).await?.into_iter().flatten() {
+            if let Some(size) = message.media().as_ref().and_then(chunked::media_size) {
+                let meta = CaptionMeta::parse(message.text());
+                found.insert(message.id(), Remote { size, meta });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Rebuild an unreadable record from its message and caption, keeping what
+/// of it can still be read; `None` if the message is gone
+async fn recover(client: &Client, raw: &Value) -> Result<Option<FileRecord>> {
+    let Some(message_id) = raw.get("message_id").and_then(Value::as_i64) else {
+        return Ok(None);
+    };
+    let field = |name: &str| raw.get(name).cloned().unwrap_or(Value::Null);
+    let chat: Option<ChatRef> = serde_json::from_value(field("chat")).ok().flatten();
+    let packed = match &chat {
+        Some(chat) => chat.packed()?,
+        None => saved_messages(client).await?,
+    };
+    api_usage::request("messages.getMessages");
+    let Some(message) = client.get_messages_by_id(packed, &[message_id as i32]).await?.pop().flatten() else {
+        return Ok(None);
+    };
+    let meta = CaptionMeta::parse(message.text());
+    let mut record = match (message.media(), meta) {
+        (Some(Media::Document(document)), meta) => {
+            let mut record = chat_import::document_record(&message, &document, chat);
+            if let Some(meta) = meta {
+                meta.apply(&mut record);
+                record.file_size = meta.size;
+            }
+            record
+        }
+        (Some(Media::Photo(photo)), Some(meta)) if meta.photo => chat_import::photo_record(&message, &photo, &meta, chat),
+        _ => return Ok(None),
+    };
+    // Split files and hidden names are only known to the index
+    if let Some(name) = raw.get("filename").and_then(Value::as_str) {
+        record.filename = name.to_string();
+    }
+    if let Some(file_id) = raw.get("file_id").and_then(Value::as_str) {
+        record.file_id = file_id.to_string();
+    }
+    if let Some(folder) = raw.get("folder").and_then(Value::as_str) {
+        record.folder = folder.to_string();
+    }
+    if let Ok(pieces) = serde_json::from_value::<Vec<SplitPiece>>(field("split_pieces")) {
+        record.split_pieces = pieces;
+    }
+    Ok(Some(record))
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use grammers_client::types::Media;
+use grammers_client::Client;
+use grammers_session::{PackedChat, PackedType};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::caption::CaptionMeta;
+use crate::database::{ChatRef, Database, FileRecord, SplitPiece};
+use crate::{chat_import, snapshot};
+use crate::{refresh_file_list, set_status, AppWindow};
+
+/// How often the group is scanned for files other members added or deleted
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Group new uploads go to instead of Saved Messages
+static DRIVE: RwLock<Option<ChatRef>> = RwLock::new(None);
+
+pub fn current() -> Option<ChatRef> {
+    DRIVE.read().unwrap().clone()
+}
+
+pub fn set(drive: Option<ChatRef>) {
+    *DRIVE.write().unwrap() = drive;
+}
+
+/// Whether `chat` can serve as a shared drive: a basic group or a supergroup
+pub fn is_group(chat: PackedChat) -> bool {
+    matches!(chat.ty, PackedType::Chat | PackedType::Megagroup | PackedType::Gigagroup)
+}
+
+/// Whether `record` lives in the current drive group, where deleting it
+/// removes it for every member
+pub fn holds(record: &FileRecord) -> bool {
+    match (&record.chat, current()) {
+        (Some(chat), Some(drive)) => chat.id == drive.id,
+        _ => false,
+    }
+}
+
+/// Records for every document in `drive`, and every photo TeleDrive sent
+/// there, newest first. Captions written by TeleDrive restore the folder,
+/// tags and hash, and join split files back together; split files with
+/// pieces still missing are left out
+pub async fn scan(client: &Client, drive: &ChatRef) -> Result<Vec<FileRecord>> {
+    api_usage::request("messages.getHistory");
+    let mut messages = client.iter_messages(drive.packed()?);
+    let mut records = Vec::new();
+    // Pieces of split files seen so far by hash, as (piece number, piece, record)
+    let mut pieces: HashMap<String, Vec<(u32, SplitPiece, FileRecord)>> = HashMap::new();
+    while let Some(message) = messages.next().await? {
+        let document = match message.media() {
+            Some(Media::Document(document)) => document,
+            Some(Media::Photo(photo)) => {
+                if let Some(meta) = CaptionMeta::parse(message.text()).filter(|m| m.photo) {
+                    records.push(chat_import::photo_record(&message, &photo, &meta, Some(drive.clone())));
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        // Snapshot packs and manifests only make sense as a whole
+        if snapshot::is_repository_caption(message.text()) {
+            continue;
+        }
+        let meta = CaptionMeta::parse(message.text());
+        let size = document.size().max(0) as u64;
+        let mut record = chat_import::document_record(&message, &document, Some(drive.clone()));
+        let Some(meta) = meta else {
+            records.push(record);
+            continue;
+        };
+        meta.apply(&mut record);
+        
+        let Some((number, count)) = meta.piece else {
+            records.push(record);
+            continue;
+        };
+        let found = pieces.entry(meta.sha256).or_default();
+        found.push((number, SplitPiece { message_id: message.id(), size, sha256: None }, record));
+        if found.len() < count as usize {
+            continue;
+        }
+        
+        // Every piece is here; the first one stands for the whole file
+        let mut found = std::mem::take(found);
+        found.sort_by_key(|(number, _, _)| *number);
+        let split_pieces: Vec<SplitPiece> = found.iter().map(|(_, piece, _)| piece.clone()).collect();
+        let (_, _, mut record) = found.swap_remove(0);
+        if let Some((name, _)) = record.filename.rsplit_once(".part") {
+            record.filename = name.to_string();
+            record.file_id = format!("tg_file_{}", name);
+        }
+        record.file_size = meta.size;
+        record.split_pieces = split_pieces;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Scan `drive` and bring the index in line with it; returns how many
+/// records were added and removed
+pub async fn refresh(client: &Client, db: &Database, drive: &ChatRef) -> Result<(usize, usize)> {
+    let scanned = scan(client, drive).await?;
+    db.sync_chat(drive, scanned).await
+}
+
+/// Rescan the drive group every few minutes while one is set, so every
+/// member sees the same files. `refresh_now` forces a scan, e.g. after
+/// signing in or picking a group
+pub async fn run_refresher(
+    client: Arc<Mutex<Option<Client>>>,
+    db: Arc<Database>,
+    refresh_now: Arc<Notify>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    loop {
+        let tg_client = client.lock().unwrap().clone();
+        if let (Some(drive), Some(tg_client)) = (current(), tg_client) {
+            match refresh(&tg_client, &db, &drive).await {
+                Ok((0, 0)) => {}
+                Ok((added, removed)) => {
+                    info!("Group drive {}: {} files added, {} removed", drive.name, added, removed);
+                    set_status(&ui_handle, tr!(
+                        "{group}: {added} new and {removed} deleted files",
+                        group = drive.name, added = added, removed = removed,
+                    ));
+                    refresh_file_list(&ui_handle, &db);
+                }
+                Err(e) => warn!("Failed to scan group drive {}: {:?}", drive.name, e),
+            }
+        }
+        
+        tokio::select! {
+            _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+            _ = refresh_now.notified() => {}
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use crate::database::FileRecord;
+
+/// Longest a hook may run before the transfer gives up on it
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Lines of hook output kept for the error message
+const OUTPUT_TAIL_LINES: usize = 5;
+
+/// Shell commands run around transfers; empty means no hook
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    /// Runs before a file is uploaded; a non-zero exit cancels the upload
+    pub pre_upload: String,
+    pub post_upload: String,
+    pub post_download: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HookEvent {
+    PreUpload,
+    PostUpload,
+    PostDownload,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::PreUpload => "pre-upload",
+            HookEvent::PostUpload => "post-upload",
+            HookEvent::PostDownload => "post-download",
+        }
+    }
+}
+
+/// A hook exited with an error, which stops the upload for `pre-upload`
+#[derive(Debug, thiserror::Error)]
+#[error("The {event} hook rejected {name}: {reason}")]
+pub struct HookRejected {
+    pub event: &'static str,
+    pub name: String,
+    pub reason: String,
+}
+
+/// File a hook runs for, passed as `TELEDRIVE_*` environment variables
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HookFile {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub folder: String,
+    pub message_id: Option<i32>,
+    pub sha256: Option<String>,
+}
+
+impl HookFile {
+    /// A stored file at local path `path`
+    pub fn stored(path: &Path, record: &FileRecord) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            name: record.filename.clone(),
+            size: record.file_size,
+            folder: record.folder.clone(),
+            message_id: record.message_id,
+            sha256: record.sha256.clone(),
+        }
+    }
+}
+
+/// Run `command` through the shell for `event`. Does nothing when `command` is empty
+pub async fn run(command: &str, event: HookEvent, file: &HookFile) -> Result<()> {
+    if command.trim().is_empty() {
+        return Ok(());
+    }
+    info!("Running {} hook for {}", event.name(), file.name);
+    
+    let mut shell = if cfg!(windows) {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command)
+        .env("TELEDRIVE_EVENT", event.name())
+        .env("TELEDRIVE_PATH", &file.path)
+        .env("TELEDRIVE_NAME", &file.name)
+        .env("TELEDRIVE_SIZE", file.size.to_string())
+        .env("TELEDRIVE_FOLDER", &file.folder)
+        .env("TELEDRIVE_MESSAGE_ID", file.message_id.map(|id| id.to_string()).unwrap_or_default())
+        .env("TELEDRIVE_SHA256", file.sha256.as_deref().unwrap_or_default())
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+    
+    let output = tokio::time::timeout(HOOK_TIMEOUT, shell.output()).await
+        .map_err(|_| HookRejected {
+            event: event.name(),
+            name: file.name.clone(),
+            reason: format!("timed out after {} minutes", HOOK_TIMEOUT.as_secs() / 60),
+        })?
+        .with_context(|| format!("Failed to start the {} hook", event.name()))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = if stderr.trim().is_empty() { stdout } else { stderr };
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..].join(" / ");
+    Err(HookRejected {
+        event: event.name(),
+        name: file.name.clone(),
+        reason: if tail.is_empty() { output.status.to_string() } else { tail },
+    }.into())
+}
@@ -0,0 +1,118 @@
+use slint::{ComponentHandle, Model};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use crate::{AppWindow, Tr};
+
+/// Translate a UI string, filling `{name}` placeholders from the named arguments:
+/// `tr!("Uploaded {name}", name = filename)`
+macro_rules! tr {
+    ($text:literal) => {
+        $crate::i18n::tr($text)
+    };
+    ($text:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::tr_args($text, &[$((stringify!($name), $value.to_string())),+])
+    };
+}
+
+/// Bundled locales: code, native name and catalog mapping English text to the translation.
+/// English needs no catalog, as the source strings are English
+const LOCALES: &[(&str, &str, &str)] = &[
+    ("en", "English", "{}"),
+    ("de", "Deutsch", include_str!("../locales/de.json")),
+    ("es", "Español", include_str!("../locales/es.json")),
+    ("ru", "Русский", include_str!("../locales/ru.json")),
+];
+
+type Catalog = HashMap<String, String>;
+
+fn catalogs() -> &'static HashMap<&'static str, Catalog> {
+    static CATALOGS: OnceLock<HashMap<&'static str, Catalog>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        LOCALES.iter()
+            .map(|(code, _, json)| (*code, serde_json::from_str(json).unwrap_or_default()))
+            .collect()
+    })
+}
+
+fn current() -> &'static RwLock<String> {
+    static CURRENT: OnceLock<RwLock<String>> = OnceLock::new();
+    CURRENT.get_or_init(|| RwLock::new("en".to_string()))
+}
+
+/// Codes and native names of the bundled languages, for the language picker
+pub fn languages() -> impl Iterator<Item = (&'static str, &'static str)> {
+    LOCALES.iter().map(|(code, name, _)| (*code, *name))
+}
+
+/// Switch the UI language; empty picks it from the environment (`LANG` etc.).
+/// Unknown languages fall back to English
+pub fn set_language(code: &str) {
+    let code = if code.is_empty() { system_language() } else { code.to_string() };
+    let code = if catalogs().contains_key(code.as_str()) { code } else { "en".to_string() };
+    *current().write().unwrap() = code;
+}
+
+pub fn language() -> String {
+    current().read().unwrap().clone()
+}
+
+/// Language of the user's locale, e.g. "de" for `de_DE.UTF-8`
+fn system_language() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| value.split(['_', '.', '-']).next().map(str::to_lowercase))
+        .unwrap_or_else(|| "en".to_string())
+}
+
+/// Translation of `text` in the current language, or `text` itself if there is none
+pub fn tr(text: &str) -> String {
+    let language = current().read().unwrap();
+    catalogs()
+        .get(language.as_str())
+        .and_then(|catalog| catalog.get(text))
+        .cloned()
+        .unwrap_or_else(|| text.to_string())
+}
+
+/// Like `tr`, then replace each `{name}` with its value
+pub fn tr_args(text: &str, args: &[(&str, String)]) -> String {
+    let mut translated = tr(text);
+    for (name, value) in args {
+        translated = translated.replace(&format!("{{{}}}", name), value);
+    }
+    translated
+}
+
+/// Wire the Slint `Tr` global to the catalogs and fill the language picker
+pub fn install(ui: &AppWindow) {
+    let tr_global = ui.global::<Tr>();
+    tr_global.on_translate(|_, text| tr(&text).into());
+    tr_global.on_format(|_, text, args| {
+        let names: Vec<String> = (0..args.row_count()).map(|i| i.to_string()).collect();
+        let args: Vec<(&str, String)> = names.iter()
+            .map(String::as_str)
+            .zip(args.iter().map(|arg| arg.to_string()))
+            .collect();
+        tr_args(&text, &args).into()
+    });
+    
+    let names: Vec<slint::SharedString> = languages().map(|(_, name)| name.into()).collect();
+    ui.set_languages(std::rc::Rc::new(slint::VecModel::from(names)).into());
+    show_language(ui);
+}
+
+/// Push the current language to the UI, re-evaluating every translated binding
+pub fn show_language(ui: &AppWindow) {
+    let language = language();
+    let index = languages().position(|(code, _)| code == language).unwrap_or(0);
+    ui.set_language_index(index as i32);
+    ui.global::<Tr>().set_language(language.into());
+}
+
+/// Code of the language at `index` in the picker
+pub fn language_at(index: usize) -> Option<&'static str> {
+    languages().nth(index).map(|(code, _)| code)
+}
@@ -0,0 +1,71 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+use tracing::warn;
+
+use crate::paths;
+
+/// Ignore file read from the root of an uploaded or synced folder
+pub const FILE_NAME: &str = ".teledriveignore";
+/// Patterns for every folder, in the config folder; the built-in defaults
+/// apply while it doesn't exist
+const GLOBAL_FILE: &str = "teledriveignore";
+
+/// Junk no one wants backed up
+const DEFAULT_PATTERNS: &[&str] = &[
+    "node_modules/",
+    "__pycache__/",
+    ".git/",
+    "*.tmp",
+    "*.swp",
+    "~$*",
+    ".DS_Store",
+    "Thumbs.db",
+    "desktop.ini",
+];
+
+/// Files under a folder that are left out, written like `.gitignore`: the
+/// global patterns first, then the folder's own `.teledriveignore`, whose
+/// `!pattern` lines can bring back what the global ones leave out
+pub struct IgnoreRules {
+    matcher: Gitignore,
+}
+
+impl IgnoreRules {
+    pub fn load(root: &Path) -> Self {
+        let mut builder = GitignoreBuilder::new(root);
+        let global = paths::config_file(GLOBAL_FILE);
+        if global.exists() {
+            if let Some(e) = builder.add(&global) {
+                warn!("Ignoring bad lines in {:?}: {}", global, e);
+            }
+        } else {
+            for pattern in DEFAULT_PATTERNS {
+                let _ = builder.add_line(None, pattern);
+            }
+        }
+        let own = root.join(FILE_NAME);
+        if own.exists() {
+            if let Some(e) = builder.add(&own) {
+                warn!("Ignoring bad lines in {:?}: {}", own, e);
+            }
+        }
+        
+        let matcher = builder.build().unwrap_or_else(|e| {
+            warn!("Failed to read the ignore patterns for {:?}: {}", root, e);
+            Gitignore::empty()
+        });
+        Self { matcher }
+    }
+    
+    /// Whether `path`, found while walking the folder, is left out. Its
+    /// parents are not looked at: an ignored folder is never walked into
+    pub fn ignores_entry(&self, path: &Path, is_dir: bool) -> bool {
+        self.matcher.matched(path, is_dir).is_ignore()
+    }
+    
+    /// Whether the file at `path`, under the folder or relative to it, is
+    /// left out, itself or through one of its parents
+    pub fn ignores(&self, path: &Path) -> bool {
+        self.matcher.matched_path_or_any_parents(path, false).is_ignore()
+    }
+}
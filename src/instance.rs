@@ -0,0 +1,105 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Where the running instance says how to reach it. Relative, so each
+/// profile has one instance of its own
+const INSTANCE_FILE: &str = "instance.json";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Contents of the instance file. The token keeps other local programs
+/// from queueing uploads
+#[derive(Serialize, Deserialize)]
+struct InstanceInfo {
+    port: u16,
+    token: String,
+}
+
+/// Sent by a second instance
+#[derive(Serialize, Deserialize)]
+struct HandOff {
+    token: String,
+    paths: Vec<PathBuf>,
+}
+
+/// Listens for later instances while this one runs
+pub struct Primary {
+    listener: TcpListener,
+    token: String,
+}
+
+/// Become the running instance, or hand `paths` to the one already running
+/// and return `None`. Two instances would overwrite each other's database
+pub fn claim(paths: &[PathBuf]) -> Result<Option<Primary>> {
+    if let Ok(json) = std::fs::read_to_string(INSTANCE_FILE) {
+        if let Ok(running) = serde_json::from_str::<InstanceInfo>(&json) {
+            match hand_off(&running, paths) {
+                Ok(()) => return Ok(None),
+                // Left behind by an instance that crashed
+                Err(e) => info!("No running instance answered: {:#}", e),
+            }
+        }
+    }
+    
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).context("Failed to listen for other instances")?;
+    let info = InstanceInfo {
+        port: listener.local_addr()?.port(),
+        token: format!("{:032x}", rand::random::<u128>()),
+    };
+    std::fs::write(INSTANCE_FILE, serde_json::to_string(&info)?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(INSTANCE_FILE, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(Some(Primary { listener, token: info.token }))
+}
+
+fn hand_off(running: &InstanceInfo, paths: &[PathBuf]) -> Result<()> {
+    let addr = (Ipv4Addr::LOCALHOST, running.port).into();
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let message = HandOff { token: running.token.clone(), paths: paths.to_vec() };
+    writeln!(stream, "{}", serde_json::to_string(&message)?)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.trim() != "ok" {
+        bail!("Unexpected reply {:?}", reply.trim());
+    }
+    Ok(())
+}
+
+impl Primary {
+    /// Call `on_hand_off` with the paths each later instance passes on, from
+    /// a thread of its own
+    pub fn serve(self, on_hand_off: impl Fn(Vec<PathBuf>) + Send + 'static) {
+        std::thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let result = stream.map_err(Into::into).and_then(|mut stream| {
+                    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+                    let mut line = String::new();
+                    BufReader::new(&stream).read_line(&mut line)?;
+                    let message: HandOff = serde_json::from_str(&line)?;
+                    if message.token != self.token {
+                        bail!("Wrong token");
+                    }
+                    writeln!(stream, "ok")?;
+                    Ok(message.paths)
+                });
+                match result {
+                    Ok(paths) => on_hand_off(paths),
+                    Err(e) => warn!("Ignoring a message from another instance: {:#}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Let the next launch start fresh instead of trying to reach this one
+pub fn release() {
+    let _ = std::fs::remove_file(INSTANCE_FILE);
+}
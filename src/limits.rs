@@ -0,0 +1,55 @@
+use anyhow::Result;
+use grammers_client::Client;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::api_usage;
+use crate::format_size;
+
+/// Largest document a regular account can send (4000 parts of 512 KB)
+pub const STANDARD_MAX_FILE_SIZE: u64 = 2000 * 1024 * 1024;
+/// Largest document a Telegram Premium account can send
+pub const PREMIUM_MAX_FILE_SIZE: u64 = 4000 * 1024 * 1024;
+
+/// Returned before an oversized upload starts when splitting is turned off
+#[derive(Debug, thiserror::Error)]
+#[error("{name} is {}, over the {} limit of this account", format_size(*.size), format_size(*.limit))]
+pub struct TooLarge {
+    pub name: String,
+    pub size: u64,
+    pub limit: u64,
+}
+
+/// Upload limits of the signed-in account
+#[derive(Debug, Default)]
+pub struct AccountLimits {
+    premium: AtomicBool,
+}
+
+impl AccountLimits {
+    /// Ask Telegram whether the account has Premium and remember the answer
+    pub async fn detect(&self, client: &Client) -> Result<bool> {
+        api_usage::request("users.getUsers");
+        let me = client.get_me().await?;
+        let premium = me.raw.premium;
+        self.premium.store(premium, Ordering::SeqCst);
+        Ok(premium)
+    }
+    
+    pub fn is_premium(&self) -> bool {
+        self.premium.load(Ordering::SeqCst)
+    }
+    
+    /// Largest single document the account may send
+    pub fn max_file_size(&self) -> u64 {
+        if self.is_premium() {
+            PREMIUM_MAX_FILE_SIZE
+        } else {
+            STANDARD_MAX_FILE_SIZE
+        }
+    }
+    
+    /// Number of documents a file of `size` bytes has to be split into (1 if it fits)
+    pub fn pieces_for(&self, size: u64) -> u64 {
+        size.div_ceil(self.max_file_size()).max(1)
+    }
+}
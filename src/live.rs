@@ -0,0 +1,103 @@
+use anyhow::Result;
+use grammers_client::types::{Chat, Media, Message};
+use grammers_client::{Client, Update};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::caption::{self, CaptionMeta};
+use crate::database::{Database, RecordKey};
+use crate::{chat_import, group_drive, refresh_file_list, set_status, snapshot, AppWindow};
+
+/// Wait before looking for a client again, when signed out or disconnected
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Updates are waited for this long before checking whether the client was
+/// replaced by a reconnect
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Follow what happens in the storage chat as it happens: files sent to
+/// Saved Messages from another device are added to the index, and records
+/// whose messages were deleted in Telegram are marked as missing. New files
+/// in a group drive start a rescan through `refresh_drive` instead
+pub async fn run_updates(
+    client: Arc<Mutex<Option<Client>>>,
+    db: Arc<Database>,
+    refresh_drive: Arc<Notify>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    loop {
+        let Some(tg_client) = client.lock().unwrap().clone() else {
+            tokio::time::sleep(RETRY_DELAY).await;
+            continue;
+        };
+        let update = match tokio::time::timeout(RECHECK_INTERVAL, tg_client.next_update()).await {
+            Ok(Ok(update)) => update,
+            Ok(Err(e)) => {
+                // The connection monitor reconnects; the new client is picked up next time
+                warn!("Stopped receiving updates: {:?}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+            Err(_) => continue,
+        };
+        
+        let changed = match update {
+            Update::NewMessage(message) => new_message(&db, &refresh_drive, message).await,
+            Update::MessageDeleted(deletion) => {
+                match db.mark_missing(deletion.channel_id(), deletion.messages()).await {
+                    Ok(0) => Ok(false),
+                    Ok(count) => {
+                        info!("{} stored files were deleted in Telegram", count);
+                        set_status(&ui_handle, tr!("{count} file(s) were deleted in Telegram", count = count));
+                        Ok(true)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            _ => Ok(false),
+        };
+        match changed {
+            Ok(true) => refresh_file_list(&ui_handle, &db),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to handle an update: {:?}", e),
+        }
+    }
+}
+
+/// Add a document sent to Saved Messages by hand, from the phone say;
+/// returns whether the index changed
+async fn new_message(db: &Database, refresh_drive: &Notify, message: Message) -> Result<bool> {
+    let chat = message.chat();
+    if let Some(drive) = group_drive::current() {
+        if chat.id() == drive.id {
+            refresh_drive.notify_one();
+        }
+        return Ok(false);
+    }
+    if !matches!(&chat, Chat::User(user) if user.is_self()) {
+        return Ok(false);
+    }
+    let Some(Media::Document(document)) = message.media() else {
+        return Ok(false);
+    };
+    if is_ours(message.text(), document.name()) || db.find(RecordKey::new(None, message.id())).is_some() {
+        return Ok(false);
+    }
+    
+    let record = chat_import::document_record(&message, &document, None);
+    info!("{} was sent to Saved Messages from elsewhere; adding it", record.filename);
+    db.insert_file(record).await?;
+    Ok(true)
+}
+
+/// Whether a document was sent by TeleDrive, which records it itself:
+/// captioned with its metadata, a snapshot pack, a stream still uploading,
+/// or sent under a random name when names are hidden
+fn is_ours(caption: &str, name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default();
+    CaptionMeta::parse(caption).is_some()
+        || snapshot::is_repository_caption(caption)
+        || caption == caption::PENDING
+        || (stem.len() == 32 && stem.chars().all(|c| c.is_ascii_hexdigit()))
+}
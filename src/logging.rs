@@ -0,0 +1,96 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use crate::crash::BreadcrumbLayer;
+
+/// Folder holding the log files
+pub const LOG_DIR: &str = "logs";
+const LOG_FILE_PREFIX: &str = "teledrive";
+/// Daily files kept before the oldest is deleted
+const MAX_LOG_FILES: usize = 7;
+
+/// How much gets logged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Order of the entries in the log level picker
+    const ALL: [LogLevel; 5] = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+    
+    pub fn index(self) -> i32 {
+        Self::ALL.iter().position(|l| *l == self).unwrap_or(0) as i32
+    }
+    
+    pub fn from_index(index: i32) -> Self {
+        usize::try_from(index).ok()
+            .and_then(|i| Self::ALL.get(i).copied())
+            .unwrap_or_default()
+    }
+    
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Changes the level of the running subscriber
+pub struct LogControl {
+    level: reload::Handle<LevelFilter, Registry>,
+    /// Flushes the file writer when dropped, so keep it alive until exit
+    _guard: WorkerGuard,
+}
+
+impl LogControl {
+    pub fn set_level(&self, level: LogLevel) {
+        if let Err(e) = self.level.reload(level.filter()) {
+            eprintln!("Failed to change log level: {}", e);
+        }
+    }
+}
+
+/// Log to the console and to a file in `LOG_DIR` that rotates daily, so
+/// problems are diagnosable when started from a desktop icon
+pub fn init(level: LogLevel) -> Result<LogControl> {
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(LOG_DIR)?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let (filter, handle) = reload::Layer::new(level.filter());
+    
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(BreadcrumbLayer)
+        .with(fmt::layer())
+        .with(fmt::layer().with_ansi(false).with_writer(writer))
+        .try_init()?;
+    Ok(LogControl { level: handle, _guard: guard })
+}
+
+/// Show the log folder in the file manager
+pub fn open_log_dir() -> Result<()> {
+    std::fs::create_dir_all(LOG_DIR)?;
+    open::that(LOG_DIR)?;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+use zeroize::Zeroizing;
+
+use crate::AppWindow;
+
+/// What the sign-in flow is waiting for the user to type
+#[derive(Debug, Clone, Copy)]
+pub enum AuthStep {
+    /// Login code sent by Telegram
+    Code,
+    /// Two-step verification password, entered in a masked field
+    Password,
+}
+
+impl AuthStep {
+    /// Value of the window's `auth-step` property
+    fn name(self) -> &'static str {
+        match self {
+            AuthStep::Code => "code",
+            AuthStep::Password => "password",
+        }
+    }
+}
+
+/// Hands answers typed into the login form to the sign-in task waiting for them
+#[derive(Default)]
+pub struct AuthPrompt {
+    pending: Mutex<Option<oneshot::Sender<Zeroizing<String>>>>,
+}
+
+impl AuthPrompt {
+    /// Show the field for `step` and wait until it is submitted or cancelled
+    pub async fn ask(
+        &self,
+        ui_handle: &slint::Weak<AppWindow>,
+        step: AuthStep,
+        hint: String,
+    ) -> Result<Zeroizing<String>> {
+        let (tx, rx) = oneshot::channel();
+        *self.pending.lock().unwrap() = Some(tx);
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            ui.set_password_hint(hint.into());
+            ui.set_auth_step(step.name().into());
+        });
+        
+        let answer = rx.await.map_err(|_| anyhow!("Sign-in cancelled"));
+        let _ = ui_handle.upgrade_in_event_loop(|ui| ui.set_auth_step("".into()));
+        answer
+    }
+    
+    pub fn answer(&self, text: Zeroizing<String>) {
+        if let Some(tx) = self.pending.lock().unwrap().take() {
+            let _ = tx.send(text);
+        }
+    }
+    
+    /// Make the waiting `ask` fail
+    pub fn cancel(&self) {
+        self.pending.lock().unwrap().take();
+    }
+}
@@ -1,95 +1,172 @@
 use anyhow::{Context, Result};
+use clap::Parser;
 use grammers_client::Client;
 use grammers_client::SignInError;
-use grammers_client::types::InputMessage;
-use grammers_session::Session;
-use serde::{Deserialize, Serialize};
+use grammers_client::types::{InputMedia, InputMessage};
+use grammers_session::PackedChat;
+use slint::Model;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use tokio::io::AsyncWriteExt;
-use tracing::{info, error};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn, error};
+use zeroize::Zeroizing;
+
+#[macro_use]
+mod i18n;
+
+mod album;
+mod api_usage;
+mod app_lock;
+mod archive;
+mod audit;
+mod autostart;
+mod backup;
+mod bot;
+mod caption;
+mod chat_import;
+mod chat_picker;
+mod chunked;
+mod cli;
+mod clipboard;
+mod collision;
+mod compression;
+mod config;
+mod content_index;
+mod connection;
+mod crash;
+mod cron;
+mod database;
+mod duplicates;
+mod encryption;
+mod errors;
+mod date_range;
+mod dedup;
+mod document_info;
+mod downscale;
+mod file_attrs;
+mod file_kind;
+mod folder;
+mod fsck;
+mod group_drive;
+mod hooks;
+mod ignore_file;
+mod instance;
+mod limits;
+mod live;
+mod logging;
+mod login;
+mod media_info;
+mod migrate;
+mod notifications;
+mod ocr;
+mod orphans;
+mod output;
+mod palette;
+mod paths;
+mod phone;
+mod photo;
+mod pipe;
+mod player;
+mod profile;
+mod read_only;
+mod report;
+mod retry;
+mod scheduler;
+mod screenshot;
+mod secrets;
+mod selection;
+mod session_crypt;
+mod sftp;
+mod snapshot;
+mod speed;
+mod stats;
+mod stream;
+mod sync;
+mod teledrive_import;
+mod thumbnails;
+mod transfer;
+mod tray;
+mod updates;
+mod verify;
+mod video_thumb;
+mod webhooks;
+
+use app_lock::AppLock;
+use audit::Action;
+use backup::BackupJob;
+use caption::CaptionMeta;
+use collision::CollisionPrompt;
+use compression::CompressionRules;
+use config::{Settings, Theme};
+use connection::{init_telegram_client, run_connection_monitor, show_state, ConnectParams, ConnectionState};
+use database::{ChatRef, Database, FileRecord, HistoryEntry, Listing, RecordKey, SortOrder, SplitPiece, TransferOutcome};
+use date_range::{DatePreset, DateRange};
+use document_info::DocumentInfo;
+use downscale::DownscaleRules;
+use file_attrs::FileAttrs;
+use hooks::{HookEvent, HookFile};
+use limits::{AccountLimits, TooLarge, STANDARD_MAX_FILE_SIZE};
+use logging::LogLevel;
+use login::{AuthPrompt, AuthStep};
+use media_info::MediaInfo;
+use notifications::{notify_transfer_finished, MIN_NOTIFY_DURATION};
+use photo::SendAs;
+use player::{AudioPlayer, RemoteReader};
+use scheduler::{parse_start_time, run_scheduler, Scheduler};
+use session_crypt::{KeySource, SessionKey};
+use stats::StorageStats;
+use sync::{Resolution, SyncEngine, SyncPair};
+use tray::{Tray, TrayAction};
+use webhooks::WebhookEvent;
+use transfer::{
+    flood_wait_seconds, is_connection_error, is_missing_parts, Cancelled, Paused, ResumeState,
+    TransferControl, TransferKind, TransferQueue, TransferState, UploadOptions,
+};
 
 // Include Slint UI
 slint::include_modules!();
 
 // Use the generated FileEntry from Slint
 use slint_generatedAppWindow::FileEntry as SlintFileEntry;
+use slint_generatedAppWindow::TransferEntry as SlintTransferEntry;
+use slint_generatedAppWindow::ScheduledEntry as SlintScheduledEntry;
+use slint_generatedAppWindow::BackupJobEntry as SlintBackupJobEntry;
+use slint_generatedAppWindow::SyncPairEntry as SlintSyncPairEntry;
+use slint_generatedAppWindow::ConflictEntry as SlintConflictEntry;
+use slint_generatedAppWindow::DuplicateEntry as SlintDuplicateEntry;
+use slint_generatedAppWindow::FolderEntry as SlintFolderEntry;
 
 // Constants
 const DB_FILE: &str = "telegram_cloud.json";
+const SESSION_FILE: &str = "telegram_cloud.session";
+/// Session used against a test DC, unless SESSION_NAME says otherwise
+const TEST_SESSION_FILE: &str = "telegram_cloud.test.session";
+const PAUSED_TRANSFERS_FILE: &str = "paused_transfers.json";
+const TRANSFER_JOURNAL_FILE: &str = "transfer_journal.json";
+const SETTINGS_FILE: &str = "settings.json";
+const SCHEDULED_UPLOADS_FILE: &str = "scheduled_uploads.json";
+const SYNC_STATE_FILE: &str = "sync_state.json";
+const TRAY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const PLAYER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often the app lock checks for idle time
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// Time for the window to disappear before the screen is captured
+const SCREENSHOT_DELAY: Duration = Duration::from_millis(300);
+/// Folder in the temp dir that files opened from the context menu are downloaded to
+const OPEN_DIR: &str = "teledrive-open";
+/// Folder in the temp dir that files being re-encrypted pass through
+const REENCRYPT_DIR: &str = "teledrive-reencrypt";
 
-/// File record structure for JSON storage
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct FileRecord {
-    filename: String,
-    file_id: String,
-    upload_date: String,
-    file_size: u64,
-}
-
-/// Database management using JSON file storage
-struct Database {
-    file_path: PathBuf,
-    records: Arc<Mutex<Vec<FileRecord>>>,
-}
-
-impl Database {
-    async fn new(db_path: &str) -> Result<Self> {
-        let file_path = PathBuf::from(db_path);
-        let records = if file_path.exists() {
-            let content = tokio::fs::read_to_string(&file_path).await?;
-            serde_json::from_str(&content).unwrap_or_default()
-        } else {
-            Vec::new()
-        };
-        
-        Ok(Self {
-            file_path,
-            records: Arc::new(Mutex::new(records)),
-        })
-    }
-    
-    async fn save(&self) -> Result<()> {
-        let records = self.records.lock().unwrap().clone();
-        let json = serde_json::to_string_pretty(&records)?;
-        
-        let mut file = tokio::fs::File::create(&self.file_path).await?;
-        file.write_all(json.as_bytes()).await?;
-        
-        Ok(())
-    }
-    
-    async fn insert_file(&self, filename: &str, file_id: &str, file_size: u64) -> Result<()> {
-        let upload_date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-        
-        let record = FileRecord {
-            filename: filename.to_string(),
-            file_id: file_id.to_string(),
-            upload_date,
-            file_size,
-        };
-        
-        self.records.lock().unwrap().push(record);
-        self.save().await?;
-        
-        Ok(())
-    }
-    
-    fn get_all_files(&self) -> Result<Vec<SlintFileEntry>> {
-        let records = self.records.lock().unwrap();
-        let mut files: Vec<SlintFileEntry> = records
-            .iter()
-            .map(|r| SlintFileEntry {
-                filename: r.filename.clone().into(),
-                file_id: r.file_id.clone().into(),
-                upload_date: r.upload_date.clone().into(),
-                size: format_size(r.file_size).into(),
-            })
-            .collect();
-        
-        files.reverse();
-        Ok(files)
-    }
+/// Update the status line from any thread
+fn set_status(ui_handle: &slint::Weak<AppWindow>, text: impl Into<String>) {
+    let text = text.into();
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_status_text(text.into());
+    });
 }
 
 /// Format file size
@@ -109,88 +186,290 @@ fn format_size(size: u64) -> String {
     }
 }
 
-/// Upload file to Telegram
+/// Result of a completed upload
+struct UploadResult {
+    file_id: String,
+    message_id: i32,
+    /// Every document sent, when the file had to be split
+    pieces: Vec<SplitPiece>,
+    /// Size of the JPEG Telegram made, when sent as a photo
+    photo_size: Option<u64>,
+}
+
+/// Resolve the Saved Messages chat
+async fn saved_messages(client: &Client) -> Result<PackedChat> {
+    api_usage::request("users.getUsers");
+    let me = client.get_me().await?;
+    Ok(me.pack())
+}
+
+/// Chat new uploads go to: the shared group drive when one is set,
+/// otherwise Saved Messages
+async fn storage_chat(client: &Client) -> Result<PackedChat> {
+    match group_drive::current() {
+        Some(drive) => drive.packed(),
+        None => saved_messages(client).await,
+    }
+}
+
+/// Chat holding the documents of `record`: the chat it was imported from
+/// or uploaded to, or Saved Messages
+async fn record_chat(client: &Client, record: Option<&FileRecord>) -> Result<PackedChat> {
+    match record.and_then(|r| r.chat.as_ref()) {
+        Some(chat) => chat.packed(),
+        None => saved_messages(client).await,
+    }
+}
+
+/// Delete every message holding `record` and drop it from the index.
+/// Files imported from another chat are only dropped from the index; their
+/// messages belong to that conversation. Files in the group drive are
+/// deleted for every member, and those the bot sent are deleted from its chat
+async fn delete_stored_file(client: &Client, db: &Database, record: &FileRecord) -> Result<()> {
+    read_only::check("deleting")?;
+    let message_id = record.message_id.context("Record has no message")?;
+    if record.chat.is_none() || group_drive::holds(record) || record.sent_by_bot {
+        let chat = record_chat(client, Some(record)).await?;
+        // Pieces other files share stay until the last of them is deleted
+        let shared = db.shared_message_ids(record);
+        let ids: Vec<i32> = record.message_ids().into_iter().filter(|id| !shared.contains(id)).collect();
+        api_usage::request("messages.deleteMessages");
+        client.delete_messages(chat, &ids).await?;
+    }
+    db.remove_file(RecordKey::new(record.chat.as_ref(), message_id)).await?;
+    audit::record(Action::Delete, record.path(), "");
+    Ok(())
+}
+
+/// Forward the messages holding `record` to `dest`; Telegram copies the
+/// document server-side, nothing is uploaded again
+async fn forward_stored_file(client: &Client, record: &FileRecord, dest: PackedChat) -> Result<()> {
+    let chat = record_chat(client, Some(record)).await?;
+    api_usage::request("messages.forwardMessages");
+    client.forward_messages(dest, &record.message_ids(), chat).await?;
+    audit::record(Action::Share, record.path(), "Forwarded to a chat");
+    Ok(())
+}
+
+/// Report transfer progress to the UI, scaled into `[0, scale]`, along with
+/// the speed and time left
+fn report_progress(ui_handle: &slint::Weak<AppWindow>, done: u64, total: u64, scale: f32) {
+    let progress = (done as f32 / total.max(1) as f32).min(1.0) * scale;
+    let speed = speed::record(done, total).describe();
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_upload_progress(progress);
+        ui.set_transfer_speed(speed.into());
+    });
+}
+
+/// Upload file to Telegram, as consecutive documents of at most `piece_size`
+/// bytes when it is larger than that, each captioned with `meta`. With
+/// `hidden_name` the documents are sent under that name with no caption
+/// instead. With content-defined pieces planned in `resume`, those found in
+/// `known` are pointed at rather than sent again. With `as_photo` the file,
+/// which then fits in one piece, is sent as a Telegram photo
 async fn upload_file_to_telegram(
     client: &Client,
     file_path: &Path,
+    meta: &CaptionMeta,
+    hidden_name: Option<&str>,
+    as_photo: bool,
+    piece_size: u64,
+    mut known: HashMap<String, SplitPiece>,
+    resume: &mut ResumeState,
+    control: &TransferControl,
     ui_handle: slint::Weak<AppWindow>,
-) -> Result<String> {
+) -> Result<UploadResult> {
     let filename = file_path
         .file_name()
         .and_then(|n| n.to_str())
         .context("Invalid filename")?;
+    let size = tokio::fs::metadata(file_path).await?.len();
+    let ranges: Vec<(u64, u64)> = if resume.chunks.is_empty() {
+        (0..size.div_ceil(piece_size).max(1))
+            .map(|i| (i * piece_size, ((i + 1) * piece_size).min(size)))
+            .collect()
+    } else {
+        resume.chunks.iter().map(|c| (c.start, c.end)).collect()
+    };
+    let piece_count = ranges.len() as u64;
     
-    info!("Starting upload for: {}", filename);
-    
-    let metadata = tokio::fs::metadata(file_path).await?;
-    let file_size = metadata.len();
+    if resume.parts_done > 0 || resume.pieces_done > 0 {
+        info!("Resuming upload for: {} from piece {} part {}", filename, resume.pieces_done, resume.parts_done);
+    } else {
+        info!("Starting upload for: {}", filename);
+    }
     
-    info!("File size: {} bytes", file_size);
+    let chat = storage_chat(client).await?;
+    let mut photo_size = None;
+    // Players need the real content under its real name, in one piece
+    let describe = piece_count == 1 && hidden_name.is_none() && !meta.encrypted && !meta.compressed;
     
-    // Update UI
-    let ui_clone = ui_handle.clone();
-    if let Some(ui) = ui_clone.upgrade() {
-        let filename_clone = filename.to_string();
-        let _result = ui.invoke_from_event_loop(move || {
-            ui.set_status_text(format!("Uploading {}...", filename_clone).into());
-            ui.set_upload_progress(0.1);
-        });
+    while (resume.pieces_done as u64) < piece_count {
+        let (start, end) = ranges[resume.pieces_done as usize];
+        let chunk_hash = resume.chunks.get(resume.pieces_done as usize).map(|c| c.sha256.clone());
+        // The first piece is always sent so the file gets a message of its own
+        let stored = chunk_hash.as_ref()
+            .filter(|_| resume.pieces_done > 0)
+            .and_then(|hash| known.get(hash));
+        if let Some(piece) = stored {
+            info!("Piece {} of {} is already stored in message {}", resume.pieces_done + 1, filename, piece.message_id);
+            resume.piece_messages.push(piece.clone());
+            resume.pieces_done += 1;
+            report_progress(&ui_handle, end, size, 0.95);
+            continue;
+        }
+        
+        let document_name = hidden_name.unwrap_or(filename);
+        let name = if piece_count > 1 {
+            set_status(&ui_handle, tr!("Uploading {name} (part {part} of {count})...", name = filename, part = resume.pieces_done + 1, count = piece_count));
+            format!("{}.part{:03}", document_name, resume.pieces_done + 1)
+        } else {
+            set_status(&ui_handle, tr!("Uploading {name}...", name = filename));
+            document_name.to_string()
+        };
+        
+        // Upload parts; the last few percent are reserved for sending the message
+        let uploaded = chunked::upload_parts(client, file_path, &name, start..end, resume, control, |done, _| {
+            report_progress(&ui_handle, start + done, size, 0.95);
+        }).await?;
+        
+        // Send to the storage chat. From here on the piece is no longer cancellable,
+        // so a late cancel never leaves an unrecorded document behind
+        info!("Sending {} to storage...", name);
+        let mut piece_meta = meta.clone();
+        if piece_count > 1 {
+            piece_meta.piece = Some((resume.pieces_done + 1, piece_count as u32));
+        }
+        let caption = if hidden_name.is_some() { String::new() } else { piece_meta.to_caption() };
+        let input_msg = InputMessage::text(caption);
+        let input_msg = if as_photo {
+            input_msg.photo(uploaded)
+        } else if describe {
+            let (info_path, info_name) = (file_path.to_path_buf(), name.clone());
+            let info = tokio::task::spawn_blocking(move || DocumentInfo::read(&info_path, &info_name)).await?;
+            let thumbnail = match &info.thumbnail {
+                Some(frame) => video_thumb::upload(client, frame).await,
+                None => None,
+            };
+            info.message(input_msg, uploaded, thumbnail)
+        } else {
+            input_msg.document(uploaded)
+        };
+        api_usage::request("messages.sendMedia");
+        let message = client.send_message(chat, input_msg).await?;
+        if as_photo {
+            photo_size = message.media().as_ref().and_then(chunked::media_size);
+        }
+        
+        let piece = SplitPiece { message_id: message.id(), size: end - start, sha256: chunk_hash };
+        if let Some(hash) = &piece.sha256 {
+            known.insert(hash.clone(), piece.clone());
+        }
+        resume.piece_messages.push(piece);
+        resume.pieces_done += 1;
+        resume.restart_piece();
     }
     
-    // Upload file
-    let uploaded = client.upload_file(file_path).await?;
+    report_progress(&ui_handle, size, size, 1.0);
     
-    // Update progress
-    let ui_clone = ui_handle.clone();
-    if let Some(ui) = ui_clone.upgrade() {
-        let _result = ui.invoke_from_event_loop(move || {
-            ui.set_upload_progress(0.8);
-        });
+    info!("Upload completed!");
+    let mut pieces = std::mem::take(&mut resume.piece_messages);
+    let message_id = pieces.first().map(|p| p.message_id).context("No document was sent")?;
+    if pieces.len() == 1 {
+        pieces.clear();
     }
+    Ok(UploadResult {
+        file_id: format!("tg_file_{}", filename),
+        message_id,
+        pieces,
+        photo_size,
+    })
+}
+
+/// Download a stored document from its chat into `dest`, joining the
+/// pieces back together when the file was split on upload
+async fn download_file_from_telegram(
+    client: &Client,
+    db: &Database,
+    key: RecordKey,
+    dest: &Path,
+    resume: &mut ResumeState,
+    control: &TransferControl,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<()> {
+    let record = db.find(key).context("The file is no longer stored")?;
+    let chat = record_chat(client, Some(&record)).await?;
+    let pieces = record.message_ids();
     
-    // Send to Saved Messages - use Chat reference
-    info!("Sending file to Saved Messages...");
-    let me = client.get_me().await?;
-    let chat = client.resolve_username("me").await?
-        .ok_or_else(|| anyhow::anyhow!("Failed to resolve self"))?;
+    // Sizes are needed up front to know where each piece starts in `dest`
+    let mut medias = Vec::with_capacity(pieces.len());
+    for id in &pieces {
+        api_usage::request("messages.getMessages");
+        let message = client.get_messages_by_id(chat, &[*id]).await?
+            .pop()
+            .flatten()
+            .with_context(|| format!("Message {} no longer exists", id))?;
+        let media = message.media().context("Message has no attached file")?;
+        let size = chunked::media_size(&media).unwrap_or(0);
+        medias.push((media, size));
+    }
+    let total: u64 = medias.iter().map(|(_, size)| size).sum();
     
-    // Create input message
-    let input_msg = InputMessage::default().document(uploaded);
-    client.send_message(chat, input_msg).await?;
+    info!(
+        "Downloading message {} to {:?} from piece {} chunk {}",
+        key.message_id, dest, resume.pieces_done, resume.parts_done
+    );
     
-    // Final progress update
-    let ui_clone = ui_handle.clone();
-    if let Some(ui) = ui_clone.upgrade() {
-        let _result = ui.invoke_from_event_loop(move || {
-            ui.set_upload_progress(1.0);
-        });
+    while (resume.pieces_done as usize) < medias.len() {
+        let (media, size) = &medias[resume.pieces_done as usize];
+        let start: u64 = medias[..resume.pieces_done as usize].iter().map(|(_, size)| size).sum();
+        
+        chunked::download_parts_parallel(client, media, dest, start, *size, resume, control, |done, _| {
+            report_progress(&ui_handle, start + done, total, 1.0);
+        }).await?;
+        
+        resume.pieces_done += 1;
+        resume.parts_done = 0;
     }
     
-    info!("Upload completed!");
-    Ok(format!("tg_file_{}", filename))
-}
-
-/// Initialize Telegram client
-async fn init_telegram_client(
-    api_id: i32,
-    api_hash: &str,
-    session_file: &str,
-) -> Result<Client> {
-    info!("Initializing Telegram client...");
+    let written = tokio::fs::metadata(dest).await?.len();
+    if total > 0 && written != total {
+        return Err(errors::IntegrityError {
+            name: record.filename.clone(),
+            expected: total,
+            actual: written,
+        }.into());
+    }
     
-    // Load session
-    let session = Session::load_file_or_create(session_file)?;
+    // Compressed files were encrypted after compressing, so undone in reverse
+    if let Some(id) = record.key_id.clone() {
+        set_status(&ui_handle, tr!("Decrypting {name}...", name = dest.file_name().unwrap_or_default().to_string_lossy()));
+        let path = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || encryption::decrypt_in_place(&path, &id)).await??;
+    }
+    if record.compressed {
+        set_status(&ui_handle, tr!("Decompressing {name}...", name = dest.file_name().unwrap_or_default().to_string_lossy()));
+        let path = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || compression::decompress_in_place(&path)).await??;
+    }
     
-    // Connect to Telegram
-    let client = Client::connect(grammers_client::Config {
-        session,
-        api_id,
-        api_hash: api_hash.to_string(),
-        params: Default::default(),
-    }).await?;
+    // Restored files keep their original timestamps instead of appearing new
+    if let Err(e) = record.attrs().apply(dest) {
+        warn!("Failed to restore attributes of {:?}: {:?}", dest, e);
+    }
     
-    info!("Client connected");
-    Ok(client)
+    info!("Download completed!");
+    Ok(())
+}
+
+/// Remove a partially downloaded file that will not be resumed
+async fn remove_partial_download(kind: &TransferKind) {
+    if let TransferKind::Download { dest, .. } = kind {
+        if let Err(e) = tokio::fs::remove_file(dest).await {
+            warn!("Failed to remove partial download {:?}: {:?}", dest, e);
+        }
+    }
 }
 
 /// Handle phone authentication
@@ -198,6 +477,8 @@ async fn authenticate_with_phone(
     client: &Client,
     phone: &str,
     api_hash: &str,
+    prompt: &AuthPrompt,
+    ui_handle: &slint::Weak<AppWindow>,
 ) -> Result<()> {
     info!("Starting authentication with phone: {}", phone);
     
@@ -207,24 +488,25 @@ async fn authenticate_with_phone(
         // Request login code
         let token = client.request_login_code(phone, api_hash).await?;
         
-        // Read code from stdin
-        println!("Enter the code you received:");
-        let mut code = String::new();
-        std::io::stdin().read_line(&mut code)?;
-        let code = code.trim();
+        set_status(ui_handle, tr!("Enter the code you received"));
+        let code = prompt.ask(ui_handle, AuthStep::Code, String::new()).await?;
         
         // Sign in
-        match client.sign_in(&token, code).await {
+        match client.sign_in(&token, code.trim()).await {
             Ok(_) => {
                 info!("Successfully signed in!");
             }
             Err(SignInError::PasswordRequired(password_token)) => {
-                println!("Enter your 2FA password:");
-                let mut password = String::new();
-                std::io::stdin().read_line(&mut password)?;
-                let password = password.trim();
-                
-                client.check_password(password_token, password).await?;
+                let hint = password_token.hint().map(|h| h.to_string()).unwrap_or_default();
+                if !hint.is_empty() {
+                    if let Err(e) = secrets::set(secrets::TWO_FA_HINT, &hint) {
+                        warn!("{:?}", e);
+                    }
+                }
+                set_status(ui_handle, tr!("Enter your two-step verification password"));
+                // Zeroized when dropped, after the check
+                let password = prompt.ask(ui_handle, AuthStep::Password, hint).await?;
+                client.check_password(password_token, password.as_bytes()).await?;
                 info!("Successfully signed in with 2FA!");
             }
             Err(e) => return Err(e.into()),
@@ -236,212 +518,4777 @@ async fn authenticate_with_phone(
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
-    
-    // Load environment variables
-    dotenv::dotenv().ok();
-    
-    let api_id = std::env::var("API_ID")
-        .context("API_ID not found in .env")?
-        .parse::<i32>()
-        .context("API_ID must be a number")?;
-    
-    let api_hash = std::env::var("API_HASH")
-        .context("API_HASH not found in .env")?;
-    
-    let session_name = std::env::var("SESSION_NAME")
-        .unwrap_or_else(|_| "telegram_cloud.session".to_string());
-    
-    info!("Starting Telegram Cloud Storage application");
-    
-    // Initialize database
-    let db = Arc::new(Database::new(DB_FILE).await?);
-    
-    // Create UI
-    let ui = AppWindow::new()?;
-    let ui_weak = ui.as_weak();
-    
-    // State management
-    let client: Arc<Mutex<Option<Client>>> = Arc::new(Mutex::new(None));
-    let selected_file: Arc<Mutex<Option<PathBuf>>> = Arc::new(Mutex::new(None));
-    
-    // Select file callback
-    {
-        let selected_file = selected_file.clone();
-        let ui_weak = ui_weak.clone();
+/// Drain the transfer queue one job at a time, pausing on FLOOD_WAIT and
+/// handing jobs back to the queue when the connection drops mid-transfer
+async fn run_transfer_worker(
+    queue: Arc<TransferQueue>,
+    client: Arc<Mutex<Option<Client>>>,
+    reconnect: Arc<Notify>,
+    db: Arc<Database>,
+    limits: Arc<AccountLimits>,
+    settings: Arc<Mutex<Settings>>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    loop {
+        // Count down any flood wait before touching Telegram again
+        while let Some(remaining) = queue.pause_remaining() {
+            let secs = remaining.as_secs_f64().ceil() as i32;
+            let _ = ui_handle.upgrade_in_event_loop(move |ui| ui.set_flood_wait_seconds(secs));
+            tokio::time::sleep(remaining.min(Duration::from_secs(1))).await;
+        }
+        let _ = ui_handle.upgrade_in_event_loop(|ui| ui.set_flood_wait_seconds(0));
         
-        ui.on_select_file(move || {
-            let file = rfd::FileDialog::new().pick_file();
-            
-            if let Some(path) = file {
-                let filename = path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("Unknown")
-                    .to_string();
-                
-                *selected_file.lock().unwrap() = Some(path);
-                
-                let ui_clone = ui_weak.clone();
-                if let Some(ui) = ui_clone.upgrade() {
-                    ui.set_selected_file(filename.as_str().into());
-                    ui.set_status_text("File selected. Ready to upload.".into());
-                }
-            }
-        });
-    }
-    
-    // Authenticate callback
-    {
-        let client = client.clone();
-        let ui_weak = ui_weak.clone();
+        let mut job = queue.next().await;
         
-        ui.on_authenticate(move |phone| {
-            let phone = phone.to_string();
-            let client = client.clone();
-            let ui_weak = ui_weak.clone();
-            let api_id = api_id;
-            let api_hash = api_hash.clone();
-            let session_name = session_name.clone();
-            
-            tokio::spawn(async move {
-                let ui_clone = ui_weak.clone();
-                if let Some(ui) = ui_clone.upgrade() {
-                    ui.set_status_text("Connecting to Telegram...".into());
-                }
-                
-                match init_telegram_client(api_id, &api_hash, &session_name).await {
-                    Ok(tg_client) => {
-                        match authenticate_with_phone(&tg_client, &phone, &api_hash).await {
-                            Ok(_) => {
-                                *client.lock().unwrap() = Some(tg_client);
-                                
-                                let ui_clone = ui_weak.clone();
-                                if let Some(ui) = ui_clone.upgrade() {
-                                    ui.set_is_authenticated(true);
-                                    ui.set_status_text("Successfully authenticated!".into());
-                                }
-                            }
-                            Err(e) => {
-                                error!("Authentication failed: {:?}", e);
-                                let ui_clone = ui_weak.clone();
-                                if let Some(ui) = ui_clone.upgrade() {
-                                    ui.set_status_text(format!("Auth failed: {}", e).into());
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to connect: {:?}", e);
-                        let ui_clone = ui_weak.clone();
-                        if let Some(ui) = ui_clone.upgrade() {
-                            ui.set_status_text(format!("Connection failed: {}", e).into());
-                        }
-                    }
-                }
-            });
+        // Clone client outside the lock to avoid holding it across await
+        let tg_client = client.lock().unwrap().clone();
+        let Some(tg_client) = tg_client else {
+            // Lost the client between going online and picking the job; wait for reconnect
+            queue.requeue_front(job);
+            queue.set_online(false);
+            continue;
+        };
+        
+        let control = queue.start(&job);
+        speed::start();
+        let started = Instant::now();
+        let started_at = chrono::Local::now();
+        update_transfer_list(&ui_handle, &queue);
+        let _ = ui_handle.upgrade_in_event_loop(|ui| {
+            ui.set_is_uploading(true);
+            ui.set_upload_progress(0.0);
+            ui.set_transfer_speed("".into());
         });
-    }
-    
-    // Upload file callback
-    {
-        let selected_file = selected_file.clone();
-        let client = client.clone();
-        let db = db.clone();
-        let ui_weak = ui_weak.clone();
         
-        ui.on_upload_file(move || {
-            let file_path = selected_file.lock().unwrap().clone();
-            
-            if let Some(path) = file_path {
-                let client = client.clone();
-                let db = db.clone();
-                let ui_weak = ui_weak.clone();
+        let result = match &job.kind {
+            TransferKind::Upload { path, options } => {
+                set_status(&ui_handle, tr!("Starting upload..."));
+                let settings = settings.lock().unwrap().clone();
+                run_upload(
+                    &tg_client,
+                    &db,
+                    &limits,
+                    &settings,
+                    path,
+                    options,
+                    &mut job.resume,
+                    &control,
+                    ui_handle.clone(),
+                ).await
+            }
+            TransferKind::Album { paths, options } => {
+                set_status(&ui_handle, tr!("Starting upload..."));
+                let settings = settings.lock().unwrap().clone();
+                run_album_upload(
+                    &tg_client,
+                    &db,
+                    &limits,
+                    &settings,
+                    paths,
+                    options,
+                    &control,
+                    ui_handle.clone(),
+                ).await
+            }
+            TransferKind::Download { message_id, chat, filename, dest, open_after, .. } => {
+                set_status(&ui_handle, tr!("Downloading {name}...", name = filename));
+                let key = RecordKey { chat: *chat, message_id: *message_id };
+                let result = download_file_from_telegram(
+                    &tg_client,
+                    &db,
+                    key,
+                    dest,
+                    &mut job.resume,
+                    &control,
+                    ui_handle.clone(),
+                ).await;
                 
-                tokio::spawn(async move {
-                    let ui_clone = ui_weak.clone();
-                    if let Some(ui) = ui_clone.upgrade() {
-                        ui.set_is_uploading(true);
-                        ui.set_upload_progress(0.0);
-                        ui.set_status_text("Starting upload...".into());
+                if result.is_ok() {
+                    let subject = db.find(key).map_or(filename.clone(), |r| r.path());
+                    if *open_after {
+                        audit::record(Action::Open, subject, "");
+                    } else {
+                        audit::record(Action::Download, subject, dest.to_string_lossy());
                     }
-                    
-                    // Clone client outside the lock to avoid holding it across await
-                    let tg_client = {
-                        let client_guard = client.lock().unwrap();
-                        client_guard.clone()
+                    if let Err(e) = db.mark_used(key, *open_after).await {
+                        warn!("Failed to note the use of {}: {:?}", filename, e);
+                    }
+                    refresh_file_list(&ui_handle, &db);
+                    let command = settings.lock().unwrap().hooks.post_download.clone();
+                    let file = match db.find(key) {
+                        Some(record) => HookFile::stored(dest, &record),
+                        None => HookFile { path: dest.clone(), name: filename.clone(), message_id: Some(*message_id), ..Default::default() },
                     };
-                    
-                    if let Some(tg_client) = tg_client {
-                        let file_size = tokio::fs::metadata(&path).await
-                            .map(|m| m.len())
-                            .unwrap_or(0);
-                        
-                        match upload_file_to_telegram(&tg_client, &path, ui_weak.clone()).await {
-                            Ok(file_id) => {
-                                let filename = path.file_name()
-                                    .and_then(|n| n.to_str())
-                                    .unwrap_or("Unknown");
-                                
-                                if let Err(e) = db.insert_file(filename, &file_id, file_size).await {
-                                    error!("Failed to save to database: {:?}", e);
-                                }
-                                
-                                let ui_clone = ui_weak.clone();
-                                if let Some(ui) = ui_clone.upgrade() {
-                                    ui.set_status_text("Upload successful!".into());
-                                    ui.set_selected_file("No file selected".into());
-                                }
-                            }
-                            Err(e) => {
-                                error!("Upload failed: {:?}", e);
-                                let ui_clone = ui_weak.clone();
-                                if let Some(ui) = ui_clone.upgrade() {
-                                    ui.set_status_text(format!("Upload failed: {}", e).into());
-                                }
-                            }
-                        }
+                    if let Err(e) = hooks::run(&command, HookEvent::PostDownload, &file).await {
+                        warn!("{:#}", e);
+                        set_status(&ui_handle, format!("{:#}", e));
                     }
+                    let webhook = settings.lock().unwrap().webhook_url.clone();
+                    webhooks::notify(&webhook, WebhookEvent::DownloadComplete { file });
                     
-                    let ui_clone = ui_weak.clone();
-                    if let Some(ui) = ui_clone.upgrade() {
-                        ui.set_is_uploading(false);
-                        ui.set_upload_progress(0.0);
+                    if *open_after {
+                        if let Err(e) = open::that(dest) {
+                            warn!("Failed to open {:?}: {:?}", dest, e);
+                        }
+                    } else {
+                        let last = dest.to_string_lossy().to_string();
+                        let _ = ui_handle.upgrade_in_event_loop(move |ui| ui.set_last_download(last.into()));
                     }
-                });
+                }
+                result.map(|_| tr!("Downloaded {name}", name = filename))
             }
-        });
-    }
-    
-    // Refresh files callback
-    {
-        let db = db.clone();
-        let ui_weak = ui_weak.clone();
+            TransferKind::Reencrypt { message_id, chat, filename } => {
+                set_status(&ui_handle, tr!("Re-encrypting {name}...", name = filename));
+                let settings = settings.lock().unwrap().clone();
+                run_reencrypt(
+                    &tg_client,
+                    &db,
+                    &limits,
+                    &settings,
+                    RecordKey { chat: *chat, message_id: *message_id },
+                    &mut job.resume,
+                    &control,
+                    ui_handle.clone(),
+                ).await
+            }
+        };
+        queue.finish(job.id);
         
-        ui.on_refresh_files(move || {
-            let db = db.clone();
-            let ui_weak = ui_weak.clone();
+        // Failures that may go away on their own are retried before they count
+        let retry_delay = match &result {
+            Err(e) if retry::is_transient(e) && !is_missing_parts(e) && !is_connection_error(e) => {
+                settings.lock().unwrap().retry.delay(job.attempts)
+            }
+            _ => None,
+        };
+        
+        // Ended for good (as opposed to paused or requeued): keep it in the history
+        let outcome = match &result {
+            Ok(_) => Some((TransferOutcome::Completed, None)),
+            Err(e) if e.is::<Cancelled>() => Some((TransferOutcome::Cancelled, None)),
+            Err(e) if e.is::<Paused>()
+                || is_missing_parts(e)
+                || flood_wait_seconds(e).is_some()
+                || is_connection_error(e)
+                || retry_delay.is_some() => None,
+            Err(e) => Some((TransferOutcome::Failed, Some(format!("{:#}", e)))),
+        };
+        if let Some((outcome, error)) = outcome {
+            let file_size = transfer_size(&job.kind, &db).await;
+            if let Some(batch) = job.kind.batch() {
+                queue.batch_files_done(batch, job.kind.file_count(), file_size);
+            }
             
-            tokio::spawn(async move {
-                match db.get_all_files() {
-                    Ok(files) => {
-                        let ui_clone = ui_weak.clone();
-                        if let Some(ui) = ui_clone.upgrade() {
-                            let files_rc = std::rc::Rc::new(slint::VecModel::from(files));
-                            ui.set_uploaded_files(files_rc.into());
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to load files: {:?}", e);
+            let entry = HistoryEntry {
+                filename: job.kind.display_name(),
+                direction: if job.kind.is_upload() { "Upload" } else { "Download" }.to_string(),
+                outcome,
+                started_at: started_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                finished_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                duration_secs: started.elapsed().as_secs(),
+                file_size,
+                error,
+                // Files opened from the context menu live in a temporary folder
+                path: match &job.kind {
+                    TransferKind::Download { dest, open_after: false, .. } if outcome == TransferOutcome::Completed => Some(dest.clone()),
+                    _ => None,
+                },
+            };
+            // Users tend to minimize the window during long transfers
+            if started.elapsed() >= MIN_NOTIFY_DURATION && outcome != TransferOutcome::Cancelled {
+                notify_transfer_finished(&entry);
+            }
+            if let Err(e) = db.add_history(entry).await {
+                error!("Failed to save transfer history: {:?}", e);
+            }
+            refresh_history(&ui_handle, &db);
+        }
+        
+        match result {
+            Ok(message) => set_status(&ui_handle, message),
+            Err(e) => {
+                if e.is::<Cancelled>() {
+                    info!("Cancelled {:?}", job.kind);
+                    remove_partial_download(&job.kind).await;
+                    set_status(&ui_handle, tr!("Cancelled {name}", name = job.kind.display_name()));
+                } else if e.is::<Paused>() {
+                    info!("Paused {:?} after {} part(s)", job.kind, job.resume.parts_done);
+                    set_status(&ui_handle, tr!("Paused {name}", name = job.kind.display_name()));
+                    queue.park(job);
+                } else if is_missing_parts(&e) {
+                    warn!("Telegram discarded the uploaded parts of {:?}; restarting", job.kind);
+                    job.resume.restart_piece();
+                    queue.requeue_front(job);
+                } else if let Some(secs) = flood_wait_seconds(&e) {
+                    warn!("FLOOD_WAIT of {}s during {:?}; pausing queue", secs, job.kind);
+                    api_usage::flood_wait(secs);
+                    queue.pause_for(Duration::from_secs(secs.into()));
+                    queue.requeue_front(job);
+                    set_status(&ui_handle, tr!("Rate limited by Telegram, resuming in {secs}s", secs = secs));
+                } else if is_connection_error(&e) {
+                    warn!("Connection dropped during {:?}: {:?}", job.kind, e);
+                    queue.requeue_front(job);
+                    queue.set_online(false);
+                    show_state(&ui_handle, ConnectionState::Reconnecting);
+                    reconnect.notify_one();
+                    set_status(&ui_handle, tr!("Connection lost, transfer will resume after reconnecting"));
+                } else if let Some(delay) = retry_delay {
+                    warn!("{:?} failed, retrying in {:?}: {:?}", job.kind, delay, e);
+                    // A damaged download starts over rather than resuming
+                    if errors::classify(&e) == errors::ErrorKind::Integrity {
+                        remove_partial_download(&job.kind).await;
+                        job.resume = ResumeState::default();
                     }
+                    set_status(&ui_handle, tr!(
+                        "{name} failed, retrying in {secs}s (retry {attempt} of {max})",
+                        name = job.kind.display_name(),
+                        secs = delay.as_secs().max(1),
+                        attempt = job.attempts + 1,
+                        max = settings.lock().unwrap().retry.max_retries,
+                    ));
+                    queue.retry_later(job, delay);
+                } else {
+                    error!("Transfer failed: {:?}", e);
+                    remove_partial_download(&job.kind).await;
+                    let error = errors::describe(&e);
+                    set_status(&ui_handle, match job.attempts {
+                        0 => tr!("Transfer failed: {error}", error = error),
+                        attempts => tr!("Transfer failed after {count} retries: {error}", count = attempts, error = error),
+                    });
                 }
-            });
+            }
+        }
+        
+        update_transfer_list(&ui_handle, &queue);
+        let _ = ui_handle.upgrade_in_event_loop(|ui| {
+            ui.set_is_uploading(false);
+            ui.set_upload_progress(0.0);
         });
     }
+}
+
+/// Size of the file a job moves, for the history panel
+async fn transfer_size(kind: &TransferKind, db: &Database) -> u64 {
+    match kind {
+        TransferKind::Upload { path, .. } => tokio::fs::metadata(path).await
+            .map(|m| m.len())
+            .unwrap_or(0),
+        TransferKind::Album { paths, .. } => {
+            let mut total = 0;
+            for path in paths {
+                total += tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+            }
+            total
+        }
+        TransferKind::Download { .. }
+        | TransferKind::Reencrypt { .. } => kind.record_key()
+            .and_then(|key| db.find(key))
+            .map_or(0, |r| r.file_size),
+    }
+}
+
+/// A file ready to be sent: checked, hashed, and compressed or encrypted as
+/// the settings say
+struct PreparedUpload {
+    filename: String,
+    file_size: u64,
+    /// Largest piece the file may be sent in
+    piece_size: u64,
+    sha256: String,
+    attrs: FileAttrs,
+    meta: CaptionMeta,
+    /// How to cut the file into content-defined pieces, if at all
+    chunking: Option<dedup::Chunking>,
+    /// Whether to send the image as a Telegram photo
+    as_photo: bool,
+    /// Smaller copy of the image, sent in place of the original
+    downscaled_copy: Option<PathBuf>,
+    compressed_copy: Option<PathBuf>,
+    /// Encrypted copy and the id of its key
+    encrypted_copy: Option<(PathBuf, String)>,
+}
+
+impl PreparedUpload {
+    /// What is actually sent for the original at `path`
+    fn upload_path<'a>(&'a self, path: &'a Path) -> &'a Path {
+        self.encrypted_copy.as_ref()
+            .map(|(copy, _)| copy.as_path())
+            .or(self.compressed_copy.as_deref())
+            .or(self.downscaled_copy.as_deref())
+            .unwrap_or(path)
+    }
     
-    ui.run()?;
+    fn remove_copies(&self) {
+        if let Some((copy, _)) = &self.encrypted_copy {
+            encryption::remove(copy);
+        }
+        if let Some(copy) = &self.compressed_copy {
+            compression::remove(copy);
+        }
+        if let Some(copy) = &self.downscaled_copy {
+            downscale::remove(copy);
+        }
+    }
+}
+
+/// Upload one file and record it, returning the status message
+async fn run_upload(
+    client: &Client,
+    db: &Arc<Database>,
+    limits: &AccountLimits,
+    settings: &Settings,
+    path: &Path,
+    options: &UploadOptions,
+    resume: &mut ResumeState,
+    control: &TransferControl,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<String> {
+    let prepared = prepare_upload(client, limits, settings, path, options, resume, ui_handle.clone()).await?;
+    // Before the resized copy, which may delete the original when it is done
+    if prepared.downscaled_copy.is_some() && resume.parts_done == 0 && resume.pieces_done == 0 {
+        if let Err(e) = keep_original(client, db, limits, settings, path, options, control, ui_handle.clone()).await {
+            prepared.remove_copies();
+            return Err(e);
+        }
+    }
+    
+    // Forwards keep the original's name and caption, which hidden names must not reveal
+    if let (0, false, Some(stored)) = (resume.pieces_done, settings.hide_names, stored_elsewhere(db, &prepared)) {
+        set_status(&ui_handle, tr!("Copying {name} from where it is already stored...", name = prepared.filename));
+        match forward_to_storage(client, &stored).await {
+            Ok(uploaded) => {
+                info!("Forwarded {} from message {} instead of uploading it again", prepared.filename, uploaded.message_id);
+                return record_upload(client, db, settings, path, options, prepared, uploaded, None, ui_handle).await;
+            }
+            Err(e) => warn!("Failed to forward {}; uploading it instead: {:?}", stored.path(), e),
+        }
+    }
+    
+    if let Some(chunking) = prepared.chunking.filter(|_| resume.chunks.is_empty() && resume.pieces_done == 0) {
+        set_status(&ui_handle, tr!("Looking for data {name} shares with stored files...", name = prepared.filename));
+        let chunk_path = path.to_path_buf();
+        let max_chunk = prepared.piece_size;
+        resume.chunks = tokio::task::spawn_blocking(move || dedup::split(&chunk_path, chunking, max_chunk)).await??;
+    }
+    let known = if resume.chunks.is_empty() {
+        HashMap::new()
+    } else {
+        dedup::known_pieces(&db.all_records(), group_drive::current().as_ref())
+    };
+    
+    // With names hidden Telegram sees a random name and no caption; the real
+    // name and metadata are only in the local index
+    let hidden_name = settings.hide_names.then(|| format!("{:032x}", rand::random::<u128>()));
+    let uploaded = upload_file_to_telegram(
+        client,
+        prepared.upload_path(path),
+        &prepared.meta,
+        hidden_name.as_deref(),
+        prepared.as_photo,
+        prepared.piece_size,
+        known,
+        resume,
+        control,
+        ui_handle.clone(),
+    ).await?;
+    record_upload(client, db, settings, path, options, prepared, uploaded, None, ui_handle).await
+}
+
+/// Upload the full-size original of an image that is sent resized into
+/// the originals folder next to it, when the settings keep originals
+async fn keep_original(
+    client: &Client,
+    db: &Arc<Database>,
+    limits: &AccountLimits,
+    settings: &Settings,
+    path: &Path,
+    options: &UploadOptions,
+    control: &TransferControl,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<()> {
+    if !settings.downscale.keep_originals {
+        return Ok(());
+    }
+    let options = UploadOptions {
+        folder: folder::normalize(&format!("{}/{}", options.folder, downscale::ORIGINALS_FOLDER)),
+        downscale: Some(false),
+        delete_after: false,
+        copy_reference: false,
+        batch: None,
+        replaces: None,
+        previous: None,
+        ..options.clone()
+    };
+    // Boxed, as it is run_upload calling itself
+    Box::pin(run_upload(client, db, limits, settings, path, &options, &mut ResumeState::default(), control, ui_handle)).await?;
+    Ok(())
+}
+
+/// A healthy record of the same content as `prepared`, stored the same way
+/// (compressed, encrypted with the same key), in a chat other than the one
+/// uploads go to now
+fn stored_elsewhere(db: &Database, prepared: &PreparedUpload) -> Option<FileRecord> {
+    if prepared.as_photo {
+        return None;
+    }
+    let target = group_drive::current().map(|drive| drive.id);
+    db.all_records().into_iter().find(|r| {
+        r.sha256.as_deref() == Some(prepared.sha256.as_str())
+            && r.chat.as_ref().map(|c| c.id) != target
+            && !r.photo
+            && !r.missing
+            && !r.corrupt
+            && r.compressed == prepared.meta.compressed
+            && r.key_id == prepared.meta.key_id
+    })
+}
+
+/// Put the documents of `stored` in the storage chat by forwarding them:
+/// Telegram copies them server-side, so none of the bytes are sent again.
+/// The forwards keep the caption written for the original
+async fn forward_to_storage(client: &Client, stored: &FileRecord) -> Result<UploadResult> {
+    let source = record_chat(client, Some(stored)).await?;
+    let dest = storage_chat(client).await?;
+    api_usage::request("messages.forwardMessages");
+    let forwarded = client.forward_messages(dest, &stored.message_ids(), source).await?;
+    let ids: Vec<i32> = forwarded.iter()
+        .map(|m| m.as_ref().map(|m| m.id()))
+        .collect::<Option<_>>()
+        .context("Telegram did not return every forwarded message")?;
+    let pieces = stored.split_pieces.iter()
+        .zip(&ids)
+        .map(|(piece, id)| SplitPiece { message_id: *id, ..piece.clone() })
+        .collect();
+    Ok(UploadResult {
+        file_id: stored.file_id.clone(),
+        message_id: *ids.first().context("Nothing was forwarded")?,
+        pieces,
+        photo_size: None,
+    })
+}
+
+/// Upload photos and videos as one album, so they stay together in the
+/// storage chat, and record each of them. Every file goes whole, and an
+/// interrupted album starts over
+async fn run_album_upload(
+    client: &Client,
+    db: &Arc<Database>,
+    limits: &AccountLimits,
+    settings: &Settings,
+    paths: &[PathBuf],
+    options: &UploadOptions,
+    control: &TransferControl,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<String> {
+    let chat = storage_chat(client).await?;
+    // Photos and documents can't share an album, so it only goes as photos
+    // when every file can
+    let mut options = options.clone();
+    if !paths.iter().all(|p| sends_as_photo(settings, &options, p, std::fs::metadata(p).map_or(u64::MAX, |m| m.len()))) {
+        options.send_images_as = Some(SendAs::Document);
+    }
+    let options = &options;
+    let mut prepared = Vec::with_capacity(paths.len());
+    let sent = async {
+        let mut media = Vec::with_capacity(paths.len());
+        let total: u64 = paths.iter().map(|p| std::fs::metadata(p).map_or(0, |m| m.len())).sum();
+        let mut done = 0;
+        for path in paths {
+            let file = prepare_upload(client, limits, settings, path, options, &mut ResumeState::default(), ui_handle.clone()).await?;
+            let keep = if file.downscaled_copy.is_some() {
+                keep_original(client, db, limits, settings, path, options, control, ui_handle.clone()).await
+            } else {
+                Ok(())
+            };
+            let upload_path = file.upload_path(path).to_path_buf();
+            let too_large = file.file_size > file.piece_size;
+            let (caption, filename, as_photo) = (file.meta.to_caption(), file.filename.clone(), file.as_photo);
+            let plain = !file.meta.encrypted && !file.meta.compressed;
+            prepared.push(file);
+            keep?;
+            if too_large {
+                anyhow::bail!("{} is too large to go in an album", filename);
+            }
+            
+            set_status(&ui_handle, tr!("Uploading {name}...", name = filename));
+            let hidden_name = settings.hide_names.then(|| format!("{:032x}", rand::random::<u128>()));
+            let size = tokio::fs::metadata(&upload_path).await?.len();
+            let uploaded = chunked::upload_parts(
+                client,
+                &upload_path,
+                hidden_name.as_deref().unwrap_or(&filename),
+                0..size,
+                &mut ResumeState::default(),
+                control,
+                |sent, _| report_progress(&ui_handle, done + sent, total, 0.95),
+            ).await?;
+            done += size;
+            let caption = if hidden_name.is_some() { String::new() } else { caption };
+            let item = InputMedia::new().caption(caption);
+            media.push(if as_photo {
+                item.photo(uploaded)
+            } else if plain && hidden_name.is_none() {
+                let (info_path, info_name) = (upload_path.clone(), filename.clone());
+                let info = tokio::task::spawn_blocking(move || DocumentInfo::read(&info_path, &info_name)).await?;
+                let thumbnail = match &info.thumbnail {
+                    Some(frame) => video_thumb::upload(client, frame).await,
+                    None => None,
+                };
+                info.media(item, uploaded, thumbnail)
+            } else {
+                item.document(uploaded)
+            });
+        }
+        
+        info!("Sending an album of {} files to storage...", media.len());
+        api_usage::request("messages.sendMultiMedia");
+        Ok::<_, anyhow::Error>(client.send_album(chat, media).await?)
+    }.await;
+    let messages = match sent {
+        Ok(messages) => messages,
+        Err(e) => {
+            for file in &prepared {
+                file.remove_copies();
+            }
+            return Err(e);
+        }
+    };
+    report_progress(&ui_handle, 1, 1, 1.0);
+    
+    let album = messages.iter().flatten().find_map(|m| m.grouped_id());
+    let mut recorded = 0;
+    for ((path, file), message) in paths.iter().zip(prepared).zip(messages) {
+        let Some(message) = message else {
+            warn!("Telegram returned no message for {} in the album", file.filename);
+            file.remove_copies();
+            continue;
+        };
+        let uploaded = UploadResult {
+            file_id: format!("tg_file_{}", file.filename),
+            message_id: message.id(),
+            pieces: Vec::new(),
+            photo_size: message.media().as_ref().filter(|_| file.as_photo).and_then(chunked::media_size),
+        };
+        record_upload(client, db, settings, path, options, file, uploaded, album, ui_handle.clone()).await?;
+        recorded += 1;
+    }
+    Ok(tr!("Uploaded an album of {count} files", count = recorded))
+}
+
+/// Whether the image at `path` of `size` bytes goes as a Telegram photo: it
+/// was asked for, uploads aren't encrypted (a photo can't be), and Telegram
+/// takes it as one
+fn sends_as_photo(settings: &Settings, options: &UploadOptions, path: &Path, size: u64) -> bool {
+    options.send_images_as.unwrap_or(settings.send_images_as) == SendAs::Photo
+        && !settings.encrypt_uploads
+        && photo::is_eligible(path, size)
+}
+
+/// Check `path` against the limits and the pre-upload hook, hash it, and
+/// make the resized, compressed or encrypted copy to send. A resumed upload keeps
+/// the choices it started with
+async fn prepare_upload(
+    client: &Client,
+    limits: &AccountLimits,
+    settings: &Settings,
+    path: &Path,
+    options: &UploadOptions,
+    resume: &mut ResumeState,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<PreparedUpload> {
+    read_only::check("uploading")?;
+    let file_size = tokio::fs::metadata(path).await
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let filename = path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("Unknown");
+    
+    // Check the size before sending anything rather than failing mid-transfer.
+    // Premium only matters once a file is over the standard limit
+    if file_size > STANDARD_MAX_FILE_SIZE {
+        if let Err(e) = limits.detect(client).await {
+            warn!("Failed to check for Telegram Premium: {:?}", e);
+        }
+    }
+    // A resumed split upload keeps the piece size it started with
+    let piece_size = resume.piece_messages.first()
+        .filter(|_| resume.chunks.is_empty())
+        .map(|p| p.size)
+        .unwrap_or_else(|| limits.max_file_size());
+    if file_size > piece_size {
+        if settings.reject_oversized {
+            return Err(TooLarge {
+                name: filename.to_string(),
+                size: file_size,
+                limit: limits.max_file_size(),
+            }.into());
+        }
+        info!("{} exceeds the {} limit; splitting into {} parts", filename, format_size(piece_size), file_size.div_ceil(piece_size));
+    }
+    
+    // A failing pre-upload hook (a virus scanner, say) stops the upload
+    let hook_file = HookFile {
+        path: path.to_path_buf(),
+        name: filename.to_string(),
+        size: file_size,
+        folder: options.folder.clone(),
+        ..Default::default()
+    };
+    hooks::run(&settings.hooks.pre_upload, HookEvent::PreUpload, &hook_file).await?;
+    
+    let attrs = FileAttrs::read(path);
+    set_status(&ui_handle, tr!("Hashing {name}...", name = filename));
+    let hash_path = path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || caption::hash_file(&hash_path)).await??;
+    
+    // Telegram recompresses photos itself, so they go as they are, whole
+    let as_photo = sends_as_photo(settings, options, path, file_size)
+        && !resume.compressed
+        && !resume.downscaled
+        && resume.key_id.is_none();
+    
+    // Large images may go as a smaller copy, which stands in for the file
+    // from here on. A resumed upload sticks with its first choice
+    let started = resume.parts_done > 0 || resume.pieces_done > 0;
+    let resize = if started {
+        resume.downscaled
+    } else {
+        !as_photo && options.downscale.unwrap_or(settings.downscale.enabled) && downscale::is_candidate(path)
+    };
+    let downscaled_copy = if resize {
+        set_status(&ui_handle, tr!("Resizing {name}...", name = filename));
+        let (source, hash, rules) = (path.to_path_buf(), sha256.clone(), settings.downscale.clone());
+        tokio::task::spawn_blocking(move || downscale::downscale(&source, &hash, &rules)).await??
+    } else {
+        None
+    };
+    if started && resume.downscaled && downscaled_copy.is_none() {
+        anyhow::bail!("{} no longer resizes as before; upload it again", filename);
+    }
+    resume.downscaled = downscaled_copy.is_some();
+    let (file_size, sha256) = match &downscaled_copy {
+        Some(copy) => {
+            let hash_path = copy.clone();
+            let hash = tokio::task::spawn_blocking(move || caption::hash_file(&hash_path)).await??;
+            (tokio::fs::metadata(copy).await?.len(), hash)
+        }
+        None => (file_size, sha256),
+    };
+    let source = downscaled_copy.as_deref().unwrap_or(path);
+    
+    // Content-defined pieces let files with data in common share what is
+    // stored, and a new version of a file reuse the pieces that didn't change;
+    // the latter are cut smaller, so a small change sends little. The group
+    // drive scan could not put such files back together, and encrypted
+    // pieces never match
+    let chunking = if settings.encrypt_uploads || as_photo || downscaled_copy.is_some() {
+        None
+    } else if options.delta && group_drive::current().is_none() && file_size > dedup::DELTA_MIN_FILE_SIZE {
+        Some(dedup::Chunking::DELTA)
+    } else if settings.dedup_chunks && file_size > dedup::MIN_FILE_SIZE {
+        Some(dedup::Chunking::SHARED)
+    } else {
+        None
+    };
+    
+    // Compressible files are sent as zstd, unless deduplicated: compressed
+    // bytes hardly ever match. A resumed upload sticks with its first choice
+    let compress = if started {
+        resume.compressed
+    } else {
+        chunking.is_none() && !as_photo && settings.compression.applies_to(source, file_size)
+    };
+    let compressed_copy = if compress {
+        set_status(&ui_handle, tr!("Compressing {name}...", name = filename));
+        let (source, hash, level) = (source.to_path_buf(), sha256.clone(), settings.compression.level);
+        tokio::task::spawn_blocking(move || compression::compress(&source, &hash, level)).await??
+    } else {
+        None
+    };
+    if started && resume.compressed && compressed_copy.is_none() {
+        anyhow::bail!("{} no longer compresses as before; upload it again", filename);
+    }
+    resume.compressed = compressed_copy.is_some();
+    let upload_path = compressed_copy.as_deref().unwrap_or(source);
+    
+    // Encrypted after compressing, which would find nothing to squeeze in
+    // ciphertext. The copy is random each time, so a resumed upload needs
+    // the very copy it started sending
+    let encrypted_copy = match (started, resume.key_id.clone()) {
+        (true, Some(id)) => {
+            let copy = encryption::copy_path(upload_path, &sha256, &id)?;
+            if !copy.exists() {
+                anyhow::bail!("The encrypted copy of {} is gone; upload it again", filename);
+            }
+            Some((copy, id))
+        }
+        (false, _) if settings.encrypt_uploads => {
+            set_status(&ui_handle, tr!("Encrypting {name}...", name = filename));
+            let (source, hash) = (upload_path.to_path_buf(), sha256.clone());
+            Some(tokio::task::spawn_blocking(move || encryption::encrypt(&source, &hash)).await??)
+        }
+        _ => None,
+    };
+    resume.key_id = encrypted_copy.as_ref().map(|(_, id)| id.clone());
+    
+    let meta = CaptionMeta {
+        version: CaptionMeta::VERSION,
+        path: path.to_string_lossy().into_owned(),
+        folder: options.folder.clone(),
+        size: file_size,
+        sha256: sha256.clone(),
+        mtime: attrs.mtime,
+        mode: attrs.mode,
+        tags: options.tags.clone(),
+        encrypted: resume.key_id.is_some(),
+        key_id: resume.key_id.clone(),
+        compressed: resume.compressed,
+        piece: None,
+        photo: as_photo,
+        downscaled: resume.downscaled,
+    };
+    
+    Ok(PreparedUpload {
+        filename: filename.to_string(),
+        file_size,
+        piece_size,
+        sha256,
+        attrs,
+        meta,
+        chunking,
+        as_photo,
+        downscaled_copy,
+        compressed_copy,
+        encrypted_copy,
+    })
+}
+
+/// Record a file that was just sent, with `album` the group of messages it
+/// was sent in, then run what follows an upload: indexing, hooks, deleting
+/// the original when asked. Fails before deleting anything when the record
+/// can't be saved. Returns the status message
+async fn record_upload(
+    client: &Client,
+    db: &Arc<Database>,
+    settings: &Settings,
+    path: &Path,
+    options: &UploadOptions,
+    prepared: PreparedUpload,
+    uploaded: UploadResult,
+    album: Option<i64>,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<String> {
+    // Taken before the copy is deleted; the rest of what the image tells is
+    // read from the original, which keeps its EXIF data
+    let downscaled_to = prepared.downscaled_copy.as_deref().and_then(|copy| image::image_dimensions(copy).ok());
+    prepared.remove_copies();
+    let filename = prepared.filename.as_str();
+    let replaced = options.replaces.and_then(|key| db.find(key));
+    
+    let mut record = FileRecord::new(filename, &uploaded.file_id, prepared.file_size, uploaded.message_id);
+    record.folder = options.folder.clone();
+    record.archive_members = options.archive_members.clone();
+    record.split_pieces = uploaded.pieces;
+    record.sha256 = Some(prepared.sha256.clone());
+    record.tags = options.tags.clone();
+    record.mtime = prepared.attrs.mtime;
+    record.mode = prepared.attrs.mode;
+    record.chat = group_drive::current();
+    record.compressed = prepared.compressed_copy.is_some();
+    record.downscaled = prepared.downscaled_copy.is_some();
+    record.key_id = prepared.encrypted_copy.as_ref().map(|(_, id)| id.clone());
+    record.album = album;
+    record.previous = options.previous;
+    // What is stored is Telegram's JPEG, so the original's hash no longer fits
+    if let Some(size) = uploaded.photo_size {
+        record.filename = photo::stored_name(filename);
+        record.file_size = size;
+        record.sha256 = None;
+        record.photo = true;
+    }
+    if let Some(old) = &replaced {
+        record.upload_date = old.upload_date;
+    }
+    
+    let media_path = path.to_path_buf();
+    record.media = tokio::task::spawn_blocking(move || MediaInfo::read(&media_path)).await?;
+    if let (Some(media), Some((width, height))) = (record.media.as_mut(), downscaled_to) {
+        media.width = Some(width);
+        media.height = Some(height);
+    }
+    
+    if settings.ocr_images && ocr::is_candidate(filename) {
+        set_status(&ui_handle, tr!("Recognizing text in {name}...", name = filename));
+        let image_path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || ocr::recognize(&image_path)).await? {
+            Ok(text) => record.ocr_text = text,
+            Err(e) => warn!("OCR failed for {}: {:?}", filename, e),
+        }
+    }
+    
+    let hook_file = HookFile::stored(path, &record);
+    let stored_path = record.path();
+    let key = RecordKey::new(record.chat.as_ref(), uploaded.message_id);
+    // Nothing below deletes anything unless the new copy is recorded
+    db.insert_file(record).await.context("Failed to save to database")?;
+    audit::record(Action::Upload, stored_path, path.to_string_lossy());
+    
+    // The new copy is recorded, so the old one can go
+    if let Some(old) = &replaced {
+        if let Err(e) = delete_stored_file(client, db, old).await {
+            warn!("Failed to delete the previous copy of {}: {:?}", filename, e);
+        }
+    }
+    // Only the latest version is kept; the pieces it shares with the one it
+    // follows stay with it
+    if let Some(old) = options.previous.and_then(|key| db.find(key)) {
+        if let Err(e) = delete_stored_file(client, db, &old).await {
+            warn!("Failed to delete the previous version of {}: {:?}", filename, e);
+        }
+    }
+    
+    // Before the file may be deleted below
+    if settings.index_contents && content_index::is_indexable(filename) {
+        let text_path = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || content_index::extract_text(&text_path)).await? {
+            Ok(Some(text)) => db.index_contents(key, &text),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to index contents of {}: {:?}", filename, e),
+        }
+    }
+    
+    // Before the file may be deleted below
+    if let Err(e) = hooks::run(&settings.hooks.post_upload, HookEvent::PostUpload, &hook_file).await {
+        warn!("{:#}", e);
+    }
+    webhooks::notify(&settings.webhook_url, WebhookEvent::UploadComplete { file: hook_file });
+    
+    if options.delete_after {
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            warn!("Failed to remove {:?} after upload: {:?}", path, e);
+        }
+    }
+    
+    let mut message = tr!("Uploaded {name}", name = filename);
+    if options.copy_reference {
+        match db.find(key).and_then(|record| screenshot::message_link(&record)) {
+            Some(link) => match arboard::Clipboard::new().and_then(|mut c| c.set_text(link)) {
+                Ok(()) => message.push_str(&tr!(", link copied to clipboard")),
+                Err(e) => warn!("Failed to copy link to clipboard: {:?}", e),
+            },
+            None => message.push_str(&tr!(", no link copied: only files in a channel or supergroup have one")),
+        }
+    }
+    
+    refresh_file_list(&ui_handle, db);
+    Ok(message)
+}
+
+/// Upload what `input` yields as `name` while it is read, without writing
+/// it to disk (standard input, an archive being packed). Nothing is known
+/// about the data until it ends, so it is neither deduplicated nor
+/// compressed here, can't be resumed, and its documents get their captions
+/// once all of it was sent
+async fn run_stream_upload(
+    client: &Client,
+    db: &Database,
+    limits: &AccountLimits,
+    settings: &Settings,
+    input: impl std::io::Read + Send + 'static,
+    name: &str,
+    options: &UploadOptions,
+    control: &TransferControl,
+) -> Result<FileRecord> {
+    read_only::check("uploading")?;
+    // The size is unknown, so the limit always matters
+    if let Err(e) = limits.detect(client).await {
+        warn!("Failed to check for Telegram Premium: {:?}", e);
+    }
+    let piece_size = limits.max_file_size();
+    let chat = storage_chat(client).await?;
+    let hidden_name = settings.hide_names.then(|| format!("{:032x}", rand::random::<u128>()));
+    let document_name = hidden_name.as_deref().unwrap_or(name);
+    
+    let (mut reader, pumped) = pipe::pump(input, settings.encrypt_uploads)?;
+    let mut pieces: Vec<SplitPiece> = Vec::new();
+    let sent: Result<()> = async {
+        loop {
+            let piece_name = match pieces.len() {
+                0 => document_name.to_string(),
+                n => format!("{}.part{:03}", document_name, n + 1),
+            };
+            info!("Streaming {}", piece_name);
+            let Some((uploaded, piece_len)) = chunked::upload_stream(client, &mut reader, &piece_name, piece_size, control).await? else {
+                return Ok(());
+            };
+            let caption = if hidden_name.is_some() { "" } else { caption::PENDING };
+            api_usage::request("messages.sendMedia");
+            let message = client.send_message(chat, InputMessage::text(caption).document(uploaded)).await?;
+            pieces.push(SplitPiece { message_id: message.id(), size: piece_len, sha256: None });
+            // A short piece is the last
+            if piece_len < piece_size {
+                return Ok(());
+            }
+        }
+    }.await;
+    drop(reader);
+    let pumped = pumped.await.context("Reading the input panicked")?;
+    let pumped = match (sent, pumped) {
+        (Ok(()), Ok(pumped)) if !pieces.is_empty() => pumped,
+        (sent, pumped) => {
+            // Whatever was sent is incomplete
+            let ids: Vec<i32> = pieces.iter().map(|p| p.message_id).collect();
+            if !ids.is_empty() {
+                api_usage::request("messages.deleteMessages");
+                if let Err(e) = client.delete_messages(chat, &ids).await {
+                    warn!("Failed to delete the incomplete upload of {}: {:?}", name, e);
+                }
+            }
+            sent?;
+            pumped?;
+            anyhow::bail!("Nothing to upload");
+        }
+    };
+    
+    let mut record = FileRecord::new(name, &format!("tg_file_{}", name), pumped.size, pieces[0].message_id);
+    record.folder = options.folder.clone();
+    record.archive_members = options.archive_members.clone();
+    record.sha256 = Some(pumped.sha256.clone());
+    record.tags = options.tags.clone();
+    record.chat = group_drive::current();
+    record.key_id = pumped.key_id.clone();
+    if pieces.len() > 1 {
+        record.split_pieces = pieces.clone();
+    }
+    db.insert_file(record.clone()).await?;
+    
+    if hidden_name.is_none() {
+        let meta = CaptionMeta {
+            version: CaptionMeta::VERSION,
+            path: name.to_string(),
+            folder: options.folder.clone(),
+            size: pumped.size,
+            sha256: pumped.sha256,
+            tags: options.tags.clone(),
+            encrypted: pumped.key_id.is_some(),
+            key_id: pumped.key_id,
+            ..Default::default()
+        };
+        let count = pieces.len() as u32;
+        for (number, piece) in (1..).zip(&pieces) {
+            let mut piece_meta = meta.clone();
+            piece_meta.piece = (count > 1).then_some((number, count));
+            api_usage::request("messages.editMessage");
+            if let Err(e) = client.edit_message(chat, piece.message_id, InputMessage::text(piece_meta.to_caption())).await {
+                warn!("Failed to caption {}: {:?}", name, e);
+            }
+        }
+    }
+    Ok(record)
+}
+
+/// Download a stored file and upload it again encrypted with the current
+/// key in place of the old copy. Starts over when interrupted
+async fn run_reencrypt(
+    client: &Client,
+    db: &Arc<Database>,
+    limits: &AccountLimits,
+    settings: &Settings,
+    key: RecordKey,
+    resume: &mut ResumeState,
+    control: &TransferControl,
+    ui_handle: slint::Weak<AppWindow>,
+) -> Result<String> {
+    read_only::check("re-encrypting")?;
+    let record = db.find(key).context("The file is no longer stored")?;
+    let dir = paths::cache_dir(REENCRYPT_DIR).join(key.to_string().replace(':', "-"));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await?;
+    let plain = folder::local_path(&dir, &record.filename).context("The stored name is not a plain file name")?;
+    
+    *resume = ResumeState::default();
+    download_file_from_telegram(client, db, key, &plain, resume, control, ui_handle.clone()).await?;
+    
+    *resume = ResumeState::default();
+    let options = UploadOptions {
+        folder: record.folder.clone(),
+        archive_members: record.archive_members.clone(),
+        tags: record.tags.clone(),
+        // The old copy is only deleted once the encrypted one is recorded
+        replaces: Some(key),
+        ..Default::default()
+    };
+    let settings = Settings { encrypt_uploads: true, ..settings.clone() };
+    let result = run_upload(client, db, limits, &settings, &plain, &options, resume, control, ui_handle).await;
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    result.map(|_| tr!("Re-encrypted {name}", name = record.filename))
+}
+
+/// Upload a screenshot taken into the cache, copying its t.me link when done
+/// if the drive is a channel or supergroup
+fn queue_screenshot(ui: &AppWindow, queue: &TransferQueue, path: PathBuf) {
+    queue.push(TransferKind::Upload {
+        path,
+        options: UploadOptions {
+            delete_after: true,
+            copy_reference: true,
+            ..Default::default()
+        },
+    });
+    update_transfer_list(&ui.as_weak(), queue);
+    ui.set_status_text(queued_status(queue).into());
+}
+
+/// The stored file a row of the file list refers to by its key
+fn row_file(db: &Database, key: &str) -> Option<(RecordKey, FileRecord)> {
+    let key: RecordKey = key.parse().ok()?;
+    Some((key, db.find(key)?))
+}
+
+/// Whether stored files may be changed; shows why not in read-only mode
+fn writable(ui_handle: &slint::Weak<AppWindow>) -> bool {
+    if read_only::is_enabled() {
+        set_status(ui_handle, errors::read_only_message());
+        return false;
+    }
+    true
+}
+
+/// Open the folder holding `path` in the file manager, with `path`
+/// selected where the platform supports it
+fn reveal(path: &Path) -> std::io::Result<()> {
+    if cfg!(windows) {
+        std::process::Command::new("explorer").arg("/select,").arg(path).spawn()?;
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open").arg("-R").arg(path).spawn()?;
+    } else {
+        open::that(path.parent().unwrap_or(path))?;
+    }
+    Ok(())
+}
+
+/// Everything known about a stored file, one fact per line
+fn file_details(record: &FileRecord) -> String {
+    let mut lines = vec![
+        tr!("Name: {value}", value = record.filename),
+        tr!("Folder: {value}", value = if record.folder.is_empty() { "/" } else { &record.folder }),
+        tr!("Size: {value}", value = format_size(record.file_size)),
+        tr!("Uploaded: {value}", value = record.upload_date.format(database::DATE_FORMAT)),
+        tr!("Chat: {value}", value = record.chat_label()),
+    ];
+    if let Some(message_id) = record.message_id {
+        lines.push(tr!("Message: {value}", value = message_id));
+    }
+    if record.split_pieces.len() > 1 {
+        lines.push(tr!("Stored in {count} parts", count = record.split_pieces.len()));
+    }
+    if let Some(id) = &record.key_id {
+        lines.push(tr!("Encrypted with key {id}", id = id));
+    }
+    if !record.tags.is_empty() {
+        lines.push(tr!("Tags: {value}", value = record.tags.join(", ")));
+    }
+    if let Some(media) = &record.media {
+        lines.push(media.describe());
+    }
+    if let Some(mtime) = record.mtime.and_then(|t| chrono::DateTime::from_timestamp(t, 0)) {
+        lines.push(tr!("Modified: {value}", value = mtime.with_timezone(&chrono::Local).format(database::DATE_FORMAT)));
+    }
+    if let Some(sha256) = &record.sha256 {
+        lines.push(format!("SHA-256: {}", sha256));
+    }
+    if let Some(previous) = record.previous {
+        lines.push(tr!("New version of message {value}", value = previous.message_id));
+    }
+    lines.join("\n")
+}
+
+/// Show the current encryption key as a numbered recovery phrase
+fn show_recovery_phrase(ui: &AppWindow, keys: &encryption::Keys) {
+    match keys.phrase() {
+        Ok(phrase) => ui.set_recovery_phrase(encryption::numbered(&phrase).as_str().into()),
+        Err(e) => error!("Failed to make a recovery phrase: {:?}", e),
+    }
+}
+
+/// Reload the file list in the UI using its current search text
+fn refresh_file_list(ui_handle: &slint::Weak<AppWindow>, db: &Arc<Database>) {
+    let db = db.clone();
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        let range = match DatePreset::from_index(ui.get_date_preset()) {
+            DatePreset::Custom => DateRange::custom(&ui.get_date_from(), &ui.get_date_to())
+                .unwrap_or_else(|e| {
+                    ui.set_status_text(tr!("Invalid date range: {error}", error = format!("{:#}", e)).into());
+                    DateRange::default()
+                }),
+            preset => DateRange::preset(preset, chrono::Local::now()),
+        };
+        let order = if ui.get_sort_by_capture() { SortOrder::CaptureDate } else { SortOrder::UploadDate };
+        let listing = Listing::from_index(ui.get_listing());
+        match db.get_all_files(&ui.get_search_text(), &range, order, listing) {
+            Ok(mut files) => {
+                // Keep the selection across reloads, dropping files no longer listed
+                let selected = selection::selected_rows(&ui);
+                for file in files.iter_mut() {
+                    file.selected = selected.contains(&file.key);
+                }
+                let selected_count = files.iter().filter(|f| f.selected).count();
+                let files_rc = std::rc::Rc::new(slint::VecModel::from(files));
+                ui.set_uploaded_files(files_rc.into());
+                ui.set_selected_count(selected_count as i32);
+            }
+            Err(e) => error!("Failed to load files: {:?}", e),
+        }
+        
+        refresh_folders(&ui, &db);
+        refresh_stats(&ui, &db);
+    });
+}
+
+/// Rebuild the virtual folder tree beside the file list
+fn refresh_folders(ui: &AppWindow, db: &Database) {
+    let records = db.all_records();
+    let root = SlintFolderEntry {
+        path: "".into(),
+        name: "".into(),
+        depth: 0,
+        files: records.len() as i32,
+    };
+    let folders: Vec<SlintFolderEntry> = std::iter::once(root)
+        .chain(folder::tree(records.iter().map(|r| r.folder.as_str())).into_iter().map(|(path, files)| {
+            SlintFolderEntry {
+                name: folder::name(&path).into(),
+                depth: path.matches('/').count() as i32 + 1,
+                path: path.into(),
+                files: files as i32,
+            }
+        }))
+        .collect();
+    ui.set_folders(std::rc::Rc::new(slint::VecModel::from(folders)).into());
+}
+
+/// Recompute the statistics view from the database
+fn refresh_stats(ui: &AppWindow, db: &Database) {
+    let stats = StorageStats::compute(&db.all_records());
+    let model = |bars: Vec<_>| slint::ModelRc::new(slint::VecModel::from(bars));
+    
+    ui.set_stats_total_size(format_size(stats.total_bytes).into());
+    ui.set_stats_total_files(stats.total_files as i32);
+    ui.set_stats_by_kind(model(stats.by_kind));
+    ui.set_stats_by_month(model(stats.by_month));
+    ui.set_stats_largest(model(stats.largest));
+    ui.set_stats_by_chat(model(stats.by_chat));
+}
+
+/// Reload the transfer history panel
+fn refresh_history(ui_handle: &slint::Weak<AppWindow>, db: &Arc<Database>) {
+    let db = db.clone();
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        let history = std::rc::Rc::new(slint::VecModel::from(db.get_history()));
+        ui.set_transfer_history(history.into());
+    });
+}
+
+/// Reload the activity log shown in the window
+fn refresh_activity(ui_handle: &slint::Weak<AppWindow>) {
+    let _ = ui_handle.upgrade_in_event_loop(|ui| {
+        let entries = std::rc::Rc::new(slint::VecModel::from(audit::shown_entries()));
+        ui.set_activity_log(entries.into());
+    });
+}
+
+/// One-line transfer summary for the tray tooltip
+fn tray_status(ui: &AppWindow) -> String {
+    let queued = ui.get_queued_uploads();
+    if ui.get_flood_wait_seconds() > 0 {
+        tr!("Rate limited, resuming in {secs}s", secs = ui.get_flood_wait_seconds())
+    } else if ui.get_is_uploading() {
+        tr!(
+            "Transferring: {percent}% ({count} queued)",
+            percent = (ui.get_upload_progress() * 100.0).round(),
+            count = queued,
+        )
+    } else if queued > 0 {
+        tr!("{count} transfer(s) queued", count = queued)
+    } else {
+        tr!("Idle")
+    }
+}
+
+/// Mirror the uploads waiting for their start time into the UI
+fn update_scheduled_list(ui_handle: &slint::Weak<AppWindow>, scheduler: &Scheduler) {
+    let entries: Vec<SlintScheduledEntry> = scheduler.entries()
+        .into_iter()
+        .map(|entry| SlintScheduledEntry {
+            id: entry.id as i32,
+            name: entry.path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("Unknown")
+                .into(),
+            start_at: entry.start_at_local().format("%Y-%m-%d %H:%M").to_string().into(),
+        })
+        .collect();
+    
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_scheduled_uploads(std::rc::Rc::new(slint::VecModel::from(entries)).into());
+    });
+}
+
+/// Mirror the configured backup jobs into the settings panel
+fn update_backup_jobs(ui_handle: &slint::Weak<AppWindow>, settings: &Settings) {
+    let entries: Vec<SlintBackupJobEntry> = settings.backup_jobs
+        .iter()
+        .map(|job| SlintBackupJobEntry {
+            name: job.folder_root().into(),
+            source: job.source.display().to_string().into(),
+            schedule: job.schedule.clone().into(),
+            last_run: job.last_run_label().into(),
+        })
+        .collect();
+    
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_backup_jobs(std::rc::Rc::new(slint::VecModel::from(entries)).into());
+    });
+}
+
+/// Short description of a pair's include/exclude rules
+fn describe_rules(pair: &SyncPair) -> String {
+    let mut parts = Vec::new();
+    if !pair.include.is_empty() {
+        parts.push(tr!("only {patterns}", patterns = pair.include.join(", ")));
+    }
+    if !pair.exclude.is_empty() {
+        parts.push(tr!("except {patterns}", patterns = pair.exclude.join(", ")));
+    }
+    parts.join("; ")
+}
+
+/// Mirror the configured sync pairs into the sync panel
+fn update_sync_pairs(ui_handle: &slint::Weak<AppWindow>, settings: &Settings) {
+    let entries: Vec<SlintSyncPairEntry> = settings.sync_pairs
+        .iter()
+        .map(|pair| SlintSyncPairEntry {
+            local: pair.local.display().to_string().into(),
+            remote_folder: pair.remote_folder.clone().into(),
+            propagate_deletes: pair.propagate_deletes,
+            interval_minutes: pair.interval_minutes as i32,
+            rules: describe_rules(pair).into(),
+        })
+        .collect();
+    
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_sync_pairs(std::rc::Rc::new(slint::VecModel::from(entries)).into());
+    });
+}
+
+/// Mirror the sync conflicts waiting for a decision into the sync panel
+fn update_conflicts(ui_handle: &slint::Weak<AppWindow>, sync: &SyncEngine) {
+    let entries: Vec<SlintConflictEntry> = sync.conflicts()
+        .into_iter()
+        .map(|conflict| SlintConflictEntry {
+            id: conflict.id as i32,
+            path: conflict.local_path.display().to_string().into(),
+            details: conflict.describe().into(),
+        })
+        .collect();
+    
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_sync_conflicts(std::rc::Rc::new(slint::VecModel::from(entries)).into());
+    });
+}
+
+/// Rescan the library for duplicate content and show the sets
+fn update_duplicates(ui_handle: &slint::Weak<AppWindow>, db: &Database) {
+    let sets = duplicates::find_duplicates(&db.all_records());
+    let wasted: u64 = sets.iter().map(|set| set.wasted()).sum();
+    let summary = if sets.is_empty() {
+        tr!("No duplicates found")
+    } else {
+        tr!("{count} duplicate set(s), {size} wasted", count = sets.len(), size = format_size(wasted))
+    };
+    let entries: Vec<SlintDuplicateEntry> = sets.iter().map(|set| set.to_entry()).collect();
+    
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_duplicate_sets(std::rc::Rc::new(slint::VecModel::from(entries)).into());
+        ui.set_duplicates_wasted(summary.into());
+    });
+}
+
+/// Status line after queueing, noting when jobs wait for a connection
+fn queued_status(queue: &TransferQueue) -> String {
+    if queue.is_online() {
+        tr!("{count} transfer(s) queued", count = queue.pending())
+    } else {
+        tr!("{count} transfer(s) queued; they will start once connected", count = queue.pending())
+    }
+}
+
+/// Mirror the active and waiting jobs into the UI
+fn update_transfer_list(ui_handle: &slint::Weak<AppWindow>, queue: &TransferQueue) {
+    let pending = queue.pending() as i32;
+    let entries: Vec<SlintTransferEntry> = queue.snapshot()
+        .into_iter()
+        .map(|(job, state)| SlintTransferEntry {
+            id: job.id as i32,
+            name: job.kind.display_name().into(),
+            direction: if job.kind.is_upload() { "Upload" } else { "Download" }.into(),
+            active: state == TransferState::Active,
+            paused: state == TransferState::Paused,
+        })
+        .collect();
+    
+    // Folder uploads show overall progress across all of their files
+    let (batch_label, batch_progress) = match queue.batches().first() {
+        Some(batch) => (
+            tr!("{name}: {done} of {total} files", name = batch.name, done = batch.done_files, total = batch.total_files),
+            batch.done_bytes as f32 / batch.total_bytes.max(1) as f32,
+        ),
+        None => (String::new(), 0.0),
+    };
+    
+    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+        ui.set_queued_uploads(pending);
+        ui.set_batch_label(batch_label.into());
+        ui.set_batch_progress(batch_progress);
+        ui.set_transfers(std::rc::Rc::new(slint::VecModel::from(entries)).into());
+    });
+}
+
+/// API hash from .env or the OS keyring. One found in .env is copied to the
+/// keyring, after which it can be removed from .env
+fn load_api_hash() -> Result<String> {
+    let Ok(api_hash) = std::env::var("API_HASH") else {
+        return secrets::get(secrets::API_HASH)?
+            .map(|hash| hash.to_string())
+            .context("API_HASH not found in .env or the OS keyring");
+    };
+    match secrets::get(secrets::API_HASH) {
+        Ok(Some(stored)) if *stored == api_hash => {}
+        Ok(_) => match secrets::set(secrets::API_HASH, &api_hash) {
+            Ok(()) => info!("API_HASH stored in the OS keyring; it can be removed from .env"),
+            Err(e) => warn!("{:?}", e),
+        },
+        Err(e) => warn!("{:?}", e),
+    }
+    Ok(api_hash)
+}
+
+/// Print what `upload` or `download` did; fails when any file did, so
+/// scripts see a non-zero exit code
+fn finish_transfers(format: output::Format, results: &[output::TransferResult]) -> Result<()> {
+    output::print_results(format, results);
+    let failed = results.iter().filter(|r| !r.ok).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} file(s) failed", failed, results.len());
+    }
+    Ok(())
+}
+
+/// Run a command-line subcommand instead of the window, with the session
+/// the desktop app signed in
+async fn run_command(
+    command: cli::Command,
+    format: output::Format,
+    params: ConnectParams,
+    db: Arc<Database>,
+    settings: Arc<Mutex<Settings>>,
+) -> Result<()> {
+    let app_lock = settings.lock().unwrap().app_lock.clone();
+    if let Some(lock) = app_lock {
+        let passphrase = match std::env::var("TELEDRIVE_PASSPHRASE") {
+            Ok(passphrase) => passphrase,
+            Err(_) => rpassword::prompt_password("Passphrase: ")?,
+        };
+        if !lock.verify(&passphrase) {
+            anyhow::bail!("Wrong passphrase");
+        }
+        if settings.lock().unwrap().session_encryption == Some(KeySource::Passphrase) {
+            *params.session_key.lock().unwrap() = Some(lock.session_key(&passphrase)?);
+        }
+    }
+    
+    // These only read the index, so work offline
+    match &command {
+        cli::Command::List { query, starred } => {
+            let query = query.as_deref().unwrap_or_default().to_lowercase();
+            let records: Vec<FileRecord> = db.all_records()
+                .into_iter()
+                .rev()
+                .filter(|r| r.starred || !starred)
+                .filter(|r| r.filename.to_lowercase().contains(&query) || r.folder.to_lowercase().contains(&query))
+                .collect();
+            output::print_records(format, &records);
+            return Ok(());
+        }
+        // The Bot API needs no user session
+        cli::Command::Upload { files, folder, via_bot: true, .. } => {
+            let settings = settings.lock().unwrap().clone();
+            let chat = settings.bot_upload_chat.clone()
+                .context("No chat set for bot uploads; pick one under Settings in the desktop app")?;
+            let token = match std::env::var("BOT_TOKEN") {
+                Ok(token) => token,
+                Err(_) => secrets::get(secrets::BOT_TOKEN)?
+                    .map(|token| token.to_string())
+                    .context("BOT_TOKEN not found in .env or the OS keyring")?,
+            };
+            if files.iter().any(|f| cli::is_stdin(f)) {
+                anyhow::bail!("Standard input can't be uploaded through the bot");
+            }
+            let mut results = Vec::new();
+            for path in files {
+                let result = bot::upload_file(&db, &settings, &token, &chat, path, folder).await;
+                results.push(output::TransferResult::from_stored(&path.to_string_lossy(), result));
+            }
+            return finish_transfers(format, &results);
+        }
+        cli::Command::Snapshot { action: cli::SnapshotAction::List } => {
+            output::print_snapshots(format, &snapshot::Repository::load()?.snapshots);
+            return Ok(());
+        }
+        cli::Command::Status => {
+            let records = db.all_records();
+            let queue = TransferQueue::new(PAUSED_TRANSFERS_FILE, TRANSFER_JOURNAL_FILE);
+            let mut transfers: Vec<output::PendingTransfer> = queue.snapshot()
+                .iter()
+                .map(|(job, state)| output::PendingTransfer::new(job, Some(*state)))
+                .collect();
+            transfers.extend(queue.restored_jobs().iter().map(|job| output::PendingTransfer::new(job, None)));
+            output::print_status(format, &output::Status {
+                files: records.len(),
+                total_size: records.iter().map(|r| r.file_size).sum(),
+                transfers,
+            });
+            return Ok(());
+        }
+        _ => {}
+    }
+    
+    let no_ui = slint::Weak::default();
+    let tg_client = init_telegram_client(&params, no_ui.clone()).await?;
+    if !tg_client.is_authorized().await? {
+        anyhow::bail!("Not signed in; sign in once with the desktop app first");
+    }
+    
+    let client = Arc::new(Mutex::new(Some(tg_client.clone())));
+    // The desktop app's queue files belong to it; an SFTP server or a command
+    // running beside it would overwrite its unfinished jobs with its own
+    let queue = Arc::new(TransferQueue::in_memory());
+    let reconnect = Arc::new(Notify::new());
+    queue.set_online(true);
+    tokio::spawn(run_transfer_worker(
+        queue.clone(),
+        client.clone(),
+        reconnect.clone(),
+        db.clone(),
+        Arc::new(AccountLimits::default()),
+        settings.clone(),
+        no_ui.clone(),
+    ));
+    tokio::spawn(run_connection_monitor(client.clone(), queue.clone(), reconnect, params.clone(), no_ui));
+    
+    match command {
+        cli::Command::Serve { protocol: cli::ServeProtocol::Sftp { listen, user } } => {
+            let password = std::env::var("SFTP_PASSWORD")
+                .context("SFTP_PASSWORD not set; choose a password for SFTP clients")?;
+            sftp::run_sftp_server(listen, sftp::SftpContext { client, db, queue, user, password }).await
+        }
+        cli::Command::Upload { files, folder, name, as_photo, as_document, downscale, full_size, .. } => {
+            if files.iter().filter(|f| cli::is_stdin(f)).count() > 1 {
+                anyhow::bail!("Standard input can only be uploaded once");
+            }
+            if files.iter().any(|f| cli::is_stdin(f)) && name.is_none() {
+                anyhow::bail!("Give standard input a name with --name");
+            }
+            // Run here rather than through the queue, to wait for each result
+            let settings = settings.lock().unwrap().clone();
+            let limits = AccountLimits::default();
+            let send_images_as = match (as_photo, as_document) {
+                (true, _) => Some(SendAs::Photo),
+                (_, true) => Some(SendAs::Document),
+                _ => None,
+            };
+            let downscale = match (downscale, full_size) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            let options = UploadOptions { folder: folder.clone(), send_images_as, downscale, ..Default::default() };
+            let mut results = Vec::new();
+            for path in files {
+                if let (true, Some(name)) = (cli::is_stdin(&path), &name) {
+                    let result = run_stream_upload(
+                        &tg_client,
+                        &db,
+                        &limits,
+                        &settings,
+                        std::io::stdin(),
+                        name,
+                        &options,
+                        &TransferControl::default(),
+                    ).await;
+                    results.push(output::TransferResult::from_stored(name, result));
+                    continue;
+                }
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("Unknown").to_string();
+                let result = run_upload(
+                    &tg_client,
+                    &db,
+                    &limits,
+                    &settings,
+                    &path,
+                    &options,
+                    &mut ResumeState::default(),
+                    &TransferControl::default(),
+                    no_ui.clone(),
+                ).await;
+                results.push(match result {
+                    Ok(_) => output::TransferResult {
+                        message_id: db.find_in_folder(&folder, &name).and_then(|r| r.message_id),
+                        file: path.to_string_lossy().to_string(),
+                        ok: true,
+                        error: None,
+                    },
+                    Err(e) => output::TransferResult {
+                        file: path.to_string_lossy().to_string(),
+                        ok: false,
+                        message_id: None,
+                        error: Some(format!("{:#}", e)),
+                    },
+                });
+            }
+            finish_transfers(format, &results)
+        }
+        cli::Command::Download { names, dest } => {
+            let mut results = Vec::new();
+            for name in names {
+                let result = match db.find_by_name(&name).and_then(|r| Some((r.key()?, r))) {
+                    Some((key, record)) => match folder::local_path(&dest, &record.filename) {
+                        Some(path) => download_file_from_telegram(
+                            &tg_client,
+                            &db,
+                            key,
+                            &path,
+                            &mut ResumeState::default(),
+                            &TransferControl::default(),
+                            no_ui.clone(),
+                        ).await.map(|_| (key, path)),
+                        None => Err(anyhow::anyhow!("{:?} is not a plain file name", record.filename)),
+                    },
+                    None => Err(anyhow::anyhow!("No stored file matches {:?}", name)),
+                };
+                if let Ok((key, path)) = &result {
+                    if let Some(record) = db.find(*key) {
+                        audit::record(Action::Download, record.path(), path.to_string_lossy());
+                    }
+                    if let Err(e) = db.mark_used(*key, false).await {
+                        warn!("Failed to note the use of {:?}: {:?}", name, e);
+                    }
+                }
+                results.push(match result {
+                    Ok((key, path)) => output::TransferResult {
+                        file: path.to_string_lossy().to_string(),
+                        ok: true,
+                        message_id: Some(key.message_id),
+                        error: None,
+                    },
+                    Err(e) => output::TransferResult {
+                        file: name,
+                        ok: false,
+                        message_id: None,
+                        error: Some(format!("{:#}", e)),
+                    },
+                });
+            }
+            finish_transfers(format, &results)
+        }
+        cli::Command::Backup { dir, folder, name } => {
+            // "." has no name to give the archive
+            let dir = std::fs::canonicalize(&dir).with_context(|| format!("Failed to open {:?}", dir))?;
+            let root = dir.clone();
+            let files = tokio::task::spawn_blocking(move || folder::collect_files(&root)).await??;
+            if files.is_empty() {
+                anyhow::bail!("{:?} has no files to back up", dir);
+            }
+            let name = match name {
+                Some(name) => name,
+                None => archive::archive_name(&dir)?,
+            };
+            let settings = settings.lock().unwrap().clone();
+            let options = UploadOptions { folder, archive_members: archive::members(&files)?, ..Default::default() };
+            // Packed as it uploads, so the archive never has to fit on disk
+            let level = settings.compression.level;
+            let archive = pipe::produce(move |out| archive::write_archive(&files, level, out));
+            let result = run_stream_upload(
+                &tg_client,
+                &db,
+                &AccountLimits::default(),
+                &settings,
+                archive,
+                &name,
+                &options,
+                &TransferControl::default(),
+            ).await;
+            finish_transfers(format, &[output::TransferResult::from_stored(&name, result)])
+        }
+        cli::Command::Cat { name } => {
+            let record = db.find_by_name(&name).with_context(|| format!("No stored file matches {:?}", name))?;
+            pipe::write_to(&tg_client, &db, &record, std::io::stdout()).await
+        }
+        cli::Command::Snapshot { action: cli::SnapshotAction::Create { dir } } => {
+            let dir = std::fs::canonicalize(&dir).with_context(|| format!("Failed to open {:?}", dir))?;
+            let encrypt = settings.lock().unwrap().encrypt_uploads;
+            let taken = snapshot::create(&tg_client, &dir, encrypt, &TransferControl::default()).await?;
+            output::print_snapshots(format, &[taken]);
+            Ok(())
+        }
+        cli::Command::Snapshot { action: cli::SnapshotAction::Restore { id, dest } } => {
+            let repository = snapshot::Repository::load()?;
+            let taken = repository.find(&id)?;
+            snapshot::restore(&tg_client, &repository, taken, &dest, &TransferControl::default()).await?;
+            output::print_restore(format, taken, &dest);
+            Ok(())
+        }
+        cli::Command::Snapshot { action: cli::SnapshotAction::Forget { keep_last, keep_daily, keep_weekly, keep_monthly, prune } } => {
+            let retention = snapshot::Retention {
+                last: keep_last.unwrap_or(0),
+                daily: keep_daily.unwrap_or(0),
+                weekly: keep_weekly.unwrap_or(0),
+                monthly: keep_monthly.unwrap_or(0),
+            };
+            let mut report = snapshot::forget(&tg_client, &retention).await?;
+            if prune {
+                let encrypt = settings.lock().unwrap().encrypt_uploads;
+                let pruned = snapshot::prune(&tg_client, encrypt, &TransferControl::default()).await?;
+                report = snapshot::PruneReport { forgotten: report.forgotten, ..pruned };
+            }
+            output::print_prune(format, &report);
+            Ok(())
+        }
+        cli::Command::Snapshot { action: cli::SnapshotAction::Prune } => {
+            let encrypt = settings.lock().unwrap().encrypt_uploads;
+            let report = snapshot::prune(&tg_client, encrypt, &TransferControl::default()).await?;
+            output::print_prune(format, &report);
+            Ok(())
+        }
+        cli::Command::Check { repair } => {
+            let report = fsck::check(Some(&tg_client), &db, repair).await?;
+            output::print_check(format, &report);
+            let left = report.issues.iter().filter(|i| !i.repaired).count();
+            if left > 0 {
+                anyhow::bail!("{} issue(s) left in the index", left);
+            }
+            Ok(())
+        }
+        cli::Command::Gc { clean } => {
+            let drive = group_drive::current();
+            // Pieces sent by uploads that will carry on later. They sit in the
+            // desktop app's queue files, read here without being taken over
+            let desktop_queue = TransferQueue::new(PAUSED_TRANSFERS_FILE, TRANSFER_JOURNAL_FILE);
+            let busy: HashSet<i32> = desktop_queue.snapshot()
+                .into_iter()
+                .map(|(job, _)| job)
+                .chain(desktop_queue.restored_jobs())
+                .flat_map(|job| job.resume.piece_messages)
+                .map(|piece| piece.message_id)
+                .collect();
+            let mut report = orphans::scan(&tg_client, &db, drive.as_ref(), &busy).await?;
+            if clean {
+                orphans::clean(&tg_client, &db, drive.as_ref(), &mut report).await?;
+            }
+            output::print_gc(format, &report);
+            Ok(())
+        }
+        cli::Command::Migrate { to, move_files } => {
+            let to = Some(to.as_str()).filter(|name| *name != "default");
+            if to == profile::current() {
+                anyhow::bail!("Pick a profile other than the one running");
+            }
+            if let Some(name) = to {
+                profile::validate(name)?;
+            }
+            let folder = profile::folder(to).context("Profile folders are unknown")?;
+            let settings_file = paths::profile_config_file(to, SETTINGS_FILE).context("Profile folders are unknown")?;
+            let target_settings = Settings::load(settings_file);
+            if target_settings.session_encryption.is_some() {
+                anyhow::bail!("The other profile's session is encrypted; turn that off there while migrating");
+            }
+            let session_file = folder.join(&params.session_file);
+            if !session_file.exists() {
+                anyhow::bail!("No session in {:?}; sign in with that profile first", folder);
+            }
+            let target_params = ConnectParams {
+                session_file: session_file.to_string_lossy().into_owned(),
+                session_key: Default::default(),
+                ..params.clone()
+            };
+            let target_client = init_telegram_client(&target_params, slint::Weak::default()).await?;
+            if !target_client.is_authorized().await? {
+                anyhow::bail!("The other profile is not signed in");
+            }
+            let target = migrate::Target {
+                client: target_client,
+                db: Arc::new(Database::new(&folder.join(DB_FILE).to_string_lossy()).await?),
+                settings: target_settings,
+            };
+            let report = migrate::migrate(&tg_client, &db, &target, move_files).await?;
+            output::print_migration(format, &report);
+            let failed = report.files.iter().filter(|f| f.outcome == migrate::Outcome::Failed).count();
+            if failed > 0 {
+                anyhow::bail!("{} of {} file(s) failed", failed, report.files.len());
+            }
+            Ok(())
+        }
+        cli::Command::List { .. }
+        | cli::Command::Status
+        | cli::Command::Snapshot { action: cli::SnapshotAction::List } => unreachable!("handled before connecting"),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut cli = cli::Cli::parse();
+    // Relative to where the app was started, which is about to change
+    let files: Vec<PathBuf> = cli.files.iter().filter_map(|p| std::fs::canonicalize(p).ok()).collect();
+    if let (Some(command), Ok(started_in)) = (&mut cli.command, std::env::current_dir()) {
+        command.make_absolute(&started_in);
+    }
+    let format = output::Format::from_flags(cli.json, cli.porcelain);
+    
+    // Load environment variables, from where the app was started (as earlier
+    // versions did) or its config folder
+    dotenv::dotenv().ok();
+    // Before anything is read or written: portable mode keeps everything next
+    // to the executable, and a profile has a folder of its own
+    let moved = paths::init(cli.portable)?;
+    dotenv::from_path(paths::config_file(".env")).ok();
+    // A test server has a profile of its own, so its files stay apart
+    let server = connection::server_from_env()?;
+    profile::activate(profile::resolve(cli.profile.as_deref(), server.is_some())?)?;
+    
+    // Initialize logging; the configured level applies once settings are loaded
+    let log = match logging::init(LogLevel::default()) {
+        Ok(log) => Some(log),
+        Err(e) => {
+            eprintln!("Logging to a file is unavailable: {:#}", e);
+            // Standard output may be carrying a file (`teledrive cat`)
+            tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+            None
+        }
+    };
+    crash::install_panic_hook();
+    for (from, to) in &moved {
+        info!("Moved {:?} to {:?}", from, to);
+    }
+    
+    // A second launch (e.g. "Open with TeleDrive") hands its files to the
+    // window already open instead of loading the same database twice
+    let primary = match cli.command {
+        Some(_) => None,
+        None => match instance::claim(&files)? {
+            Some(primary) => Some(primary),
+            None => {
+                info!("Handed {} file(s) to the running instance", files.len());
+                return Ok(());
+            }
+        },
+    };
+    
+    let api_id = std::env::var("API_ID")
+        .context("API_ID not found in .env")?
+        .parse::<i32>()
+        .context("API_ID must be a number")?;
+    
+    let api_hash = load_api_hash()?;
+    
+    // A test DC account must never overwrite the real session
+    let default_session = match server {
+        Some(_) => TEST_SESSION_FILE,
+        None => SESSION_FILE,
+    };
+    let session_name = std::env::var("SESSION_NAME")
+        .unwrap_or_else(|_| default_session.to_string());
+    if let Some(server) = server {
+        warn!("Connecting to {} instead of Telegram's production servers, with session {}", server, session_name);
+    }
+    
+    let connect_params = ConnectParams {
+        api_id,
+        api_hash,
+        session_file: session_name,
+        session_key: Default::default(),
+        server,
+    };
+    
+    info!("Starting Telegram Cloud Storage application");
+    if paths::is_portable() {
+        info!("Portable mode: keeping files in {:?}", std::env::current_dir().unwrap_or_default());
+    }
+    
+    // Initialize database
+    let db = Arc::new(Database::new(DB_FILE).await?);
+    let settings = Arc::new(Mutex::new(Settings::load(SETTINGS_FILE)));
+    i18n::set_language(&settings.lock().unwrap().language);
+    read_only::set(settings.lock().unwrap().read_only);
+    group_drive::set(settings.lock().unwrap().group_drive.clone());
+    if let Some(log) = &log {
+        log.set_level(settings.lock().unwrap().log_level);
+    }
+    // A passphrase-derived key only becomes known on unlock
+    if settings.lock().unwrap().session_encryption == Some(KeySource::Keyring) {
+        match session_crypt::keyring_key() {
+            Ok(key) => *connect_params.session_key.lock().unwrap() = Some(key),
+            Err(e) => error!("Failed to get the session key: {:?}", e),
+        }
+    }
+    
+    if let Some(command) = cli.command {
+        return run_command(command, format, connect_params, db, settings).await;
+    }
+    let start_minimized = cli.minimized;
+    
+    // Create UI
+    let ui = AppWindow::new()?;
+    let ui_weak = ui.as_weak();
+    
+    ui.set_auto_start(settings.lock().unwrap().auto_start);
+    ui.set_check_updates(settings.lock().unwrap().check_updates);
+    {
+        let settings = settings.lock().unwrap();
+        ui.set_hook_pre_upload(settings.hooks.pre_upload.as_str().into());
+        ui.set_hook_post_upload(settings.hooks.post_upload.as_str().into());
+        ui.set_hook_post_download(settings.hooks.post_download.as_str().into());
+        ui.set_webhook_url(settings.webhook_url.as_str().into());
+        if let Some(dir) = &settings.download_dir {
+            ui.set_download_dir(dir.to_string_lossy().to_string().into());
+        }
+    }
+    ui.set_reject_oversized(settings.lock().unwrap().reject_oversized);
+    ui.set_max_retries(settings.lock().unwrap().retry.max_retries as i32);
+    ui.set_dedup_chunks(settings.lock().unwrap().dedup_chunks);
+    ui.set_album_uploads(settings.lock().unwrap().album_uploads);
+    ui.set_images_as_index(settings.lock().unwrap().send_images_as.index());
+    ui.set_encrypt_uploads(settings.lock().unwrap().encrypt_uploads);
+    ui.set_hide_names(settings.lock().unwrap().hide_names);
+    {
+        let mut names: Vec<slint::SharedString> = vec![tr!("Default").into()];
+        names.extend(profile::list().into_iter().map(Into::into));
+        let current = profile::current().unwrap_or_default();
+        ui.set_profile_name(current.into());
+        ui.set_profile_index(names.iter().skip(1).position(|n| n.as_str() == current).map_or(0, |i| i as i32 + 1));
+        ui.set_profiles(Rc::new(slint::VecModel::from(names)).into());
+    }
+    if settings.lock().unwrap().encrypt_uploads {
+        match encryption::Keys::load() {
+            Ok(keys) => ui.set_encryption_key_id(keys.current_id().into()),
+            Err(e) => error!("Failed to load the encryption keys: {:?}", e),
+        }
+    }
+    {
+        let compression = settings.lock().unwrap().compression.clone();
+        ui.set_compress_uploads(compression.enabled);
+        ui.set_compression_level(compression.level);
+        ui.set_compression_min_mb((compression.min_size / (1024 * 1024)) as i32);
+        ui.set_compression_skip(compression.skip_extensions.join(", ").into());
+    }
+    {
+        let downscale = settings.lock().unwrap().downscale.clone();
+        ui.set_downscale_images(downscale.enabled);
+        ui.set_downscale_max_side(downscale.max_side as i32);
+        ui.set_downscale_quality(downscale.quality as i32);
+        ui.set_downscale_keep_originals(downscale.keep_originals);
+    }
+    ui.set_index_contents(settings.lock().unwrap().index_contents);
+    ui.set_ocr_available(ocr::AVAILABLE);
+    ui.set_ocr_images(settings.lock().unwrap().ocr_images);
+    ui.set_verify_in_background(settings.lock().unwrap().verify_in_background);
+    i18n::install(&ui);
+    ui.set_theme_index(settings.lock().unwrap().theme.index());
+    ui.set_grid_view(settings.lock().unwrap().grid_view);
+    ui.set_log_level_index(settings.lock().unwrap().log_level.index());
+    {
+        let countries: Vec<slint::SharedString> = phone::country_labels().into_iter().map(Into::into).collect();
+        ui.set_phone_countries(Rc::new(slint::VecModel::from(countries)).into());
+        let saved = settings.lock().unwrap().phone_country.clone();
+        let country = Some(saved).filter(|c| !c.is_empty())
+            .or_else(phone::system_region)
+            .and_then(|iso| phone::country_index(&iso))
+            .or_else(|| phone::country_index("US"))
+            .unwrap_or(0);
+        ui.set_phone_country_index(country as i32);
+    }
+    ui.invoke_apply_theme();
+    {
+        let settings = settings.lock().unwrap();
+        ui.set_app_lock_enabled(settings.app_lock.is_some());
+        ui.set_lock_idle_minutes(settings.app_lock.as_ref().map_or(15, |l| l.idle_minutes) as i32);
+        ui.set_locked(settings.app_lock.is_some());
+        app_lock::set_locked(settings.app_lock.is_some());
+        ui.set_session_encryption_index(KeySource::index(settings.session_encryption));
+        ui.set_read_only(settings.read_only);
+        ui.set_group_drive(settings.group_drive.as_ref().map(|d| d.name.clone()).unwrap_or_default().into());
+        ui.set_bot_upload_chat(settings.bot_upload_chat.as_ref().map(|c| c.name.clone()).unwrap_or_default().into());
+    }
+    ui.set_test_server(connect_params.server.map(|s| s.to_string()).unwrap_or_default().into());
+    
+    // State management
+    let client: Arc<Mutex<Option<Client>>> = Arc::new(Mutex::new(None));
+    let selected_files: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
+    let queue = Arc::new(TransferQueue::new(PAUSED_TRANSFERS_FILE, TRANSFER_JOURNAL_FILE));
+    ui.set_restored_transfers(queue.restored() as i32);
+    let reconnect = Arc::new(Notify::new());
+    let limits = Arc::new(AccountLimits::default());
+    let scheduler = Arc::new(Scheduler::new(SCHEDULED_UPLOADS_FILE));
+    let sync = Arc::new(SyncEngine::new(SYNC_STATE_FILE, client.clone()));
+    
+    // Background transfer worker
+    tokio::spawn(run_transfer_worker(
+        queue.clone(),
+        client.clone(),
+        reconnect.clone(),
+        db.clone(),
+        limits.clone(),
+        settings.clone(),
+        ui_weak.clone(),
+    ));
+    
+    // Hand scheduled uploads to the queue when they are due
+    tokio::spawn(run_scheduler(
+        scheduler.clone(),
+        queue.clone(),
+        sync.clone(),
+        settings.clone(),
+        db.clone(),
+        ui_weak.clone(),
+    ));
+    
+    // Look for new releases in the background
+    let check_updates_now = Arc::new(Notify::new());
+    tokio::spawn(updates::run_update_checker(settings.clone(), check_updates_now.clone(), ui_weak.clone()));
+    
+    // Keep a shared group drive in step with what other members upload and delete
+    let refresh_group_drive = Arc::new(Notify::new());
+    tokio::spawn(group_drive::run_refresher(client.clone(), db.clone(), refresh_group_drive.clone(), ui_weak.clone()));
+    
+    // Follow new and deleted messages in the storage chat as they happen
+    tokio::spawn(live::run_updates(client.clone(), db.clone(), refresh_group_drive.clone(), ui_weak.clone()));
+    tokio::spawn(verify::run_verifier(client.clone(), db.clone(), settings.clone(), ui_weak.clone()));
+    tokio::spawn(api_usage::run_reporter(ui_weak.clone()));
+    
+    // Fetch thumbnails for the grid view on demand
+    let thumbnails_wanted = Arc::new(Notify::new());
+    tokio::spawn(thumbnails::run_fetcher(client.clone(), db.clone(), thumbnails_wanted.clone(), ui_weak.clone()));
+    
+    // Answer commands sent to the companion bot, once a token is set
+    let stored_bot_token = secrets::get(secrets::BOT_TOKEN).unwrap_or_else(|e| {
+        warn!("{:?}", e);
+        None
+    });
+    ui.set_bot_enabled(stored_bot_token.is_some());
+    let (bot_token, bot_token_rx) = tokio::sync::watch::channel(
+        stored_bot_token.map(|t| t.to_string()).unwrap_or_default(),
+    );
+    tokio::spawn(bot::run_bot(
+        bot::BotContext { client: client.clone(), db: db.clone(), queue: queue.clone() },
+        bot_token_rx,
+    ));
+    
+    // Serve stored media to players without downloading it first
+    tokio::spawn(stream::run_stream_server(client.clone(), db.clone()));
+    
+    // Keep the client alive across sleep and network changes
+    tokio::spawn(run_connection_monitor(
+        client.clone(),
+        queue.clone(),
+        reconnect.clone(),
+        connect_params.clone(),
+        ui_weak.clone(),
+    ));
+    
+    // Select file callback
+    {
+        let selected_files = selected_files.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_select_file(move || {
+            let files = rfd::FileDialog::new().pick_files();
+            
+            if let Some(paths) = files.filter(|p| !p.is_empty()) {
+                let label = if paths.len() == 1 {
+                    paths[0].file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("Unknown")
+                        .to_string()
+                } else {
+                    tr!("{count} files selected", count = paths.len())
+                };
+                
+                *selected_files.lock().unwrap() = paths;
+                
+                let ui_clone = ui_weak.clone();
+                if let Some(ui) = ui_clone.upgrade() {
+                    ui.set_selected_file(label.as_str().into());
+                    ui.set_status_text(tr!("Ready to upload.").into());
+                }
+            }
+        });
+    }
+    
+    // Authenticate callback
+    ui.on_check_phone(|country, number| {
+        match phone::normalize(country.max(0) as usize, &number) {
+            Ok(_) => slint::SharedString::new(),
+            Err(e) => e.message().into(),
+        }
+    });
+    let auth_prompt = Arc::new(AuthPrompt::default());
+    {
+        let auth_prompt = auth_prompt.clone();
+        
+        ui.on_submit_auth_answer(move |text| {
+            auth_prompt.answer(Zeroizing::new(text.to_string()));
+        });
+    }
+    {
+        let auth_prompt = auth_prompt.clone();
+        
+        ui.on_cancel_auth(move || auth_prompt.cancel());
+    }
+    {
+        let auth_prompt = auth_prompt.clone();
+        let client = client.clone();
+        let queue = queue.clone();
+        let limits = limits.clone();
+        let connect_params = connect_params.clone();
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_authenticate(move |number| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let country = ui.get_phone_country_index().max(0) as usize;
+            let phone = match phone::normalize(country, &number) {
+                Ok(phone) => phone,
+                Err(e) => {
+                    ui.set_status_text(e.message().into());
+                    return;
+                }
+            };
+            if let Some((iso, _, _)) = phone::COUNTRIES.get(country) {
+                let mut settings = settings.lock().unwrap();
+                if settings.phone_country != *iso {
+                    settings.phone_country = iso.to_string();
+                    if let Err(e) = settings.save(SETTINGS_FILE) {
+                        error!("Failed to save settings: {:?}", e);
+                    }
+                }
+            }
+            let client = client.clone();
+            let queue = queue.clone();
+            let limits = limits.clone();
+            let ui_weak = ui_weak.clone();
+            let params = connect_params.clone();
+            let auth_prompt = auth_prompt.clone();
+            let refresh_group_drive = refresh_group_drive.clone();
+            let thumbnails_wanted = thumbnails_wanted.clone();
+            let grid_view = settings.lock().unwrap().grid_view;
+            
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_is_connecting(true);
+                ui.set_connection_state("connecting".into());
+                ui.set_status_text(tr!("Connecting to Telegram...").into());
+            }
+            
+            tokio::spawn(async move {
+                match init_telegram_client(&params, ui_weak.clone()).await {
+                    Ok(tg_client) => {
+                        match authenticate_with_phone(&tg_client, &phone, &params.api_hash, &auth_prompt, &ui_weak).await {
+                            Ok(_) => {
+                                audit::record(Action::Login, phone.clone(), "");
+                                // Persist the authorization so reconnects don't need a new login
+                                let key = params.session_key.lock().unwrap().clone();
+                                if let Err(e) = session_crypt::save(tg_client.session(), &params.session_file, key.as_ref()) {
+                                    warn!("Failed to save session: {:?}", e);
+                                }
+                                // Known limits let oversized files be flagged as they are queued
+                                match limits.detect(&tg_client).await {
+                                    Ok(premium) => info!("Telegram Premium: {}", premium),
+                                    Err(e) => warn!("Failed to check for Telegram Premium: {:?}", e),
+                                }
+                                
+                                *client.lock().unwrap() = Some(tg_client);
+                                queue.set_online(true);
+                                refresh_group_drive.notify_one();
+                                if grid_view {
+                                    thumbnails_wanted.notify_one();
+                                }
+                                
+                                show_state(&ui_weak, ConnectionState::Connected);
+                                let ui_clone = ui_weak.clone();
+                                if let Some(ui) = ui_clone.upgrade() {
+                                    ui.set_is_authenticated(true);
+                                    ui.set_status_text(tr!("Successfully authenticated!").into());
+                                }
+                            }
+                            Err(e) => {
+                                error!("Authentication failed: {:?}", e);
+                                show_state(&ui_weak, ConnectionState::SignedOut);
+                                let ui_clone = ui_weak.clone();
+                                if let Some(ui) = ui_clone.upgrade() {
+                                    ui.set_status_text(tr!("Auth failed: {error}", error = errors::describe(&e)).into());
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to connect: {:?}", e);
+                        show_state(&ui_weak, ConnectionState::Offline);
+                        let ui_clone = ui_weak.clone();
+                        if let Some(ui) = ui_clone.upgrade() {
+                            ui.set_status_text(tr!("Connection failed: {error}", error = errors::describe(&e)).into());
+                        }
+                    }
+                }
+                
+                let _ = ui_weak.upgrade_in_event_loop(|ui| ui.set_is_connecting(false));
+            });
+        });
+    }
+    
+    // Upload file callback
+    {
+        let selected_files = selected_files.clone();
+        let queue = queue.clone();
+        let limits = limits.clone();
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_upload_file(move || {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let mut paths = std::mem::take(&mut *selected_files.lock().unwrap());
+            let reject_oversized = settings.lock().unwrap().reject_oversized;
+            let mut warnings = Vec::new();
+            // 0 follows the setting, 1 sends images as photos, 2 as documents
+            let send_images_as = match ui_weak.upgrade().map_or(0, |ui| ui.get_upload_images_as()) {
+                1 => Some(SendAs::Photo),
+                2 => Some(SendAs::Document),
+                _ => None,
+            };
+            // Likewise 0 follows the setting, 1 downscales large images, 2 keeps them full size
+            let downscale = match ui_weak.upgrade().map_or(0, |ui| ui.get_upload_downscale()) {
+                1 => Some(true),
+                2 => Some(false),
+                _ => None,
+            };
+            let options = UploadOptions { send_images_as, downscale, ..Default::default() };
+            
+            if settings.lock().unwrap().album_uploads {
+                let (albums, single) = album::group(paths, limits.max_file_size());
+                for paths in albums {
+                    queue.push(TransferKind::Album { paths, options: options.clone() });
+                }
+                paths = single;
+            }
+            
+            for path in paths {
+                // Flag oversized files now instead of failing once the transfer starts
+                let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                let pieces = limits.pieces_for(size);
+                if pieces > 1 {
+                    let name = path.file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("Unknown")
+                        .to_string();
+                    if reject_oversized {
+                        warnings.push(TooLarge { name, size, limit: limits.max_file_size() }.to_string());
+                        continue;
+                    }
+                    warnings.push(tr!("{name} will be split into {count} parts", name = name, count = pieces));
+                }
+                queue.push(TransferKind::Upload { path, options: options.clone() });
+            }
+            
+            update_transfer_list(&ui_weak, &queue);
+            if let Some(ui) = ui_weak.upgrade() {
+                let mut status = queued_status(&queue);
+                for warning in warnings {
+                    status.push_str(". ");
+                    status.push_str(&warning);
+                }
+                ui.set_selected_file("".into());
+                ui.set_status_text(status.into());
+            }
+        });
+    }
+    
+    // Schedule upload callback: hold the selected files back until the given time
+    {
+        let selected_files = selected_files.clone();
+        let scheduler = scheduler.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_schedule_upload(move |start_time| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let start_at = match parse_start_time(&start_time, chrono::Local::now()) {
+                Ok(start_at) => start_at,
+                Err(e) => {
+                    set_status(&ui_weak, tr!("Invalid start time: {error}", error = format!("{:#}", e)));
+                    return;
+                }
+            };
+            
+            let paths = std::mem::take(&mut *selected_files.lock().unwrap());
+            let count = paths.len();
+            for path in paths {
+                scheduler.schedule(path, start_at);
+            }
+            
+            update_scheduled_list(&ui_weak, &scheduler);
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_selected_file("".into());
+                ui.set_status_text(tr!(
+                    "{count} upload(s) scheduled for {time}",
+                    count = count,
+                    time = start_at.format("%Y-%m-%d %H:%M"),
+                ).into());
+            }
+        });
+    }
+    
+    // Cancel scheduled upload callback
+    {
+        let scheduler = scheduler.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_cancel_scheduled(move |id| {
+            scheduler.cancel(id as u64);
+            update_scheduled_list(&ui_weak, &scheduler);
+        });
+    }
+    
+    // Select folder callback: queue every file, mirroring the tree in virtual folders
+    {
+        let queue = queue.clone();
+        let limits = limits.clone();
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_select_folder(move |pack| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let Some(root) = rfd::FileDialog::new().pick_folder() else {
+                return;
+            };
+            
+            let queue = queue.clone();
+            let limits = limits.clone();
+            let settings = settings.clone();
+            let ui_weak = ui_weak.clone();
+            set_status(&ui_weak, tr!("Scanning {path}...", path = root.display()));
+            
+            tokio::spawn(async move {
+                let scan_root = root.clone();
+                let files = match tokio::task::spawn_blocking(move || folder::collect_files(&scan_root)).await {
+                    Ok(Ok(files)) => files,
+                    Ok(Err(e)) => {
+                        error!("Failed to scan {:?}: {:?}", root, e);
+                        set_status(&ui_weak, tr!("Failed to read folder: {error}", error = e));
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Folder scan panicked: {:?}", e);
+                        return;
+                    }
+                };
+                
+                if files.is_empty() {
+                    set_status(&ui_weak, tr!("Folder is empty"));
+                    return;
+                }
+                
+                // Many small files upload far faster as one archive
+                if pack {
+                    set_status(&ui_weak, tr!("Packing {count} files...", count = files.len()));
+                    let pack_root = root.clone();
+                    let level = settings.lock().unwrap().compression.level;
+                    let packed = tokio::task::spawn_blocking(move || archive::pack_folder(&pack_root, &files, level)).await;
+                    
+                    match packed {
+                        Ok(Ok((path, members))) => {
+                            queue.push(TransferKind::Upload {
+                                path,
+                                options: UploadOptions {
+                                    delete_after: true,
+                                    archive_members: members,
+                                    ..Default::default()
+                                },
+                            });
+                            update_transfer_list(&ui_weak, &queue);
+                            set_status(&ui_weak, queued_status(&queue));
+                        }
+                        Ok(Err(e)) => {
+                            error!("Failed to pack {:?}: {:?}", root, e);
+                            set_status(&ui_weak, tr!("Failed to create archive: {error}", error = e));
+                        }
+                        Err(e) => error!("Packing panicked: {:?}", e),
+                    }
+                    return;
+                }
+                
+                let name = root.file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("Folder")
+                    .to_string();
+                let total_bytes = files.iter().map(|f| f.size).sum();
+                let batch = queue.start_batch(name, files.len(), total_bytes);
+                
+                // Photos and videos of the same folder go in albums together
+                let mut files = files;
+                if settings.lock().unwrap().album_uploads {
+                    let mut folders: Vec<String> = files.iter().map(|f| f.folder.clone()).collect();
+                    folders.sort();
+                    folders.dedup();
+                    let max_size = limits.max_file_size();
+                    for folder in folders {
+                        let paths = files.iter().filter(|f| f.folder == folder).map(|f| f.path.clone()).collect();
+                        let (albums, _) = album::group(paths, max_size);
+                        let grouped: HashSet<&PathBuf> = albums.iter().flatten().collect();
+                        files.retain(|f| f.folder != folder || !grouped.contains(&f.path));
+                        for paths in albums {
+                            queue.push(TransferKind::Album {
+                                paths,
+                                options: UploadOptions {
+                                    folder: folder.clone(),
+                                    batch: Some(batch),
+                                    ..Default::default()
+                                },
+                            });
+                        }
+                    }
+                }
+                
+                for file in files {
+                    queue.push(TransferKind::Upload {
+                        path: file.path,
+                        options: UploadOptions {
+                            folder: file.folder,
+                            batch: Some(batch),
+                            ..Default::default()
+                        },
+                    });
+                }
+                
+                update_transfer_list(&ui_weak, &queue);
+                set_status(&ui_weak, queued_status(&queue));
+            });
+        });
+    }
+    
+    // Paste from clipboard callback
+    {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_paste_clipboard(move || {
+            if !writable(&ui_weak) {
+                return;
+            }
+            match clipboard::paste_files() {
+                Ok(files) => {
+                    for file in files {
+                        queue.push(TransferKind::Upload {
+                            path: file.path,
+                            options: UploadOptions {
+                                delete_after: file.temporary,
+                                ..Default::default()
+                            },
+                        });
+                    }
+                    
+                    update_transfer_list(&ui_weak, &queue);
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_status_text(queued_status(&queue).into());
+                    }
+                }
+                Err(e) => {
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_status_text(tr!("Nothing to paste: {error}", error = e).into());
+                    }
+                }
+            }
+        });
+    }
+    
+    // Screenshot callbacks: the whole screen, or a region picked from it,
+    // kept in `region_shot` while it is being picked
+    let region_shot: Rc<RefCell<Option<PathBuf>>> = Rc::new(RefCell::new(None));
+    {
+        let queue = queue.clone();
+        let region_shot = region_shot.clone();
+        let ui_weak = ui_weak.clone();
+        
+        let capture = move |pick_region: bool| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            // Get our own window out of the shot first
+            if let Some(ui) = ui_weak.upgrade() {
+                let _ = ui.hide();
+            }
+            
+            let queue = queue.clone();
+            let region_shot = region_shot.clone();
+            let ui_weak = ui_weak.clone();
+            slint::Timer::single_shot(SCREENSHOT_DELAY, move || {
+                let result = screenshot::capture_primary_screen();
+                
+                if let Some(ui) = ui_weak.upgrade() {
+                    let _ = ui.show();
+                    match result {
+                        Ok(path) if pick_region => {
+                            let (width, height) = image::image_dimensions(&path).unwrap_or((1, 1));
+                            match slint::Image::load_from_path(&path) {
+                                Ok(image) => {
+                                    ui.set_region_shot(image);
+                                    ui.set_region_shot_ratio(width as f32 / height.max(1) as f32);
+                                    ui.set_region_shot_open(true);
+                                    *region_shot.borrow_mut() = Some(path);
+                                }
+                                Err(e) => {
+                                    error!("Failed to show the screenshot: {:?}", e);
+                                    ui.set_status_text(tr!("Screenshot failed: {error}", error = e).into());
+                                }
+                            }
+                        }
+                        Ok(path) => queue_screenshot(&ui, &queue, path),
+                        Err(e) => {
+                            error!("Screenshot failed: {:?}", e);
+                            ui.set_status_text(tr!("Screenshot failed: {error}", error = e).into());
+                        }
+                    }
+                }
+            });
+        };
+        let capture_region = capture.clone();
+        ui.on_capture_screenshot(move || capture(false));
+        ui.on_capture_region(move || capture_region(true));
+    }
+    {
+        let queue = queue.clone();
+        let region_shot = region_shot.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_region_picked(move |x, y, width, height| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.set_region_shot_open(false);
+            ui.set_region_shot(slint::Image::default());
+            let Some(path) = region_shot.borrow_mut().take() else { return };
+            match screenshot::crop(&path, x, y, width, height) {
+                Ok(()) => queue_screenshot(&ui, &queue, path),
+                Err(e) => {
+                    error!("Failed to crop the screenshot: {:?}", e);
+                    ui.set_status_text(tr!("Screenshot failed: {error}", error = e).into());
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_region_cancelled(move || {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.set_region_shot_open(false);
+            ui.set_region_shot(slint::Image::default());
+            if let Some(path) = region_shot.borrow_mut().take() {
+                let _ = std::fs::remove_file(path);
+            }
+        });
+    }
+    
+    // Download file callbacks: into the default downloads folder when one is
+    // set, otherwise (and always for "Save as...") wherever the user picks
+    {
+        let db = db.clone();
+        let queue = queue.clone();
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_download_file(move |key, ask| {
+            let Some((key, record)) = row_file(&db, &key) else {
+                return;
+            };
+            
+            let download_dir = settings.lock().unwrap().download_dir.clone();
+            let dest = match download_dir {
+                Some(dir) if !ask => folder::local_path(&dir, &record.filename)
+                    .and_then(|dest| CollisionPrompt::new([dest.as_path()]).resolve(dest)),
+                dir => {
+                    let mut dialog = rfd::FileDialog::new().set_file_name(&record.filename);
+                    if let Some(dir) = dir {
+                        dialog = dialog.set_directory(dir);
+                    }
+                    dialog.save_file()
+                }
+            };
+            
+            if let Some(dest) = dest {
+                queue.push(TransferKind::Download {
+                    message_id: key.message_id,
+                    chat: key.chat,
+                    filename: record.filename,
+                    dest,
+                    batch: None,
+                    open_after: false,
+                });
+                
+                update_transfer_list(&ui_weak, &queue);
+                if let Some(ui) = ui_weak.upgrade() {
+                    ui.set_status_text(queued_status(&queue).into());
+                }
+            }
+        });
+    }
+    
+    // Context menu actions on a single file
+    {
+        let db = db.clone();
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_open_file(move |key| {
+            let Some((key, record)) = row_file(&db, &key) else {
+                return;
+            };
+            let dir = paths::cache_dir(OPEN_DIR);
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                error!("Failed to create {:?}: {:?}", dir, e);
+                return;
+            }
+            
+            // Opened before and unchanged since: no need to fetch it again
+            let Some(dest) = folder::local_path(&dir, &format!("{}_{}_{}", key.chat.unwrap_or(0), key.message_id, record.filename)) else {
+                return;
+            };
+            if std::fs::metadata(&dest).map_or(false, |m| m.len() == record.file_size) {
+                if let Err(e) = open::that(&dest) {
+                    set_status(&ui_weak, tr!("Could not open {name}: {error}", name = record.filename, error = e));
+                    return;
+                }
+                audit::record(Action::Open, record.path(), "");
+                let db = db.clone();
+                let ui_weak = ui_weak.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = db.mark_used(key, true).await {
+                        warn!("Failed to note the use of {}: {:?}", record.filename, e);
+                    }
+                    refresh_file_list(&ui_weak, &db);
+                });
+                return;
+            }
+            queue.push(TransferKind::Download {
+                message_id: key.message_id,
+                chat: key.chat,
+                filename: record.filename,
+                dest,
+                batch: None,
+                open_after: true,
+            });
+            update_transfer_list(&ui_weak, &queue);
+            set_status(&ui_weak, queued_status(&queue));
+        });
+    }
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_rename_file(move |key, name| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let name = name.trim().to_string();
+            if name.is_empty() || name.contains('/') || name.contains('\\') {
+                set_status(&ui_weak, tr!("Enter a file name without slashes"));
+                return;
+            }
+            let Some((key, record)) = row_file(&db, &key) else { return };
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                match db.update_records(&[key], |r| r.filename = name.clone()).await {
+                    Ok(_) => {
+                        audit::record(Action::Rename, record.path(), format!("Renamed to {}", name));
+                        set_status(&ui_weak, tr!("Renamed to {name}", name = name));
+                    }
+                    Err(e) => {
+                        error!("Failed to rename: {:?}", e);
+                        set_status(&ui_weak, tr!("Could not rename: {error}", error = e));
+                    }
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_copy_link(move |key| {
+            let Some((_, record)) = row_file(&db, &key) else {
+                return;
+            };
+            let Some(link) = screenshot::message_link(&record) else {
+                set_status(&ui_weak, tr!("{name} has no link to share: only files in a channel or supergroup have one", name = record.filename));
+                return;
+            };
+            match arboard::Clipboard::new().and_then(|mut c| c.set_text(link)) {
+                Ok(()) => {
+                    audit::record(Action::Share, record.path(), "Link copied");
+                    set_status(&ui_weak, tr!("Link to {name} copied", name = record.filename));
+                }
+                Err(e) => {
+                    warn!("Failed to copy link: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not copy to the clipboard: {error}", error = e));
+                }
+            }
+        });
+    }
+    {
+        let db = db.clone();
+        
+        ui.on_file_details(move |key| {
+            row_file(&db, &key)
+                .map(|(_, record)| file_details(&record))
+                .unwrap_or_default()
+                .into()
+        });
+    }
+    
+    // Cancel transfer callback
+    {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_cancel_transfer(move |id| {
+            if let Some(job) = queue.cancel(id as u64) {
+                if let Some(batch) = job.kind.batch() {
+                    queue.batch_files_done(batch, job.kind.file_count(), 0);
+                }
+
+                // A paused download may have left part of the file on disk
+                if job.resume.parts_done > 0 || job.resume.pieces_done > 0 {
+                    tokio::spawn(async move { remove_partial_download(&job.kind).await });
+                }
+            }
+            update_transfer_list(&ui_weak, &queue);
+        });
+    }
+    
+    // Pause / resume transfer callbacks
+    {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_pause_transfer(move |id| {
+            queue.pause(id as u64);
+            update_transfer_list(&ui_weak, &queue);
+        });
+    }
+    {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_resume_transfer(move |id| {
+            queue.resume(id as u64);
+            update_transfer_list(&ui_weak, &queue);
+        });
+    }
+    
+    // Unfinished transfers of the previous session
+    {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_resume_restored_transfers(move || {
+            info!("Resuming {} transfers from the last session", queue.restored());
+            queue.resume_restored();
+            update_transfer_list(&ui_weak, &queue);
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_restored_transfers(0);
+                ui.set_status_text(queued_status(&queue).into());
+            }
+        });
+    }
+    {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_discard_restored_transfers(move || {
+            for job in queue.discard_restored() {
+                if job.resume.parts_done > 0 || job.resume.pieces_done > 0 {
+                    tokio::spawn(async move { remove_partial_download(&job.kind).await });
+                }
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_restored_transfers(0);
+            }
+        });
+    }
+    
+    // Stream file callback: copy a URL any media player can open
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_stream_file(move |key| {
+            let Some((key, record)) = row_file(&db, &key) else { return };
+            let Some(url) = stream::stream_url(key, &record.filename) else {
+                set_status(&ui_weak, tr!("The streaming server is not running"));
+                return;
+            };
+            
+            match arboard::Clipboard::new().and_then(|mut c| c.set_text(url.clone())) {
+                Ok(()) => set_status(&ui_weak, tr!("Stream URL copied: {url}", url = url)),
+                Err(e) => {
+                    warn!("Failed to copy stream URL: {:?}", e);
+                    set_status(&ui_weak, tr!("Stream URL: {url}", url = url));
+                }
+            }
+        });
+    }
+    
+    // Audio player callbacks
+    let player = match AudioPlayer::new() {
+        Ok(player) => Some(Arc::new(player)),
+        Err(e) => {
+            warn!("Audio playback unavailable: {:?}", e);
+            None
+        }
+    };
+    ui.set_player_available(player.is_some());
+    
+    if let Some(player) = &player {
+        {
+            let player = player.clone();
+            let client = client.clone();
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            
+            ui.on_play_audio(move |key| {
+                let Some(tg_client) = client.lock().unwrap().clone() else {
+                    set_status(&ui_weak, tr!("Connect to Telegram to play audio"));
+                    return;
+                };
+                let Some((key, record)) = row_file(&db, &key) else { return };
+                let chat = match &record.chat {
+                    Some(chat) => chat.name.clone(),
+                    None => tr!("Saved Messages"),
+                };
+                let title = record.filename;
+                let player = player.clone();
+                let db = db.clone();
+                let ui_weak = ui_weak.clone();
+                set_status(&ui_weak, tr!("Loading {name}...", name = title));
+                
+                tokio::spawn(async move {
+                    match stream::resolve(&tg_client, &db, key).await {
+                        Ok(Some((segments, _))) => {
+                            let reader = RemoteReader::new(tokio::runtime::Handle::current(), tg_client, segments);
+                            player.play(title.clone(), reader);
+                            set_status(&ui_weak, tr!("Playing {name}", name = title));
+                        }
+                        Ok(None) => set_status(&ui_weak, tr!("Message {id} no longer exists in {chat}", id = key.message_id, chat = chat)),
+                        Err(e) => {
+                            error!("Failed to start playback: {:?}", e);
+                            set_status(&ui_weak, tr!("Playback failed: {error}", error = errors::describe(&e)));
+                        }
+                    }
+                });
+            });
+        }
+        {
+            let player = player.clone();
+            ui.on_player_toggle_pause(move || player.toggle_pause());
+        }
+        {
+            let player = player.clone();
+            ui.on_player_seek(move |seconds| player.seek(Duration::from_secs_f32(seconds.max(0.0))));
+        }
+        {
+            let player = player.clone();
+            ui.on_player_stop(move || player.stop());
+        }
+    }
+    
+    // Refresh files callback
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_refresh_files(move || {
+            refresh_file_list(&ui_weak, &db);
+        });
+    }
+    {
+        let settings = settings.clone();
+        let thumbnails_wanted = thumbnails_wanted.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_grid_view(move |enabled| {
+            {
+                let mut settings = settings.lock().unwrap();
+                settings.grid_view = enabled;
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!("Failed to save settings: {:?}", e);
+                }
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_grid_view(enabled);
+            }
+            if enabled {
+                thumbnails_wanted.notify_one();
+            }
+        });
+    }
+    
+    // Export a CSV/JSON report of every record
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_export_report(move || {
+            let Some(path) = rfd::FileDialog::new()
+                .set_file_name("teledrive-files.csv")
+                .add_filter("CSV", &["csv"])
+                .add_filter("JSON", &["json"])
+                .save_file()
+            else {
+                return;
+            };
+            let records = db.all_records();
+            match report::export(&records, &path) {
+                Ok(()) => set_status(&ui_weak, tr!("Exported {count} records to {path}", count = records.len(), path = path.display())),
+                Err(e) => {
+                    error!("Failed to export report: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not export: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Import the index of the Node.js TeleDrive from a JSON export
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_import_teledrive(move || {
+            let Some(path) = rfd::FileDialog::new()
+                .add_filter("JSON", &["json"])
+                .pick_file()
+            else {
+                return;
+            };
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                let result = async {
+                    let json = tokio::fs::read_to_string(&path).await?;
+                    let import = teledrive_import::parse(&json)?;
+                    let found = import.records.len();
+                    let added = db.import_records(import.records).await?;
+                    anyhow::Ok((found, added, import.skipped))
+                }.await;
+                match result {
+                    Ok((found, added, skipped)) => set_status(&ui_weak, tr!(
+                        "Imported {added} of {found} files ({skipped} skipped)",
+                        added = added, found = found, skipped = skipped,
+                    )),
+                    Err(e) => {
+                        error!("Failed to import {:?}: {:?}", path, e);
+                        set_status(&ui_weak, tr!("Could not import: {error}", error = format!("{:#}", e)));
+                    }
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    
+    // Selection callbacks: click, Ctrl+click, Shift+click, Ctrl+A, Escape
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_select_row(move |key, ctrl, shift| {
+            if let Some(ui) = ui_weak.upgrade() {
+                selection::click_row(&ui, key, ctrl, shift);
+            }
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_select_all(move || {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let all: HashSet<slint::SharedString> = ui.get_uploaded_files().iter().map(|f| f.key).filter(|key| !key.is_empty()).collect();
+            selection::show(&ui, &all);
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_clear_selection(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                selection::show(&ui, &HashSet::new());
+            }
+        });
+    }
+    
+    // Batch download: every selected file into one folder, tracked as a batch
+    {
+        let db = db.clone();
+        let queue = queue.clone();
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_download_selected(move || {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let records: Vec<FileRecord> = selection::selected_keys(&ui)
+                .into_iter()
+                .filter_map(|key| db.find(key))
+                .collect();
+            if records.is_empty() {
+                return;
+            }
+            let download_dir = settings.lock().unwrap().download_dir.clone();
+            let Some(dir) = download_dir.or_else(|| rfd::FileDialog::new().pick_folder()) else {
+                return;
+            };
+            
+            // Recreate the virtual folders so equal names don't collide;
+            // paths that would leave the folder are skipped
+            let (records, dests): (Vec<FileRecord>, Vec<PathBuf>) = records.into_iter()
+                .filter_map(|r| {
+                    let dest = folder::local_path(&dir, &r.path());
+                    if dest.is_none() {
+                        warn!("Not downloading {:?}: its path leaves the download folder", r.path());
+                    }
+                    Some((r, dest?))
+                })
+                .unzip();
+            let mut prompt = CollisionPrompt::new(dests.iter().map(PathBuf::as_path));
+            let downloads: Vec<(FileRecord, PathBuf)> = records.into_iter()
+                .zip(dests)
+                .filter_map(|(record, dest)| Some((record, prompt.resolve(dest)?)))
+                .collect();
+            if downloads.is_empty() {
+                return;
+            }
+            
+            let name = tr!("{count} selected files", count = downloads.len());
+            let total_bytes = downloads.iter().map(|(r, _)| r.file_size).sum();
+            let batch = queue.start_batch(name, downloads.len(), total_bytes);
+            for (record, dest) in downloads {
+                if let Some(dest_dir) = dest.parent() {
+                    if let Err(e) = std::fs::create_dir_all(dest_dir) {
+                        error!("Failed to create {:?}: {:?}", dest_dir, e);
+                    }
+                }
+                queue.push(TransferKind::Download {
+                    message_id: record.message_id.unwrap_or_default(),
+                    chat: record.chat.as_ref().map(|c| c.id),
+                    dest,
+                    filename: record.filename,
+                    batch: Some(batch),
+                    open_after: false,
+                });
+            }
+            
+            update_transfer_list(&ui_weak, &queue);
+            ui.set_status_text(queued_status(&queue).into());
+        });
+    }
+    
+    // Batch delete callback (also Del on the selection)
+    {
+        let client = client.clone();
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_delete_selected(move || {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let records: Vec<FileRecord> = selection::selected_keys(&ui)
+                .into_iter()
+                .filter_map(|key| db.find(key))
+                .collect();
+            let description = match records.as_slice() {
+                [] => return,
+                [record] => tr!("Delete {name} from Telegram? This cannot be undone.", name = record.filename),
+                _ => tr!(
+                    "Delete {count} files ({size}) from Telegram? This cannot be undone.",
+                    count = records.len(),
+                    size = format_size(records.iter().map(|r| r.file_size).sum()),
+                ),
+            };
+            let Some(tg_client) = client.lock().unwrap().clone() else {
+                set_status(&ui_weak, tr!("Connect to Telegram to delete files"));
+                return;
+            };
+            
+            let confirmed = rfd::MessageDialog::new()
+                .set_level(rfd::MessageLevel::Warning)
+                .set_title(tr!("Delete files"))
+                .set_description(description)
+                .set_buttons(rfd::MessageButtons::YesNo)
+                .show();
+            if confirmed != rfd::MessageDialogResult::Yes {
+                return;
+            }
+            
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                let mut deleted = 0;
+                for record in &records {
+                    match delete_stored_file(&tg_client, &db, record).await {
+                        Ok(()) => deleted += 1,
+                        Err(e) => error!("Failed to delete {}: {:?}", record.filename, e),
+                    }
+                }
+                match records.as_slice() {
+                    [record] if deleted == 1 => set_status(&ui_weak, tr!("Deleted {name}", name = record.filename)),
+                    _ => set_status(&ui_weak, tr!("Deleted {done} of {total} files", done = deleted, total = records.len())),
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    
+    // Move a virtual folder with its contents (drag onto another folder, or typed)
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_move_folder(move |from, to| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let to = folder::normalize(&to);
+            if from.is_empty() || to == from.as_str() {
+                return;
+            }
+            if folder::moved_path(&to, &from, "").is_some() {
+                set_status(&ui_weak, tr!("Cannot move {folder} into itself", folder = from));
+                return;
+            }
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                match db.move_folder(&from, &to).await {
+                    Ok(moved) => {
+                        audit::record(Action::Move, format!("{}/", from), format!("Moved to /{} with {} files", to, moved));
+                        set_status(&ui_weak, tr!("Moved {folder} ({count} files)", folder = from, count = moved));
+                    }
+                    Err(e) => {
+                        error!("Failed to move folder: {:?}", e);
+                        set_status(&ui_weak, tr!("Could not move files: {error}", error = e));
+                    }
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    
+    // "Send copy to..." callbacks: pick a dialog and forward the selection there
+    let dialogs: Arc<Mutex<Vec<chat_picker::DialogChoice>>> = Arc::new(Mutex::new(Vec::new()));
+    {
+        let client = client.clone();
+        let dialogs = dialogs.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_load_chats(move || {
+            let Some(tg_client) = client.lock().unwrap().clone() else {
+                set_status(&ui_weak, tr!("Connect to Telegram to send files"));
+                if let Some(ui) = ui_weak.upgrade() {
+                    ui.invoke_close_chat_picker();
+                }
+                return;
+            };
+            let dialogs = dialogs.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                match chat_picker::load_dialogs(&tg_client).await {
+                    Ok(loaded) => *dialogs.lock().unwrap() = loaded,
+                    Err(e) => {
+                        error!("Failed to load dialogs: {:?}", e);
+                        set_status(&ui_weak, tr!("Could not load chats: {error}", error = errors::describe(&e)));
+                    }
+                }
+                let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                    let results = chat_picker::filter(&dialogs.lock().unwrap(), &ui.get_chat_query());
+                    ui.set_chat_results(std::rc::Rc::new(slint::VecModel::from(results)).into());
+                    ui.set_chats_loading(false);
+                });
+            });
+        });
+    }
+    {
+        let dialogs = dialogs.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_filter_chats(move |query| {
+            let results = chat_picker::filter(&dialogs.lock().unwrap(), &query);
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_chat_results(std::rc::Rc::new(slint::VecModel::from(results)).into());
+            }
+        });
+    }
+    {
+        let client = client.clone();
+        let db = db.clone();
+        let dialogs = dialogs.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_send_copy(move |index| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.invoke_close_chat_picker();
+            let Some((dest, chat_name)) = dialogs.lock().unwrap()
+                .get(index as usize)
+                .map(|d| (d.chat, d.name.clone()))
+            else {
+                return;
+            };
+            let Some(tg_client) = client.lock().unwrap().clone() else {
+                set_status(&ui_weak, tr!("Connect to Telegram to send files"));
+                return;
+            };
+            let records: Vec<FileRecord> = selection::selected_keys(&ui)
+                .into_iter()
+                .filter_map(|key| db.find(key))
+                .collect();
+            
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                let mut sent = 0;
+                for record in &records {
+                    match forward_stored_file(&tg_client, record, dest).await {
+                        Ok(()) => sent += 1,
+                        Err(e) => error!("Failed to forward {}: {:?}", record.filename, e),
+                    }
+                }
+                set_status(&ui_weak, tr!("Sent {done} of {total} files to {chat}", done = sent, total = records.len(), chat = chat_name));
+            });
+        });
+    }
+    
+    // "Import from chat": index every document in a dialog's history
+    {
+        let client = client.clone();
+        let db = db.clone();
+        let dialogs = dialogs.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_import_from_chat(move |index| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.invoke_close_chat_picker();
+            let Some((chat, chat_name)) = dialogs.lock().unwrap()
+                .get(index as usize)
+                .map(|d| (d.chat, d.name.clone()))
+            else {
+                return;
+            };
+            let Some(tg_client) = client.lock().unwrap().clone() else {
+                set_status(&ui_weak, tr!("Connect to Telegram to import files"));
+                return;
+            };
+            
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                set_status(&ui_weak, tr!("Scanning {chat}...", chat = chat_name));
+                let scanned = chat_import::scan(&tg_client, chat, &chat_name, |messages, found| {
+                    set_status(&ui_weak, tr!(
+                        "Scanning {chat}: {messages} messages, {found} documents...",
+                        chat = chat_name, messages = messages, found = found,
+                    ));
+                }).await;
+                let records = match scanned {
+                    Ok(records) if records.is_empty() => {
+                        set_status(&ui_weak, tr!("No documents found in {chat}", chat = chat_name));
+                        return;
+                    }
+                    Ok(records) => records,
+                    Err(e) => {
+                        error!("Failed to scan {}: {:?}", chat_name, e);
+                        set_status(&ui_weak, tr!("Could not import: {error}", error = errors::describe(&e)));
+                        return;
+                    }
+                };
+                
+                // Ask on the UI thread, where the dialog belongs
+                let _ = ui_weak.clone().upgrade_in_event_loop(move |_| {
+                    let confirmed = rfd::MessageDialog::new()
+                        .set_title(tr!("Import from chat"))
+                        .set_description(tr!(
+                            "Add {count} documents ({size}) from {chat} to the index?",
+                            count = records.len(),
+                            size = format_size(records.iter().map(|r| r.file_size).sum()),
+                            chat = chat_name,
+                        ))
+                        .set_buttons(rfd::MessageButtons::YesNo)
+                        .show();
+                    if confirmed != rfd::MessageDialogResult::Yes {
+                        return;
+                    }
+                    tokio::spawn(async move {
+                        let found = records.len();
+                        match db.import_records(records).await {
+                            Ok(added) => set_status(&ui_weak, tr!(
+                                "Added {added} of {found} documents from {chat}; the rest were already indexed",
+                                added = added, found = found, chat = chat_name,
+                            )),
+                            Err(e) => {
+                                error!("Failed to import from {}: {:?}", chat_name, e);
+                                set_status(&ui_weak, tr!("Could not import: {error}", error = e));
+                            }
+                        }
+                        refresh_file_list(&ui_weak, &db);
+                    });
+                });
+            });
+        });
+    }
+    
+    // Shared group drive: store uploads in a group and index what its members share
+    {
+        let dialogs = dialogs.clone();
+        let settings = settings.clone();
+        let refresh_group_drive = refresh_group_drive.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_group_drive(move |index| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.invoke_close_chat_picker();
+            let Some((chat, name)) = dialogs.lock().unwrap()
+                .get(index as usize)
+                .map(|d| (d.chat, d.name.clone()))
+            else {
+                return;
+            };
+            if !group_drive::is_group(chat) {
+                set_status(&ui_weak, tr!("{chat} is not a group; pick a group or supergroup", chat = name));
+                return;
+            }
+            
+            let drive = ChatRef::new(&name, chat);
+            {
+                let mut settings = settings.lock().unwrap();
+                settings.group_drive = Some(drive.clone());
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!("Failed to save settings: {:?}", e);
+                }
+            }
+            group_drive::set(Some(drive));
+            ui.set_group_drive(name.clone().into());
+            set_status(&ui_weak, tr!("New uploads go to {group}; scanning it for shared files...", group = name));
+            refresh_group_drive.notify_one();
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_leave_group_drive(move || {
+            {
+                let mut settings = settings.lock().unwrap();
+                settings.group_drive = None;
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!("Failed to save settings: {:?}", e);
+                }
+            }
+            group_drive::set(None);
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_group_drive("".into());
+            }
+            set_status(&ui_weak, tr!("New uploads go to Saved Messages"));
+        });
+    }
+    // Chat `upload --via-bot` sends files to
+    {
+        let dialogs = dialogs.clone();
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_bot_upload_chat(move |index| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.invoke_close_chat_picker();
+            let Some((chat, name)) = dialogs.lock().unwrap()
+                .get(index as usize)
+                .map(|d| (d.chat, d.name.clone()))
+            else {
+                return;
+            };
+            if !bot::can_store_in(chat) {
+                set_status(&ui_weak, tr!("{chat} is not a channel or supergroup", chat = name));
+                return;
+            }
+            
+            {
+                let mut settings = settings.lock().unwrap();
+                settings.bot_upload_chat = Some(ChatRef::new(&name, chat));
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!("Failed to save settings: {:?}", e);
+                }
+            }
+            ui.set_bot_upload_chat(name.clone().into());
+            set_status(&ui_weak, tr!("Bot uploads go to {chat}; add the bot there as an admin", chat = name));
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_clear_bot_upload_chat(move || {
+            {
+                let mut settings = settings.lock().unwrap();
+                settings.bot_upload_chat = None;
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!("Failed to save settings: {:?}", e);
+                }
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_bot_upload_chat("".into());
+            }
+        });
+    }
+    {
+        let refresh_group_drive = refresh_group_drive.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_refresh_group_drive(move || {
+            set_status(&ui_weak, tr!("Scanning the group drive..."));
+            refresh_group_drive.notify_one();
+        });
+    }
+    
+    // Batch edits of the index: move to a virtual folder, add a tag
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_move_selected(move |folder| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let keys = selection::selected_keys(&ui);
+            let folder = folder::normalize(&folder);
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                let moving: Vec<String> = keys.iter().filter_map(|key| db.find(*key)).map(|r| r.path()).collect();
+                match db.update_records(&keys, |r| r.folder = folder.clone()).await {
+                    Ok(moved) => {
+                        for path in moving {
+                            audit::record(Action::Move, path, format!("Moved to /{}", folder));
+                        }
+                        set_status(&ui_weak, tr!("Moved {count} files", count = moved));
+                    }
+                    Err(e) => {
+                        error!("Failed to move files: {:?}", e);
+                        set_status(&ui_weak, tr!("Could not move files: {error}", error = e));
+                    }
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_tag_selected(move |tag| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let tag = tag.trim().to_string();
+            let Some(ui) = ui_weak.upgrade() else { return };
+            if tag.is_empty() {
+                return;
+            }
+            let keys = selection::selected_keys(&ui);
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                let result = db.update_records(&keys, |r| {
+                    if !r.tags.contains(&tag) {
+                        r.tags.push(tag.clone());
+                    }
+                }).await;
+                match result {
+                    Ok(tagged) => set_status(&ui_weak, tr!("Tagged {count} files with {tag}", count = tagged, tag = tag)),
+                    Err(e) => {
+                        error!("Failed to tag files: {:?}", e);
+                        set_status(&ui_weak, tr!("Could not tag files: {error}", error = e));
+                    }
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_toggle_star(move |key| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let Some((key, record)) = row_file(&db, &key) else { return };
+            let starred = !record.starred;
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                if let Err(e) = db.update_records(&[key], |r| r.starred = starred).await {
+                    error!("Failed to star {}: {:?}", record.filename, e);
+                    set_status(&ui_weak, tr!("Could not star {name}: {error}", name = record.filename, error = e));
+                }
+                refresh_file_list(&ui_weak, &db);
+            });
+        });
+    }
+    
+    // Duplicate finder callbacks
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_find_duplicates(move || {
+            update_duplicates(&ui_weak, &db);
+        });
+    }
+    {
+        let client = client.clone();
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_delete_duplicates(move |hash| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let Some(tg_client) = client.lock().unwrap().clone() else {
+                set_status(&ui_weak, tr!("Connect to Telegram to delete files"));
+                return;
+            };
+            let redundant: Vec<FileRecord> = duplicates::find_duplicates(&db.all_records())
+                .iter()
+                .filter(|set| hash.is_empty() || set.sha256 == hash.as_str())
+                .flat_map(|set| set.redundant().to_vec())
+                .collect();
+            if redundant.is_empty() {
+                return;
+            }
+            
+            let confirmed = rfd::MessageDialog::new()
+                .set_level(rfd::MessageLevel::Warning)
+                .set_title(tr!("Delete duplicates"))
+                .set_description(tr!(
+                    "Delete {count} redundant copies ({size}) from Telegram, keeping the oldest of each?",
+                    count = redundant.len(),
+                    size = format_size(redundant.iter().map(|r| r.file_size).sum()),
+                ))
+                .set_buttons(rfd::MessageButtons::YesNo)
+                .show();
+            if confirmed != rfd::MessageDialogResult::Yes {
+                return;
+            }
+            
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                let mut deleted = 0;
+                for record in &redundant {
+                    match delete_stored_file(&tg_client, &db, record).await {
+                        Ok(()) => deleted += 1,
+                        Err(e) => error!("Failed to delete duplicate {}: {:?}", record.filename, e),
+                    }
+                }
+                set_status(&ui_weak, tr!("Deleted {done} of {total} redundant copies", done = deleted, total = redundant.len()));
+                refresh_file_list(&ui_weak, &db);
+                update_duplicates(&ui_weak, &db);
+            });
+        });
+    }
+    
+    // Index check callback
+    {
+        let client = client.clone();
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_check_index(move |repair| {
+            if repair {
+                let confirmed = rfd::MessageDialog::new()
+                    .set_level(rfd::MessageLevel::Warning)
+                    .set_title(tr!("Repair index"))
+                    .set_description(tr!("Rebuild damaged records from Telegram, and drop duplicate records and files whose messages were deleted?"))
+                    .set_buttons(rfd::MessageButtons::YesNo)
+                    .show();
+                if confirmed != rfd::MessageDialogResult::Yes {
+                    return;
+                }
+            }
+            let tg_client = client.lock().unwrap().clone();
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                set_status(&ui_weak, tr!("Checking the index..."));
+                let report = match fsck::check(tg_client.as_ref(), &db, repair).await {
+                    Ok(report) => report,
+                    Err(e) => {
+                        error!("Index check failed: {:?}", e);
+                        set_status(&ui_weak, tr!("Index check failed: {error}", error = format!("{:#}", e)));
+                        return;
+                    }
+                };
+                let repaired = report.issues.iter().filter(|i| i.repaired).count();
+                let summary = if report.issues.is_empty() {
+                    tr!("No issues in {count} records", count = report.records)
+                } else {
+                    tr!("{count} issue(s), {repaired} repaired", count = report.issues.len(), repaired = repaired)
+                };
+                let issues: Vec<slint::SharedString> = report.issues.iter()
+                    .filter(|i| !i.repaired)
+                    .map(|i| format!("{}: {}", i.subject, i.detail).into())
+                    .collect();
+                set_status(&ui_weak, summary.clone());
+                let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                    ui.set_check_issues(std::rc::Rc::new(slint::VecModel::from(issues)).into());
+                    ui.set_check_summary(summary.into());
+                });
+                if repaired > 0 {
+                    refresh_file_list(&ui_weak, &db);
+                }
+            });
+        });
+    }
+    
+    // Command palette callbacks: fuzzy search over actions and files
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_palette_search(move |query| {
+            let results = palette::search(&query, &db.all_records());
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_palette_results(std::rc::Rc::new(slint::VecModel::from(results)).into());
+            }
+        });
+    }
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_palette_activate(move |entry| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            ui.invoke_close_palette();
+            match entry.action.as_str() {
+                palette::FILE_ACTION => {
+                    // Narrow the list down to the file and select it once reloaded
+                    if let Some((_, record)) = row_file(&db, &entry.key) {
+                        ui.set_search_text(record.filename.into());
+                        ui.set_selection_anchor(entry.key.clone());
+                        refresh_file_list(&ui_weak, &db);
+                        let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                            selection::show(&ui, &HashSet::from([entry.key]));
+                        });
+                    }
+                }
+                "select-file" => ui.invoke_select_file(),
+                "select-folder" => ui.invoke_select_folder(ui.get_pack_folders()),
+                "upload" if ui.get_selected_file() != "" => ui.invoke_upload_file(),
+                "upload" => set_status(&ui_weak, tr!("Select files to upload first")),
+                "paste" => ui.invoke_paste_clipboard(),
+                "screenshot" => ui.invoke_capture_screenshot(),
+                "screenshot-region" => ui.invoke_capture_region(),
+                "search" => ui.invoke_focus_search(),
+                "refresh" => ui.invoke_refresh_files(),
+                "clear-history" => ui.invoke_clear_history(),
+                other => warn!("Unknown palette action {:?}", other),
+            }
+        });
+    }
+    
+    // App lock callbacks
+    let last_activity = Rc::new(Cell::new(Instant::now()));
+    // Passphrase-derived session key, known once unlocked or a passphrase is set
+    let passphrase_key: Rc<RefCell<Option<SessionKey>>> = Rc::new(RefCell::new(None));
+    {
+        let last_activity = last_activity.clone();
+        
+        ui.on_user_active(move || last_activity.set(Instant::now()));
+    }
+    ui.on_app_locked(|| app_lock::set_locked(true));
+    {
+        let settings = settings.clone();
+        let last_activity = last_activity.clone();
+        let passphrase_key = passphrase_key.clone();
+        let session_key = connect_params.session_key.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_unlock(move |passphrase| {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let (lock, encryption) = {
+                let settings = settings.lock().unwrap();
+                (settings.app_lock.clone(), settings.session_encryption)
+            };
+            let Some(lock) = lock else {
+                ui.set_locked(false);
+                app_lock::set_locked(false);
+                return;
+            };
+            if !lock.verify(&passphrase) {
+                warn!("Wrong app lock passphrase");
+                ui.set_unlock_error(tr!("Wrong passphrase").into());
+                return;
+            }
+            if passphrase_key.borrow().is_none() {
+                match lock.session_key(&passphrase) {
+                    Ok(key) => {
+                        if encryption == Some(KeySource::Passphrase) {
+                            *session_key.lock().unwrap() = Some(key.clone());
+                        }
+                        *passphrase_key.borrow_mut() = Some(key);
+                    }
+                    Err(e) => error!("Failed to derive session key: {:?}", e),
+                }
+            }
+            last_activity.set(Instant::now());
+            ui.set_unlock_error("".into());
+            ui.set_locked(false);
+            app_lock::set_locked(false);
+        });
+    }
+    {
+        let settings = settings.clone();
+        let passphrase_key = passphrase_key.clone();
+        let params = connect_params.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_app_lock(move |passphrase, repeated, idle_minutes| {
+            if passphrase != repeated {
+                set_status(&ui_weak, tr!("The passphrases do not match"));
+                return;
+            }
+            let result = AppLock::new(&passphrase, idle_minutes.max(0) as u32).and_then(|lock| {
+                let key = lock.session_key(&passphrase)?;
+                Ok((lock, key))
+            });
+            let (lock, key) = match result {
+                Ok(lock_and_key) => lock_and_key,
+                Err(e) => {
+                    error!("Failed to set app lock: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not set the app lock: {error}", error = e));
+                    return;
+                }
+            };
+            let mut settings = settings.lock().unwrap();
+            // A new passphrase means a new key for a session protected by the old one
+            if settings.session_encryption == Some(KeySource::Passphrase) {
+                let mut session_key = params.session_key.lock().unwrap();
+                if let Err(e) = session_crypt::reencrypt(&params.session_file, session_key.as_ref(), Some(&key)) {
+                    error!("Failed to re-encrypt session: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not set the app lock: {error}", error = e));
+                    return;
+                }
+                *session_key = Some(key.clone());
+            }
+            *passphrase_key.borrow_mut() = Some(key);
+            settings.app_lock = Some(lock);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_app_lock_enabled(true);
+            }
+            set_status(&ui_weak, tr!("App lock enabled"));
+        });
+    }
+    {
+        let settings = settings.clone();
+        let passphrase_key = passphrase_key.clone();
+        let params = connect_params.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_remove_app_lock(move || {
+            let mut settings = settings.lock().unwrap();
+            // Without a passphrase there is nothing left to derive the key from
+            if settings.session_encryption == Some(KeySource::Passphrase) {
+                let mut session_key = params.session_key.lock().unwrap();
+                if let Err(e) = session_crypt::reencrypt(&params.session_file, session_key.as_ref(), None) {
+                    error!("Failed to decrypt session: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not decrypt the session: {error}", error = e));
+                    return;
+                }
+                *session_key = None;
+                settings.session_encryption = None;
+            }
+            *passphrase_key.borrow_mut() = None;
+            settings.app_lock = None;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_app_lock_enabled(false);
+                ui.set_session_encryption_index(KeySource::index(settings.session_encryption));
+            }
+            set_status(&ui_weak, tr!("App lock removed"));
+        });
+    }
+    {
+        let settings = settings.clone();
+        let passphrase_key = passphrase_key.clone();
+        let params = connect_params.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_session_encryption(move |index| {
+            let source = KeySource::from_index(index);
+            let mut settings = settings.lock().unwrap();
+            let new_key = match source {
+                None => Ok(None),
+                Some(KeySource::Keyring) => session_crypt::keyring_key().map(Some),
+                Some(KeySource::Passphrase) => match passphrase_key.borrow().clone() {
+                    Some(key) => Ok(Some(key)),
+                    None => Err(anyhow::anyhow!("Set an app lock passphrase first")),
+                },
+            };
+            let mut session_key = params.session_key.lock().unwrap();
+            let result = new_key.and_then(|new_key| {
+                session_crypt::reencrypt(&params.session_file, session_key.as_ref(), new_key.as_ref())?;
+                Ok(new_key)
+            });
+            match result {
+                Ok(new_key) => {
+                    *session_key = new_key;
+                    settings.session_encryption = source;
+                    if let Err(e) = settings.save(SETTINGS_FILE) {
+                        error!("Failed to save settings: {:?}", e);
+                    }
+                    info!("Session encryption: {:?}", source);
+                    set_status(&ui_weak, match source {
+                        Some(_) => tr!("The session file is now encrypted"),
+                        None => tr!("The session file is now stored unencrypted"),
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to change session encryption: {:?}", e);
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_session_encryption_index(KeySource::index(settings.session_encryption));
+                    }
+                    set_status(&ui_weak, tr!("Could not change session encryption: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Auto-start setting callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_auto_start(move |enabled| {
+            if let Err(e) = autostart::set_enabled(enabled) {
+                error!("Failed to update auto-start: {:?}", e);
+                if let Some(ui) = ui_weak.upgrade() {
+                    ui.set_auto_start(!enabled);
+                    ui.set_status_text(tr!("Could not change launch at login: {error}", error = e).into());
+                }
+                return;
+            }
+            
+            let mut settings = settings.lock().unwrap();
+            settings.auto_start = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Hook commands callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_hooks(move |pre_upload, post_upload, post_download| {
+            let mut settings = settings.lock().unwrap();
+            settings.hooks = hooks::Hooks {
+                pre_upload: pre_upload.trim().to_string(),
+                post_upload: post_upload.trim().to_string(),
+                post_download: post_download.trim().to_string(),
+            };
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            set_status(&ui_weak, tr!("Hooks saved"));
+        });
+    }
+    
+    // Default downloads folder callbacks
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_choose_download_dir(move || {
+            let Some(dir) = rfd::FileDialog::new().pick_folder() else {
+                return;
+            };
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_download_dir(dir.to_string_lossy().to_string().into());
+            }
+            let mut settings = settings.lock().unwrap();
+            settings.download_dir = Some(dir);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_clear_download_dir(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_download_dir("".into());
+            }
+            let mut settings = settings.lock().unwrap();
+            settings.download_dir = None;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_open_containing_folder(move |path| {
+            let path = PathBuf::from(path.as_str());
+            if !path.exists() {
+                set_status(&ui_weak, tr!("{path} no longer exists", path = path.display()));
+                return;
+            }
+            if let Err(e) = reveal(&path) {
+                warn!("Failed to show {:?}: {:?}", path, e);
+            }
+        });
+    }
+    
+    // Webhook callbacks
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_webhook(move |url| {
+            let url = url.trim().to_string();
+            if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+                set_status(&ui_weak, tr!("The webhook URL must start with http:// or https://"));
+                return;
+            }
+            let mut settings = settings.lock().unwrap();
+            settings.webhook_url = url;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            set_status(&ui_weak, tr!("Webhook saved"));
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_test_webhook(move |url| {
+            let url = url.trim().to_string();
+            let ui_weak = ui_weak.clone();
+            tokio::spawn(async move {
+                match webhooks::send(&url, &WebhookEvent::Test).await {
+                    Ok(()) => set_status(&ui_weak, tr!("The webhook answered")),
+                    Err(e) => {
+                        warn!("Webhook test failed: {:?}", e);
+                        set_status(&ui_weak, tr!("Webhook test failed: {error}", error = errors::describe(&e)));
+                    }
+                }
+            });
+        });
+    }
+    
+    // Companion bot callback; an empty token turns the bot off
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_bot_token(move |token| {
+            let token = token.trim().to_string();
+            let result = if token.is_empty() {
+                secrets::delete(secrets::BOT_TOKEN)
+            } else {
+                secrets::set(secrets::BOT_TOKEN, &token)
+            };
+            if let Err(e) = result {
+                error!("Failed to store bot token: {:?}", e);
+                set_status(&ui_weak, tr!("Could not store the bot token: {error}", error = e));
+                return;
+            }
+            let enabled = !token.is_empty();
+            bot_token.send_replace(token);
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_bot_enabled(enabled);
+            }
+            set_status(&ui_weak, if enabled {
+                tr!("Bot remote control enabled; send /help to your bot")
+            } else {
+                tr!("Bot remote control turned off")
+            });
+        });
+    }
+    
+    // Update check callbacks
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_check_updates(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.check_updates = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            if enabled {
+                check_updates_now.notify_one();
+            }
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_dismiss_update(move || {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let mut settings = settings.lock().unwrap();
+            settings.dismissed_update = ui.get_update_version().to_string();
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            ui.set_update_version("".into());
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_open_update(move |url| {
+            if let Err(e) = open::that(url.as_str()) {
+                error!("Failed to open {}: {:?}", url, e);
+                set_status(&ui_weak, tr!("Could not open the browser: {error}", error = e));
+            }
+        });
+    }
+    
+    // Read-only mode setting callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_read_only(move |enabled| {
+            {
+                let mut settings = settings.lock().unwrap();
+                settings.read_only = enabled;
+                if let Err(e) = settings.save(SETTINGS_FILE) {
+                    error!("Failed to save settings: {:?}", e);
+                }
+            }
+            read_only::set(enabled);
+            set_status(&ui_weak, if enabled {
+                tr!("Read-only mode on: browsing and downloading only")
+            } else {
+                tr!("Read-only mode off")
+            });
+        });
+    }
+    
+    // Oversized file setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_reject_oversized(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.reject_oversized = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Profile callbacks: start the app again as another profile, new or existing
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_switch_profile(move |name| {
+            // An empty name is the default profile
+            let name = Some(name.as_str()).filter(|n| !n.is_empty());
+            if name == profile::current() {
+                return;
+            }
+            if let Some(name) = name {
+                if let Err(e) = profile::validate(name) {
+                    set_status(&ui_weak, format!("{:#}", e));
+                    return;
+                }
+            }
+            match profile::launch(name) {
+                Ok(()) => {
+                    info!("Switching to profile {:?}", name);
+                    let _ = slint::quit_event_loop();
+                }
+                Err(e) => {
+                    error!("Failed to switch profile: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not switch profile: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Hidden file names setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_hide_names(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.hide_names = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Upload encryption setting callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_encrypt_uploads(move |enabled| {
+            let first_key = enabled && !encryption::Keys::exist().unwrap_or(true);
+            // Creates the first key, so a keyring problem shows up right away
+            let keys = match encryption::Keys::load() {
+                Ok(keys) => keys,
+                Err(e) => {
+                    error!("Failed to load the encryption keys: {:?}", e);
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_encrypt_uploads(false);
+                    }
+                    set_status(&ui_weak, tr!("Could not set up encryption: {error}", error = e));
+                    return;
+                }
+            };
+            let mut settings = settings.lock().unwrap();
+            settings.encrypt_uploads = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_encryption_key_id(keys.current_id().into());
+                // Written down now, before anything depends on the key
+                if first_key {
+                    show_recovery_phrase(&ui, &keys);
+                }
+            }
+            set_status(&ui_weak, match enabled {
+                true => tr!("New uploads are encrypted; export the key so they can't be lost with this computer"),
+                false => tr!("New uploads are stored unencrypted"),
+            });
+        });
+    }
+    
+    // Encryption key export callback
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_export_encryption_key(move || {
+            let result = encryption::Keys::load().and_then(|keys| {
+                let name = format!("teledrive-key-{}.json", keys.current_id());
+                let Some(path) = rfd::FileDialog::new().set_file_name(name).save_file() else {
+                    return Ok(None);
+                };
+                keys.export(&path)?;
+                Ok(Some(path))
+            });
+            match result {
+                Ok(Some(path)) => set_status(&ui_weak, tr!("Key saved to {path}; keep it somewhere safe", path = path.display())),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to export the encryption key: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not export the key: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Encryption key import callback
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_import_encryption_key(move || {
+            let Some(path) = rfd::FileDialog::new().add_filter("Key", &["json"]).pick_file() else {
+                return;
+            };
+            let result = encryption::import(&path).and_then(|key| {
+                let mut keys = encryption::Keys::load()?;
+                keys.adopt(key)?;
+                Ok(keys.current_id())
+            });
+            match result {
+                Ok(id) => {
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_encryption_key_id(id.clone().into());
+                    }
+                    set_status(&ui_weak, tr!("Imported key {id}; new uploads use it", id = id));
+                }
+                Err(e) => {
+                    error!("Failed to import {:?}: {:?}", path, e);
+                    set_status(&ui_weak, tr!("Could not import the key: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Encryption key rotation callback
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_rotate_encryption_key(move || {
+            let confirmed = rfd::MessageDialog::new()
+                .set_title(tr!("New encryption key"))
+                .set_description(tr!("Encrypt new uploads with a new key? Files stored so far keep opening with the old one until you re-encrypt them: select them and choose Re-encrypt."))
+                .set_buttons(rfd::MessageButtons::YesNo)
+                .show();
+            if confirmed != rfd::MessageDialogResult::Yes {
+                return;
+            }
+            let rotated = encryption::Keys::load().and_then(|mut keys| {
+                let id = keys.rotate()?;
+                Ok((keys, id))
+            });
+            match rotated {
+                Ok((keys, id)) => {
+                    info!("Rotated the encryption key to {}", id);
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_encryption_key_id(id.clone().into());
+                        show_recovery_phrase(&ui, &keys);
+                    }
+                    set_status(&ui_weak, tr!("New uploads use key {id}; export it to keep a copy", id = id));
+                }
+                Err(e) => {
+                    error!("Failed to rotate the encryption key: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not create a new key: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Recovery phrase callbacks: show the current key as words, or restore
+    // a key from words written down earlier
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_show_recovery_phrase(move || {
+            let Some(ui) = ui_weak.upgrade() else { return };
+            match encryption::Keys::load() {
+                Ok(keys) => show_recovery_phrase(&ui, &keys),
+                Err(e) => set_status(&ui_weak, tr!("Could not set up encryption: {error}", error = e)),
+            }
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_restore_from_phrase(move |phrase| {
+            let result = encryption::from_phrase(&phrase).and_then(|key| {
+                let mut keys = encryption::Keys::load()?;
+                keys.adopt(key)?;
+                Ok(keys.current_id())
+            });
+            match result {
+                Ok(id) => {
+                    info!("Restored encryption key {} from its recovery phrase", id);
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_encryption_key_id(id.clone().into());
+                    }
+                    set_status(&ui_weak, tr!("Restored key {id}; files encrypted with it open again", id = id));
+                }
+                Err(e) => {
+                    warn!("Failed to restore a key from a recovery phrase: {:#}", e);
+                    set_status(&ui_weak, tr!("Could not restore the key: {error}", error = e));
+                }
+            }
+        });
+    }
+    
+    // Re-encrypt selected files with the current key, in the background
+    {
+        let db = db.clone();
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_reencrypt_selected(move || {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let Some(ui) = ui_weak.upgrade() else { return };
+            let current = match encryption::Keys::load() {
+                Ok(keys) => keys.current_id(),
+                Err(e) => {
+                    set_status(&ui_weak, tr!("Could not set up encryption: {error}", error = e));
+                    return;
+                }
+            };
+            let records: Vec<FileRecord> = selection::selected_keys(&ui)
+                .into_iter()
+                .filter_map(|key| db.find(key))
+                .filter(|r| r.key_id.as_deref() != Some(current.as_str()))
+                .collect();
+            if records.is_empty() {
+                set_status(&ui_weak, tr!("The selected files already use the current key"));
+                return;
+            }
+            for record in records {
+                queue.push(TransferKind::Reencrypt {
+                    message_id: record.message_id.unwrap_or_default(),
+                    chat: record.chat.as_ref().map(|c| c.id),
+                    filename: record.filename,
+                });
+            }
+            update_transfer_list(&ui_weak, &queue);
+            ui.set_status_text(queued_status(&queue).into());
+        });
+    }
+    
+    // Compression settings callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_compression(move |enabled, level, min_mb, skip| {
+            let rules = CompressionRules {
+                enabled,
+                level: level.clamp(1, 19),
+                min_size: min_mb.max(0) as u64 * 1024 * 1024,
+                skip_extensions: CompressionRules::parse_extensions(&skip),
+            };
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_compression_skip(rules.skip_extensions.join(", ").into());
+            }
+            let mut settings = settings.lock().unwrap();
+            settings.compression = rules;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            set_status(&ui_weak, tr!("Compression settings saved"));
+        });
+    }
+    
+    // Image downscaling settings callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_downscale(move |enabled, max_side, quality, keep_originals| {
+            let mut settings = settings.lock().unwrap();
+            settings.downscale = DownscaleRules {
+                enabled,
+                max_side: max_side.max(1) as u32,
+                quality: quality.clamp(1, 100) as u8,
+                keep_originals,
+            };
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            set_status(&ui_weak, tr!("Image size settings saved"));
+        });
+    }
+    
+    // Chunk deduplication setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_dedup_chunks(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.dedup_chunks = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Album setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_album_uploads(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.album_uploads = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Transfer retry setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_max_retries(move |retries| {
+            let mut settings = settings.lock().unwrap();
+            settings.retry.max_retries = retries.max(0) as u32;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Content indexing setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_index_contents(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.index_contents = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // OCR setting callback
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_ocr_images(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.ocr_images = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_verify_in_background(move |enabled| {
+            let mut settings = settings.lock().unwrap();
+            settings.verify_in_background = enabled;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Language picker callback
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_set_language(move |index| {
+            let Some(code) = i18n::language_at(index as usize) else { return };
+            i18n::set_language(code);
+            if let Some(ui) = ui_weak.upgrade() {
+                i18n::show_language(&ui);
+            }
+            
+            let mut settings = settings.lock().unwrap();
+            settings.language = code.to_string();
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Default for how images are sent
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_images_as(move |index| {
+            let mut settings = settings.lock().unwrap();
+            settings.send_images_as = SendAs::from_index(index);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Theme picker callback; the palette itself is switched on the Slint side
+    {
+        let settings = settings.clone();
+        
+        ui.on_set_theme(move |index| {
+            let mut settings = settings.lock().unwrap();
+            settings.theme = Theme::from_index(index);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    
+    // Log level and log folder callbacks
+    let log = Rc::new(log);
+    {
+        let settings = settings.clone();
+        let log = log.clone();
+        
+        ui.on_set_log_level(move |index| {
+            let level = LogLevel::from_index(index);
+            if let Some(log) = log.as_ref() {
+                log.set_level(level);
+            }
+            let mut settings = settings.lock().unwrap();
+            settings.log_level = level;
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_open_log_folder(move || {
+            if let Err(e) = logging::open_log_dir() {
+                error!("Failed to open log folder: {:?}", e);
+                set_status(&ui_weak, tr!("Could not open the log folder: {error}", error = e));
+            }
+        });
+    }
+    
+    // Backup job callbacks: pick a folder and back it up on the given schedule
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_add_backup_job(move |schedule| {
+            if !writable(&ui_weak) {
+                return;
+            }
+            let schedule = schedule.trim().to_string();
+            if let Err(e) = schedule.parse::<cron::CronSchedule>() {
+                set_status(&ui_weak, tr!("Invalid schedule: {error}", error = format!("{:#}", e)));
+                return;
+            }
+            let Some(source) = rfd::FileDialog::new().pick_folder() else {
+                return;
+            };
+            
+            let mut settings = settings.lock().unwrap();
+            if settings.backup_jobs.iter().any(|job| job.source == source) {
+                set_status(&ui_weak, tr!("{path} is already backed up", path = source.display()));
+                return;
+            }
+            settings.backup_jobs.push(BackupJob {
+                source,
+                schedule,
+                last_run: None,
+            });
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            update_backup_jobs(&ui_weak, &settings);
+        });
+    }
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_remove_backup_job(move |index| {
+            let mut settings = settings.lock().unwrap();
+            if index < 0 || index as usize >= settings.backup_jobs.len() {
+                return;
+            }
+            settings.backup_jobs.remove(index as usize);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            update_backup_jobs(&ui_weak, &settings);
+        });
+    }
+    
+    // Sync pair callbacks
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_add_sync_pair(move |remote_folder, propagate_deletes, include, exclude| {
+            let remote_folder = folder::normalize(&remote_folder);
+            if remote_folder.is_empty() {
+                set_status(&ui_weak, tr!("Enter the virtual folder to sync with"));
+                return;
+            }
+            let include = sync::parse_patterns(&include);
+            let exclude = sync::parse_patterns(&exclude);
+            let Some(local) = rfd::FileDialog::new().pick_folder() else {
+                return;
+            };
+            
+            let mut settings = settings.lock().unwrap();
+            if settings.sync_pairs.iter().any(|pair| pair.local == local) {
+                set_status(&ui_weak, tr!("{path} is already synced", path = local.display()));
+                return;
+            }
+            let pair = SyncPair {
+                local,
+                remote_folder,
+                propagate_deletes,
+                interval_minutes: 15,
+                include,
+                exclude,
+            };
+            if let Err(e) = sync::SyncRules::new(&pair) {
+                set_status(&ui_weak, tr!("Invalid sync rules: {error}", error = format!("{:#}", e)));
+                return;
+            }
+            settings.sync_pairs.push(pair);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            update_sync_pairs(&ui_weak, &settings);
+        });
+    }
+    {
+        let settings = settings.clone();
+        let sync = sync.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_remove_sync_pair(move |index| {
+            let mut settings = settings.lock().unwrap();
+            if index < 0 || index as usize >= settings.sync_pairs.len() {
+                return;
+            }
+            let pair = settings.sync_pairs.remove(index as usize);
+            sync.forget(&pair);
+            if let Err(e) = settings.save(SETTINGS_FILE) {
+                error!("Failed to save settings: {:?}", e);
+            }
+            update_sync_pairs(&ui_weak, &settings);
+        });
+    }
+    {
+        let settings = settings.clone();
+        let sync = sync.clone();
+        let db = db.clone();
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_sync_now(move |index| {
+            let Some(pair) = settings.lock().unwrap().sync_pairs.get(index as usize).cloned() else {
+                return;
+            };
+            let webhook = settings.lock().unwrap().webhook_url.clone();
+            let sync = sync.clone();
+            let db = db.clone();
+            let queue = queue.clone();
+            let ui_weak = ui_weak.clone();
+            set_status(&ui_weak, tr!("Syncing {path}...", path = pair.local.display()));
+            
+            tokio::spawn(async move {
+                match sync.sync(&pair, &db, &queue).await {
+                    Ok(report) => {
+                        update_transfer_list(&ui_weak, &queue);
+                        update_conflicts(&ui_weak, &sync);
+                        refresh_file_list(&ui_weak, &db);
+                        set_status(&ui_weak, tr!("Synced {path}: {summary}", path = pair.local.display(), summary = report.summary()));
+                    }
+                    Err(e) => {
+                        error!("Sync of {:?} failed: {:?}", pair.local, e);
+                        webhooks::notify(&webhook, WebhookEvent::SyncError {
+                            path: pair.local.display().to_string(),
+                            error: format!("{:#}", e),
+                        });
+                        set_status(&ui_weak, tr!("Sync failed: {error}", error = errors::describe(&e)));
+                    }
+                }
+            });
+        });
+    }
+    
+    // Sync conflict callback
+    {
+        let sync = sync.clone();
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_resolve_conflict(move |id, choice| {
+            let resolution = match choice.as_str() {
+                "local" => Resolution::KeepLocal,
+                "remote" => Resolution::KeepRemote,
+                _ => Resolution::KeepBoth,
+            };
+            let sync = sync.clone();
+            let queue = queue.clone();
+            let ui_weak = ui_weak.clone();
+            
+            tokio::spawn(async move {
+                if let Err(e) = sync.resolve(id as u64, resolution, &queue).await {
+                    error!("Failed to resolve conflict: {:?}", e);
+                    set_status(&ui_weak, tr!("Could not resolve conflict: {error}", error = format!("{:#}", e)));
+                }
+                update_conflicts(&ui_weak, &sync);
+                update_transfer_list(&ui_weak, &queue);
+            });
+        });
+    }
+    
+    // Clear history callback
+    {
+        let db = db.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_clear_history(move || {
+            let db = db.clone();
+            let ui_weak = ui_weak.clone();
+            
+            tokio::spawn(async move {
+                if let Err(e) = db.clear_history().await {
+                    error!("Failed to clear history: {:?}", e);
+                }
+                refresh_history(&ui_weak, &db);
+            });
+        });
+    }
+    {
+        let ui_weak = ui_weak.clone();
+        
+        ui.on_refresh_activity(move || {
+            refresh_activity(&ui_weak);
+        });
+    }
+    
+    // The library is browsable before connecting
+    refresh_file_list(&ui_weak, &db);
+    refresh_history(&ui_weak, &db);
+    refresh_activity(&ui_weak);
+    update_transfer_list(&ui_weak, &queue);
+    update_scheduled_list(&ui_weak, &scheduler);
+    update_backup_jobs(&ui_weak, &settings.lock().unwrap());
+    update_sync_pairs(&ui_weak, &settings.lock().unwrap());
+    
+    // System tray: closing the window hides it while transfers keep running
+    let tray = match Tray::new() {
+        Ok(tray) => Some(tray),
+        Err(e) => {
+            warn!("System tray unavailable: {:?}", e);
+            None
+        }
+    };
+    
+    // Mirror playback position into the player bar
+    let player_timer = slint::Timer::default();
+    if let Some(player) = player {
+        let ui_weak = ui_weak.clone();
+        
+        player_timer.start(slint::TimerMode::Repeated, PLAYER_POLL_INTERVAL, move || {
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let status = player.status();
+            ui.set_player_active(status.active);
+            ui.set_player_paused(status.paused);
+            ui.set_player_title(status.title.into());
+            ui.set_player_position(status.position.as_secs_f32());
+            ui.set_player_duration(status.duration.map_or(0.0, |d| d.as_secs_f32()));
+        });
+    }
+    
+    // Lock again once the window has been left alone long enough
+    let lock_timer = slint::Timer::default();
+    {
+        let settings = settings.clone();
+        let ui_weak = ui_weak.clone();
+        
+        lock_timer.start(slint::TimerMode::Repeated, LOCK_CHECK_INTERVAL, move || {
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let idle_minutes = settings.lock().unwrap().app_lock.as_ref().map_or(0, |l| l.idle_minutes);
+            let idle_limit = Duration::from_secs(idle_minutes as u64 * 60);
+            if idle_minutes > 0 && !ui.get_locked() && last_activity.get().elapsed() >= idle_limit {
+                info!("Locking after {} idle minutes", idle_minutes);
+                ui.invoke_lock_app();
+            }
+        });
+    }
+    
+    // Files passed on the command line, or by later launches, are uploaded
+    {
+        let selected_files = selected_files.clone();
+        let ui_weak = ui_weak.clone();
+        let upload = move |ui: &AppWindow, paths: Vec<PathBuf>| {
+            let paths: Vec<PathBuf> = paths.into_iter().filter(|p| p.is_file()).collect();
+            if !paths.is_empty() {
+                *selected_files.lock().unwrap() = paths;
+                ui.invoke_upload_file();
+            }
+        };
+        upload(&ui, files);
+        if let Some(primary) = primary {
+            primary.serve(move |paths| {
+                let upload = upload.clone();
+                let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                    let _ = ui.show();
+                    ui.window().set_minimized(false);
+                    upload(&ui, paths);
+                });
+            });
+        }
+    }
+    
+    // Once the window is up, offer to send reports of earlier crashes
+    slint::Timer::single_shot(Duration::from_secs(1), crash::offer_pending_reports);
+    
+    let tray_timer = slint::Timer::default();
+    if let Some(tray) = tray {
+        let queue = queue.clone();
+        let ui_weak = ui_weak.clone();
+        
+        ui.window().on_close_requested(|| slint::CloseRequestResponse::HideWindow);
+        
+        tray_timer.start(slint::TimerMode::Repeated, TRAY_POLL_INTERVAL, move || {
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            
+            match tray.poll() {
+                Some(TrayAction::OpenWindow) => {
+                    let _ = ui.show();
+                }
+                Some(TrayAction::PauseAll) => {
+                    queue.pause_all();
+                    update_transfer_list(&ui_weak, &queue);
+                }
+                Some(TrayAction::Quit) => {
+                    let _ = slint::quit_event_loop();
+                }
+                None => {}
+            }
+            
+            tray.set_status(&tray_status(&ui));
+        });
+        
+        // Login launches start hidden in the tray
+        if !start_minimized {
+            ui.show()?;
+        }
+        slint::run_event_loop_until_quit()?;
+    } else {
+        ui.run()?;
+    }
     
+    instance::release();
     Ok(())
 }
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tracing::debug;
+
+use crate::file_kind::FileKind;
+
+/// MP4 timestamps count seconds from 1904-01-01 rather than the Unix epoch
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Photo and video metadata read at upload time
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaInfo {
+    /// When the photo was taken or the video recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captured_at: Option<DateTime<Local>>,
+    /// Camera make and model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    /// Latitude and longitude in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps: Option<(f64, f64)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+}
+
+impl MediaInfo {
+    /// Metadata of the photo or video at `path`; `None` for other files or
+    /// when nothing could be read. Blocking, run it off the async runtime
+    pub fn read(path: &Path) -> Option<Self> {
+        let filename = path.file_name()?.to_str()?;
+        let info = match FileKind::from_filename(filename) {
+            FileKind::Image => Some(read_photo(path)),
+            FileKind::Video => read_video(path)
+                .map_err(|e| debug!("No video metadata in {:?}: {:?}", path, e))
+                .ok(),
+            _ => None,
+        }?;
+        (info != Self::default()).then_some(info)
+    }
+    
+    /// One line for the file details, e.g. "Taken 2024-05-01 12:00 · Canon EOS R6 · 6000×4000"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(captured_at) = self.captured_at {
+            parts.push(tr!("Taken {date}", date = captured_at.format("%Y-%m-%d %H:%M")));
+        }
+        if let Some(camera) = &self.camera {
+            parts.push(camera.clone());
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            parts.push(format!("{}×{}", width, height));
+        }
+        if let Some(duration) = self.duration_secs {
+            let secs = duration.round() as u64;
+            parts.push(format!("{}:{:02}", secs / 60, secs % 60));
+        }
+        if let Some((lat, lon)) = self.gps {
+            parts.push(format!("{:.5}, {:.5}", lat, lon));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// EXIF fields, plus the pixel size from the image header
+fn read_photo(path: &Path) -> MediaInfo {
+    let mut info = MediaInfo::default();
+    if let Ok((width, height)) = image::image_dimensions(path) {
+        info.width = Some(width);
+        info.height = Some(height);
+    }
+    
+    let Some(exif) = File::open(path).ok()
+        .and_then(|file| exif::Reader::new().read_from_container(&mut BufReader::new(file)).ok())
+    else {
+        debug!("No EXIF data in {:?}", path);
+        return info;
+    };
+    
+    info.captured_at = ascii_field(&exif, exif::Tag::DateTimeOriginal)
+        .or_else(|| ascii_field(&exif, exif::Tag::DateTime))
+        .and_then(|text| parse_exif_date(&text));
+    
+    // Models usually repeat the make ("Canon" / "Canon EOS R6")
+    info.camera = match (ascii_field(&exif, exif::Tag::Make), ascii_field(&exif, exif::Tag::Model)) {
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    };
+    
+    let latitude = gps_coordinate(&exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S");
+    let longitude = gps_coordinate(&exif, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W");
+    info.gps = latitude.zip(longitude);
+    
+    info
+}
+
+fn ascii_field(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(values) => values.first()
+            .map(|bytes| String::from_utf8_lossy(bytes).trim().to_string())
+            .filter(|text| !text.is_empty()),
+        _ => None,
+    }
+}
+
+/// EXIF dates are local time without a zone, "YYYY:MM:DD HH:MM:SS"
+fn parse_exif_date(text: &str) -> Option<DateTime<Local>> {
+    let naive = chrono::NaiveDateTime::parse_from_str(text, "%Y:%m:%d %H:%M:%S").ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+/// Degrees from the degrees/minutes/seconds rationals, negated for the
+/// southern or western hemisphere
+fn gps_coordinate(exif: &exif::Exif, tag: exif::Tag, ref_tag: exif::Tag, negative_ref: &str) -> Option<f64> {
+    let exif::Value::Rational(parts) = &exif.get_field(tag, exif::In::PRIMARY)?.value else {
+        return None;
+    };
+    let [degrees, minutes, seconds] = parts.get(..3)? else {
+        return None;
+    };
+    let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+    let negative = ascii_field(exif, ref_tag).map_or(false, |r| r == negative_ref);
+    Some(if negative { -value } else { value })
+}
+
+/// Duration, size of the first video track and recording time from an MP4/MOV header
+fn read_video(path: &Path) -> Result<MediaInfo> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mp4 = mp4::Mp4Reader::read_header(BufReader::new(file), size)
+        .context("Not an MP4/MOV file")?;
+    
+    let mut info = MediaInfo {
+        duration_secs: Some(mp4.duration().as_secs_f64()),
+        ..Default::default()
+    };
+    if let Some(track) = mp4.tracks().values()
+        .find(|track| matches!(track.track_type(), Ok(mp4::TrackType::Video)))
+    {
+        info.width = Some(track.width() as u32);
+        info.height = Some(track.height() as u32);
+    }
+    // Zero when the recorder didn't set it
+    let created = mp4.moov.mvhd.creation_time as i64;
+    if created > 0 {
+        info.captured_at = Local.timestamp_opt(created - MP4_EPOCH_OFFSET, 0).single()
+            .filter(|date| date.timestamp() > 0);
+    }
+    Ok(info)
+}
@@ -0,0 +1,279 @@
+use anyhow::{bail, Context, Result};
+use grammers_client::types::{Message, PackedChat};
+use grammers_client::Client;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::config::Settings;
+use crate::database::{Database, FileRecord, SplitPiece};
+use crate::limits::AccountLimits;
+use crate::transfer::{ResumeState, TransferControl, UploadOptions};
+use crate::{chunked, delete_stored_file, download_file_from_telegram, folder, group_drive, paths, read_only, record_chat, run_upload, saved_messages};
+
+/// Folder in the cache files are downloaded to when they have to be uploaded again
+const MIGRATE_DIR: &str = "migrate";
+/// Sent to the other account first, so the chat between the two exists
+const GREETING: &str = "TeleDrive is copying files to this account";
+
+/// How one file got to the other account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// Copied by Telegram, without the bytes passing through here
+    Forwarded,
+    /// Downloaded and sent again
+    Uploaded,
+    /// The other account already stores the same content
+    Present,
+    Failed,
+}
+
+impl Outcome {
+    /// Name in `--porcelain` output
+    pub fn key(self) -> &'static str {
+        match self {
+            Outcome::Forwarded => "forwarded",
+            Outcome::Uploaded => "uploaded",
+            Outcome::Present => "present",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Migrated {
+    pub path: String,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What `migrate` did with each file
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub files: Vec<Migrated>,
+    /// Whether the files were deleted from this account once copied
+    pub moved: bool,
+}
+
+/// The other account, and where its files go
+pub struct Target {
+    pub client: Client,
+    pub db: Arc<Database>,
+    pub settings: Settings,
+}
+
+/// The private chat between the two accounts, which messages cross by
+struct Bridge {
+    /// The target account, as the source reaches it
+    to_target: PackedChat,
+    /// The source account, as the target reaches it
+    to_source: PackedChat,
+}
+
+/// Copy every file in `db` to the target account and record it in the
+/// target's index, moving it when `move_files` is set. Files are forwarded
+/// through the chat between the accounts, which needs the target to have a
+/// username; what can't be forwarded (no username, protected chats) is
+/// downloaded and uploaded again
+pub async fn migrate(client: &Client, db: &Database, target: &Target, move_files: bool) -> Result<MigrationReport> {
+    // Before anything is copied, rather than failing every delete afterwards
+    if move_files {
+        read_only::check("deleting")?;
+    }
+    let bridge = match open_bridge(client, &target.client).await {
+        Ok(bridge) => Some(bridge),
+        Err(e) => {
+            warn!("Files will be uploaded again rather than forwarded: {:#}", e);
+            None
+        }
+    };
+    let dest = match &target.settings.group_drive {
+        Some(drive) => drive.packed()?,
+        None => saved_messages(&target.client).await?,
+    };
+    
+    let mut report = MigrationReport { moved: move_files, ..Default::default() };
+    let records: Vec<FileRecord> = db.all_records();
+    for (done, record) in records.iter().enumerate() {
+        info!("Migrating {} ({}/{})", record.path(), done + 1, records.len());
+        let result = if record.missing {
+            Err(anyhow::anyhow!("Its message was deleted"))
+        } else {
+            migrate_file(client, db, target, bridge.as_ref(), dest, record).await
+        };
+        let (outcome, error) = match result {
+            Ok(outcome) => (outcome, None),
+            Err(e) => {
+                warn!("Failed to migrate {}: {:?}", record.path(), e);
+                (Outcome::Failed, Some(format!("{:#}", e)))
+            }
+        };
+        if move_files && outcome != Outcome::Failed {
+            if let Err(e) = delete_stored_file(client, db, record).await {
+                warn!("Failed to delete {} after copying it: {:?}", record.path(), e);
+            }
+        }
+        report.files.push(Migrated { path: record.path(), outcome, error });
+    }
+    Ok(report)
+}
+
+async fn migrate_file(
+    client: &Client,
+    db: &Database,
+    target: &Target,
+    bridge: Option<&Bridge>,
+    dest: PackedChat,
+    record: &FileRecord,
+) -> Result<Outcome> {
+    let present = record.sha256.as_ref()
+        .is_some_and(|hash| target.db.all_records().iter().any(|r| r.sha256.as_ref() == Some(hash) && !r.missing));
+    if present {
+        return Ok(Outcome::Present);
+    }
+    
+    if let Some(bridge) = bridge {
+        match forward(client, &target.client, bridge, dest, record).await {
+            Ok(ids) => {
+                let mut copy = record.clone();
+                copy.message_id = ids.first().copied();
+                copy.split_pieces = record.split_pieces.iter()
+                    .zip(&ids)
+                    .map(|(piece, id)| SplitPiece { message_id: *id, ..piece.clone() })
+                    .collect();
+                copy.chat = target.settings.group_drive.clone();
+                copy.album = None;
+                copy.verified = None;
+                target.db.insert_file(copy).await?;
+                return Ok(Outcome::Forwarded);
+            }
+            Err(e) => warn!("Failed to forward {}; uploading it again: {:?}", record.path(), e),
+        }
+    }
+    upload(client, db, target, record).await?;
+    Ok(Outcome::Uploaded)
+}
+
+/// Have the two accounts find each other: the source by the target's
+/// username, the target by the chat the source's greeting starts
+async fn open_bridge(source: &Client, target: &Client) -> Result<Bridge> {
+    api_usage::request("users.getUsers");
+    let target_me = target.get_me().await?;
+    let username = target_me.username().context("The account to copy to has no username to reach it by")?;
+    api_usage::request("contacts.resolveUsername");
+    let to_target = source.resolve_username(username).await?
+        .with_context(|| format!("Could not find @{}", username))?
+        .pack();
+    api_usage::request("users.getUsers");
+    let source_id = source.get_me().await?.id();
+    api_usage::request("messages.sendMessage");
+    let greeting = source.send_message(to_target, GREETING).await?;
+    
+    api_usage::request("messages.getDialogs");
+    let mut dialogs = target.iter_dialogs();
+    let mut to_source = None;
+    while let Some(dialog) = dialogs.next().await? {
+        if dialog.chat().id() == source_id {
+            to_source = Some(dialog.chat().pack());
+            break;
+        }
+    }
+    api_usage::request("messages.deleteMessages");
+    source.delete_messages(to_target, &[greeting.id()]).await?;
+    let to_source = to_source.context("The account to copy to can't see the chat with this one")?;
+    Ok(Bridge { to_target, to_source })
+}
+
+/// Forward the documents of `record` to the target, then on to `dest`,
+/// returning the ids they got there. Private chats number messages per
+/// account, so the target picks them out as the newest in the chat
+async fn forward(source: &Client, target: &Client, bridge: &Bridge, dest: PackedChat, record: &FileRecord) -> Result<Vec<i32>> {
+    let ids = record.message_ids();
+    let chat = record_chat(source, Some(record)).await?;
+    api_usage::request("messages.forwardMessages");
+    let sent: Vec<_> = source.forward_messages(bridge.to_target, &ids, chat).await?
+        .into_iter()
+        .flatten()
+        .collect();
+    let result = async {
+        if sent.len() != ids.len() {
+            bail!("Telegram forwarded {} of {} messages", sent.len(), ids.len());
+        }
+        api_usage::request("messages.getHistory");
+        let mut history = target.iter_messages(bridge.to_source).limit(ids.len());
+        let mut received = Vec::with_capacity(ids.len());
+        while let Some(message) = history.next().await? {
+            received.push(message);
+        }
+        received.reverse();
+        // Something else sent meanwhile would be caught by the sizes
+        let sizes = |messages: &[Message]| -> Vec<Option<u64>> {
+            messages.iter().map(|m| m.media().as_ref().and_then(chunked::media_size)).collect()
+        };
+        if sizes(&received) != sizes(&sent) {
+            bail!("The forwarded messages did not arrive as sent");
+        }
+        
+        let received_ids: Vec<i32> = received.iter().map(|m| m.id()).collect();
+        api_usage::request("messages.forwardMessages");
+        target.forward_messages(dest, &received_ids, bridge.to_source).await?
+            .iter()
+            .map(|m| m.as_ref().map(|m| m.id()))
+            .collect::<Option<Vec<i32>>>()
+            .context("Telegram did not return every forwarded message")
+    }.await;
+    
+    // The chat between the accounts was only the way across
+    let sent_ids: Vec<i32> = sent.iter().map(|m| m.id()).collect();
+    api_usage::request("messages.deleteMessages");
+    if let Err(e) = source.delete_messages(bridge.to_target, &sent_ids).await {
+        warn!("Failed to clear the chat with the other account: {:?}", e);
+    }
+    result
+}
+
+/// Download `record` and upload it from the target account
+async fn upload(client: &Client, db: &Database, target: &Target, record: &FileRecord) -> Result<()> {
+    let key = record.key().context("Record has no message")?;
+    let dir = paths::cache_dir(MIGRATE_DIR).join(key.to_string().replace(':', "-"));
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = folder::local_path(&dir, &record.filename).context("The stored name is not a plain file name")?;
+    let result = async {
+        download_file_from_telegram(
+            client,
+            db,
+            key,
+            &path,
+            &mut ResumeState::default(),
+            &TransferControl::default(),
+            slint::Weak::default(),
+        ).await?;
+        
+        let options = UploadOptions {
+            folder: record.folder.clone(),
+            tags: record.tags.clone(),
+            ..Default::default()
+        };
+        // Uploads go to the drive set for this process, which is the source's
+        let source_drive = group_drive::current();
+        group_drive::set(target.settings.group_drive.clone());
+        let uploaded = run_upload(
+            &target.client,
+            &target.db,
+            &AccountLimits::default(),
+            &target.settings,
+            &path,
+            &options,
+            &mut ResumeState::default(),
+            &TransferControl::default(),
+            slint::Weak::default(),
+        ).await;
+        group_drive::set(source_drive);
+        uploaded
+    }.await;
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    result.map(|_| ())
+}
@@ -0,0 +1,37 @@
+use notify_rust::Notification;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::database::{HistoryEntry, TransferOutcome};
+use crate::i18n::tr;
+
+/// Only transfers running at least this long raise a desktop notification
+pub const MIN_NOTIFY_DURATION: Duration = Duration::from_secs(30);
+
+const APP_NAME: &str = "Telegram Cloud Storage";
+
+/// Show an OS notification for a finished transfer.
+///
+/// Runs on a blocking thread since some backends (D-Bus) wait for the server.
+pub fn notify_transfer_finished(entry: &HistoryEntry) {
+    let summary = match entry.outcome {
+        TransferOutcome::Completed => tr!("{direction} complete", direction = tr(&entry.direction)),
+        TransferOutcome::Failed => tr!("{direction} failed", direction = tr(&entry.direction)),
+        TransferOutcome::Cancelled => tr!("{direction} cancelled", direction = tr(&entry.direction)),
+    };
+    let body = match &entry.error {
+        Some(error) => format!("{}\n{}", entry.filename, error),
+        None => entry.filename.clone(),
+    };
+    
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = Notification::new()
+            .appname(APP_NAME)
+            .summary(&summary)
+            .body(&body)
+            .show()
+        {
+            warn!("Failed to show desktop notification: {:?}", e);
+        }
+    });
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::file_kind::FileKind;
+
+/// Tesseract language used for recognition
+#[cfg(feature = "ocr")]
+const LANGUAGE: &str = "eng";
+
+/// Whether this build can recognise text (the `ocr` feature, which needs
+/// Tesseract and Leptonica installed)
+pub const AVAILABLE: bool = cfg!(feature = "ocr");
+
+/// Whether an OCR pass makes sense for a file of this name
+pub fn is_candidate(filename: &str) -> bool {
+    AVAILABLE && FileKind::from_filename(filename) == FileKind::Image
+}
+
+/// Text recognised in the image at `path`, trimmed; `None` if there is none.
+/// Blocking and slow, run it off the async runtime
+#[cfg(feature = "ocr")]
+pub fn recognize(path: &Path) -> Result<Option<String>> {
+    let path = path.to_str().ok_or_else(|| anyhow::anyhow!("Non UTF-8 path {:?}", path))?;
+    let text = tesseract::ocr(path, LANGUAGE)?;
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok((!text.is_empty()).then_some(text))
+}
+
+#[cfg(not(feature = "ocr"))]
+pub fn recognize(_path: &Path) -> Result<Option<String>> {
+    anyhow::bail!("Built without OCR support")
+}
@@ -0,0 +1,149 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use grammers_client::types::Media;
+use grammers_client::Client;
+use serde::Serialize;
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::caption::CaptionMeta;
+use crate::database::{ChatRef, Database, FileRecord, RecordKey};
+use crate::{chunked, read_only, saved_messages, snapshot};
+
+/// Messages are deleted this many at a time
+const DELETE_BATCH: usize = 100;
+
+/// A document or photo TeleDrive sent to the storage chat that no record
+/// points at, e.g. left behind by an upload that failed half way
+#[derive(Debug, Serialize)]
+pub struct OrphanMessage {
+    pub message_id: i32,
+    pub name: String,
+    pub size: u64,
+    pub date: DateTime<Local>,
+}
+
+/// A record with a message no longer in the storage chat
+#[derive(Debug, Serialize)]
+pub struct MissingMessage {
+    pub message_id: i32,
+    pub path: String,
+    /// Every message of the record as scanned, so `clean` leaves alone a
+    /// record that changed since
+    #[serde(skip)]
+    pub pieces: Vec<i32>,
+}
+
+/// What `scan` found, and whether `clean` dealt with it
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    pub orphans: Vec<OrphanMessage>,
+    pub missing: Vec<MissingMessage>,
+    pub cleaned: bool,
+}
+
+/// Whether `record` is stored in `drive`, or in Saved Messages without one
+fn stored_in(record: &FileRecord, drive: Option<&ChatRef>) -> bool {
+    match (&record.chat, drive) {
+        (None, None) => true,
+        (Some(chat), Some(drive)) => chat.id == drive.id,
+        _ => false,
+    }
+}
+
+/// Compare the storage chat, `drive` or Saved Messages, with the index.
+/// Only documents and photos with a TeleDrive caption can be orphans, so
+/// anything else kept there is never touched; neither are the messages in
+/// `busy`, pieces of uploads still to finish, nor snapshot packs and manifests
+pub async fn scan(client: &Client, db: &Database, drive: Option<&ChatRef>, busy: &HashSet<i32>) -> Result<GcReport> {
+    let chat = match drive {
+        Some(drive) => drive.packed()?,
+        None => saved_messages(client).await?,
+    };
+    let records: Vec<FileRecord> = db.all_records().into_iter().filter(|r| stored_in(r, drive)).collect();
+    let mut referenced: HashSet<i32> = records.iter().flat_map(|r| r.message_ids()).collect();
+    referenced.extend(busy);
+    let repository = snapshot::Repository::load()?;
+    referenced.extend(repository.packs.values()
+        .chain(repository.snapshots.iter().filter_map(|s| s.manifest.as_ref()))
+        .filter(|pack| pack.chat.as_ref().map(|c| c.id) == drive.map(|d| d.id))
+        .map(|pack| pack.message_id));
+    
+    let mut report = GcReport::default();
+    let mut present = HashSet::new();
+    api_usage::request("messages.getHistory");
+    let mut messages = client.iter_messages(chat);
+    while let Some(message) = messages.next().await? {
+        let Some(media) = message.media() else {
+            continue;
+        };
+        let Some(size) = chunked::media_size(&media) else {
+            continue;
+        };
+        present.insert(message.id());
+        if referenced.contains(&message.id()) || snapshot::is_repository_caption(message.text()) {
+            continue;
+        }
+        let Some(meta) = CaptionMeta::parse(message.text()) else {
+            continue;
+        };
+        // Photos have no name of their own
+        let name = match &media {
+            Media::Document(document) => document.name().to_string(),
+            _ => meta.path,
+        };
+        report.orphans.push(OrphanMessage {
+            message_id: message.id(),
+            name,
+            size,
+            date: message.date().with_timezone(&Local),
+        });
+    }
+    
+    for record in &records {
+        let Some(message_id) = record.message_id else {
+            continue;
+        };
+        if record.message_ids().iter().all(|id| present.contains(id)) {
+            continue;
+        }
+        report.missing.push(MissingMessage { message_id, path: record.path(), pieces: record.message_ids() });
+    }
+    info!("Storage chat: {} orphaned messages, {} records without their message", report.orphans.len(), report.missing.len());
+    Ok(report)
+}
+
+/// Delete the orphaned messages `scan` found and drop the records whose
+/// message is gone, along with whatever pieces of them are left
+pub async fn clean(client: &Client, db: &Database, drive: Option<&ChatRef>, report: &mut GcReport) -> Result<()> {
+    read_only::check("deleting")?;
+    let chat = match drive {
+        Some(drive) => drive.packed()?,
+        None => saved_messages(client).await?,
+    };
+    let orphans: Vec<i32> = report.orphans.iter().map(|o| o.message_id).collect();
+    for batch in orphans.chunks(DELETE_BATCH) {
+        api_usage::request("messages.deleteMessages");
+        client.delete_messages(chat, batch).await?;
+    }
+    
+    for missing in &report.missing {
+        let key = RecordKey::new(drive, missing.message_id);
+        if db.find(key).map_or(true, |record| record.message_ids() != missing.pieces) {
+            continue;
+        }
+        let Some(record) = db.remove_file(key).await? else {
+            continue;
+        };
+        // Pieces other files share stay
+        let shared = db.shared_message_ids(&record);
+        let left: Vec<i32> = missing.pieces.iter().copied().filter(|id| !shared.contains(id)).collect();
+        api_usage::request("messages.deleteMessages");
+        if let Err(e) = client.delete_messages(chat, &left).await {
+            warn!("Failed to delete what is left of {}: {:?}", missing.path, e);
+        }
+    }
+    report.cleaned = true;
+    Ok(())
+}
@@ -0,0 +1,398 @@
+use serde::Serialize;
+
+use crate::database::{FileRecord, DATE_FORMAT};
+use crate::snapshot::{PruneReport, Snapshot};
+use crate::format_size;
+use crate::fsck::CheckReport;
+use crate::migrate::{MigrationReport, Outcome};
+use crate::orphans::GcReport;
+use crate::transfer::{TransferJob, TransferState};
+
+/// How subcommands print their results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned text for people
+    Human,
+    /// One JSON document on stdout
+    Json,
+    /// Tab-separated fields, one record per line, stable across versions
+    Porcelain,
+}
+
+impl Format {
+    pub fn from_flags(json: bool, porcelain: bool) -> Self {
+        if json {
+            Format::Json
+        } else if porcelain {
+            Format::Porcelain
+        } else {
+            Format::Human
+        }
+    }
+}
+
+/// A stored file as printed by `list`
+#[derive(Serialize)]
+struct FileEntry<'a> {
+    name: &'a str,
+    folder: &'a str,
+    size: u64,
+    uploaded: String,
+    message_id: Option<i32>,
+    sha256: Option<&'a str>,
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<i64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    photo: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    missing: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    starred: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    corrupt: bool,
+}
+
+impl<'a> From<&'a FileRecord> for FileEntry<'a> {
+    fn from(record: &'a FileRecord) -> Self {
+        Self {
+            name: &record.filename,
+            folder: &record.folder,
+            size: record.file_size,
+            uploaded: record.upload_date.to_rfc3339(),
+            message_id: record.message_id,
+            sha256: record.sha256.as_deref(),
+            tags: &record.tags,
+            album: record.album,
+            photo: record.photo,
+            missing: record.missing,
+            starred: record.starred,
+            corrupt: record.corrupt,
+        }
+    }
+}
+
+/// A snapshot as printed by `snapshot list`
+#[derive(Serialize)]
+struct SnapshotEntry<'a> {
+    id: &'a str,
+    time: String,
+    source: &'a std::path::Path,
+    files: usize,
+    size: u64,
+}
+
+/// What `snapshot restore` put back
+#[derive(Serialize)]
+struct RestoreEntry<'a> {
+    id: &'a str,
+    dest: &'a std::path::Path,
+    files: usize,
+    size: u64,
+}
+
+/// A transfer not yet finished, as printed by `status`
+#[derive(Serialize)]
+pub struct PendingTransfer {
+    pub name: String,
+    pub upload: bool,
+    /// "active", "queued", "paused", or "unfinished" for one left by a
+    /// session that ended
+    pub state: &'static str,
+}
+
+impl PendingTransfer {
+    pub fn new(job: &TransferJob, state: Option<TransferState>) -> Self {
+        Self {
+            name: job.kind.display_name(),
+            upload: job.kind.is_upload(),
+            state: match state {
+                Some(TransferState::Active) => "active",
+                Some(TransferState::Queued) => "queued",
+                Some(TransferState::Paused) => "paused",
+                None => "unfinished",
+            },
+        }
+    }
+}
+
+/// Library totals and transfers waiting to run, as printed by `status`
+#[derive(Serialize)]
+pub struct Status {
+    pub files: usize,
+    pub total_size: u64,
+    pub transfers: Vec<PendingTransfer>,
+}
+
+/// Outcome of one file of `upload` or `download`
+#[derive(Serialize)]
+pub struct TransferResult {
+    pub file: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TransferResult {
+    /// Result of storing `file` as the record `stored`
+    pub fn from_stored(file: &str, stored: anyhow::Result<FileRecord>) -> Self {
+        match stored {
+            Ok(record) => Self { file: file.to_string(), ok: true, message_id: record.message_id, error: None },
+            Err(e) => Self { file: file.to_string(), ok: false, message_id: None, error: Some(format!("{:#}", e)) },
+        }
+    }
+}
+
+fn print_json(value: &impl Serialize) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("Failed to write JSON: {}", e),
+    }
+}
+
+/// Tabs and newlines would split a porcelain field
+fn field(text: &str) -> String {
+    text.replace(['\t', '\n'], " ")
+}
+
+pub fn print_records(format: Format, records: &[FileRecord]) {
+    match format {
+        Format::Json => print_json(&records.iter().map(FileEntry::from).collect::<Vec<_>>()),
+        Format::Porcelain => {
+            for record in records {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    record.message_id.map(|id| id.to_string()).unwrap_or_default(),
+                    record.file_size,
+                    record.upload_date.to_rfc3339(),
+                    field(&record.folder),
+                    field(&record.filename),
+                );
+            }
+        }
+        Format::Human => {
+            for record in records {
+                let path = match record.folder.as_str() {
+                    "" => record.filename.clone(),
+                    folder => format!("{}/{}", folder, record.filename),
+                };
+                println!("{:>10}  {}  {}", format_size(record.file_size), record.upload_date.format(DATE_FORMAT), path);
+            }
+        }
+    }
+}
+
+pub fn print_snapshots(format: Format, snapshots: &[Snapshot]) {
+    match format {
+        Format::Json => print_json(&snapshots.iter().map(|s| SnapshotEntry {
+            id: &s.id,
+            time: s.time.to_rfc3339(),
+            source: &s.source,
+            files: s.files.len(),
+            size: s.size(),
+        }).collect::<Vec<_>>()),
+        Format::Porcelain => {
+            for snapshot in snapshots {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    snapshot.id,
+                    snapshot.time.to_rfc3339(),
+                    snapshot.files.len(),
+                    snapshot.size(),
+                    field(&snapshot.source.to_string_lossy()),
+                );
+            }
+        }
+        Format::Human => {
+            if snapshots.is_empty() {
+                println!("No snapshots");
+            }
+            for snapshot in snapshots {
+                println!(
+                    "{}  {}  {:>6} files {:>10}  {}",
+                    &snapshot.id[..8.min(snapshot.id.len())],
+                    snapshot.time.format(DATE_FORMAT),
+                    snapshot.files.len(),
+                    format_size(snapshot.size()),
+                    snapshot.source.display(),
+                );
+            }
+        }
+    }
+}
+
+pub fn print_restore(format: Format, snapshot: &Snapshot, dest: &std::path::Path) {
+    match format {
+        Format::Json => print_json(&RestoreEntry {
+            id: &snapshot.id,
+            dest,
+            files: snapshot.files.len(),
+            size: snapshot.size(),
+        }),
+        Format::Porcelain => {
+            println!("{}\t{}\t{}\t{}", snapshot.id, snapshot.files.len(), snapshot.size(), field(&dest.to_string_lossy()));
+        }
+        Format::Human => {
+            println!("Restored {} files to {}", snapshot.files.len(), dest.display());
+        }
+    }
+}
+
+pub fn print_prune(format: Format, report: &PruneReport) {
+    match format {
+        Format::Json => print_json(report),
+        Format::Porcelain => {
+            println!("forgotten\t{}", report.forgotten);
+            println!("packs_deleted\t{}", report.packs_deleted);
+            println!("packs_repacked\t{}", report.packs_repacked);
+            println!("bytes_freed\t{}", report.bytes_freed);
+        }
+        Format::Human => {
+            println!(
+                "Forgot {} snapshot(s), deleted {} and rewrote {} pack(s), freeing {}",
+                report.forgotten,
+                report.packs_deleted,
+                report.packs_repacked,
+                format_size(report.bytes_freed),
+            );
+        }
+    }
+}
+
+pub fn print_check(format: Format, report: &CheckReport) {
+    match format {
+        Format::Json => print_json(report),
+        Format::Porcelain => {
+            for issue in &report.issues {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    issue.kind,
+                    issue.message_id.map(|id| id.to_string()).unwrap_or_default(),
+                    if issue.repaired { "repaired" } else { "left" },
+                    field(&issue.subject),
+                    field(&issue.detail),
+                );
+            }
+        }
+        Format::Human => {
+            for issue in &report.issues {
+                let repaired = if issue.repaired { " (repaired)" } else { "" };
+                println!("{}: {}{}", issue.subject, issue.detail, repaired);
+            }
+            if report.issues.is_empty() {
+                println!("No issues in {} records", report.records);
+            }
+        }
+    }
+}
+
+pub fn print_gc(format: Format, report: &GcReport) {
+    match format {
+        Format::Json => print_json(report),
+        Format::Porcelain => {
+            for orphan in &report.orphans {
+                println!("orphan\t{}\t{}\t{}", orphan.message_id, orphan.size, field(&orphan.name));
+            }
+            for missing in &report.missing {
+                println!("missing\t{}\t{}", missing.message_id, field(&missing.path));
+            }
+        }
+        Format::Human => {
+            for orphan in &report.orphans {
+                println!(
+                    "No file points at message {}: {} ({}, {})",
+                    orphan.message_id,
+                    orphan.name,
+                    format_size(orphan.size),
+                    orphan.date.format(DATE_FORMAT),
+                );
+            }
+            for missing in &report.missing {
+                println!("Message {} of {} was deleted", missing.message_id, missing.path);
+            }
+            match (report.orphans.len() + report.missing.len(), report.cleaned) {
+                (0, _) => println!("The storage chat and the index agree"),
+                (_, true) => println!("Deleted {} message(s) and dropped {} file(s)", report.orphans.len(), report.missing.len()),
+                (_, false) => println!("Run with --clean to delete those messages and drop those files"),
+            }
+        }
+    }
+}
+
+pub fn print_migration(format: Format, report: &MigrationReport) {
+    match format {
+        Format::Json => print_json(report),
+        Format::Porcelain => {
+            for file in &report.files {
+                println!("{}\t{}", file.outcome.key(), field(&file.path));
+            }
+        }
+        Format::Human => {
+            for file in report.files.iter().filter(|f| f.outcome == Outcome::Failed) {
+                println!("{}: {}", file.path, file.error.as_deref().unwrap_or_default());
+            }
+            let count = |outcome| report.files.iter().filter(|f| f.outcome == outcome).count();
+            println!(
+                "{} forwarded, {} uploaded again, {} already there, {} failed",
+                count(Outcome::Forwarded),
+                count(Outcome::Uploaded),
+                count(Outcome::Present),
+                count(Outcome::Failed),
+            );
+            if report.moved {
+                println!("Files copied were deleted from this account");
+            }
+        }
+    }
+}
+
+pub fn print_status(format: Format, status: &Status) {
+    match format {
+        Format::Json => print_json(status),
+        Format::Porcelain => {
+            println!("files\t{}", status.files);
+            println!("size\t{}", status.total_size);
+            for transfer in &status.transfers {
+                let direction = if transfer.upload { "upload" } else { "download" };
+                println!("transfer\t{}\t{}\t{}", direction, transfer.state, field(&transfer.name));
+            }
+        }
+        Format::Human => {
+            println!("{} files, {}", status.files, format_size(status.total_size));
+            if status.transfers.is_empty() {
+                println!("No transfers waiting");
+            }
+            for transfer in &status.transfers {
+                let direction = if transfer.upload { "Upload" } else { "Download" };
+                println!("{} of {} ({})", direction, transfer.name, transfer.state);
+            }
+        }
+    }
+}
+
+pub fn print_results(format: Format, results: &[TransferResult]) {
+    match format {
+        Format::Json => print_json(&results),
+        Format::Porcelain => {
+            for result in results {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    if result.ok { "ok" } else { "error" },
+                    result.message_id.map(|id| id.to_string()).unwrap_or_default(),
+                    field(&result.file),
+                    field(result.error.as_deref().unwrap_or_default()),
+                );
+            }
+        }
+        Format::Human => {
+            for result in results {
+                match &result.error {
+                    None => println!("{}: done", result.file),
+                    Some(error) => eprintln!("{}: {}", result.file, error),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::database::FileRecord;
+use crate::file_kind::FileKind;
+use crate::i18n::tr;
+use crate::slint_generatedAppWindow::PaletteEntry as SlintPaletteEntry;
+
+/// Entries listed in the command palette at most
+const MAX_RESULTS: usize = 20;
+
+/// Actions reachable from the palette: id passed back on activation, and label
+pub const ACTIONS: &[(&str, &str)] = &[
+    ("select-file", "Select Files"),
+    ("select-folder", "Select Folder"),
+    ("upload", "Upload to Telegram"),
+    ("paste", "Paste"),
+    ("screenshot", "Capture Screenshot"),
+    ("screenshot-region", "Capture Region"),
+    ("search", "Search files..."),
+    ("refresh", "Refresh"),
+    ("clear-history", "Clear History"),
+];
+
+/// Palette action for a stored file rather than a command
+pub const FILE_ACTION: &str = "file";
+
+/// How well `query` matches `candidate` as a case-insensitive subsequence;
+/// `None` when some query character is missing. Consecutive characters and
+/// matches at word starts score higher, long candidates slightly lower
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0i64;
+    let mut pos = 0;
+    let mut previous: Option<usize> = None;
+    
+    for q in query.chars().flat_map(char::to_lowercase).filter(|c| !c.is_whitespace()) {
+        let found = pos + candidate[pos..].iter().position(|&c| c == q)?;
+        score += 1;
+        if previous == Some(found.wrapping_sub(1)) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        previous = Some(found);
+        pos = found + 1;
+    }
+    
+    Some(score * 100 - candidate.len() as i64)
+}
+
+/// Actions and files matching `query`, best first; actions win ties
+pub fn search(query: &str, records: &[FileRecord]) -> Vec<SlintPaletteEntry> {
+    let mut matches: Vec<(i64, SlintPaletteEntry)> = ACTIONS
+        .iter()
+        .filter_map(|(action, label)| {
+            let label = tr(label);
+            fuzzy_score(query, &label).map(|score| (score + 1, SlintPaletteEntry {
+                label: label.into(),
+                detail: tr("Action").into(),
+                action: (*action).into(),
+                key: Default::default(),
+            }))
+        })
+        .collect();
+    
+    matches.extend(records.iter().rev().filter_map(|record| {
+        let key = record.key()?;
+        let score = fuzzy_score(query, &record.filename)?;
+        let kind = FileKind::from_filename(&record.filename);
+        Some((score, SlintPaletteEntry {
+            label: format!("{} {}", kind.icon(), record.filename).into(),
+            detail: if record.folder.is_empty() { tr(kind.label()) } else { record.folder.clone() }.into(),
+            action: FILE_ACTION.into(),
+            key: key.to_string().into(),
+        }))
+    }));
+    
+    // Stable, so equally good files stay newest first
+    matches.sort_by(|a, b| b.0.cmp(&a.0));
+    matches.into_iter().take(MAX_RESULTS).map(|(_, entry)| entry).collect()
+}
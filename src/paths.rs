@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::profile;
+
+/// Marker next to the executable that turns on portable mode
+pub const PORTABLE_FLAG: &str = "portable.flag";
+/// Folder for temporary copies in portable mode
+const CACHE_DIR: &str = "cache";
+/// What earlier versions kept in the folder they were started from, moved
+/// to the data folder on first launch
+const OLD_DATA: &[&str] = &[
+    "telegram_cloud.json",
+    "telegram_cloud.session",
+    "content_index.json",
+    "paused_transfers.json",
+    "transfer_journal.json",
+    "scheduled_uploads.json",
+    "sync_state.json",
+    "sftp_host_key.pem",
+    "logs",
+    "crashes",
+    profile::PROFILES_DIR,
+];
+/// Config files, moved to the config folder instead
+const OLD_CONFIG: &[&str] = &["settings.json", ".env"];
+
+/// Where the app keeps its files
+struct Dirs {
+    config: PathBuf,
+    cache: PathBuf,
+}
+
+static DIRS: OnceLock<Dirs> = OnceLock::new();
+static PORTABLE: OnceLock<bool> = OnceLock::new();
+
+/// Decide where the app keeps its files, before any are read, and make the
+/// data folder the working directory so the files can be named plainly.
+///
+/// Normally these are the per-user folders of the OS (e.g. ~/.local/share,
+/// ~/.config and ~/.cache on Linux, AppData on Windows); files earlier
+/// versions left in the folder the app was started from are moved there.
+/// Portable mode (`--portable`, or a portable.flag next to the executable)
+/// keeps everything next to the executable instead, e.g. on a USB stick.
+///
+/// Returns what was moved, as (from, to), for the log
+pub fn init(portable: bool) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let exe = std::env::current_exe().context("Failed to locate the running executable")?;
+    let exe_dir = exe.parent().context("Failed to locate the running executable")?;
+    if portable || exe_dir.join(PORTABLE_FLAG).exists() {
+        std::env::set_current_dir(exe_dir)
+            .with_context(|| format!("Failed to switch to the portable folder {:?}", exe_dir))?;
+        let _ = PORTABLE.set(true);
+        let _ = DIRS.set(Dirs { config: exe_dir.to_path_buf(), cache: exe_dir.join(CACHE_DIR) });
+        return Ok(Vec::new());
+    }
+    
+    let old = std::env::current_dir().context("Failed to get the working directory")?;
+    let Some(project) = ProjectDirs::from("", "", "TeleDrive") else {
+        // No home folder to speak of; keep to the working directory
+        return Ok(Vec::new());
+    };
+    let dirs = Dirs { config: project.config_dir().to_path_buf(), cache: project.cache_dir().to_path_buf() };
+    let data = project.data_dir();
+    for dir in [data, &dirs.config, &dirs.cache] {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    }
+    
+    let mut moved = Vec::new();
+    if old != data {
+        let session = std::env::var("SESSION_NAME").ok().filter(|name| Path::new(name).is_relative());
+        let data_names = OLD_DATA.iter().copied().chain(session.as_deref());
+        for (name, to) in data_names.map(|n| (n, data)).chain(OLD_CONFIG.iter().map(|n| (*n, dirs.config.as_path()))) {
+            let (from, to) = (old.join(name), to.join(name));
+            if from.exists() && !to.exists() && move_path(&from, &to).is_ok() {
+                moved.push((from, to));
+            }
+        }
+        // Profiles keep their settings in the config folder as well
+        for name in profile::list_in(data) {
+            let from = data.join(profile::PROFILES_DIR).join(&name).join("settings.json");
+            let to = dirs.config.join(profile::PROFILES_DIR).join(&name).join("settings.json");
+            if from.exists() && !to.exists() {
+                let _ = std::fs::create_dir_all(to.parent().unwrap_or(&dirs.config));
+                if move_path(&from, &to).is_ok() {
+                    moved.push((from, to));
+                }
+            }
+        }
+    }
+    
+    std::env::set_current_dir(data).with_context(|| format!("Failed to switch to {:?}", data))?;
+    let _ = DIRS.set(dirs);
+    Ok(moved)
+}
+
+/// Rename, or copy and delete when `to` is on another drive
+fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        // Folders of logs and reports aren't worth copying file by file
+        return Err(std::io::ErrorKind::Unsupported.into());
+    }
+    std::fs::copy(from, to)?;
+    std::fs::remove_file(from)
+}
+
+pub fn is_portable() -> bool {
+    PORTABLE.get().copied().unwrap_or(false)
+}
+
+/// Config file `name` of the current profile (settings.json, .env)
+pub fn config_file(name: impl AsRef<Path>) -> PathBuf {
+    let name = name.as_ref();
+    match (DIRS.get(), profile::current()) {
+        (Some(dirs), Some(profile)) if !is_portable() => dirs.config.join(profile::PROFILES_DIR).join(profile).join(name),
+        // Portable profiles are in the working directory already
+        (Some(dirs), None) => dirs.config.join(name),
+        _ => name.to_path_buf(),
+    }
+}
+
+/// Config file `name` of profile `profile`, or of the default profile for
+/// `None`, whichever profile this process runs as
+pub fn profile_config_file(profile: Option<&str>, name: impl AsRef<Path>) -> Option<PathBuf> {
+    let name = name.as_ref();
+    match (DIRS.get(), profile) {
+        (Some(dirs), Some(profile)) if !is_portable() => Some(dirs.config.join(profile::PROFILES_DIR).join(profile).join(name)),
+        (Some(dirs), None) => Some(dirs.config.join(name)),
+        _ => profile::folder(profile).map(|dir| dir.join(name)),
+    }
+}
+
+/// Folder `name` for temporary files: in the OS cache folder, under "cache"
+/// next to the executable when portable so nothing is left on the computer,
+/// or in the system temp folder when neither is known
+pub fn cache_dir(name: &str) -> PathBuf {
+    match DIRS.get() {
+        Some(dirs) => dirs.cache.join(name),
+        None => std::env::temp_dir().join(name),
+    }
+}
@@ -0,0 +1,149 @@
+/// Countries for the login screen picker: ISO code, English name, calling code
+pub const COUNTRIES: &[(&str, &str, &str)] = &[
+    ("AR", "Argentina", "54"),
+    ("AM", "Armenia", "374"),
+    ("AU", "Australia", "61"),
+    ("AT", "Austria", "43"),
+    ("AZ", "Azerbaijan", "994"),
+    ("BY", "Belarus", "375"),
+    ("BE", "Belgium", "32"),
+    ("BR", "Brazil", "55"),
+    ("BG", "Bulgaria", "359"),
+    ("CA", "Canada", "1"),
+    ("CL", "Chile", "56"),
+    ("CN", "China", "86"),
+    ("CO", "Colombia", "57"),
+    ("HR", "Croatia", "385"),
+    ("CZ", "Czechia", "420"),
+    ("DK", "Denmark", "45"),
+    ("EG", "Egypt", "20"),
+    ("EE", "Estonia", "372"),
+    ("FI", "Finland", "358"),
+    ("FR", "France", "33"),
+    ("GE", "Georgia", "995"),
+    ("DE", "Germany", "49"),
+    ("GR", "Greece", "30"),
+    ("HU", "Hungary", "36"),
+    ("IN", "India", "91"),
+    ("ID", "Indonesia", "62"),
+    ("IR", "Iran", "98"),
+    ("IQ", "Iraq", "964"),
+    ("IE", "Ireland", "353"),
+    ("IL", "Israel", "972"),
+    ("IT", "Italy", "39"),
+    ("JP", "Japan", "81"),
+    ("KZ", "Kazakhstan", "7"),
+    ("KG", "Kyrgyzstan", "996"),
+    ("LV", "Latvia", "371"),
+    ("LT", "Lithuania", "370"),
+    ("MY", "Malaysia", "60"),
+    ("MX", "Mexico", "52"),
+    ("MD", "Moldova", "373"),
+    ("NL", "Netherlands", "31"),
+    ("NZ", "New Zealand", "64"),
+    ("NG", "Nigeria", "234"),
+    ("NO", "Norway", "47"),
+    ("PK", "Pakistan", "92"),
+    ("PE", "Peru", "51"),
+    ("PH", "Philippines", "63"),
+    ("PL", "Poland", "48"),
+    ("PT", "Portugal", "351"),
+    ("RO", "Romania", "40"),
+    ("RU", "Russia", "7"),
+    ("SA", "Saudi Arabia", "966"),
+    ("RS", "Serbia", "381"),
+    ("SG", "Singapore", "65"),
+    ("SK", "Slovakia", "421"),
+    ("ZA", "South Africa", "27"),
+    ("KR", "South Korea", "82"),
+    ("ES", "Spain", "34"),
+    ("SE", "Sweden", "46"),
+    ("CH", "Switzerland", "41"),
+    ("TJ", "Tajikistan", "992"),
+    ("TH", "Thailand", "66"),
+    ("TR", "Turkey", "90"),
+    ("UA", "Ukraine", "380"),
+    ("AE", "United Arab Emirates", "971"),
+    ("GB", "United Kingdom", "44"),
+    ("US", "United States", "1"),
+    ("UZ", "Uzbekistan", "998"),
+    ("VN", "Vietnam", "84"),
+];
+
+/// E.164 allows at most 15 digits including the country code
+const MAX_DIGITS: usize = 15;
+/// Shortest plausible number including the country code
+const MIN_DIGITS: usize = 8;
+
+/// Why a phone number was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhoneError {
+    Empty,
+    InvalidCharacter,
+    TooShort,
+    TooLong,
+}
+
+impl PhoneError {
+    /// Feedback shown under the phone number field
+    pub fn message(self) -> String {
+        match self {
+            PhoneError::Empty => String::new(),
+            PhoneError::InvalidCharacter => tr!("Use only digits, spaces, dashes and a leading +"),
+            PhoneError::TooShort => tr!("The number is too short"),
+            PhoneError::TooLong => tr!("The number is too long (at most 15 digits with the country code)"),
+        }
+    }
+}
+
+/// Picker entries, e.g. "Germany (+49)"
+pub fn country_labels() -> Vec<String> {
+    COUNTRIES.iter().map(|(_, name, code)| format!("{} (+{})", name, code)).collect()
+}
+
+/// Picker index of the country with ISO code `iso`
+pub fn country_index(iso: &str) -> Option<usize> {
+    COUNTRIES.iter().position(|(code, _, _)| code.eq_ignore_ascii_case(iso))
+}
+
+/// Country of the user's locale, e.g. "DE" for `de_DE.UTF-8`
+pub fn system_region() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| value.split('.').next()?.split(['_', '-']).nth(1).map(str::to_uppercase))
+}
+
+/// Normalize `input` to E.164 (`+491701234567`). Numbers starting with `+` or
+/// `00` already carry a country code; anything else is taken as a national
+/// number in the country at `country` in `COUNTRIES`, dropping a trunk `0`
+pub fn normalize(country: usize, input: &str) -> Result<String, PhoneError> {
+    let compact: String = input
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '(' | ')' | '.' | '\u{a0}'))
+        .collect();
+    if compact.is_empty() {
+        return Err(PhoneError::Empty);
+    }
+    
+    let digits = if let Some(rest) = compact.strip_prefix('+') {
+        rest.to_string()
+    } else if let Some(rest) = compact.strip_prefix("00") {
+        rest.to_string()
+    } else {
+        let dial = COUNTRIES.get(country).map_or("", |(_, _, code)| code);
+        format!("{}{}", dial, compact.strip_prefix('0').unwrap_or(&compact))
+    };
+    
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(PhoneError::InvalidCharacter);
+    }
+    if digits.len() < MIN_DIGITS {
+        return Err(PhoneError::TooShort);
+    }
+    if digits.len() > MAX_DIGITS {
+        return Err(PhoneError::TooLong);
+    }
+    Ok(format!("+{}", digits))
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Largest image Telegram accepts as a photo
+pub const MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Formats Telegram turns into photos; anything else only goes as a document
+const EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// How images are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SendAs {
+    /// The original file, byte for byte
+    #[default]
+    Document,
+    /// Recompressed by Telegram into a JPEG with a preview in the chat
+    Photo,
+}
+
+impl SendAs {
+    /// Order of the entries in the settings picker
+    const ALL: [SendAs; 2] = [SendAs::Document, SendAs::Photo];
+    
+    pub fn index(self) -> i32 {
+        Self::ALL.iter().position(|s| *s == self).unwrap_or(0) as i32
+    }
+    
+    pub fn from_index(index: i32) -> Self {
+        usize::try_from(index).ok()
+            .and_then(|i| Self::ALL.get(i).copied())
+            .unwrap_or_default()
+    }
+}
+
+/// Whether the image at `path` of `size` bytes can be sent as a photo
+pub fn is_eligible(path: &Path, size: u64) -> bool {
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    EXTENSIONS.contains(&extension.as_str()) && size <= MAX_SIZE
+}
+
+/// Name a file sent as a photo is stored under: Telegram keeps a JPEG
+pub fn stored_name(filename: &str) -> String {
+    Path::new(filename).with_extension("jpg").to_string_lossy().into_owned()
+}
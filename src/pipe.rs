@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use grammers_client::Client;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::mpsc as std_mpsc;
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::io::SyncIoBridge;
+
+use crate::chunked::{fetch_chunk, DOWNLOAD_CHUNK_SIZE, UPLOAD_PART_SIZE};
+use crate::database::{Database, FileRecord};
+use crate::encryption::{self, Encryptor, Keys};
+use crate::stream;
+
+/// Chunks requested ahead of the one being written
+const PREFETCH: usize = 4;
+/// Chunks fetched but not yet written before fetching waits
+const BUFFERED: usize = 8;
+/// Bytes read ahead of the upload
+const PIPE_SIZE: usize = 4 * UPLOAD_PART_SIZE;
+
+/// What went through `pump`
+pub struct Pumped {
+    /// SHA-256 of the input, hex encoded
+    pub sha256: String,
+    /// Size of the input
+    pub size: u64,
+    /// Key the output is encrypted with
+    pub key_id: Option<String>,
+}
+
+/// Pass the blocking `input` on through the returned reader, hashing it and,
+/// with `encrypt`, encrypting it with the current key on the way.
+///
+/// The handle resolves once all of the input went through. A failure ends
+/// the output early, so what was read is only complete if the handle says so
+pub fn pump(input: impl Read + Send + 'static, encrypt: bool) -> Result<(DuplexStream, JoinHandle<Result<Pumped>>)> {
+    let keys = if encrypt { Some(Keys::load()?) } else { None };
+    let (reader, writer) = tokio::io::duplex(PIPE_SIZE);
+    let mut input = HashingReader { inner: input, hasher: Sha256::new(), size: 0 };
+    let mut writer = SyncIoBridge::new(writer);
+    let handle = tokio::task::spawn_blocking(move || {
+        let key_id = match &keys {
+            Some(keys) => {
+                let mut encryptor = Encryptor::new(writer, keys.current())?;
+                std::io::copy(&mut input, &mut encryptor)?;
+                encryptor.finish()?;
+                Some(keys.current_id())
+            }
+            None => {
+                std::io::copy(&mut input, &mut writer)?;
+                writer.flush()?;
+                None
+            }
+        };
+        Ok(Pumped { sha256: format!("{:x}", input.hasher.finalize()), size: input.size, key_id })
+    });
+    Ok((reader, handle))
+}
+
+/// Run `produce` on a thread of its own and read what it writes, e.g. an
+/// archive as it is packed. If it fails, so does reading
+pub fn produce(produce: impl FnOnce(&mut dyn Write) -> Result<()> + Send + 'static) -> impl Read + Send + 'static {
+    let (sender, receiver) = std_mpsc::sync_channel(BUFFERED);
+    std::thread::spawn(move || {
+        let mut writer = ChannelWriter { sender: sender.clone() };
+        if let Err(e) = produce(&mut writer) {
+            let _ = sender.send(Err(std::io::Error::other(format!("{:#}", e))));
+        }
+    });
+    ChannelReader { receiver, chunk: Vec::new(), read: 0 }
+}
+
+/// Sends what is written to it to a `ChannelReader`
+struct ChannelWriter {
+    sender: std_mpsc::SyncSender<std::io::Result<Vec<u8>>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sender.send(Ok(buf.to_vec())).map_err(|_| std::io::ErrorKind::BrokenPipe)?;
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads what a `ChannelWriter` sent until every sender is gone
+struct ChannelReader {
+    receiver: std_mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.read == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.read = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.read);
+        buf[..n].copy_from_slice(&self.chunk[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+/// Hashes what is read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+/// Write a stored file to `out` as it arrives from Telegram, decrypting and
+/// decompressing on the way, without keeping any of it on disk
+pub async fn write_to(client: &Client, db: &Database, record: &FileRecord, out: impl Write + Send + 'static) -> Result<()> {
+    let key = record.key().context("The file has no stored message")?;
+    let (segments, _) = stream::segments(client, db, key).await?
+        .with_context(|| format!("The message holding {} no longer exists", record.filename))?;
+    
+    let (sender, receiver) = mpsc::channel(BUFFERED);
+    let (key_id, compressed) = (record.key_id.clone(), record.compressed);
+    let writer = tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut input = ChunkReader { receiver, chunk: Vec::new(), read: 0 };
+        let mut out: Box<dyn Write> = if compressed {
+            Box::new(zstd::stream::write::Decoder::new(out)?)
+        } else {
+            Box::new(out)
+        };
+        let result = match &key_id {
+            Some(id) => encryption::decrypt(&mut input, id, &mut out),
+            None => std::io::copy(&mut input, &mut out).map(|_| ()).map_err(Into::into),
+        };
+        match result.and_then(|()| Ok(out.flush()?)) {
+            // Whatever reads the output stopped early, like `head` does
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => Ok(()),
+            other => other,
+        }
+    });
+    
+    let fetched = async {
+        for segment in &segments {
+            let chunks = segment.size.div_ceil(DOWNLOAD_CHUNK_SIZE as u64) as u32;
+            let mut requests = VecDeque::new();
+            let mut next = 0;
+            loop {
+                while next < chunks && requests.len() < PREFETCH {
+                    requests.push_back(tokio::spawn(fetch_chunk(client.clone(), segment.location.clone(), next)));
+                    next += 1;
+                }
+                let Some(request) = requests.pop_front() else {
+                    break;
+                };
+                let (_, bytes) = request.await.context("Chunk download panicked")??;
+                if sender.send(bytes).await.is_err() {
+                    // The writer stopped; its result says why
+                    return Ok(());
+                }
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    }.await;
+    
+    drop(sender);
+    let written = writer.await.context("Writing the output panicked")?;
+    fetched?;
+    written
+}
+
+/// Reads the chunks sent over a channel in order, from a blocking thread
+struct ChunkReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.read == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.read = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.read);
+        buf[..n].copy_from_slice(&self.chunk[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
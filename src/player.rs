@@ -0,0 +1,322 @@
+use anyhow::{Context, Result};
+use grammers_client::Client;
+use rodio::{Decoder, OutputStream, Sink, Source};
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::chunked::{fetch_chunk, DOWNLOAD_CHUNK_SIZE};
+use crate::stream::Segment;
+
+/// Chunks kept in memory by a `RemoteReader`, so seeking back a little is instant
+const CACHED_CHUNKS: usize = 4;
+/// Chunks fetched ahead of the one being read
+const PREFETCH_CHUNKS: usize = 2;
+
+/// Reads a stored file straight from Telegram, a chunk at a time.
+///
+/// Meant for a non-runtime thread such as the audio thread: a task on the
+/// runtime `handle` fetches the chunks in order, a few ahead, into a bounded
+/// channel, and reads only wait on what it sends. Seeking elsewhere starts
+/// a new task from there.
+pub struct RemoteReader {
+    handle: Handle,
+    client: Client,
+    segments: Vec<Segment>,
+    total: u64,
+    position: u64,
+    cache: VecDeque<((usize, u32), Vec<u8>)>,
+    fetcher: Option<Fetcher>,
+}
+
+/// The task fetching ahead, and the chunk it sends next
+struct Fetcher {
+    chunks: tokio_mpsc::Receiver<((usize, u32), Result<Vec<u8>>)>,
+    next: Option<(usize, u32)>,
+    task: JoinHandle<()>,
+}
+
+impl RemoteReader {
+    pub fn new(handle: Handle, client: Client, segments: Vec<Segment>) -> Self {
+        let total = segments.iter().map(|s| s.size).sum();
+        Self {
+            handle,
+            client,
+            segments,
+            total,
+            position: 0,
+            cache: VecDeque::new(),
+            fetcher: None,
+        }
+    }
+    
+    /// Segment index, chunk index within it and offset within the chunk of `position`
+    fn locate(&self, position: u64) -> Option<(usize, u32, usize)> {
+        let chunk_size = DOWNLOAD_CHUNK_SIZE as u64;
+        let mut start = 0;
+        for (index, segment) in self.segments.iter().enumerate() {
+            if position < start + segment.size {
+                let within = position - start;
+                return Some((index, (within / chunk_size) as u32, (within % chunk_size) as usize));
+            }
+            start += segment.size;
+        }
+        None
+    }
+    
+    /// Fetch from `key` onwards, dropping whatever was being fetched
+    fn start_fetching(&mut self, key: (usize, u32)) {
+        if let Some(old) = self.fetcher.take() {
+            old.task.abort();
+        }
+        let (sender, chunks) = tokio_mpsc::channel(PREFETCH_CHUNKS);
+        let task = self.handle.spawn(fetch_ahead(self.client.clone(), self.segments.clone(), key, sender));
+        self.fetcher = Some(Fetcher { chunks, next: Some(key), task });
+    }
+    
+    fn chunk(&mut self, key: (usize, u32)) -> std::io::Result<&[u8]> {
+        if let Some(index) = self.cache.iter().position(|(k, _)| *k == key) {
+            return Ok(&self.cache[index].1);
+        }
+        
+        if self.fetcher.as_ref().map_or(true, |f| f.next != Some(key)) {
+            self.start_fetching(key);
+        }
+        let Some(fetcher) = self.fetcher.as_mut() else {
+            return Err(std::io::Error::other("Chunk fetching stopped"));
+        };
+        let received = fetcher.chunks.blocking_recv();
+        let bytes = match received {
+            Some((fetched, Ok(bytes))) => {
+                fetcher.next = next_key(&self.segments, fetched);
+                bytes
+            }
+            Some((_, Err(e))) => {
+                self.fetcher = None;
+                return Err(std::io::Error::other(e));
+            }
+            None => {
+                self.fetcher = None;
+                return Err(std::io::Error::other("Chunk fetching stopped"));
+            }
+        };
+        
+        if self.cache.len() >= CACHED_CHUNKS {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((key, bytes));
+        Ok(&self.cache.back().unwrap().1)
+    }
+}
+
+/// Key of the chunk after `key` in `segments`, if any
+fn next_key(segments: &[Segment], (segment, chunk): (usize, u32)) -> Option<(usize, u32)> {
+    let chunks = segments[segment].size.div_ceil(DOWNLOAD_CHUNK_SIZE as u64) as u32;
+    if chunk + 1 < chunks {
+        Some((segment, chunk + 1))
+    } else if segment + 1 < segments.len() {
+        Some((segment + 1, 0))
+    } else {
+        None
+    }
+}
+
+/// Send the chunks from `key` to the end in order, until the reader stops
+/// taking them or a fetch fails
+async fn fetch_ahead(
+    client: Client,
+    segments: Vec<Segment>,
+    key: (usize, u32),
+    sender: tokio_mpsc::Sender<((usize, u32), Result<Vec<u8>>)>,
+) {
+    let mut key = Some(key);
+    while let Some(current) = key {
+        let result = fetch_chunk(client.clone(), segments[current.0].location.clone(), current.1)
+            .await
+            .map(|(_, bytes)| bytes);
+        let failed = result.is_err();
+        if sender.send((current, result)).await.is_err() || failed {
+            return;
+        }
+        key = next_key(&segments, current);
+    }
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some((segment, chunk, offset)) = self.locate(self.position) else {
+            return Ok(0);
+        };
+        let bytes = self.chunk((segment, chunk))?;
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        
+        let n = buf.len().min(bytes.len() - offset);
+        buf[..n].copy_from_slice(&bytes[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RemoteReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.total as i64 + offset,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+        if target < 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek before start"));
+        }
+        self.position = target as u64;
+        Ok(self.position)
+    }
+}
+
+impl Drop for RemoteReader {
+    fn drop(&mut self) {
+        if let Some(fetcher) = self.fetcher.take() {
+            fetcher.task.abort();
+        }
+    }
+}
+
+enum Command {
+    Play { title: String, reader: RemoteReader },
+    Stop,
+}
+
+/// What the player is doing, for the UI
+#[derive(Debug, Clone, Default)]
+pub struct PlayerStatus {
+    pub title: String,
+    pub active: bool,
+    pub paused: bool,
+    pub position: Duration,
+    /// Unknown for formats whose length can't be read up front
+    pub duration: Option<Duration>,
+}
+
+/// Plays one stored audio file at a time on the default output device.
+///
+/// The output stream lives on a dedicated thread, as it can't be moved
+/// between threads; the sink is shared so controls act on it directly.
+pub struct AudioPlayer {
+    commands: mpsc::Sender<Command>,
+    sink: Arc<Mutex<Option<Arc<Sink>>>>,
+    status: Arc<Mutex<PlayerStatus>>,
+}
+
+impl AudioPlayer {
+    pub fn new() -> Result<Self> {
+        let (commands, receiver) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let sink: Arc<Mutex<Option<Arc<Sink>>>> = Arc::new(Mutex::new(None));
+        let status = Arc::new(Mutex::new(PlayerStatus::default()));
+        
+        let thread_sink = sink.clone();
+        let thread_status = status.clone();
+        std::thread::Builder::new()
+            .name("audio-player".to_string())
+            .spawn(move || {
+                let (_stream, output) = match OutputStream::try_default() {
+                    Ok(output) => {
+                        let _ = ready_tx.send(Ok(()));
+                        output
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                
+                for command in receiver {
+                    if let Some(old) = thread_sink.lock().unwrap().take() {
+                        old.stop();
+                    }
+                    *thread_status.lock().unwrap() = PlayerStatus::default();
+                    
+                    let Command::Play { title, reader } = command else {
+                        continue;
+                    };
+                    
+                    // Decoding starts by reading the file header, which may take a moment
+                    let source = match Decoder::new(reader) {
+                        Ok(source) => source,
+                        Err(e) => {
+                            error!("Failed to decode {}: {:?}", title, e);
+                            continue;
+                        }
+                    };
+                    let duration = source.total_duration();
+                    let new_sink = match Sink::try_new(&output) {
+                        Ok(sink) => Arc::new(sink),
+                        Err(e) => {
+                            error!("Failed to open audio output: {:?}", e);
+                            continue;
+                        }
+                    };
+                    new_sink.append(source);
+                    
+                    info!("Playing {}", title);
+                    *thread_status.lock().unwrap() = PlayerStatus {
+                        title,
+                        active: true,
+                        paused: false,
+                        position: Duration::ZERO,
+                        duration,
+                    };
+                    *thread_sink.lock().unwrap() = Some(new_sink);
+                }
+            })?;
+        
+        ready_rx.recv()
+            .context("Audio thread exited")?
+            .context("No audio output device")?;
+        
+        Ok(Self { commands, sink, status })
+    }
+    
+    /// Start playing `reader`, replacing whatever was playing
+    pub fn play(&self, title: String, reader: RemoteReader) {
+        let _ = self.commands.send(Command::Play { title, reader });
+    }
+    
+    pub fn toggle_pause(&self) {
+        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
+            if sink.is_paused() {
+                sink.play();
+            } else {
+                sink.pause();
+            }
+        }
+    }
+    
+    pub fn seek(&self, position: Duration) {
+        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
+            if let Err(e) = sink.try_seek(position) {
+                error!("Seek failed: {:?}", e);
+            }
+        }
+    }
+    
+    pub fn stop(&self) {
+        let _ = self.commands.send(Command::Stop);
+    }
+    
+    pub fn status(&self) -> PlayerStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        if let Some(sink) = self.sink.lock().unwrap().as_ref() {
+            status.position = sink.get_pos();
+            status.paused = sink.is_paused();
+            status.active = !sink.empty();
+        }
+        status
+    }
+}
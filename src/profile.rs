@@ -0,0 +1,119 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::paths;
+
+/// Folder, next to the default profile's files, holding one folder per
+/// named profile
+pub const PROFILES_DIR: &str = "profiles";
+
+/// Profile the app runs as against a test server, and only then
+pub const TEST_PROFILE: &str = "test";
+
+/// Named profile this process runs as; unset for the default one
+static CURRENT: OnceLock<String> = OnceLock::new();
+/// Folder the default profile's files are in, where the app was started
+static BASE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Run as profile `name`, or the default profile for `None`. Every file the
+/// app keeps (session, database, settings, logs) is relative to the working
+/// directory, so a named profile moves into a folder of its own
+pub fn activate(name: Option<&str>) -> Result<()> {
+    let base = std::env::current_dir().context("Failed to get the working directory")?;
+    let _ = BASE.set(base.clone());
+    let Some(name) = name.filter(|n| !n.is_empty()) else {
+        return Ok(());
+    };
+    validate(name)?;
+    let dir = base.join(PROFILES_DIR).join(name);
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create profile folder {:?}", dir))?;
+    std::env::set_current_dir(&dir).with_context(|| format!("Failed to switch to profile folder {:?}", dir))?;
+    let _ = CURRENT.set(name.to_string());
+    Ok(())
+}
+
+/// Profile to run as when `--profile` asked for `requested`. A test server
+/// always gets the test profile, so test accounts never end up in a real
+/// index, and production never uses it
+pub fn resolve(requested: Option<&str>, test_server: bool) -> Result<Option<&str>> {
+    let requested = requested.filter(|n| !n.is_empty());
+    match (requested, test_server) {
+        (None, true) => Ok(Some(TEST_PROFILE)),
+        (Some(name), true) if name != TEST_PROFILE => {
+            bail!("A test server always runs as the {:?} profile, not {:?}", TEST_PROFILE, name)
+        }
+        (Some(TEST_PROFILE), false) => bail!("The {:?} profile is reserved for test servers", TEST_PROFILE),
+        _ => Ok(requested),
+    }
+}
+
+/// Names become folder names, so only plain ones are allowed
+pub fn validate(name: &str) -> Result<()> {
+    if name.len() > 64 || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        bail!("Profile names may only use letters, digits, - and _");
+    }
+    Ok(())
+}
+
+pub fn current() -> Option<&'static str> {
+    CURRENT.get().map(String::as_str)
+}
+
+/// Folder profile `name` (`None` for the default one) keeps its session and
+/// index in
+pub fn folder(name: Option<&str>) -> Option<PathBuf> {
+    let base = BASE.get()?;
+    Some(match name {
+        Some(name) => base.join(PROFILES_DIR).join(name),
+        None => base.clone(),
+    })
+}
+
+/// Named profiles created so far, sorted, without the test profile
+pub fn list() -> Vec<String> {
+    let mut names = BASE.get().map(|base| list_in(base)).unwrap_or_default();
+    names.retain(|name| name != TEST_PROFILE);
+    names
+}
+
+/// Named profiles kept under `base`, sorted
+pub fn list_in(base: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(base.join(PROFILES_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| validate(name).is_ok())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Keyring entry `name` of the current profile; the default profile keeps
+/// the plain names it always used
+pub fn keyring_name(name: &str) -> String {
+    match current() {
+        Some(profile) => format!("{}@{}", name, profile),
+        None => name.to_string(),
+    }
+}
+
+/// Start another instance as profile `name` (`None` for the default one).
+/// It runs from the folder this one was started in, so `.env` is found again
+pub fn launch(name: Option<&str>) -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to locate the running executable")?;
+    let mut command = std::process::Command::new(exe);
+    if let Some(base) = BASE.get() {
+        command.current_dir(base);
+    }
+    if let Some(name) = name {
+        command.arg("--profile").arg(name);
+    }
+    if paths::is_portable() {
+        command.arg("--portable");
+    }
+    command.spawn().context("Failed to start TeleDrive")?;
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Mirrors `Settings::read_only`, so every path that would change stored
+/// files can check it without being handed the settings
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Refused because read-only mode is on
+#[derive(Debug, thiserror::Error)]
+#[error("Read-only mode is on; {0} is disabled")]
+pub struct ReadOnly(pub &'static str);
+
+/// Fail with `ReadOnly` when read-only mode is on. `action` names what was
+/// attempted, e.g. "uploading"
+pub fn check(action: &'static str) -> Result<(), ReadOnly> {
+    if is_enabled() {
+        return Err(ReadOnly(action));
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::database::FileRecord;
+
+/// One line of an exported report
+#[derive(Serialize)]
+struct ReportRow<'a> {
+    name: &'a str,
+    folder: &'a str,
+    size: u64,
+    /// RFC 3339
+    uploaded: String,
+    chat: String,
+    message_id: Option<i32>,
+    sha256: Option<&'a str>,
+    tags: &'a [String],
+}
+
+impl<'a> ReportRow<'a> {
+    fn new(record: &'a FileRecord) -> Self {
+        Self {
+            name: &record.filename,
+            folder: &record.folder,
+            size: record.file_size,
+            uploaded: record.upload_date.to_rfc3339(),
+            chat: record.chat_label(),
+            message_id: record.message_id,
+            sha256: record.sha256.as_deref(),
+            tags: &record.tags,
+        }
+    }
+}
+
+/// Report format, picked from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    /// JSON for `.json` files, CSV for anything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ReportFormat::Json,
+            _ => ReportFormat::Csv,
+        }
+    }
+}
+
+/// Write a report of `records` to `path`
+pub fn export(records: &[FileRecord], path: &Path) -> Result<()> {
+    let rows: Vec<ReportRow> = records.iter().map(ReportRow::new).collect();
+    let content = match ReportFormat::from_path(path) {
+        ReportFormat::Json => serde_json::to_string_pretty(&rows)?,
+        ReportFormat::Csv => to_csv(&rows),
+    };
+    std::fs::write(path, content).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// RFC 4180 CSV with a header line; tags are joined with `;`
+fn to_csv(rows: &[ReportRow]) -> String {
+    let mut out = String::from("name,folder,size,uploaded,chat,message_id,sha256,tags\r\n");
+    for row in rows {
+        let fields = [
+            row.name.to_string(),
+            row.folder.to_string(),
+            row.size.to_string(),
+            row.uploaded.clone(),
+            row.chat.clone(),
+            row.message_id.map(|id| id.to_string()).unwrap_or_default(),
+            row.sha256.unwrap_or_default().to_string(),
+            row.tags.join(";"),
+        ];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&line.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,105 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::errors::{self, ErrorKind};
+use crate::hooks::HookRejected;
+use crate::transfer::{Cancelled, Paused};
+
+/// Exponential backoff schedule with jitter for retrying transient failures
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    max_attempts: u32,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration, max_attempts: u32) -> Self {
+        Self {
+            base,
+            max,
+            max_attempts,
+            attempt: 0,
+        }
+    }
+    
+    /// Number of retries handed out so far
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+    
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+    
+    /// Delay before the next attempt, or `None` once all attempts are used up.
+    ///
+    /// Half of the exponential delay is fixed and half is random ("equal jitter"),
+    /// so clients that dropped together don't reconnect in lockstep.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= self.max_attempts {
+            return None;
+        }
+        
+        let exp = self.base.saturating_mul(1u32 << self.attempt.min(16));
+        let capped = exp.min(self.max);
+        self.attempt += 1;
+        
+        let half = capped / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        Some(half + Duration::from_millis(jitter_ms))
+    }
+}
+
+/// How failed transfers are retried before the failure is reported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Retries after the first failure; 0 reports failures straight away
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub base_delay_secs: u64,
+    pub max_delay_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_secs: 10,
+            max_delay_secs: 10 * 60,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `retries_done + 1`, or `None` once all retries are used up
+    pub fn delay(&self, retries_done: u32) -> Option<Duration> {
+        let mut backoff = Backoff::new(
+            Duration::from_secs(self.base_delay_secs),
+            Duration::from_secs(self.max_delay_secs),
+            self.max_retries,
+        );
+        for _ in 0..retries_done {
+            backoff.next_delay()?;
+        }
+        backoff.next_delay()
+    }
+}
+
+/// Whether a failed transfer is worth another try. Refusals that would only
+/// happen again are not: read-only mode, files over the limit, a full disk,
+/// a rejected sign-in, a hook saying no or a local file that is gone
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    if err.is::<Cancelled>() || err.is::<Paused>() {
+        return false;
+    }
+    let refused = err.chain().any(|cause| {
+        cause.is::<HookRejected>() || cause.downcast_ref::<std::io::Error>().map_or(false, |io| {
+            matches!(io.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied)
+        })
+    });
+    !refused && matches!(errors::classify(err), ErrorKind::Network | ErrorKind::Integrity | ErrorKind::Other)
+}
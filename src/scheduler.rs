@@ -0,0 +1,220 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use crate::backup::{run_due_backups, BackupClock};
+use crate::config::Settings;
+use crate::database::Database;
+use crate::errors;
+use crate::sync::SyncEngine;
+use crate::transfer::TransferQueue;
+use crate::webhooks::{self, WebhookEvent};
+use crate::{
+    queued_status, refresh_file_list, set_status, update_backup_jobs, update_conflicts,
+    update_scheduled_list, update_transfer_list, AppWindow,
+};
+
+/// Longest the scheduler sleeps, so clock changes and suspend are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+/// An upload waiting for its start time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledUpload {
+    pub id: u64,
+    pub path: PathBuf,
+    /// Seconds since the Unix epoch
+    pub start_at: i64,
+}
+
+impl ScheduledUpload {
+    pub fn start_at_local(&self) -> DateTime<Local> {
+        Local.timestamp_opt(self.start_at, 0).single().unwrap_or_else(Local::now)
+    }
+}
+
+/// Uploads held back until a given time, persisted so they survive a restart
+pub struct Scheduler {
+    entries: Mutex<Vec<ScheduledUpload>>,
+    file: PathBuf,
+    wake: Notify,
+    next_id: AtomicU64,
+}
+
+impl Scheduler {
+    /// Create the scheduler, restoring entries saved in `file`
+    pub fn new(file: impl AsRef<Path>) -> Self {
+        let file = file.as_ref().to_path_buf();
+        let entries: Vec<ScheduledUpload> = std::fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let next_id = entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        
+        Self {
+            entries: Mutex::new(entries),
+            file,
+            wake: Notify::new(),
+            next_id: AtomicU64::new(next_id),
+        }
+    }
+    
+    /// Hold `path` back until `start_at`
+    pub fn schedule(&self, path: PathBuf, start_at: DateTime<Local>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.entries.lock().unwrap().push(ScheduledUpload {
+            id,
+            path,
+            start_at: start_at.timestamp(),
+        });
+        self.save();
+        self.wake.notify_one();
+        id
+    }
+    
+    /// Drop a scheduled upload; returns whether it existed
+    pub fn cancel(&self, id: u64) -> bool {
+        let removed = {
+            let mut entries = self.entries.lock().unwrap();
+            let before = entries.len();
+            entries.retain(|e| e.id != id);
+            entries.len() != before
+        };
+        if removed {
+            self.save();
+        }
+        removed
+    }
+    
+    /// Waiting uploads, soonest first
+    pub fn entries(&self) -> Vec<ScheduledUpload> {
+        let mut entries = self.entries.lock().unwrap().clone();
+        entries.sort_by_key(|e| e.start_at);
+        entries
+    }
+    
+    /// Remove and return every entry whose time has come
+    fn take_due(&self, now: i64) -> Vec<ScheduledUpload> {
+        let due: Vec<ScheduledUpload> = {
+            let mut entries = self.entries.lock().unwrap();
+            let (due, waiting) = entries.drain(..).partition(|e| e.start_at <= now);
+            *entries = waiting;
+            due
+        };
+        if !due.is_empty() {
+            self.save();
+        }
+        due
+    }
+    
+    /// Time until the next entry is due
+    fn next_due_in(&self, now: i64) -> Option<Duration> {
+        self.entries.lock().unwrap()
+            .iter()
+            .map(|e| Duration::from_secs((e.start_at - now).max(0) as u64))
+            .min()
+    }
+    
+    fn save(&self) {
+        let entries = self.entries.lock().unwrap().clone();
+        let result = serde_json::to_string_pretty(&entries)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| std::fs::write(&self.file, json).map_err(Into::into));
+        if let Err(e) = result {
+            error!("Failed to save scheduled uploads: {:?}", e);
+        }
+    }
+}
+
+/// Parse a start time typed by the user: "HH:MM" (the next time the clock
+/// shows it) or "YYYY-MM-DD HH:MM"
+pub fn parse_start_time(text: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let text = text.trim();
+    
+    if let Ok(time) = NaiveTime::parse_from_str(text, "%H:%M") {
+        let mut date = now.date_naive();
+        if now.time() >= time {
+            date = date.succ_opt().context("Date out of range")?;
+        }
+        return date.and_time(time)
+            .and_local_timezone(Local)
+            .earliest()
+            .context("That time does not exist in the local time zone");
+    }
+    
+    let datetime = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M")
+        .context("Use HH:MM or YYYY-MM-DD HH:MM")?;
+    let start_at = datetime.and_local_timezone(Local)
+        .earliest()
+        .context("That time does not exist in the local time zone")?;
+    if start_at <= now {
+        bail!("{} is in the past", text);
+    }
+    Ok(start_at)
+}
+
+/// Move scheduled uploads into the transfer queue as they become due, start
+/// recurring backup jobs when their schedule fires and run due folder syncs
+pub async fn run_scheduler(
+    scheduler: Arc<Scheduler>,
+    queue: Arc<TransferQueue>,
+    sync: Arc<SyncEngine>,
+    settings: Arc<Mutex<Settings>>,
+    db: Arc<Database>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    let mut backup_clock = BackupClock::default();
+    
+    loop {
+        let now = Local::now().timestamp();
+        let due = scheduler.take_due(now);
+        if !due.is_empty() {
+            for entry in due {
+                info!("Scheduled upload of {:?} is due", entry.path);
+                queue.push_upload(entry.path);
+            }
+            update_transfer_list(&ui_handle, &queue);
+            update_scheduled_list(&ui_handle, &scheduler);
+            set_status(&ui_handle, queued_status(&queue));
+        }
+        
+        let backed_up = run_due_backups(&mut backup_clock, &settings, &db, &queue).await;
+        if backed_up > 0 {
+            update_transfer_list(&ui_handle, &queue);
+            set_status(&ui_handle, tr!("Scheduled backup queued {count} file(s)", count = backed_up));
+        }
+        update_backup_jobs(&ui_handle, &settings.lock().unwrap());
+        
+        for (pair, result) in sync.run_due(&settings, &db, &queue).await {
+            match result {
+                Ok(report) if report.is_empty() => {}
+                Ok(report) => {
+                    update_transfer_list(&ui_handle, &queue);
+                    update_conflicts(&ui_handle, &sync);
+                    refresh_file_list(&ui_handle, &db);
+                    set_status(&ui_handle, tr!("Synced {path}: {summary}", path = pair.local.display(), summary = report.summary()));
+                }
+                Err(e) => {
+                    error!("Sync of {:?} failed: {:?}", pair.local, e);
+                    let webhook = settings.lock().unwrap().webhook_url.clone();
+                    webhooks::notify(&webhook, WebhookEvent::SyncError {
+                        path: pair.local.display().to_string(),
+                        error: format!("{:#}", e),
+                    });
+                    set_status(&ui_handle, tr!("Sync of {path} failed: {error}", path = pair.local.display(), error = errors::describe(&e)));
+                }
+            }
+        }
+        
+        let sleep = scheduler.next_due_in(now).unwrap_or(MAX_SLEEP).min(MAX_SLEEP);
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = scheduler.wake.notified() => {}
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use xcap::Monitor;
+
+use crate::database::FileRecord;
+use crate::paths;
+
+/// Capture the primary monitor to a timestamped PNG in the temp dir
+pub fn capture_primary_screen() -> Result<PathBuf> {
+    let monitors = Monitor::all().context("Failed to list monitors")?;
+    let monitor = monitors
+        .iter()
+        .find(|m| m.is_primary())
+        .or_else(|| monitors.first())
+        .context("No monitor found")?;
+    
+    let image = monitor.capture_image().context("Screen capture failed")?;
+    
+    let dir = paths::cache_dir("teledrive-screenshots");
+    std::fs::create_dir_all(&dir)?;
+    
+    let name = format!("Screenshot_{}.png", chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
+    let path = dir.join(name);
+    image.save_with_format(&path, image::ImageFormat::Png)?;
+    
+    Ok(path)
+}
+
+/// Cut the screenshot at `path` down to a region, given as fractions of
+/// its width and height, in place
+pub fn crop(path: &Path, x: f32, y: f32, width: f32, height: f32) -> Result<()> {
+    let image = image::open(path).context("Failed to read the screenshot")?;
+    let (full_width, full_height) = (image.width() as f32, image.height() as f32);
+    let left = (x.clamp(0.0, 1.0) * full_width) as u32;
+    let top = (y.clamp(0.0, 1.0) * full_height) as u32;
+    let right = ((x + width).clamp(0.0, 1.0) * full_width).ceil() as u32;
+    let bottom = ((y + height).clamp(0.0, 1.0) * full_height).ceil() as u32;
+    if right <= left || bottom <= top {
+        bail!("The region is empty");
+    }
+    image.crop_imm(left, top, right - left, bottom - top).save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// t.me link to the file `record`, which members of its chat can open.
+/// Only supergroups and channels have such links; files in Saved Messages
+/// or a private chat have none
+pub fn message_link(record: &FileRecord) -> Option<String> {
+    let message_id = record.message_id?;
+    let chat = record.chat.as_ref()?;
+    matches!(chat.kind.as_str(), "megagroup" | "broadcast" | "gigagroup")
+        .then(|| format!("https://t.me/c/{}/{}", chat.id, message_id))
+}
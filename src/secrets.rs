@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::profile;
+
+/// Service name the secrets are filed under in the OS keyring (Secret
+/// Service, Keychain or Credential Manager)
+const SERVICE: &str = "teledrive";
+
+/// Names of the stored secrets
+pub const API_HASH: &str = "api-hash";
+pub const TWO_FA_HINT: &str = "2fa-hint";
+pub const SESSION_KEY: &str = "session-key";
+pub const BOT_TOKEN: &str = "bot-token";
+pub const FILE_KEYS: &str = "file-keys";
+
+/// Each profile files its secrets under names of its own
+fn entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, &profile::keyring_name(name)).context("OS keyring unavailable")
+}
+
+/// Stored secret, `None` if it was never set
+pub fn get(name: &str) -> Result<Option<Zeroizing<String>>> {
+    match entry(name)?.get_password() {
+        Ok(value) => Ok(Some(Zeroizing::new(value))),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {} from the keyring", name)),
+    }
+}
+
+pub fn set(name: &str, value: &str) -> Result<()> {
+    entry(name)?
+        .set_password(value)
+        .with_context(|| format!("Failed to store {} in the keyring", name))
+}
+
+/// Forget a secret; nothing to do if it was never set
+pub fn delete(name: &str) -> Result<()> {
+    match entry(name)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to remove {} from the keyring", name)),
+    }
+}
+
+/// 256-bit key stored hex encoded under `name`, generated on first use
+pub fn key(name: &str) -> Result<Zeroizing<[u8; 32]>> {
+    if let Some(hex) = get(name)? {
+        return decode_key(&hex).with_context(|| format!("Malformed {} in the keyring", name));
+    }
+    let mut key = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(key.as_mut_slice());
+    let hex = Zeroizing::new(key.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    set(name, &hex)?;
+    Ok(key)
+}
+
+/// Whether `given` equals `expected`, in constant time: their hashes are
+/// compared byte for byte without stopping at the first difference
+pub fn same_secret(given: &str, expected: &str) -> bool {
+    let (given, expected) = (Sha256::digest(given), Sha256::digest(expected));
+    given.iter().zip(expected.iter()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// 256-bit key from its 64 hex digits, `None` if it is anything else
+pub fn decode_key(hex: &str) -> Option<Zeroizing<[u8; 32]>> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != 64 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut key = Zeroizing::new([0u8; 32]);
+    for (byte, digits) in key.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn decodes_hex_keys() {
+        let hex = "00ff".repeat(16);
+        let key = decode_key(&format!("{}\n", hex)).unwrap();
+        assert_eq!(key[..2], [0x00, 0xff]);
+        assert_eq!(key[30..], [0x00, 0xff]);
+    }
+    
+    #[test]
+    fn rejects_malformed_keys() {
+        // 64 bytes, but not 64 hex digits
+        let multibyte = format!("{}é", "0".repeat(62));
+        assert_eq!(multibyte.len(), 64);
+        for hex in ["", "00ff", &"0g".repeat(32), &"+f".repeat(32), &multibyte] {
+            assert!(decode_key(hex).is_none(), "{:?} decoded", hex);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use slint::{Model, SharedString};
+use std::collections::HashSet;
+
+use crate::database::RecordKey;
+use crate::AppWindow;
+
+/// Selection after clicking `clicked` in a list showing `visible` in order:
+/// a plain click selects just that row, Ctrl toggles it, Shift extends from
+/// the anchor (the last row clicked without Shift) to it
+pub fn click(visible: &[SharedString], selected: &HashSet<SharedString>, anchor: &SharedString, clicked: &SharedString, ctrl: bool, shift: bool) -> HashSet<SharedString> {
+    let position = |key: &SharedString| visible.iter().position(|v| v == key);
+    match (shift, position(anchor), position(clicked)) {
+        (true, Some(from), Some(to)) => {
+            let range = visible[from.min(to)..=from.max(to)].iter().cloned();
+            if ctrl {
+                selected.iter().cloned().chain(range).collect()
+            } else {
+                range.collect()
+            }
+        }
+        _ if ctrl => {
+            let mut selected = selected.clone();
+            if !selected.remove(clicked) {
+                selected.insert(clicked.clone());
+            }
+            selected
+        }
+        _ => HashSet::from([clicked.clone()]),
+    }
+}
+
+/// Keys of the selected rows of the file list, as the rows hold them
+pub fn selected_rows(ui: &AppWindow) -> HashSet<SharedString> {
+    ui.get_uploaded_files()
+        .iter()
+        .filter(|f| f.selected && !f.key.is_empty())
+        .map(|f| f.key)
+        .collect()
+}
+
+/// Files of the selected rows of the file list
+pub fn selected_keys(ui: &AppWindow) -> Vec<RecordKey> {
+    ui.get_uploaded_files()
+        .iter()
+        .filter(|f| f.selected)
+        .filter_map(|f| f.key.parse().ok())
+        .collect()
+}
+
+/// Mark exactly the rows of the files in `keys` as selected
+pub fn show(ui: &AppWindow, keys: &HashSet<SharedString>) {
+    let files = ui.get_uploaded_files();
+    for row in 0..files.row_count() {
+        let Some(mut file) = files.row_data(row) else { continue };
+        let selected = keys.contains(&file.key);
+        if file.selected != selected {
+            file.selected = selected;
+            files.set_row_data(row, file);
+        }
+    }
+    ui.set_selected_count(keys.len() as i32);
+}
+
+/// Handle a click on the row of file `key`
+pub fn click_row(ui: &AppWindow, key: SharedString, ctrl: bool, shift: bool) {
+    if key.is_empty() {
+        return;
+    }
+    let files = ui.get_uploaded_files();
+    let visible: Vec<SharedString> = files.iter().map(|f| f.key).collect();
+    let keys = click(&visible, &selected_rows(ui), &ui.get_selection_anchor(), &key, ctrl, shift);
+    if !shift {
+        ui.set_selection_anchor(key);
+    }
+    show(ui, &keys);
+}
@@ -0,0 +1,109 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use grammers_session::Session;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+use crate::secrets;
+
+/// Marks an encrypted session file; plain grammers sessions never start with it
+const MAGIC: &[u8] = b"TDSESS1\0";
+const NONCE_LEN: usize = 12;
+
+/// Where the key protecting the session file comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// Random key kept in the OS keyring
+    Keyring,
+    /// Derived from the app lock passphrase, so the session opens only after unlocking
+    Passphrase,
+}
+
+impl KeySource {
+    /// Order of the entries in the session encryption picker, "Off" first
+    const ALL: [Option<KeySource>; 3] = [None, Some(KeySource::Keyring), Some(KeySource::Passphrase)];
+    
+    pub fn index(source: Option<Self>) -> i32 {
+        Self::ALL.iter().position(|s| *s == source).unwrap_or(0) as i32
+    }
+    
+    pub fn from_index(index: i32) -> Option<Self> {
+        usize::try_from(index).ok()
+            .and_then(|i| Self::ALL.get(i).copied())
+            .flatten()
+    }
+}
+
+pub type SessionKey = Zeroizing<[u8; 32]>;
+
+/// Key for reading and writing the session, filled once it is known (at
+/// startup for the keyring, on unlock for the passphrase). Stays empty while
+/// the session is stored in plain text
+pub type SharedSessionKey = Arc<Mutex<Option<SessionKey>>>;
+
+/// Key kept in the OS keyring, created on first use
+pub fn keyring_key() -> Result<SessionKey> {
+    secrets::key(secrets::SESSION_KEY)
+}
+
+/// Load the session, decrypting it in memory when needed; a missing file
+/// gives a new session
+pub fn load(path: impl AsRef<Path>, key: Option<&SessionKey>) -> Result<Session> {
+    let bytes = match std::fs::read(path.as_ref()) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Session::new()),
+        Err(e) => return Err(e).context("Failed to read session file"),
+    };
+    let Some(sealed) = bytes.strip_prefix(MAGIC) else {
+        return Session::load(&bytes).context("Failed to load session file");
+    };
+    let Some(key) = key else {
+        bail!("The session file is encrypted; unlock the app first");
+    };
+    if sealed.len() < NONCE_LEN {
+        bail!("Truncated session file");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let plain = Zeroizing::new(
+        ChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Could not decrypt the session file; wrong key"))?,
+    );
+    Session::load(&plain).context("Failed to load session file")
+}
+
+/// Save the session, encrypted when a key is given
+pub fn save(session: &Session, path: impl AsRef<Path>, key: Option<&SessionKey>) -> Result<()> {
+    let plain = Zeroizing::new(session.save());
+    let bytes = match key {
+        Some(key) => {
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+                .encrypt(&nonce, plain.as_slice())
+                .map_err(|_| anyhow!("Failed to encrypt the session"))?;
+            [MAGIC, nonce.as_slice(), &ciphertext[..]].concat()
+        }
+        None => plain.to_vec(),
+    };
+    // Write next to the file first so a crash can't leave half a session
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Rewrite the session at `path` from one key (or none) to another. Nothing
+/// to do when no session has been saved yet
+pub fn reencrypt(path: impl AsRef<Path>, from: Option<&SessionKey>, to: Option<&SessionKey>) -> Result<()> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(());
+    }
+    let session = load(path, from)?;
+    save(&session, path, to)
+}
@@ -0,0 +1,564 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use grammers_client::Client;
+use russh::server::{Auth, Msg, Session};
+use russh::{Channel, ChannelId};
+use russh_keys::key::KeyPair;
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::{error, info, warn};
+
+use crate::chunked::{fetch_chunk, DOWNLOAD_CHUNK_SIZE};
+use crate::database::{Database, FileRecord, RecordKey};
+use crate::stream::{self, Segment};
+use crate::transfer::{ResumeState, TransferControl, TransferKind, TransferQueue, UploadOptions};
+use crate::{delete_stored_file, download_file_from_telegram, folder, paths, read_only, secrets};
+
+/// Private key identifying the server to clients, created on first start
+const HOST_KEY_FILE: &str = "sftp_host_key.pem";
+/// Directory under the system temp dir holding files being written
+const TEMP_DIR: &str = "teledrive-sftp";
+/// Delay before answering a wrong password, to slow down guessing
+const AUTH_REJECTION_TIME: Duration = Duration::from_secs(2);
+
+/// What the SFTP server needs to reach the stored files
+#[derive(Clone)]
+pub struct SftpContext {
+    pub client: Arc<Mutex<Option<Client>>>,
+    pub db: Arc<Database>,
+    /// Writes are uploaded through the transfer queue once the file is closed
+    pub queue: Arc<TransferQueue>,
+    pub user: String,
+    pub password: String,
+}
+
+/// Serve the virtual filesystem over SFTP on `listen` until the process exits
+pub async fn run_sftp_server(listen: SocketAddr, context: SftpContext) -> Result<()> {
+    let config = russh::server::Config {
+        keys: vec![load_host_key()?],
+        auth_rejection_time: AUTH_REJECTION_TIME,
+        ..Default::default()
+    };
+    info!("SFTP server listening on {}", listen);
+    russh::server::run(Arc::new(config), listen, SftpServer { context }).await?;
+    Ok(())
+}
+
+fn load_host_key() -> Result<KeyPair> {
+    if let Ok(pem) = std::fs::read_to_string(HOST_KEY_FILE) {
+        return russh_keys::decode_secret_key(&pem, None).context("Invalid SFTP host key");
+    }
+    let key = KeyPair::generate_ed25519().context("Failed to generate SFTP host key")?;
+    let mut pem = Vec::new();
+    russh_keys::encode_pkcs8_pem(&key, &mut pem)?;
+    std::fs::write(HOST_KEY_FILE, pem)?;
+    info!("Created SFTP host key {}", HOST_KEY_FILE);
+    Ok(key)
+}
+
+struct SftpServer {
+    context: SftpContext,
+}
+
+impl russh::server::Server for SftpServer {
+    type Handler = SshSession;
+    
+    fn new_client(&mut self, peer: Option<SocketAddr>) -> SshSession {
+        info!("SFTP connection from {:?}", peer);
+        SshSession {
+            context: self.context.clone(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+/// One SSH connection; only the `sftp` subsystem is offered
+struct SshSession {
+    context: SftpContext,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+#[async_trait]
+impl russh::server::Handler for SshSession {
+    type Error = anyhow::Error;
+    
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        // Both checked either way, so the time taken doesn't tell which was wrong
+        let user_ok = secrets::same_secret(user, &self.context.user);
+        let password_ok = secrets::same_secret(password, &self.context.password);
+        if user_ok & password_ok {
+            Ok(Auth::Accept)
+        } else {
+            warn!("Rejected SFTP login for {:?}", user);
+            Ok(Auth::Reject { proceed_with_methods: None })
+        }
+    }
+    
+    async fn channel_open_session(&mut self, channel: Channel<Msg>, _session: &mut Session) -> Result<bool, Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        Ok(true)
+    }
+    
+    async fn subsystem_request(&mut self, channel_id: ChannelId, name: &str, session: &mut Session) -> Result<(), Self::Error> {
+        match self.channels.remove(&channel_id) {
+            Some(channel) if name == "sftp" => {
+                session.channel_success(channel_id);
+                let handler = SftpHandler::new(self.context.clone());
+                tokio::spawn(russh_sftp::server::run(channel.into_stream(), handler));
+            }
+            _ => session.channel_failure(channel_id),
+        }
+        Ok(())
+    }
+}
+
+/// An open SFTP handle
+enum OpenHandle {
+    /// Listing of a directory; `None` once it has been sent
+    Dir(Option<Vec<File>>),
+    /// A stored file being read straight from Telegram
+    Read {
+        segments: Vec<Segment>,
+        /// Last chunk fetched, keyed by segment and chunk index
+        cached: Option<((usize, u32), Vec<u8>)>,
+    },
+    /// A file being written to a local temporary copy
+    Write {
+        file: tokio::fs::File,
+        path: PathBuf,
+        folder: String,
+        /// Every write goes to the end, whatever its offset
+        append: bool,
+        /// Stored file the copy takes the place of once uploaded
+        replaces: Option<RecordKey>,
+    },
+}
+
+/// Maps SFTP paths onto the virtual folders: `/a/b/name` is `name` in folder `a/b`
+struct SftpHandler {
+    context: SftpContext,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+    /// Folders created in this session that hold no files yet
+    created_dirs: BTreeSet<String>,
+}
+
+impl SftpHandler {
+    fn new(context: SftpContext) -> Self {
+        Self {
+            context,
+            handles: HashMap::new(),
+            next_handle: 0,
+            created_dirs: BTreeSet::new(),
+        }
+    }
+    
+    fn add_handle(&mut self, handle: OpenHandle) -> String {
+        self.next_handle += 1;
+        let id = self.next_handle.to_string();
+        self.handles.insert(id.clone(), handle);
+        id
+    }
+    
+    fn client(&self) -> Result<Client, StatusCode> {
+        self.context.client.lock().unwrap().clone().ok_or(StatusCode::NoConnection)
+    }
+    
+    /// Every folder that exists: those holding files plus those created here
+    fn folders(&self) -> BTreeSet<String> {
+        let records = self.context.db.all_records();
+        folder::tree(records.iter().map(|r| r.folder.as_str()))
+            .into_iter()
+            .map(|(path, _)| path)
+            .chain(self.created_dirs.iter().cloned())
+            .collect()
+    }
+    
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty() || self.folders().contains(path)
+    }
+    
+    fn find_file(&self, path: &str) -> Option<FileRecord> {
+        let (folder, name) = split_path(path);
+        self.context.db.find_in_folder(folder, name)
+    }
+    
+    fn listing(&self, dir: &str) -> Vec<File> {
+        let mut files: Vec<File> = self.folders()
+            .into_iter()
+            .filter(|f| parent_of(f) == dir)
+            .map(|f| File::new(folder::name(&f), dir_attrs()))
+            .collect();
+        let mut seen = BTreeSet::new();
+        // Newest record wins when a name was uploaded more than once
+        for record in self.context.db.all_records().iter().rev() {
+            if record.folder == dir && record.message_id.is_some() && seen.insert(record.filename.clone()) {
+                files.push(File::new(record.filename.as_str(), file_attrs(record)));
+            }
+        }
+        files
+    }
+    
+    async fn read_chunk(&mut self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, StatusCode> {
+        let client = self.client()?;
+        let Some(OpenHandle::Read { segments, cached }) = self.handles.get_mut(handle) else {
+            return Err(StatusCode::Failure);
+        };
+        let chunk_size = DOWNLOAD_CHUNK_SIZE as u64;
+        let mut start = 0;
+        for (index, segment) in segments.iter().enumerate() {
+            if offset < start + segment.size {
+                let within = offset - start;
+                let key = (index, (within / chunk_size) as u32);
+                if cached.as_ref().map(|(k, _)| *k) != Some(key) {
+                    let (_, bytes) = fetch_chunk(client, segment.location.clone(), key.1).await
+                        .map_err(|e| {
+                            error!("SFTP read failed: {:?}", e);
+                            StatusCode::Failure
+                        })?;
+                    *cached = Some((key, bytes));
+                }
+                let bytes = &cached.as_ref().unwrap().1;
+                let from = (within % chunk_size) as usize;
+                let to = (from + len as usize).min(bytes.len());
+                // Short reads are allowed; the client asks again for the rest
+                return Ok(bytes.get(from..to).unwrap_or_default().to_vec());
+            }
+            start += segment.size;
+        }
+        Err(StatusCode::Eof)
+    }
+}
+
+#[async_trait]
+impl russh_sftp::server::Handler for SftpHandler {
+    type Error = StatusCode;
+    
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+    
+    async fn init(&mut self, _version: u32, _extensions: HashMap<String, String>) -> Result<Version, Self::Error> {
+        Ok(Version::new())
+    }
+    
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let path = format!("/{}", normalize(&path));
+        Ok(Name { id, files: vec![File::dummy(path)] })
+    }
+    
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let path = normalize(&path);
+        if self.is_dir(&path) {
+            return Ok(Attrs { id, attrs: dir_attrs() });
+        }
+        let record = self.find_file(&path).ok_or(StatusCode::NoSuchFile)?;
+        Ok(Attrs { id, attrs: file_attrs(&record) })
+    }
+    
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        self.stat(id, path).await
+    }
+    
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let attrs = match self.handles.get(&handle) {
+            Some(OpenHandle::Dir(_)) => dir_attrs(),
+            Some(OpenHandle::Read { segments, .. }) => FileAttributes {
+                size: Some(segments.iter().map(|s| s.size).sum()),
+                permissions: Some(0o100444),
+                ..Default::default()
+            },
+            Some(OpenHandle::Write { file, .. }) => FileAttributes {
+                size: file.metadata().await.ok().map(|m| m.len()),
+                permissions: Some(0o100644),
+                ..Default::default()
+            },
+            None => return Err(StatusCode::Failure),
+        };
+        Ok(Attrs { id, attrs })
+    }
+    
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let path = normalize(&path);
+        if !self.is_dir(&path) {
+            return Err(StatusCode::NoSuchFile);
+        }
+        let files = self.listing(&path);
+        let handle = self.add_handle(OpenHandle::Dir(Some(files)));
+        Ok(Handle { id, handle })
+    }
+    
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Dir(files)) => match files.take() {
+                Some(files) => Ok(Name { id, files }),
+                None => Err(StatusCode::Eof),
+            },
+            _ => Err(StatusCode::Failure),
+        }
+    }
+    
+    async fn mkdir(&mut self, id: u32, path: String, _attrs: FileAttributes) -> Result<Status, Self::Error> {
+        let path = normalize(&path);
+        if read_only::is_enabled() {
+            return Err(StatusCode::PermissionDenied);
+        }
+        if path.is_empty() || self.is_dir(&path) {
+            return Err(StatusCode::Failure);
+        }
+        self.created_dirs.insert(path);
+        Ok(ok_status(id))
+    }
+    
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        let path = normalize(&path);
+        if read_only::is_enabled() {
+            return Err(StatusCode::PermissionDenied);
+        }
+        if !self.listing(&path).is_empty() {
+            return Err(StatusCode::Failure);
+        }
+        self.created_dirs.remove(&path);
+        Ok(ok_status(id))
+    }
+    
+    async fn open(&mut self, id: u32, filename: String, pflags: OpenFlags, _attrs: FileAttributes) -> Result<Handle, Self::Error> {
+        let path = normalize(&filename);
+        let (folder, name) = split_path(&path);
+        
+        if pflags.contains(OpenFlags::WRITE) {
+            if read_only::is_enabled() {
+                return Err(StatusCode::PermissionDenied);
+            }
+            if name.is_empty() || !self.is_dir(folder) {
+                return Err(StatusCode::NoSuchFile);
+            }
+            let existing = self.find_file(&path).and_then(|record| record.key());
+            match existing {
+                Some(_) if pflags.contains(OpenFlags::EXCLUDE) => return Err(StatusCode::Failure),
+                None if !pflags.contains(OpenFlags::CREATE) => return Err(StatusCode::NoSuchFile),
+                _ => {}
+            }
+            let dir = paths::cache_dir(TEMP_DIR).join(format!("{}-{}", std::process::id(), self.next_handle + 1));
+            let temp = dir.join(name);
+            if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+                error!("Failed to create {:?}: {:?}", dir, e);
+                return Err(StatusCode::Failure);
+            }
+            // Writes change the stored content unless the client truncates it
+            if let Some(key) = existing.filter(|_| !pflags.contains(OpenFlags::TRUNCATE)) {
+                let client = self.client()?;
+                let control = TransferControl::default();
+                let fetched = download_file_from_telegram(&client, &self.context.db, key, &temp, &mut ResumeState::default(), &control, slint::Weak::default()).await;
+                if let Err(e) = fetched {
+                    error!("Failed to fetch {} to change it over SFTP: {:?}", path, e);
+                    let _ = tokio::fs::remove_dir_all(&dir).await;
+                    return Err(StatusCode::Failure);
+                }
+            }
+            let file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(pflags.contains(OpenFlags::TRUNCATE))
+                .open(&temp)
+                .await
+                .map_err(|e| {
+                    error!("Failed to create {:?}: {:?}", temp, e);
+                    StatusCode::Failure
+                })?;
+            let handle = self.add_handle(OpenHandle::Write {
+                file,
+                path: temp,
+                folder: folder.to_string(),
+                append: pflags.contains(OpenFlags::APPEND),
+                replaces: existing,
+            });
+            return Ok(Handle { id, handle });
+        }
+        
+        let record = self.find_file(&path).ok_or(StatusCode::NoSuchFile)?;
+        let key = record.key().ok_or(StatusCode::NoSuchFile)?;
+        let client = self.client()?;
+        let segments = match stream::resolve(&client, &self.context.db, key).await {
+            Ok(Some((segments, _))) => segments,
+            Ok(None) => return Err(StatusCode::NoSuchFile),
+            Err(e) => {
+                error!("Failed to open {} over SFTP: {:?}", path, e);
+                return Err(StatusCode::Failure);
+            }
+        };
+        let handle = self.add_handle(OpenHandle::Read { segments, cached: None });
+        Ok(Handle { id, handle })
+    }
+    
+    async fn read(&mut self, id: u32, handle: String, offset: u64, len: u32) -> Result<Data, Self::Error> {
+        let data = self.read_chunk(&handle, offset, len).await?;
+        Ok(Data { id, data })
+    }
+    
+    async fn write(&mut self, id: u32, handle: String, offset: u64, data: Vec<u8>) -> Result<Status, Self::Error> {
+        let Some(OpenHandle::Write { file, append, .. }) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+        let position = if *append { std::io::SeekFrom::End(0) } else { std::io::SeekFrom::Start(offset) };
+        let written = async {
+            file.seek(position).await?;
+            file.write_all(&data).await
+        }.await;
+        written.map_err(|e| {
+            error!("SFTP write failed: {:?}", e);
+            StatusCode::Failure
+        })?;
+        Ok(ok_status(id))
+    }
+    
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        // A finished write becomes an upload replacing the file it changed;
+        // the temporary copy is removed afterwards
+        if let Some(OpenHandle::Write { mut file, path, folder, replaces, .. }) = self.handles.remove(&handle) {
+            if let Err(e) = file.flush().await {
+                error!("Failed to flush {:?}: {:?}", path, e);
+                return Err(StatusCode::Failure);
+            }
+            info!("Queueing SFTP upload of {:?} into {:?}", path, folder);
+            self.created_dirs.remove(&folder);
+            self.context.queue.push(TransferKind::Upload {
+                path,
+                options: UploadOptions {
+                    delete_after: true,
+                    folder,
+                    replaces,
+                    ..Default::default()
+                },
+            });
+        }
+        Ok(ok_status(id))
+    }
+    
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        if read_only::is_enabled() {
+            return Err(StatusCode::PermissionDenied);
+        }
+        let record = self.find_file(&normalize(&filename)).ok_or(StatusCode::NoSuchFile)?;
+        let client = self.client()?;
+        delete_stored_file(&client, &self.context.db, &record).await.map_err(|e| {
+            error!("Failed to delete {} over SFTP: {:?}", record.filename, e);
+            StatusCode::Failure
+        })?;
+        Ok(ok_status(id))
+    }
+    
+    async fn rename(&mut self, id: u32, oldpath: String, newpath: String) -> Result<Status, Self::Error> {
+        if read_only::is_enabled() {
+            return Err(StatusCode::PermissionDenied);
+        }
+        let (from, to) = (normalize(&oldpath), normalize(&newpath));
+        let db = &self.context.db;
+        let result = if self.is_dir(&from) {
+            if from.is_empty() || folder::moved_path(&to, &from, "").is_some() {
+                return Err(StatusCode::Failure);
+            }
+            if self.created_dirs.remove(&from) {
+                self.created_dirs.insert(to.clone());
+            }
+            db.move_folder(&from, &to).await
+        } else {
+            let record = self.find_file(&from).ok_or(StatusCode::NoSuchFile)?;
+            let key = record.key().ok_or(StatusCode::NoSuchFile)?;
+            let (folder, name) = split_path(&to);
+            if name.is_empty() || !self.is_dir(folder) {
+                return Err(StatusCode::NoSuchFile);
+            }
+            if self.is_dir(&to) {
+                return Err(StatusCode::Failure);
+            }
+            if to == from {
+                return Ok(ok_status(id));
+            }
+            // As on disk, a file already there is replaced: clients upload
+            // under a temporary name and rename it over the old one
+            let replaced: Vec<FileRecord> = db.all_records()
+                .into_iter()
+                .filter(|r| r.folder == folder && r.filename == name)
+                .collect();
+            if !replaced.is_empty() {
+                let client = self.client()?;
+                for old in &replaced {
+                    delete_stored_file(&client, db, old).await.map_err(|e| {
+                        error!("Failed to delete {} replaced over SFTP: {:?}", old.filename, e);
+                        StatusCode::Failure
+                    })?;
+                }
+            }
+            let (folder, name) = (folder.to_string(), name.to_string());
+            db.update_records(&[key], |r| {
+                r.folder = folder.clone();
+                r.filename = name.clone();
+            }).await
+        };
+        result.map_err(|e| {
+            error!("SFTP rename failed: {:?}", e);
+            StatusCode::Failure
+        })?;
+        Ok(ok_status(id))
+    }
+}
+
+/// Path relative to the root without leading, trailing or doubled slashes,
+/// with `.` and `..` resolved
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Folder and file name of a normalized path
+fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn parent_of(folder: &str) -> &str {
+    split_path(folder).0
+}
+
+fn dir_attrs() -> FileAttributes {
+    FileAttributes {
+        permissions: Some(0o040755),
+        ..Default::default()
+    }
+}
+
+fn file_attrs(record: &FileRecord) -> FileAttributes {
+    let mtime = record.mtime.unwrap_or_else(|| record.upload_date.timestamp());
+    FileAttributes {
+        size: Some(record.file_size),
+        permissions: Some(0o100644),
+        mtime: Some(mtime as u32),
+        atime: Some(mtime as u32),
+        ..Default::default()
+    }
+}
+
+fn ok_status(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
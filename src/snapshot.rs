@@ -0,0 +1,801 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use grammers_client::types::InputMessage;
+use grammers_client::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::database::{ChatRef, RecordKey};
+use crate::dedup::Chunker;
+use crate::file_attrs::FileAttrs;
+use crate::transfer::{ResumeState, TransferControl};
+use crate::{chunked, encryption, folder, group_drive, paths, read_only, saved_messages, storage_chat};
+
+/// Local index of the chunk store and the snapshots taken
+pub const REPOSITORY_FILE: &str = "snapshots.json";
+/// Caption of packs and manifests, which are not files of their own
+const CAPTION_TAG: &str = "#teledrive-snapshot";
+/// Temporary folder for packs being written or restored from
+const PACK_DIR: &str = "teledrive-packs";
+/// A pack is sent once it holds this much
+const PACK_SIZE: u64 = 32 * 1024 * 1024;
+/// Snapshot chunks are far smaller than deduplicated pieces, so a changed
+/// file only adds a little: 256 KB to 4 MB, about 1.25 MB on average
+const CHUNK_MIN: u64 = 256 * 1024;
+const CHUNK_BITS: u32 = 20;
+const CHUNK_MAX: u64 = 4 * 1024 * 1024;
+/// Full packs waiting to be sent while the next one is written
+const PACKS_AHEAD: usize = 1;
+
+/// Where a chunk is stored: a byte range of a pack
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkLocation {
+    /// Message holding the pack, in its chat. Older indexes saved the bare
+    /// message id; `Repository::load` fills in the chat
+    pub pack: RecordKey,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A document holding many chunks, one after the other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pack {
+    pub message_id: i32,
+    pub size: u64,
+    /// Group it was sent to, if not Saved Messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat: Option<ChatRef>,
+    /// Key it is encrypted with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+impl Pack {
+    pub fn key(&self) -> RecordKey {
+        RecordKey::new(self.chat.as_ref(), self.message_id)
+    }
+}
+
+/// A file as it was when the snapshot was taken
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// Path below the backed up folder, `/`-separated
+    pub path: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Hashes of its chunks, in order
+    pub chunks: Vec<String>,
+}
+
+/// The state of a folder at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub time: DateTime<Local>,
+    pub source: PathBuf,
+    pub files: Vec<SnapshotFile>,
+    /// Message holding the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<Pack>,
+}
+
+impl Snapshot {
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+}
+
+/// What is uploaded for each snapshot: the snapshot and where every chunk it
+/// needs is, so it can be restored even without the local index
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    #[serde(flatten)]
+    snapshot: Snapshot,
+    chunks: HashMap<String, ChunkLocation>,
+    packs: Vec<Pack>,
+}
+
+/// Chunks stored so far and the snapshots made of them
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Repository {
+    pub chunks: HashMap<String, ChunkLocation>,
+    /// Saved as a list, as the keys can't be JSON object keys
+    #[serde(with = "pack_list")]
+    pub packs: HashMap<RecordKey, Pack>,
+    /// Oldest first
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl Repository {
+    /// Empty when nothing was backed up yet. An index that can't be read is
+    /// an error: starting over would write over every earlier snapshot
+    pub fn load() -> Result<Self> {
+        let json = match std::fs::read_to_string(REPOSITORY_FILE) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", REPOSITORY_FILE)),
+        };
+        let mut repository: Self = serde_json::from_str(&json)
+            .with_context(|| format!("{} is damaged; restore it from a copy before backing up again", REPOSITORY_FILE))?;
+        repository.fill_in_pack_chats();
+        Ok(repository)
+    }
+    
+    /// Chunks from older indexes only name their pack's message; the pack
+    /// with that message in the chat it was sent to is the one meant
+    fn fill_in_pack_chats(&mut self) {
+        let chats: HashMap<i32, Option<i64>> = self.packs.keys()
+            .map(|key| (key.message_id, key.chat))
+            .collect();
+        for location in self.chunks.values_mut() {
+            if location.pack.chat.is_none() && !self.packs.contains_key(&location.pack) {
+                if let Some(chat) = chats.get(&location.pack.message_id) {
+                    location.pack.chat = *chat;
+                }
+            }
+        }
+    }
+    
+    /// Written to another file first, so a crash never leaves half an index
+    pub fn save(&self) -> Result<()> {
+        let partial = format!("{}.partial", REPOSITORY_FILE);
+        std::fs::write(&partial, serde_json::to_vec(self)?)?;
+        std::fs::rename(&partial, REPOSITORY_FILE)?;
+        Ok(())
+    }
+    
+    /// Snapshot whose id starts with `id`
+    pub fn find(&self, id: &str) -> Result<&Snapshot> {
+        let mut found = self.snapshots.iter().filter(|s| s.id.starts_with(id));
+        match (found.next(), found.next()) {
+            (Some(snapshot), None) => Ok(snapshot),
+            (Some(_), Some(_)) => bail!("More than one snapshot starts with {:?}", id),
+            (None, _) => bail!("No snapshot {:?}", id),
+        }
+    }
+}
+
+/// `Repository::packs` on disk: a list of packs, or an object keyed by
+/// message id as older versions saved it
+mod pack_list {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    
+    use super::Pack;
+    use crate::database::RecordKey;
+    
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredPacks {
+        List(Vec<Pack>),
+        ByMessageId(HashMap<String, Pack>),
+    }
+    
+    pub fn serialize<S: Serializer>(packs: &HashMap<RecordKey, Pack>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut list: Vec<&Pack> = packs.values().collect();
+        list.sort_by_key(|pack| pack.key().to_string());
+        list.serialize(serializer)
+    }
+    
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<RecordKey, Pack>, D::Error> {
+        let list = match StoredPacks::deserialize(deserializer)? {
+            StoredPacks::List(list) => list,
+            StoredPacks::ByMessageId(map) => map.into_values().collect(),
+        };
+        Ok(list.into_iter().map(|pack| (pack.key(), pack)).collect())
+    }
+}
+
+/// Whether a caption marks a pack or manifest rather than a stored file
+pub fn is_repository_caption(caption: &str) -> bool {
+    caption.starts_with(CAPTION_TAG)
+}
+
+/// A pack written to disk, ready to be sent
+struct PackFile {
+    path: PathBuf,
+    /// Chunks it holds, as (hash, offset, length)
+    chunks: Vec<(String, u64, u64)>,
+}
+
+/// Cuts files into chunks and writes the ones not stored yet into packs
+struct PackWriter {
+    /// Hashes of chunks stored or already written
+    known: HashSet<String>,
+    dir: PathBuf,
+    current: Option<(BufWriter<File>, PackFile)>,
+    size: u64,
+    full: mpsc::Sender<PackFile>,
+}
+
+impl PackWriter {
+    /// Chunk hashes of the file at `path`, writing new chunks to packs
+    fn add_file(&mut self, path: &Path, control: &TransferControl) -> Result<Vec<String>> {
+        let mut file = File::open(path)?;
+        let mut chunker = Chunker::new(CHUNK_MIN, CHUNK_BITS, CHUNK_MAX);
+        let mut buf = vec![0u8; 1024 * 1024];
+        let mut chunk = Vec::new();
+        let mut hashes = Vec::new();
+        loop {
+            control.check()?;
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let mut from = 0;
+            while let Some(cut) = chunker.next_boundary(&buf[from..n]) {
+                chunk.extend_from_slice(&buf[from..from + cut]);
+                from += cut;
+                hashes.push(self.add_chunk(&chunk)?);
+                chunk.clear();
+            }
+            chunk.extend_from_slice(&buf[from..n]);
+        }
+        if !chunk.is_empty() {
+            hashes.push(self.add_chunk(&chunk)?);
+        }
+        Ok(hashes)
+    }
+    
+    fn add_chunk(&mut self, data: &[u8]) -> Result<String> {
+        let hash = format!("{:x}", Sha256::digest(data));
+        if !self.known.insert(hash.clone()) {
+            return Ok(hash);
+        }
+        if self.current.is_none() {
+            let path = self.dir.join(format!("pack-{:016x}", rand::random::<u64>()));
+            let file = BufWriter::new(File::create(&path)?);
+            self.current = Some((file, PackFile { path, chunks: Vec::new() }));
+            self.size = 0;
+        }
+        let (file, pack) = self.current.as_mut().unwrap();
+        file.write_all(data)?;
+        pack.chunks.push((hash.clone(), self.size, data.len() as u64));
+        self.size += data.len() as u64;
+        if self.size >= PACK_SIZE {
+            self.send_current()?;
+        }
+        Ok(hash)
+    }
+    
+    /// Hand the pack being written to the uploader
+    fn send_current(&mut self) -> Result<()> {
+        let Some((file, pack)) = self.current.take() else {
+            return Ok(());
+        };
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        // Fails only when the uploader gave up; its error says why
+        self.full.blocking_send(pack).map_err(|_| anyhow!("Stopped sending packs"))?;
+        Ok(())
+    }
+}
+
+/// Back up `source` as a new snapshot, sending only the chunks no earlier
+/// snapshot stored. Packs are sent while the next ones are written, and the
+/// index is saved after each, so an interrupted run doesn't send them again
+pub async fn create(client: &Client, source: &Path, encrypt: bool, control: &TransferControl) -> Result<Snapshot> {
+    read_only::check("uploading")?;
+    let mut repository = Repository::load()?;
+    let dir = paths::cache_dir(PACK_DIR);
+    std::fs::create_dir_all(&dir)?;
+    
+    let (full, mut packs) = mpsc::channel(PACKS_AHEAD);
+    let mut writer = PackWriter {
+        known: repository.chunks.keys().cloned().collect(),
+        dir,
+        current: None,
+        size: 0,
+        full,
+    };
+    let (root, writer_control) = (source.to_path_buf(), control.clone());
+    let chunking = tokio::task::spawn_blocking(move || -> Result<Vec<SnapshotFile>> {
+        let mut files = Vec::new();
+        for file in folder::collect_files(&root)? {
+            let path = file.path.strip_prefix(&root).unwrap_or(&file.path);
+            let path = path.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let attrs = FileAttrs::read(&file.path);
+            let chunks = writer.add_file(&file.path, &writer_control)
+                .with_context(|| format!("Failed to read {:?}", file.path))?;
+            files.push(SnapshotFile { path, size: file.size, mtime: attrs.mtime, mode: attrs.mode, chunks });
+        }
+        writer.send_current()?;
+        Ok(files)
+    });
+    
+    let mut added = 0;
+    let sent = async {
+        while let Some(pack) = packs.recv().await {
+            let name = pack.path.file_name().and_then(|n| n.to_str()).unwrap_or("pack").to_string();
+            let stored = send(client, &pack.path, &name, encrypt, control).await;
+            let _ = std::fs::remove_file(&pack.path);
+            let stored = stored?;
+            for (hash, offset, length) in pack.chunks {
+                added += length;
+                repository.chunks.insert(hash, ChunkLocation { pack: stored.key(), offset, length });
+            }
+            repository.packs.insert(stored.key(), stored);
+            repository.save()?;
+        }
+        Ok::<(), anyhow::Error>(())
+    }.await;
+    // Stops the chunking if sending failed
+    drop(packs);
+    let files = chunking.await.context("Chunking panicked")?;
+    sent?;
+    let files = files?;
+    
+    let mut snapshot = Snapshot {
+        id: format!("{:016x}", rand::random::<u64>()),
+        time: Local::now(),
+        source: source.to_path_buf(),
+        files,
+        manifest: None,
+    };
+    snapshot.manifest = Some(upload_manifest(client, &repository, &snapshot, encrypt, control).await?);
+    
+    info!("Snapshot {} of {:?}: {} files, {} bytes new", snapshot.id, source, snapshot.files.len(), added);
+    repository.snapshots.push(snapshot.clone());
+    repository.save()?;
+    Ok(snapshot)
+}
+
+/// Upload the manifest of `snapshot`, with where its chunks are now
+async fn upload_manifest(
+    client: &Client,
+    repository: &Repository,
+    snapshot: &Snapshot,
+    encrypt: bool,
+    control: &TransferControl,
+) -> Result<Pack> {
+    let chunks: HashMap<String, ChunkLocation> = snapshot.files.iter()
+        .flat_map(|f| &f.chunks)
+        .filter_map(|hash| Some((hash.clone(), repository.chunks.get(hash)?.clone())))
+        .collect();
+    let needed: HashSet<RecordKey> = chunks.values().map(|c| c.pack).collect();
+    let packs = needed.iter().filter_map(|id| repository.packs.get(id).cloned()).collect();
+    let manifest = Manifest { snapshot: Snapshot { manifest: None, ..snapshot.clone() }, chunks, packs };
+    
+    let name = format!("snapshot-{}.json.zst", snapshot.id);
+    let path = paths::cache_dir(PACK_DIR).join(&name);
+    std::fs::write(&path, zstd::encode_all(serde_json::to_vec(&manifest)?.as_slice(), 0)?)?;
+    let stored = send(client, &path, &name, encrypt, control).await;
+    let _ = std::fs::remove_file(&path);
+    stored
+}
+
+/// Send the file at `path` as one document, encrypted if asked
+async fn send(client: &Client, path: &Path, name: &str, encrypt: bool, control: &TransferControl) -> Result<Pack> {
+    let encrypted = if encrypt {
+        let (source, id) = (path.to_path_buf(), name.to_string());
+        Some(tokio::task::spawn_blocking(move || encryption::encrypt(&source, &id)).await??)
+    } else {
+        None
+    };
+    let upload_path = encrypted.as_ref().map_or(path, |(copy, _)| copy.as_path());
+    let size = tokio::fs::metadata(upload_path).await?.len();
+    let result = async {
+        let uploaded = chunked::upload_parts(client, upload_path, name, 0..size, &mut ResumeState::default(), control, |_, _| {}).await?;
+        let chat = storage_chat(client).await?;
+        api_usage::request("messages.sendMedia");
+        Ok::<_, anyhow::Error>(client.send_message(chat, InputMessage::text(CAPTION_TAG).document(uploaded)).await?)
+    }.await;
+    if let Some((copy, _)) = &encrypted {
+        encryption::remove(copy);
+    }
+    Ok(Pack {
+        message_id: result?.id(),
+        size,
+        chat: group_drive::current(),
+        key_id: encrypted.map(|(_, id)| id),
+    })
+}
+
+/// Restore `snapshot` into `dest`, one pack at a time so only one has to fit
+/// on disk besides the files
+pub async fn restore(client: &Client, repository: &Repository, snapshot: &Snapshot, dest: &Path, control: &TransferControl) -> Result<()> {
+    // Where each chunk goes, by pack: (file, position in the file, chunk)
+    let mut writes: HashMap<RecordKey, Vec<(PathBuf, u64, ChunkLocation)>> = HashMap::new();
+    let mut targets = Vec::new();
+    for file in &snapshot.files {
+        let target = dest.join(&file.path);
+        if !target.starts_with(dest) || file.path.split('/').any(|part| part == "..") {
+            bail!("Refusing to restore {:?} outside {:?}", file.path, dest);
+        }
+        let mut position = 0;
+        for hash in &file.chunks {
+            let location = repository.chunks.get(hash)
+                .with_context(|| format!("Chunk {} of {} is not in the index", hash, file.path))?;
+            writes.entry(location.pack).or_default().push((target.clone(), position, location.clone()));
+            position += location.length;
+        }
+        targets.push((target, file));
+    }
+    
+    for (target, file) in &targets {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        File::create(target)?.set_len(file.size)?;
+    }
+    
+    let dir = paths::cache_dir(PACK_DIR);
+    std::fs::create_dir_all(&dir)?;
+    for (count, (key, chunks)) in writes.into_iter().enumerate() {
+        control.check()?;
+        let pack = repository.packs.get(&key)
+            .with_context(|| format!("Pack {} is not in the index", key))?;
+        info!("Restoring from pack {} ({} of the snapshot's packs)", key, count + 1);
+        let path = dir.join(fetched_name(pack));
+        let result = async {
+            fetch(client, pack, &path, control).await?;
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                let mut source = File::open(&path)?;
+                let mut data = Vec::new();
+                for (target, position, location) in chunks {
+                    source.seek(SeekFrom::Start(location.offset))?;
+                    data.resize(location.length as usize, 0);
+                    source.read_exact(&mut data)?;
+                    let mut out = File::options().write(true).open(&target)?;
+                    out.seek(SeekFrom::Start(position))?;
+                    out.write_all(&data)?;
+                }
+                Ok(())
+            }).await?
+        }.await;
+        let _ = std::fs::remove_file(&path);
+        result?;
+    }
+    
+    for (target, file) in &targets {
+        let attrs = FileAttrs { mtime: file.mtime, mode: file.mode };
+        if let Err(e) = attrs.apply(target) {
+            warn!("Failed to restore the attributes of {:?}: {:?}", target, e);
+        }
+    }
+    Ok(())
+}
+
+/// Name of the file `pack` is downloaded to
+fn fetched_name(pack: &Pack) -> String {
+    format!("restore-{}-{}", pack.key().chat.unwrap_or(0), pack.message_id)
+}
+
+/// Download `pack` to `path`, decrypted
+async fn fetch(client: &Client, pack: &Pack, path: &Path, control: &TransferControl) -> Result<()> {
+    let chat = match &pack.chat {
+        Some(chat) => chat.packed()?,
+        None => saved_messages(client).await?,
+    };
+    api_usage::request("messages.getMessages");
+    let message = client.get_messages_by_id(chat, &[pack.message_id]).await?
+        .pop()
+        .flatten()
+        .with_context(|| format!("Pack {} no longer exists", pack.message_id))?;
+    let media = message.media().context("Message has no attached file")?;
+    chunked::download_parts(client, &media, path, 0, pack.size, &mut ResumeState::default(), control, |_, _| {}).await?;
+    if let Some(id) = pack.key_id.clone() {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || encryption::decrypt_in_place(&path, &id)).await??;
+    }
+    Ok(())
+}
+
+/// Which snapshots of each folder to keep; the newest of each day, week or
+/// month counts for it, like restic's `forget`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retention {
+    pub last: usize,
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        self.last == 0 && self.daily == 0 && self.weekly == 0 && self.monthly == 0
+    }
+    
+    /// Ids of the snapshots to keep
+    pub fn keep(&self, snapshots: &[Snapshot]) -> HashSet<String> {
+        let mut keep = HashSet::new();
+        let mut sources: Vec<&Path> = snapshots.iter().map(|s| s.source.as_path()).collect();
+        sources.sort();
+        sources.dedup();
+        for source in sources {
+            let mut newest_first: Vec<&Snapshot> = snapshots.iter().filter(|s| s.source == source).collect();
+            newest_first.sort_by(|a, b| b.time.cmp(&a.time));
+            keep.extend(newest_first.iter().take(self.last).map(|s| s.id.clone()));
+            for (count, period) in [
+                (self.daily, "%Y-%m-%d"),
+                (self.weekly, "%G-W%V"),
+                (self.monthly, "%Y-%m"),
+            ] {
+                let mut periods = Vec::new();
+                for snapshot in &newest_first {
+                    let period = snapshot.time.format(period).to_string();
+                    if periods.len() < count && periods.last() != Some(&period) {
+                        periods.push(period);
+                        keep.insert(snapshot.id.clone());
+                    }
+                }
+            }
+        }
+        keep
+    }
+}
+
+/// What `forget` and `prune` did
+#[derive(Debug, Default, Serialize)]
+pub struct PruneReport {
+    pub forgotten: usize,
+    pub packs_deleted: usize,
+    pub packs_repacked: usize,
+    pub bytes_freed: u64,
+}
+
+/// Packs with more unused bytes than this share are rewritten by `prune`
+const REPACK_UNUSED: f64 = 0.5;
+
+/// Drop the snapshots `retention` doesn't keep, and their manifests
+pub async fn forget(client: &Client, retention: &Retention) -> Result<PruneReport> {
+    read_only::check("deleting")?;
+    if retention.is_empty() {
+        bail!("Nothing would be kept; give at least one --keep option");
+    }
+    let mut repository = Repository::load()?;
+    let keep = retention.keep(&repository.snapshots);
+    let (kept, forgotten): (Vec<Snapshot>, Vec<Snapshot>) = std::mem::take(&mut repository.snapshots)
+        .into_iter()
+        .partition(|s| keep.contains(&s.id));
+    repository.snapshots = kept;
+    repository.save()?;
+    
+    for manifest in forgotten.iter().filter_map(|s| s.manifest.as_ref()) {
+        if let Err(e) = delete(client, manifest).await {
+            warn!("Failed to delete manifest {}: {:?}", manifest.message_id, e);
+        }
+    }
+    info!("Forgot {} snapshot(s)", forgotten.len());
+    Ok(PruneReport { forgotten: forgotten.len(), ..Default::default() })
+}
+
+/// Delete the packs no snapshot needs any more, and rewrite those mostly
+/// holding chunks no snapshot needs. Manifests pointing at rewritten packs
+/// are uploaded again
+pub async fn prune(client: &Client, encrypt: bool, control: &TransferControl) -> Result<PruneReport> {
+    read_only::check("deleting")?;
+    let mut repository = Repository::load()?;
+    let mut report = PruneReport::default();
+    let used: HashSet<String> = repository.snapshots.iter()
+        .flat_map(|s| &s.files)
+        .flat_map(|f| f.chunks.iter().cloned())
+        .collect();
+    
+    // Chunks of each pack as (hash, location, needed)
+    let mut contents: HashMap<RecordKey, Vec<(String, ChunkLocation, bool)>> = HashMap::new();
+    for (hash, location) in &repository.chunks {
+        contents.entry(location.pack).or_default().push((hash.clone(), location.clone(), used.contains(hash)));
+    }
+    let mut moved = HashSet::new();
+    let pack_keys: Vec<RecordKey> = repository.packs.keys().copied().collect();
+    for key in pack_keys {
+        control.check()?;
+        let pack = repository.packs[&key].clone();
+        let chunks = contents.remove(&key).unwrap_or_default();
+        let needed: Vec<&(String, ChunkLocation, bool)> = chunks.iter().filter(|(_, _, needed)| *needed).collect();
+        let needed_bytes: u64 = needed.iter().map(|(_, location, _)| location.length).sum();
+        let unused_bytes = chunks.iter().map(|(_, location, _)| location.length).sum::<u64>() - needed_bytes;
+        
+        if !needed.is_empty() && (unused_bytes as f64) <= REPACK_UNUSED * pack.size as f64 {
+            continue;
+        }
+        if !needed.is_empty() {
+            info!("Rewriting pack {} without {} unused bytes", key, unused_bytes);
+            let new_pack = repack(client, &pack, &needed, encrypt, control).await?;
+            for ((hash, _, _), offset) in needed.iter().zip(new_pack.1) {
+                let length = repository.chunks[hash].length;
+                repository.chunks.insert(hash.clone(), ChunkLocation { pack: new_pack.0.key(), offset, length });
+                moved.insert(hash.clone());
+            }
+            report.bytes_freed += pack.size.saturating_sub(new_pack.0.size);
+            repository.packs.insert(new_pack.0.key(), new_pack.0);
+            report.packs_repacked += 1;
+        } else {
+            report.bytes_freed += pack.size;
+            report.packs_deleted += 1;
+        }
+        for (hash, _, needed) in &chunks {
+            if !needed {
+                repository.chunks.remove(hash);
+            }
+        }
+        repository.packs.remove(&key);
+        // Saved before deleting, so the index never points at a deleted pack
+        repository.save()?;
+        if let Err(e) = delete(client, &pack).await {
+            warn!("Failed to delete pack {}: {:?}", key, e);
+        }
+    }
+    
+    // Uploaded manifests must not point at deleted packs
+    for index in 0..repository.snapshots.len() {
+        let snapshot = &repository.snapshots[index];
+        if !snapshot.files.iter().flat_map(|f| &f.chunks).any(|hash| moved.contains(hash)) {
+            continue;
+        }
+        let old = snapshot.manifest.clone();
+        let stored = upload_manifest(client, &repository, snapshot, encrypt, control).await?;
+        repository.snapshots[index].manifest = Some(stored);
+        repository.save()?;
+        if let Some(old) = old {
+            if let Err(e) = delete(client, &old).await {
+                warn!("Failed to delete manifest {}: {:?}", old.message_id, e);
+            }
+        }
+    }
+    info!("Pruned {:?}", report);
+    Ok(report)
+}
+
+/// Send a new pack with the `needed` chunks of `pack`; returns it and the
+/// offset of each chunk in it
+async fn repack(
+    client: &Client,
+    pack: &Pack,
+    needed: &[&(String, ChunkLocation, bool)],
+    encrypt: bool,
+    control: &TransferControl,
+) -> Result<(Pack, Vec<u64>)> {
+    let dir = paths::cache_dir(PACK_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let old_path = dir.join(fetched_name(pack));
+    let name = format!("pack-{:016x}", rand::random::<u64>());
+    let new_path = dir.join(&name);
+    let result = async {
+        fetch(client, pack, &old_path, control).await?;
+        let locations: Vec<ChunkLocation> = needed.iter().map(|(_, location, _)| location.clone()).collect();
+        let (source, dest) = (old_path.clone(), new_path.clone());
+        let offsets = tokio::task::spawn_blocking(move || -> Result<Vec<u64>> {
+            let mut source = File::open(&source)?;
+            let mut out = BufWriter::new(File::create(&dest)?);
+            let mut data = Vec::new();
+            let mut offsets = Vec::with_capacity(locations.len());
+            let mut offset = 0;
+            for location in locations {
+                source.seek(SeekFrom::Start(location.offset))?;
+                data.resize(location.length as usize, 0);
+                source.read_exact(&mut data)?;
+                out.write_all(&data)?;
+                offsets.push(offset);
+                offset += location.length;
+            }
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok(offsets)
+        }).await??;
+        let stored = send(client, &new_path, &name, encrypt, control).await?;
+        Ok((stored, offsets))
+    }.await;
+    let _ = std::fs::remove_file(&old_path);
+    let _ = std::fs::remove_file(&new_path);
+    result
+}
+
+/// Delete the message holding `pack`
+async fn delete(client: &Client, pack: &Pack) -> Result<()> {
+    let chat = match &pack.chat {
+        Some(chat) => chat.packed()?,
+        None => saved_messages(client).await?,
+    };
+    api_usage::request("messages.deleteMessages");
+    client.delete_messages(chat, &[pack.message_id]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    
+    fn snapshot(id: &str, source: &str, date: (i32, u32, u32), hour: u32) -> Snapshot {
+        Snapshot {
+            id: id.to_string(),
+            time: Local.with_ymd_and_hms(date.0, date.1, date.2, hour, 0, 0).unwrap(),
+            source: PathBuf::from(source),
+            files: Vec::new(),
+            manifest: None,
+        }
+    }
+    
+    fn kept(retention: Retention, snapshots: &[Snapshot]) -> Vec<String> {
+        let mut kept: Vec<String> = retention.keep(snapshots).into_iter().collect();
+        kept.sort();
+        kept
+    }
+    
+    #[test]
+    fn keeps_the_last_snapshots() {
+        let snapshots: Vec<Snapshot> = (1..=5).map(|h| snapshot(&format!("s{}", h), "/docs", (2024, 3, 1), 10 + h)).collect();
+        assert_eq!(kept(Retention { last: 2, ..Default::default() }, &snapshots), ["s4", "s5"]);
+    }
+    
+    #[test]
+    fn keeps_the_newest_of_each_day() {
+        let snapshots = [
+            snapshot("mar1-morning", "/docs", (2024, 3, 1), 9),
+            snapshot("mar1-evening", "/docs", (2024, 3, 1), 18),
+            snapshot("mar2", "/docs", (2024, 3, 2), 12),
+            snapshot("mar3", "/docs", (2024, 3, 3), 12),
+        ];
+        assert_eq!(kept(Retention { daily: 2, ..Default::default() }, &snapshots), ["mar2", "mar3"]);
+        assert_eq!(kept(Retention { daily: 3, ..Default::default() }, &snapshots), ["mar1-evening", "mar2", "mar3"]);
+    }
+    
+    #[test]
+    fn keeps_the_newest_of_each_week() {
+        // 2024-03-04 is a Monday
+        let snapshots = [
+            snapshot("mon", "/docs", (2024, 3, 4), 12),
+            snapshot("wed", "/docs", (2024, 3, 6), 12),
+            snapshot("next-week", "/docs", (2024, 3, 11), 12),
+            snapshot("week-after", "/docs", (2024, 3, 18), 12),
+        ];
+        assert_eq!(kept(Retention { weekly: 2, ..Default::default() }, &snapshots), ["next-week", "week-after"]);
+        assert_eq!(kept(Retention { weekly: 3, ..Default::default() }, &snapshots), ["next-week", "wed", "week-after"]);
+    }
+    
+    #[test]
+    fn keeps_the_newest_of_each_month() {
+        let snapshots = [
+            snapshot("jan", "/docs", (2024, 1, 15), 12),
+            snapshot("feb-early", "/docs", (2024, 2, 10), 12),
+            snapshot("feb-late", "/docs", (2024, 2, 20), 12),
+            snapshot("mar", "/docs", (2024, 3, 5), 12),
+        ];
+        assert_eq!(kept(Retention { monthly: 2, ..Default::default() }, &snapshots), ["feb-late", "mar"]);
+    }
+    
+    #[test]
+    fn rules_add_up() {
+        let snapshots = [
+            snapshot("jan", "/docs", (2024, 1, 15), 12),
+            snapshot("feb", "/docs", (2024, 2, 10), 12),
+            snapshot("mar1", "/docs", (2024, 3, 1), 12),
+            snapshot("mar2", "/docs", (2024, 3, 2), 12),
+        ];
+        let retention = Retention { last: 1, monthly: 3, ..Default::default() };
+        assert_eq!(kept(retention, &snapshots), ["feb", "jan", "mar2"]);
+    }
+    
+    #[test]
+    fn each_folder_is_counted_on_its_own() {
+        let snapshots = [
+            snapshot("docs-old", "/docs", (2024, 3, 1), 12),
+            snapshot("docs-new", "/docs", (2024, 3, 2), 12),
+            snapshot("photos-old", "/photos", (2024, 2, 1), 12),
+            snapshot("photos-new", "/photos", (2024, 2, 2), 12),
+        ];
+        assert_eq!(kept(Retention { last: 1, ..Default::default() }, &snapshots), ["docs-new", "photos-new"]);
+    }
+    
+    #[test]
+    fn nothing_is_kept_without_rules() {
+        let snapshots = [snapshot("only", "/docs", (2024, 3, 1), 12)];
+        assert!(Retention::default().is_empty());
+        assert!(kept(Retention::default(), &snapshots).is_empty());
+    }
+}
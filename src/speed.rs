@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::database::format_duration;
+use crate::format_size;
+
+/// Current speed is measured over roughly this much recent progress
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Progress samples of the running transfer. Only one transfer runs at a
+/// time, so one meter is enough
+static METER: Mutex<Option<Meter>> = Mutex::new(None);
+
+struct Meter {
+    /// First sample: where the transfer started (or resumed) and when
+    first: (Instant, u64),
+    /// Samples within the window, oldest first
+    recent: VecDeque<(Instant, u64)>,
+}
+
+/// Throughput of the running transfer
+#[derive(Debug, Clone, Copy)]
+pub struct Speed {
+    pub done: u64,
+    pub total: u64,
+    /// Bytes per second over the last few seconds
+    pub current: f64,
+    /// Bytes per second since the transfer started or resumed
+    pub average: f64,
+}
+
+impl Speed {
+    /// Time left at the current speed, or the average one while the current
+    /// speed is not known yet
+    pub fn remaining(&self) -> Option<Duration> {
+        let rate = if self.current > 0.0 { self.current } else { self.average };
+        if rate <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(self.total.saturating_sub(self.done) as f64 / rate))
+    }
+    
+    /// e.g. "120.00 MB of 1.50 GB, 4.20 MB/s (average 3.90 MB/s), 5m 40s left"
+    pub fn describe(&self) -> String {
+        let mut text = tr!("{done} of {total}", done = format_size(self.done), total = format_size(self.total));
+        if self.current > 0.0 || self.average > 0.0 {
+            text.push_str(&tr!(
+                ", {current}/s (average {average}/s)",
+                current = format_size(self.current as u64),
+                average = format_size(self.average as u64),
+            ));
+        }
+        if let Some(left) = self.remaining() {
+            text.push_str(&tr!(", {time} left", time = format_duration(left.as_secs())));
+        }
+        text
+    }
+}
+
+/// Forget the previous transfer; called when a new one starts
+pub fn start() {
+    *METER.lock().unwrap() = None;
+}
+
+/// Note that `done` of `total` bytes are through and return the speeds so far
+pub fn record(done: u64, total: u64) -> Speed {
+    let now = Instant::now();
+    let mut meter = METER.lock().unwrap();
+    let meter = meter.get_or_insert_with(|| Meter { first: (now, done), recent: VecDeque::new() });
+    meter.recent.push_back((now, done));
+    while meter.recent.len() > 2 && meter.recent.front().map_or(false, |(at, _)| now - *at > WINDOW) {
+        meter.recent.pop_front();
+    }
+    
+    let rate = |(from_at, from_done): (Instant, u64)| {
+        let secs = (now - from_at).as_secs_f64();
+        if secs < 0.5 { 0.0 } else { done.saturating_sub(from_done) as f64 / secs }
+    };
+    Speed {
+        done,
+        total,
+        current: meter.recent.front().copied().map_or(0.0, rate),
+        average: rate(meter.first),
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use crate::database::FileRecord;
+use crate::file_kind::FileKind;
+use crate::format_size;
+use crate::slint_generatedAppWindow::StatBar as SlintStatBar;
+
+/// Months shown in the upload volume chart
+const MONTHS_SHOWN: usize = 12;
+/// Entries in the largest files list
+const LARGEST_SHOWN: usize = 5;
+
+/// Aggregates over the whole library, ready for the statistics view
+pub struct StorageStats {
+    pub total_bytes: u64,
+    pub total_files: usize,
+    pub by_kind: Vec<SlintStatBar>,
+    pub by_month: Vec<SlintStatBar>,
+    pub largest: Vec<SlintStatBar>,
+    pub by_chat: Vec<SlintStatBar>,
+}
+
+impl StorageStats {
+    pub fn compute(records: &[FileRecord]) -> Self {
+        let total_bytes = records.iter().map(|r| r.file_size).sum();
+        
+        // Count by type
+        let mut kinds: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for record in records {
+            *kinds.entry(FileKind::from_filename(&record.filename).label()).or_default() += 1;
+        }
+        let mut by_kind: Vec<(String, u64)> = kinds
+            .into_iter()
+            .map(|(label, count)| (label.to_string(), count as u64))
+            .collect();
+        by_kind.sort_by(|a, b| b.1.cmp(&a.1));
+        
+        // Upload volume per month, keyed "YYYY-MM" so keys sort chronologically
+        let mut months: BTreeMap<String, u64> = BTreeMap::new();
+        for record in records {
+            let month = record.upload_date.format("%Y-%m").to_string();
+            *months.entry(month).or_default() += record.file_size;
+        }
+        let by_month: Vec<(String, u64)> = months
+            .into_iter()
+            .rev()
+            .take(MONTHS_SHOWN)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+        
+        // Largest files
+        let mut largest: Vec<&FileRecord> = records.iter().collect();
+        largest.sort_by(|a, b| b.file_size.cmp(&a.file_size));
+        let largest: Vec<(String, u64)> = largest
+            .into_iter()
+            .take(LARGEST_SHOWN)
+            .map(|r| (r.filename.clone(), r.file_size))
+            .collect();
+        
+        // Bytes per storage chat
+        let mut chats: BTreeMap<String, u64> = BTreeMap::new();
+        for record in records {
+            *chats.entry(record.chat_label()).or_default() += record.file_size;
+        }
+        let by_chat: Vec<(String, u64)> = chats.into_iter().collect();
+        
+        Self {
+            total_bytes,
+            total_files: records.len(),
+            by_kind: bars(by_kind, |count| tr!("{count} files", count = count)),
+            by_month: bars(by_month, format_size),
+            largest: bars(largest, format_size),
+            by_chat: bars(by_chat, format_size),
+        }
+    }
+}
+
+/// Turn `(label, value)` pairs into chart bars scaled against the largest value
+pub fn bars(values: Vec<(String, u64)>, format_value: impl Fn(u64) -> String) -> Vec<SlintStatBar> {
+    let max = values.iter().map(|(_, v)| *v).max().unwrap_or(0).max(1);
+    values
+        .into_iter()
+        .map(|(label, value)| SlintStatBar {
+            label: label.into(),
+            value: format_value(value).into(),
+            fraction: value as f32 / max as f32,
+        })
+        .collect()
+}
@@ -0,0 +1,369 @@
+use anyhow::{bail, Result};
+use grammers_client::types::Media;
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+use crate::{api_usage, app_lock, secrets};
+use crate::chunked::{fetch_chunk, file_location, media_size, DOWNLOAD_CHUNK_SIZE};
+use crate::database::{Database, RecordKey};
+use crate::record_chat;
+
+/// Largest request head accepted
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+/// Port the server got and the token its URLs carry, set once it listens.
+/// The token keeps other local programs and web pages from fetching files
+static SERVER: OnceLock<(u16, String)> = OnceLock::new();
+
+/// URL a media player can open to stream a stored file; `None` until the
+/// server is listening
+pub fn stream_url(key: RecordKey, filename: &str) -> Option<String> {
+    let (port, token) = SERVER.get()?;
+    Some(format!("http://127.0.0.1:{}/stream/{}/{}/{}", port, token, key, percent_encode(filename)))
+}
+
+/// Serve stored files over HTTP with Range support, fetching bytes from
+/// Telegram as the player asks for them. Listens on localhost only, on
+/// whatever port is free
+pub async fn run_stream_server(client: Arc<Mutex<Option<Client>>>, db: Arc<Database>) {
+    let listener = match TcpListener::bind(("127.0.0.1", 0)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to start streaming server: {:?}", e);
+            return;
+        }
+    };
+    let port = match listener.local_addr() {
+        Ok(address) => address.port(),
+        Err(e) => {
+            error!("Failed to start streaming server: {:?}", e);
+            return;
+        }
+    };
+    let _ = SERVER.set((port, format!("{:032x}", rand::random::<u128>())));
+    info!("Streaming server listening on 127.0.0.1:{}", port);
+    
+    loop {
+        let (socket, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept stream connection: {:?}", e);
+                continue;
+            }
+        };
+        let client = client.clone();
+        let db = db.clone();
+        
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(socket, client, db).await {
+                // Players routinely drop connections while seeking
+                info!("Stream connection ended: {:#}", e);
+            }
+        });
+    }
+}
+
+/// One stored document, or one piece of a split file
+#[derive(Clone)]
+pub struct Segment {
+    pub location: tl::enums::InputFileLocation,
+    pub size: u64,
+}
+
+/// A parsed request
+struct Request {
+    head_only: bool,
+    key: RecordKey,
+    range: Option<ByteRange>,
+}
+
+/// Bytes asked for with a `Range` header
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ByteRange {
+    /// From the first offset to the second, inclusive, or to the end if `None`
+    From(u64, Option<u64>),
+    /// The last this many bytes
+    Last(u64),
+}
+
+impl ByteRange {
+    /// First and last offset asked for of a file of `total` bytes, `None`
+    /// when none of them exist
+    fn bounds(self, total: u64) -> Option<(u64, u64)> {
+        let last = total.checked_sub(1)?;
+        let (start, end) = match self {
+            Self::From(start, end) => (start, end.unwrap_or(last).min(last)),
+            // Players read the index at the end of a file this way
+            Self::Last(length) => (total.saturating_sub(length), last),
+        };
+        (start <= end).then_some((start, end))
+    }
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    client: Arc<Mutex<Option<Client>>>,
+    db: Arc<Database>,
+) -> Result<()> {
+    let request = match read_request(&mut socket).await? {
+        Ok(request) => request,
+        Err(status) => return write_status(&mut socket, status).await,
+    };
+    if app_lock::is_locked() {
+        return write_status(&mut socket, "403 Forbidden").await;
+    }
+    
+    let Some(client) = client.lock().unwrap().clone() else {
+        return write_status(&mut socket, "503 Service Unavailable").await;
+    };
+    // Stored compressed or encrypted, so the bytes are no use to a player
+    if db.find(request.key).is_some_and(|r| r.compressed || r.key_id.is_some()) {
+        return write_status(&mut socket, "415 Unsupported Media Type").await;
+    }
+    let (segments, mime_type) = match resolve(&client, &db, request.key).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return write_status(&mut socket, "404 Not Found").await,
+        Err(e) => {
+            write_status(&mut socket, "502 Bad Gateway").await?;
+            return Err(e);
+        }
+    };
+    let total: u64 = segments.iter().map(|s| s.size).sum();
+    
+    let (status, start, end) = match request.range {
+        Some(range) => {
+            let Some((start, end)) = range.bounds(total) else {
+                let head = format!(
+                    "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    total
+                );
+                socket.write_all(head.as_bytes()).await?;
+                return Ok(());
+            };
+            ("206 Partial Content", start, end)
+        }
+        None => ("200 OK", 0, total.saturating_sub(1)),
+    };
+    let length = if total == 0 { 0 } else { end - start + 1 };
+    
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status, mime_type, length
+    );
+    if request.range.is_some() {
+        head.push_str(&format!("Content-Range: bytes {}-{}/{}\r\n", start, end, total));
+    }
+    head.push_str("\r\n");
+    socket.write_all(head.as_bytes()).await?;
+    
+    if request.head_only || length == 0 {
+        return Ok(());
+    }
+    stream_range(&client, &segments, start, end, &mut socket).await
+}
+
+/// Read and parse the request head; `Err` holds the status to reply with
+async fn read_request(socket: &mut TcpStream) -> Result<Result<Request, &'static str>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() > MAX_HEADER_SIZE {
+            return Ok(Err("431 Request Header Fields Too Large"));
+        }
+        let n = socket.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("Connection closed before the request was complete");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    
+    let head = String::from_utf8_lossy(&buf);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let head_only = match request_line.next() {
+        Some("GET") => false,
+        Some("HEAD") => true,
+        _ => return Ok(Err("405 Method Not Allowed")),
+    };
+    
+    // /stream/<token>/<chat>:<message id>/<name>
+    let path = request_line.next().unwrap_or_default();
+    let mut parts = path.strip_prefix("/stream/").unwrap_or_default().split('/');
+    let token = SERVER.get().map(|(_, token)| token.as_str());
+    let given = parts.next().unwrap_or_default();
+    if !token.is_some_and(|token| secrets::same_secret(given, token)) {
+        return Ok(Err("403 Forbidden"));
+    }
+    let Some(key) = parts.next().and_then(|key| key.parse().ok()) else {
+        return Ok(Err("404 Not Found"));
+    };
+    
+    let range = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("range"))
+        .and_then(|(_, value)| parse_range(value.trim()));
+    
+    Ok(Ok(Request { head_only, key, range }))
+}
+
+/// Parse `bytes=start-[end]` or `bytes=-length`; multiple ranges are not supported
+fn parse_range(value: &str) -> Option<ByteRange> {
+    let spec = value.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+    match start.trim() {
+        "" => Some(ByteRange::Last(end?)),
+        start => Some(ByteRange::From(start.parse().ok()?, end)),
+    }
+}
+
+/// Locations and sizes of the documents holding a stored file that can be
+/// served as is, plus its MIME type
+pub async fn resolve(client: &Client, db: &Database, key: RecordKey) -> Result<Option<(Vec<Segment>, String)>> {
+    let Some(record) = db.find(key) else {
+        return Ok(None);
+    };
+    if record.compressed {
+        bail!("{} is stored compressed and has to be downloaded", record.filename);
+    }
+    if record.key_id.is_some() {
+        bail!("{} is stored encrypted and has to be downloaded", record.filename);
+    }
+    segments(client, db, key).await
+}
+
+/// Locations and sizes of the documents holding a stored file, as stored,
+/// plus its MIME type
+pub async fn segments(client: &Client, db: &Database, key: RecordKey) -> Result<Option<(Vec<Segment>, String)>> {
+    let Some(record) = db.find(key) else {
+        return Ok(None);
+    };
+    
+    let chat = record_chat(client, Some(&record)).await?;
+    let mut segments = Vec::new();
+    let mut mime_type = None;
+    for id in record.message_ids() {
+        api_usage::request("messages.getMessages");
+        let Some(message) = client.get_messages_by_id(chat, &[id]).await?.pop().flatten() else {
+            return Ok(None);
+        };
+        let Some(media) = message.media() else {
+            return Ok(None);
+        };
+        // Telegram keeps photos as JPEG whatever was sent
+        match &media {
+            Media::Document(document) if mime_type.is_none() => mime_type = document.mime_type().map(str::to_string),
+            Media::Photo(_) => mime_type = Some("image/jpeg".to_string()),
+            _ => {}
+        }
+        let (Some(location), Some(size)) = (file_location(&media), media_size(&media)) else {
+            return Ok(None);
+        };
+        segments.push(Segment { location, size });
+    }
+    
+    let mime_type = mime_type.unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok(Some((segments, mime_type)))
+}
+
+/// Write bytes `start..=end` of the concatenated segments to `socket`
+async fn stream_range(
+    client: &Client,
+    segments: &[Segment],
+    start: u64,
+    end: u64,
+    socket: &mut TcpStream,
+) -> Result<()> {
+    let chunk_size = DOWNLOAD_CHUNK_SIZE as u64;
+    let mut offset = start;
+    let mut segment_start = 0;
+    
+    for segment in segments {
+        let segment_end = segment_start + segment.size;
+        while offset <= end && offset < segment_end {
+            let within = offset - segment_start;
+            let index = (within / chunk_size) as u32;
+            let (_, bytes) = fetch_chunk(client.clone(), segment.location.clone(), index).await?;
+            
+            let chunk_start = index as u64 * chunk_size;
+            let from = (within - chunk_start) as usize;
+            let to = ((end + 1 - segment_start).min(chunk_start + bytes.len() as u64) - chunk_start) as usize;
+            if from >= to {
+                bail!("Telegram returned a short chunk");
+            }
+            socket.write_all(&bytes[from..to]).await?;
+            offset += (to - from) as u64;
+        }
+        segment_start = segment_end;
+    }
+    
+    socket.flush().await?;
+    Ok(())
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+async fn write_status(socket: &mut TcpStream, status: &str) -> Result<()> {
+    let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+    socket.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parses_closed_and_open_ranges() {
+        assert_eq!(parse_range("bytes=0-99"), Some(ByteRange::From(0, Some(99))));
+        assert_eq!(parse_range("bytes=100-"), Some(ByteRange::From(100, None)));
+        assert_eq!(parse_range("bytes= 5 - 10 "), Some(ByteRange::From(5, Some(10))));
+    }
+    
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-500"), Some(ByteRange::Last(500)));
+        assert_eq!(parse_range("bytes=-"), None);
+    }
+    
+    #[test]
+    fn bounds_stay_within_the_file() {
+        assert_eq!(ByteRange::From(0, Some(99)).bounds(1000), Some((0, 99)));
+        assert_eq!(ByteRange::From(900, Some(2000)).bounds(1000), Some((900, 999)));
+        assert_eq!(ByteRange::From(100, None).bounds(1000), Some((100, 999)));
+        assert_eq!(ByteRange::Last(100).bounds(1000), Some((900, 999)));
+        assert_eq!(ByteRange::Last(5000).bounds(1000), Some((0, 999)));
+    }
+    
+    #[test]
+    fn unsatisfiable_ranges_have_no_bounds() {
+        assert_eq!(ByteRange::From(1000, None).bounds(1000), None);
+        assert_eq!(ByteRange::From(10, Some(5)).bounds(1000), None);
+        assert_eq!(ByteRange::Last(0).bounds(1000), None);
+        assert_eq!(ByteRange::From(0, None).bounds(0), None);
+        assert_eq!(ByteRange::Last(10).bounds(0), None);
+    }
+    
+    #[test]
+    fn rejects_unsupported_ranges() {
+        // Multiple ranges, other units and garbage
+        assert_eq!(parse_range("bytes=0-99,200-299"), None);
+        assert_eq!(parse_range("items=0-99"), None);
+        assert_eq!(parse_range("0-99"), None);
+        assert_eq!(parse_range("bytes=abc-"), None);
+    }
+}
@@ -0,0 +1,634 @@
+use anyhow::{Context, Result};
+use chrono::TimeZone;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use grammers_client::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::caption;
+use crate::config::Settings;
+use crate::database::{Database, FileRecord, DATE_FORMAT};
+use crate::delete_stored_file;
+use crate::file_attrs::FileAttrs;
+use crate::folder;
+use crate::ignore_file::IgnoreRules;
+use crate::format_size;
+use crate::transfer::{TransferKind, TransferQueue, UploadOptions};
+
+/// A local folder kept in lockstep with a virtual folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPair {
+    pub local: PathBuf,
+    /// Virtual folder mirrored into `local`, `/`-separated
+    pub remote_folder: String,
+    /// Deleting a file on one side deletes it on the other. Otherwise the
+    /// deleted copy is restored from the other side
+    #[serde(default)]
+    pub propagate_deletes: bool,
+    /// Minutes between automatic runs
+    #[serde(default = "default_interval")]
+    pub interval_minutes: u32,
+    /// Subfolders or glob patterns to sync; everything when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Subfolders or glob patterns never synced, e.g. `target` or `*.tmp`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// Compiled include/exclude rules of a pair, along with what its ignore
+/// file leaves out.
+///
+/// A pattern without `/` matches a file or folder name at any depth (like
+/// `.gitignore`); one with `/` matches from the root of the pair. Matching a
+/// folder covers everything inside it.
+pub struct SyncRules {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    ignored: IgnoreRules,
+}
+
+impl SyncRules {
+    pub fn new(pair: &SyncPair) -> Result<Self> {
+        let include = if pair.include.is_empty() {
+            None
+        } else {
+            Some(build_globs(&pair.include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: build_globs(&pair.exclude)?,
+            ignored: IgnoreRules::load(&pair.local),
+        })
+    }
+    
+    /// Whether the file at `/`-separated relative path `key` takes part in the sync
+    pub fn allows(&self, key: &str) -> bool {
+        let included = self.include.as_ref().map_or(true, |globs| globs.is_match(key));
+        included && !self.exclude.is_match(key) && !self.ignored.ignores(Path::new(key))
+    }
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.trim().trim_matches('/');
+        if pattern.is_empty() {
+            continue;
+        }
+        let base = if pattern.contains('/') {
+            pattern.to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+        for glob in [base.clone(), format!("{}/**", base)] {
+            builder.add(GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid pattern {:?}", pattern))?);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Split a comma-separated list of patterns typed by the user
+pub fn parse_patterns(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn default_interval() -> u32 {
+    15
+}
+
+/// A file both sides agreed on at the end of a sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedFile {
+    pub sha256: String,
+    pub size: u64,
+    pub mtime: Option<i64>,
+}
+
+/// Last agreed state of a pair, keyed by `/`-separated path relative to its root
+type PairState = HashMap<String, SyncedFile>;
+
+/// What one sync run did
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub uploads: usize,
+    pub downloads: usize,
+    pub deleted_local: usize,
+    pub deleted_remote: usize,
+    pub conflicts: usize,
+}
+
+impl SyncReport {
+    pub fn is_empty(&self) -> bool {
+        self.uploads + self.downloads + self.deleted_local + self.deleted_remote + self.conflicts == 0
+    }
+    
+    pub fn summary(&self) -> String {
+        let mut summary = tr!(
+            "{uploads} upload(s), {downloads} download(s), {local} local and {remote} remote deletion(s)",
+            uploads = self.uploads,
+            downloads = self.downloads,
+            local = self.deleted_local,
+            remote = self.deleted_remote,
+        );
+        if self.conflicts > 0 {
+            summary.push_str(&tr!(", {count} conflict(s) to resolve", count = self.conflicts));
+        }
+        summary
+    }
+}
+
+/// A file changed on both sides since the last sync
+#[derive(Debug, Clone)]
+pub struct SyncConflict {
+    pub id: u64,
+    /// Local root of the pair the file belongs to
+    pub pair_local: PathBuf,
+    /// `/`-separated path relative to the pair's root
+    pub key: String,
+    pub local_path: PathBuf,
+    pub local_size: u64,
+    pub local_modified: String,
+    pub remote: FileRecord,
+}
+
+impl SyncConflict {
+    /// One line comparing both versions, for the UI
+    pub fn describe(&self) -> String {
+        tr!(
+            "local {local_size} modified {local_modified}, remote {remote_size} uploaded {remote_uploaded}",
+            local_size = format_size(self.local_size),
+            local_modified = self.local_modified,
+            remote_size = format_size(self.remote.file_size),
+            remote_uploaded = self.remote.upload_date.format(DATE_FORMAT),
+        )
+    }
+}
+
+/// How to settle a conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Upload the local file over the remote one
+    KeepLocal,
+    /// Download the remote file over the local one
+    KeepRemote,
+    /// Rename the local file aside and download the remote one in its place
+    KeepBoth,
+}
+
+/// A file found on the local side
+struct LocalFile {
+    path: PathBuf,
+    folder: String,
+    size: u64,
+    mtime: Option<i64>,
+    sha256: String,
+}
+
+impl LocalFile {
+    fn synced(&self) -> SyncedFile {
+        SyncedFile {
+            sha256: self.sha256.clone(),
+            size: self.size,
+            mtime: self.mtime,
+        }
+    }
+    
+    /// Whether `record` holds the same content, by hash when the record has one
+    fn matches(&self, record: &FileRecord) -> bool {
+        match &record.sha256 {
+            Some(sha256) => *sha256 == self.sha256,
+            None => record.file_size == self.size && record.mtime.is_some() && record.mtime == self.mtime,
+        }
+    }
+}
+
+/// Runs sync pairs and keeps their last agreed state on disk
+pub struct SyncEngine {
+    state_file: PathBuf,
+    states: Mutex<HashMap<String, PairState>>,
+    last_run: Mutex<HashMap<PathBuf, Instant>>,
+    /// Held for the duration of a run so pairs never sync concurrently
+    running: tokio::sync::Mutex<()>,
+    client: Arc<Mutex<Option<Client>>>,
+    /// Conflicts found by the latest run of each pair, waiting for the user
+    conflicts: Mutex<Vec<SyncConflict>>,
+    next_conflict_id: AtomicU64,
+}
+
+impl SyncEngine {
+    pub fn new(state_file: impl AsRef<Path>, client: Arc<Mutex<Option<Client>>>) -> Self {
+        let state_file = state_file.as_ref().to_path_buf();
+        let states = std::fs::read_to_string(&state_file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        
+        Self {
+            state_file,
+            states: Mutex::new(states),
+            last_run: Mutex::new(HashMap::new()),
+            running: tokio::sync::Mutex::new(()),
+            client,
+            conflicts: Mutex::new(Vec::new()),
+            next_conflict_id: AtomicU64::new(1),
+        }
+    }
+    
+    /// Sync every pair whose interval has elapsed
+    pub async fn run_due(
+        &self,
+        settings: &Mutex<Settings>,
+        db: &Database,
+        queue: &TransferQueue,
+    ) -> Vec<(SyncPair, Result<SyncReport>)> {
+        let pairs = settings.lock().unwrap().sync_pairs.clone();
+        let due: Vec<SyncPair> = {
+            let last_run = self.last_run.lock().unwrap();
+            pairs.into_iter()
+                .filter(|pair| match last_run.get(&pair.local) {
+                    Some(at) => at.elapsed() >= Duration::from_secs(pair.interval_minutes as u64 * 60),
+                    None => true,
+                })
+                .collect()
+        };
+        
+        let mut results = Vec::new();
+        for pair in due {
+            let result = self.sync(&pair, db, queue).await;
+            results.push((pair, result));
+        }
+        results
+    }
+    
+    /// Bring one pair into lockstep: queue the transfers needed and apply deletions
+    pub async fn sync(&self, pair: &SyncPair, db: &Database, queue: &TransferQueue) -> Result<SyncReport> {
+        let _running = self.running.lock().await;
+        self.last_run.lock().unwrap().insert(pair.local.clone(), Instant::now());
+        info!("Syncing {:?} with {:?}", pair.local, pair.remote_folder);
+        
+        let key = pair.local.to_string_lossy().into_owned();
+        let previous = self.states.lock().unwrap().get(&key).cloned().unwrap_or_default();
+        // Files outside the rules are left alone on both sides
+        let rules = Arc::new(SyncRules::new(pair)?);
+        
+        let scan_pair = pair.clone();
+        let scan_previous = previous.clone();
+        let scan_rules = rules.clone();
+        let local = tokio::task::spawn_blocking(move || scan_local(&scan_pair, &scan_previous, &scan_rules))
+            .await
+            .context("Folder scan panicked")??;
+        let mut remote = remote_files(pair, db);
+        remote.retain(|key, _| rules.allows(key));
+        
+        // Transfers already queued from an earlier run are left alone
+        let in_flight: HashSet<PathBuf> = queue.snapshot()
+            .into_iter()
+            .filter_map(|(job, _)| match job.kind {
+                TransferKind::Upload { path, .. } => Some(path),
+                TransferKind::Download { dest, .. } => Some(dest),
+                TransferKind::Album { .. } | TransferKind::Reencrypt { .. } => None,
+            })
+            .collect();
+        
+        let mut report = SyncReport::default();
+        let mut next_state = PairState::new();
+        let mut conflicts = Vec::new();
+        let keys: BTreeSet<&String> = local.keys().chain(remote.keys()).collect();
+        
+        for key in keys {
+            let prev = previous.get(key);
+            match (local.get(key), remote.get(key)) {
+                (Some(l), Some(r)) if l.matches(r) => {
+                    next_state.insert(key.clone(), l.synced());
+                }
+                (Some(l), Some(r)) => {
+                    let local_changed = prev.map_or(true, |p| p.sha256 != l.sha256);
+                    let remote_changed = match (prev, &r.sha256) {
+                        (Some(p), Some(sha256)) => p.sha256 != *sha256,
+                        (None, _) => true,
+                        (Some(_), None) => false,
+                    };
+                    if in_flight.contains(&l.path) {
+                        // A transfer (possibly a resolution) is already settling this file
+                    } else if remote_changed && !local_changed {
+                        self.download(r, &l.path, queue, &in_flight, &mut report).await;
+                    } else if local_changed && !remote_changed {
+                        upload(l, Some(r), queue, &in_flight, &mut report);
+                    } else {
+                        // Both sides changed: overwriting either would lose work
+                        info!("Sync conflict on {}", key);
+                        conflicts.push(self.conflict(pair, key, l, r));
+                        report.conflicts += 1;
+                    }
+                    if let Some(p) = prev {
+                        next_state.insert(key.clone(), p.clone());
+                    }
+                }
+                (Some(l), None) if prev.is_some() && pair.propagate_deletes => {
+                    info!("{:?} was deleted remotely; removing local copy", l.path);
+                    match tokio::fs::remove_file(&l.path).await {
+                        Ok(()) => report.deleted_local += 1,
+                        Err(e) => warn!("Failed to remove {:?}: {:?}", l.path, e),
+                    }
+                }
+                (Some(l), None) => {
+                    upload(l, None, queue, &in_flight, &mut report);
+                    if let Some(p) = prev {
+                        next_state.insert(key.clone(), p.clone());
+                    }
+                }
+                (None, Some(r)) if prev.is_some() && pair.propagate_deletes => {
+                    info!("{} was deleted locally; removing it from Telegram", key);
+                    match self.delete_remote(r, db).await {
+                        Ok(()) => report.deleted_remote += 1,
+                        Err(e) => {
+                            warn!("Failed to delete {} remotely: {:?}", key, e);
+                            next_state.insert(key.clone(), prev.unwrap().clone());
+                        }
+                    }
+                }
+                (None, Some(r)) => {
+                    let Some(dest) = folder::local_path(&pair.local, key) else {
+                        warn!("Not syncing {:?}: path leaves the sync folder", key);
+                        continue;
+                    };
+                    self.download(r, &dest, queue, &in_flight, &mut report).await;
+                    if let Some(p) = prev {
+                        next_state.insert(key.clone(), p.clone());
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        
+        self.states.lock().unwrap().insert(key, next_state);
+        self.save();
+        
+        {
+            let mut all = self.conflicts.lock().unwrap();
+            all.retain(|c| c.pair_local != pair.local);
+            all.extend(conflicts);
+        }
+        
+        info!("Sync of {:?} done: {}", pair.local, report.summary());
+        Ok(report)
+    }
+    
+    /// Forget the state of a pair that is no longer synced
+    pub fn forget(&self, pair: &SyncPair) {
+        self.states.lock().unwrap().remove(&*pair.local.to_string_lossy());
+        self.conflicts.lock().unwrap().retain(|c| c.pair_local != pair.local);
+        self.save();
+    }
+    
+    /// Conflicts waiting for the user
+    pub fn conflicts(&self) -> Vec<SyncConflict> {
+        self.conflicts.lock().unwrap().clone()
+    }
+    
+    /// Settle conflict `id` by queueing the transfer the resolution calls for.
+    /// The next sync records the agreed state once that transfer is done
+    pub async fn resolve(&self, id: u64, resolution: Resolution, queue: &TransferQueue) -> Result<()> {
+        let conflict = {
+            let mut conflicts = self.conflicts.lock().unwrap();
+            let index = conflicts.iter()
+                .position(|c| c.id == id)
+                .context("Conflict already resolved")?;
+            conflicts.remove(index)
+        };
+        let message_id = conflict.remote.message_id.context("Remote file has no message")?;
+        let download = TransferKind::Download {
+            message_id,
+            chat: conflict.remote.chat.as_ref().map(|c| c.id),
+            filename: conflict.remote.filename.clone(),
+            dest: conflict.local_path.clone(),
+            batch: None,
+            open_after: false,
+        };
+        
+        match resolution {
+            Resolution::KeepLocal => {
+                queue.push(TransferKind::Upload {
+                    path: conflict.local_path.clone(),
+                    options: UploadOptions {
+                        folder: conflict.remote.folder.clone(),
+                        delta: true,
+                        previous: conflict.remote.key(),
+                        ..Default::default()
+                    },
+                });
+            }
+            Resolution::KeepRemote => {
+                queue.push(download);
+            }
+            Resolution::KeepBoth => {
+                let renamed = conflict_copy_path(&conflict.local_path);
+                tokio::fs::rename(&conflict.local_path, &renamed).await
+                    .with_context(|| format!("Failed to rename {:?}", conflict.local_path))?;
+                info!("Kept local version of {} as {:?}", conflict.key, renamed);
+                queue.push(download);
+            }
+        }
+        
+        info!("Resolved conflict on {} with {:?}", conflict.key, resolution);
+        Ok(())
+    }
+    
+    fn conflict(&self, pair: &SyncPair, key: &str, local: &LocalFile, remote: &FileRecord) -> SyncConflict {
+        let local_modified = local.mtime
+            .and_then(|t| chrono::Local.timestamp_opt(t, 0).single())
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        
+        SyncConflict {
+            id: self.next_conflict_id.fetch_add(1, Ordering::Relaxed),
+            pair_local: pair.local.clone(),
+            key: key.to_string(),
+            local_path: local.path.clone(),
+            local_size: local.size,
+            local_modified,
+            remote: remote.clone(),
+        }
+    }
+    
+    async fn download(
+        &self,
+        record: &FileRecord,
+        dest: &Path,
+        queue: &TransferQueue,
+        in_flight: &HashSet<PathBuf>,
+        report: &mut SyncReport,
+    ) {
+        let Some(message_id) = record.message_id else {
+            return;
+        };
+        if in_flight.contains(dest) {
+            return;
+        }
+        if let Some(parent) = dest.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(parent).await {
+                warn!("Failed to create {:?}: {:?}", parent, e);
+                return;
+            }
+        }
+        
+        queue.push(TransferKind::Download {
+            message_id,
+            chat: record.chat.as_ref().map(|c| c.id),
+            filename: record.filename.clone(),
+            dest: dest.to_path_buf(),
+            batch: None,
+            open_after: false,
+        });
+        report.downloads += 1;
+    }
+    
+    /// Delete `record` along with any older version of it still stored,
+    /// which would otherwise be downloaded back as a new remote file
+    async fn delete_remote(&self, record: &FileRecord, db: &Database) -> Result<()> {
+        let client = self.client.lock().unwrap().clone().context("Not connected to Telegram")?;
+        let versions: Vec<FileRecord> = db.all_records()
+            .into_iter()
+            .filter(|r| r.folder == record.folder && r.filename == record.filename)
+            .collect();
+        for version in &versions {
+            delete_stored_file(&client, db, version).await?;
+        }
+        Ok(())
+    }
+    
+    fn save(&self) {
+        let states = self.states.lock().unwrap().clone();
+        let result = serde_json::to_string_pretty(&states)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| std::fs::write(&self.state_file, json).map_err(Into::into));
+        if let Err(e) = result {
+            error!("Failed to save sync state: {:?}", e);
+        }
+    }
+}
+
+/// Queue an upload of `file` in content-defined pieces, so the next version
+/// only sends the pieces that changed; `previous` is the stored version it
+/// follows, if any
+fn upload(file: &LocalFile, previous: Option<&FileRecord>, queue: &TransferQueue, in_flight: &HashSet<PathBuf>, report: &mut SyncReport) {
+    if in_flight.contains(&file.path) {
+        return;
+    }
+    queue.push(TransferKind::Upload {
+        path: file.path.clone(),
+        options: UploadOptions {
+            folder: file.folder.clone(),
+            delta: true,
+            previous: previous.and_then(FileRecord::key),
+            ..Default::default()
+        },
+    });
+    report.uploads += 1;
+}
+
+/// Hash every local file the rules allow, reusing hashes of files unchanged since the last sync
+fn scan_local(pair: &SyncPair, previous: &PairState, rules: &SyncRules) -> Result<HashMap<String, LocalFile>> {
+    let mut files = HashMap::new();
+    
+    for file in folder::collect_files_into(&pair.local, &pair.remote_folder)? {
+        let Some(key) = relative_key(&pair.local, &file.path) else {
+            continue;
+        };
+        if !rules.allows(&key) {
+            continue;
+        }
+        let attrs = FileAttrs::read(&file.path);
+        
+        let sha256 = match previous.get(&key) {
+            Some(p) if p.size == file.size && p.mtime.is_some() && p.mtime == attrs.mtime => p.sha256.clone(),
+            _ => match caption::hash_file(&file.path) {
+                Ok(sha256) => sha256,
+                Err(e) => {
+                    warn!("Skipping unreadable {:?}: {:?}", file.path, e);
+                    continue;
+                }
+            },
+        };
+        
+        files.insert(key, LocalFile {
+            path: file.path,
+            folder: file.folder,
+            size: file.size,
+            mtime: attrs.mtime,
+            sha256,
+        });
+    }
+    
+    Ok(files)
+}
+
+/// Records under the pair's virtual folder, keyed like local files; the newest wins
+fn remote_files(pair: &SyncPair, db: &Database) -> HashMap<String, FileRecord> {
+    let prefix = format!("{}/", pair.remote_folder);
+    let mut files = HashMap::new();
+    
+    for record in db.all_records() {
+        let relative = if record.folder == pair.remote_folder {
+            ""
+        } else if let Some(rest) = record.folder.strip_prefix(&prefix) {
+            rest
+        } else {
+            continue;
+        };
+        let key = if relative.is_empty() {
+            record.filename.clone()
+        } else {
+            format!("{}/{}", relative, record.filename)
+        };
+        // Records merged in from another instance may come in any order
+        if !files.get(&key).is_some_and(|newest: &FileRecord| newest.upload_date > record.upload_date) {
+            files.insert(key, record);
+        }
+    }
+    
+    files
+}
+
+/// Free name next to `path` for the local side of a conflict,
+/// e.g. "report (conflict 2024-05-01 103000).txt"
+fn conflict_copy_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    let extension = path.extension().and_then(|e| e.to_str());
+    let stamp = chrono::Local::now().format("%Y-%m-%d %H%M%S");
+    
+    let mut attempt = 0;
+    loop {
+        let suffix = if attempt == 0 { String::new() } else { format!(" {}", attempt) };
+        let name = match extension {
+            Some(ext) => format!("{} (conflict {}{}).{}", stem, stamp, suffix, ext),
+            None => format!("{} (conflict {}{})", stem, stamp, suffix),
+        };
+        let candidate = path.with_file_name(name);
+        if !candidate.exists() {
+            return candidate;
+        }
+        attempt += 1;
+    }
+}
+
+/// `/`-separated path of `path` relative to `root`
+fn relative_key(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<String> = relative.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Some(parts.join("/"))
+}
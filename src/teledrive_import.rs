@@ -0,0 +1,100 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::database::FileRecord;
+
+/// Outcome of reading a TeleDrive export
+pub struct LegacyImport {
+    pub records: Vec<FileRecord>,
+    /// Rows that were not imported: files stored outside Saved Messages,
+    /// unfinished uploads, or rows without a message id
+    pub skipped: usize,
+}
+
+/// Map a JSON export of the Node.js TeleDrive `files` table into records.
+///
+/// The export is an array of rows (as written by `\copy (select
+/// json_agg(files) from files) to ...` or Prisma's `findMany`) with TeleDrive's
+/// column names. Folders are rows of type `folder` linked by `parent_id`; they
+/// become the virtual folders of the files below them
+pub fn parse(json: &str) -> Result<LegacyImport> {
+    let value: Value = serde_json::from_str(json).context("Not a JSON file")?;
+    // Accept `{"files": [...]}` as well as a bare array
+    let rows = match &value {
+        Value::Array(rows) => rows,
+        Value::Object(map) => match map.get("files") {
+            Some(Value::Array(rows)) => rows,
+            _ => bail!("No \"files\" array in the export"),
+        },
+        _ => bail!("Expected an array of TeleDrive file rows"),
+    };
+    
+    let folders: HashMap<&str, (&str, Option<&str>)> = rows
+        .iter()
+        .filter(|row| text(row, "type") == Some("folder"))
+        .filter_map(|row| Some((text(row, "id")?, (text(row, "name")?, text(row, "parent_id")))))
+        .collect();
+    
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for row in rows.iter().filter(|row| text(row, "type") != Some("folder")) {
+        match to_record(row, &folders) {
+            Some(record) => records.push(record),
+            None => skipped += 1,
+        }
+    }
+    Ok(LegacyImport { records, skipped })
+}
+
+fn to_record(row: &Value, folders: &HashMap<&str, (&str, Option<&str>)>) -> Option<FileRecord> {
+    // Files shared into channels keep their message there, out of reach here
+    if row.get("forward_info").map_or(false, |v| !v.is_null()) {
+        return None;
+    }
+    // Uploads still in progress when the export was taken
+    if number(row, "upload_progress").map_or(false, |p| p < 1.0) {
+        return None;
+    }
+    let name = text(row, "name")?;
+    let message_id = number(row, "message_id")? as i32;
+    let size = number(row, "size").unwrap_or(0.0) as u64;
+    
+    let mut record = FileRecord::new(name, &format!("tg_file_{}", name), size, message_id);
+    record.folder = folder_path(text(row, "parent_id"), folders);
+    if let Some(date) = text(row, "uploaded_at").or_else(|| text(row, "created_at"))
+        .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+    {
+        record.upload_date = date.with_timezone(&Local);
+    }
+    Some(record)
+}
+
+/// `/`-joined names of the folder `parent` and its ancestors
+fn folder_path(mut parent: Option<&str>, folders: &HashMap<&str, (&str, Option<&str>)>) -> String {
+    let mut names = Vec::new();
+    while let Some((name, next)) = parent.and_then(|id| folders.get(id)) {
+        // A corrupt export could link folders in a cycle
+        if names.len() > folders.len() {
+            break;
+        }
+        names.push(*name);
+        parent = *next;
+    }
+    names.reverse();
+    names.join("/")
+}
+
+fn text<'a>(row: &'a Value, key: &str) -> Option<&'a str> {
+    row.get(key)?.as_str().filter(|s| !s.is_empty())
+}
+
+/// Numeric column; Postgres bigints usually come out as strings
+fn number(row: &Value, key: &str) -> Option<f64> {
+    match row.get(key)? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
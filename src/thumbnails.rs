@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use grammers_client::types::Media;
+use grammers_client::Client;
+use grammers_tl_types as tl;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::chunked::fetch_chunk;
+use crate::database::{Database, FileRecord};
+use crate::file_kind::FileKind;
+use crate::{record_chat, refresh_file_list, AppWindow};
+
+/// Folder thumbnails are cached in
+pub const THUMB_DIR: &str = "thumbnails";
+/// Most thumbnails fetched per pass, so a large library fills in gradually
+const BATCH: usize = 100;
+
+/// Chat and message of `record`; message ids are only unique within a chat
+fn key(record: &FileRecord) -> Option<(i64, i32)> {
+    Some((record.chat.as_ref().map_or(0, |c| c.id), record.message_id?))
+}
+
+/// Cache file for the thumbnail of `record`, whether or not it exists yet
+fn path(record: &FileRecord) -> Option<PathBuf> {
+    let (chat, message_id) = key(record)?;
+    Some(Path::new(THUMB_DIR).join(format!("{}_{}.jpg", chat, message_id)))
+}
+
+/// Cached thumbnail of `record`, if it was fetched before
+pub fn cached(record: &FileRecord) -> Option<PathBuf> {
+    path(record).filter(|p| p.exists())
+}
+
+/// Photos and videos are the files worth a thumbnail
+fn is_candidate(record: &FileRecord) -> bool {
+    matches!(FileKind::from_filename(&record.filename), FileKind::Image | FileKind::Video)
+}
+
+/// Largest thumbnail Telegram keeps for `document`, as its size type and
+/// inline bytes when they came along with the message
+fn largest_thumb(document: &tl::types::Document) -> Option<(String, Option<Vec<u8>>)> {
+    document.thumbs.as_ref()?
+        .iter()
+        .filter_map(|size| match size {
+            tl::enums::PhotoSize::Size(s) => Some((s.w * s.h, s.r#type.clone(), None)),
+            tl::enums::PhotoSize::PhotoCachedSize(s) => Some((s.w * s.h, s.r#type.clone(), Some(s.bytes.clone()))),
+            tl::enums::PhotoSize::Progressive(s) => Some((s.w * s.h, s.r#type.clone(), None)),
+            _ => None,
+        })
+        .max_by_key(|(area, _, _)| *area)
+        .map(|(_, kind, bytes)| (kind, bytes))
+}
+
+/// Download the thumbnail of `record` into the cache; false when the
+/// document has none
+async fn fetch(client: &Client, record: &FileRecord) -> Result<bool> {
+    let (Some(message_id), Some(dest)) = (record.message_id, path(record)) else {
+        return Ok(false);
+    };
+    let chat = record_chat(client, Some(record)).await?;
+    api_usage::request("messages.getMessages");
+    let message = client.get_messages_by_id(chat, &[message_id]).await?
+        .pop()
+        .flatten()
+        .with_context(|| format!("Message {} no longer exists", message_id))?;
+    let Some(Media::Document(document)) = message.media() else {
+        return Ok(false);
+    };
+    let Some(tl::enums::Document::Document(raw)) = &document.raw.document else {
+        return Ok(false);
+    };
+    let Some((thumb_size, inline)) = largest_thumb(raw) else {
+        return Ok(false);
+    };
+    
+    let bytes = match inline {
+        Some(bytes) => bytes,
+        None => {
+            // Thumbnails are far smaller than one chunk
+            let location = tl::types::InputDocumentFileLocation {
+                id: raw.id,
+                access_hash: raw.access_hash,
+                file_reference: raw.file_reference.clone(),
+                thumb_size,
+            }.into();
+            fetch_chunk(client.clone(), location, 0).await?.1
+        }
+    };
+    tokio::fs::create_dir_all(THUMB_DIR).await?;
+    tokio::fs::write(&dest, bytes).await?;
+    Ok(true)
+}
+
+/// Fetch missing thumbnails of photos and videos whenever `wanted` is
+/// notified, e.g. when the grid view is shown, then reload the file list
+pub async fn run_fetcher(
+    client: Arc<Mutex<Option<Client>>>,
+    db: Arc<Database>,
+    wanted: Arc<Notify>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    // Documents without a thumbnail, or whose fetch failed; not tried again
+    let mut skipped: HashSet<(i64, i32)> = HashSet::new();
+    loop {
+        wanted.notified().await;
+        let Some(tg_client) = client.lock().unwrap().clone() else {
+            continue;
+        };
+        
+        let missing: Vec<FileRecord> = db.all_records()
+            .into_iter()
+            .rev()
+            .filter(|r| is_candidate(r) && cached(r).is_none())
+            .filter(|r| key(r).map_or(false, |k| !skipped.contains(&k)))
+            .take(BATCH)
+            .collect();
+        let mut fetched = 0;
+        for record in &missing {
+            match fetch(&tg_client, record).await {
+                Ok(true) => fetched += 1,
+                Ok(false) => skipped.extend(key(record)),
+                Err(e) => {
+                    warn!("Failed to fetch the thumbnail of {}: {:?}", record.filename, e);
+                    skipped.extend(key(record));
+                }
+            }
+        }
+        if fetched > 0 {
+            info!("Fetched {} thumbnails", fetched);
+            refresh_file_list(&ui_handle, &db);
+        }
+        // Carry on with the next batch
+        if missing.len() == BATCH {
+            wanted.notify_one();
+        }
+    }
+}
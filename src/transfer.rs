@@ -0,0 +1,666 @@
+use grammers_mtsender::InvocationError;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::database::{ArchiveMember, RecordKey, SplitPiece};
+use crate::dedup::Chunk;
+use crate::photo::SendAs;
+
+/// Wait used when Telegram reports a flood wait without a duration
+const DEFAULT_FLOOD_WAIT_SECS: u32 = 30;
+
+/// Per-upload choices made when the file was queued
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadOptions {
+    /// Remove the local file once uploaded and recorded (e.g. a pasted image
+    /// we wrote ourselves)
+    pub delete_after: bool,
+    /// Copy the stored file's t.me link to the clipboard when done, when it
+    /// lives in a channel or supergroup
+    pub copy_reference: bool,
+    /// Virtual folder to record the file under
+    pub folder: String,
+    /// Folder upload this file belongs to, for overall progress
+    pub batch: Option<u64>,
+    /// Contents of the file when it is an archive we packed, recorded for browsing
+    pub archive_members: Vec<ArchiveMember>,
+    /// Free-form labels stored with the file
+    pub tags: Vec<String>,
+    /// Cut into content-defined pieces whatever the deduplication setting, so
+    /// a version uploaded later only sends the pieces that changed
+    pub delta: bool,
+    /// Stored file this upload takes the place of; deleted once the upload
+    /// is recorded, kept when recording fails
+    pub replaces: Option<RecordKey>,
+    /// Stored file this upload is a new version of. Its pieces are reused
+    /// where the content didn't change, and it is deleted once the new
+    /// version is recorded; kept when recording fails
+    pub previous: Option<RecordKey>,
+    /// How images are sent; `None` follows the setting
+    pub send_images_as: Option<SendAs>,
+    /// Whether large images are sent as smaller copies; `None` follows the setting
+    pub downscale: Option<bool>,
+}
+
+/// Overall progress of a multi-file transfer (folder upload, batch download)
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub name: String,
+    pub total_files: usize,
+    pub done_files: usize,
+    pub total_bytes: u64,
+    pub done_bytes: u64,
+}
+
+/// What a queued transfer does
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferKind {
+    Upload {
+        path: PathBuf,
+        #[serde(default)]
+        options: UploadOptions,
+    },
+    Download {
+        message_id: i32,
+        /// Chat holding the message; `None` for Saved Messages
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chat: Option<i64>,
+        filename: String,
+        dest: PathBuf,
+        /// Batch download this file belongs to, for overall progress
+        #[serde(default)]
+        batch: Option<u64>,
+        /// Open with the default app once downloaded
+        #[serde(default)]
+        open_after: bool,
+    },
+    /// Upload photos and videos sent together as one Telegram album
+    Album {
+        paths: Vec<PathBuf>,
+        #[serde(default)]
+        options: UploadOptions,
+    },
+    /// Download a stored file and upload it again encrypted with the current key
+    Reencrypt {
+        message_id: i32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chat: Option<i64>,
+        filename: String,
+    },
+}
+
+impl TransferKind {
+    /// Human readable name of the file being transferred
+    pub fn display_name(&self) -> String {
+        match self {
+            TransferKind::Upload { path, .. } => path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("Unknown")
+                .to_string(),
+            TransferKind::Album { paths, .. } => {
+                let first = paths.first()
+                    .and_then(|p| p.file_name())
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("Unknown");
+                format!("{} (+{})", first, paths.len().saturating_sub(1))
+            }
+            TransferKind::Download { filename, .. }
+            | TransferKind::Reencrypt { filename, .. } => filename.clone(),
+        }
+    }
+    
+    /// Stored file the transfer reads, for downloads and re-encryption
+    pub fn record_key(&self) -> Option<RecordKey> {
+        match self {
+            TransferKind::Download { message_id, chat, .. }
+            | TransferKind::Reencrypt { message_id, chat, .. } => Some(RecordKey { chat: *chat, message_id: *message_id }),
+            TransferKind::Upload { .. } | TransferKind::Album { .. } => None,
+        }
+    }
+    
+    pub fn is_upload(&self) -> bool {
+        matches!(self, TransferKind::Upload { .. } | TransferKind::Album { .. } | TransferKind::Reencrypt { .. })
+    }
+    
+    /// How many files the transfer moves
+    pub fn file_count(&self) -> usize {
+        match self {
+            TransferKind::Album { paths, .. } => paths.len(),
+            _ => 1,
+        }
+    }
+    
+    /// Multi-file transfer this one is part of
+    pub fn batch(&self) -> Option<u64> {
+        match self {
+            TransferKind::Upload { options, .. }
+            | TransferKind::Album { options, .. } => options.batch,
+            TransferKind::Download { batch, .. } => *batch,
+            TransferKind::Reencrypt { .. } => None,
+        }
+    }
+}
+
+/// How far a transfer got, so it can pick up where it stopped
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResumeState {
+    /// Telegram-side id of the partially uploaded file (uploads only)
+    pub upload_file_id: i64,
+    /// Parts (uploads) or chunks (downloads) of the current piece already transferred
+    pub parts_done: u32,
+    /// Pieces of a split file already transferred
+    #[serde(default)]
+    pub pieces_done: u32,
+    /// Messages holding the pieces sent so far (split uploads only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub piece_messages: Vec<SplitPiece>,
+    /// Content-defined pieces of a deduplicated upload, fixed when it starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<Chunk>,
+    /// Whether the upload sends a zstd copy of the file
+    #[serde(default)]
+    pub compressed: bool,
+    /// Whether the upload sends a smaller copy of the image
+    #[serde(default)]
+    pub downscaled: bool,
+    /// Key of the encrypted copy being sent, if the upload is encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+impl ResumeState {
+    /// Start the current piece over, keeping pieces already sent
+    pub fn restart_piece(&mut self) {
+        self.upload_file_id = 0;
+        self.parts_done = 0;
+    }
+}
+
+/// A single queued transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferJob {
+    pub id: u64,
+    pub kind: TransferKind,
+    #[serde(default)]
+    pub resume: ResumeState,
+    /// Automatic retries made after failures so far
+    #[serde(default)]
+    pub attempts: u32,
+    /// Not run again before this, while waiting to retry
+    #[serde(skip)]
+    pub retry_at: Option<Instant>,
+}
+
+/// Where a job currently sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferState {
+    Active,
+    Queued,
+    Paused,
+}
+
+/// Returned by a transfer that was stopped through its cancellation token
+#[derive(Debug, thiserror::Error)]
+#[error("Transfer cancelled")]
+pub struct Cancelled;
+
+/// Returned by a transfer that stopped at a chunk boundary because it was paused
+#[derive(Debug, thiserror::Error)]
+#[error("Transfer paused")]
+pub struct Paused;
+
+/// Signals the running transfer listens to
+#[derive(Debug, Clone, Default)]
+pub struct TransferControl {
+    cancel: CancellationToken,
+    pause: CancellationToken,
+}
+
+impl TransferControl {
+    /// Called between chunks: fails with `Cancelled` or `Paused` if either was requested
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.cancel.is_cancelled() {
+            Err(Cancelled.into())
+        } else if self.pause.is_cancelled() {
+            Err(Paused.into())
+        } else {
+            Ok(())
+        }
+    }
+    
+    /// Resolves once the transfer is cancelled, to abort an in-flight request
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+}
+
+/// The job the worker is currently running
+struct ActiveTransfer {
+    job: TransferJob,
+    control: TransferControl,
+}
+
+/// FIFO queue of pending transfers, drained by a single worker task.
+///
+/// Jobs can be queued while offline; they are held until `set_online(true)`.
+/// Paused jobs are kept aside (and on disk) until resumed. Running and pending
+/// jobs are journaled to disk as well, so the ones a quit or crash left
+/// unfinished can be resumed in the next session. A queue made with
+/// `in_memory` keeps neither.
+pub struct TransferQueue {
+    jobs: Mutex<VecDeque<TransferJob>>,
+    active: Mutex<Option<ActiveTransfer>>,
+    paused: Mutex<Vec<TransferJob>>,
+    /// `None` for a queue kept in memory only
+    paused_file: Option<PathBuf>,
+    /// Unfinished jobs of the previous session, until resumed or discarded
+    restored: Mutex<Vec<TransferJob>>,
+    journal_file: Option<PathBuf>,
+    batches: Mutex<HashMap<u64, Batch>>,
+    paused_until: Mutex<Option<Instant>>,
+    online: AtomicBool,
+    wake: Notify,
+    next_id: AtomicU64,
+}
+
+impl TransferQueue {
+    /// Create the queue, restoring jobs paused in a previous session from
+    /// `paused_file` and those it left unfinished from `journal_file`
+    pub fn new(paused_file: impl AsRef<Path>, journal_file: impl AsRef<Path>) -> Self {
+        Self::with_files(Some(paused_file.as_ref().to_path_buf()), Some(journal_file.as_ref().to_path_buf()))
+    }
+    
+    /// Create a queue that keeps nothing on disk, for CLI commands and
+    /// servers running beside the desktop app, whose files they must not
+    /// overwrite with their own jobs
+    pub fn in_memory() -> Self {
+        Self::with_files(None, None)
+    }
+    
+    fn with_files(paused_file: Option<PathBuf>, journal_file: Option<PathBuf>) -> Self {
+        let load = |path: &Option<PathBuf>| -> Vec<TransferJob> {
+            path.as_ref()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default()
+        };
+        let paused = load(&paused_file);
+        let restored = load(&journal_file);
+        let next_id = paused.iter().chain(&restored).map(|job| job.id).max().unwrap_or(0) + 1;
+        
+        Self {
+            jobs: Mutex::new(VecDeque::new()),
+            active: Mutex::new(None),
+            paused: Mutex::new(paused),
+            paused_file,
+            restored: Mutex::new(restored),
+            journal_file,
+            batches: Mutex::new(HashMap::new()),
+            paused_until: Mutex::new(None),
+            online: AtomicBool::new(false),
+            wake: Notify::new(),
+            next_id: AtomicU64::new(next_id),
+        }
+    }
+    
+    /// Queue a plain upload of `path`
+    pub fn push_upload(&self, path: PathBuf) -> u64 {
+        self.push(TransferKind::Upload {
+            path,
+            options: UploadOptions::default(),
+        })
+    }
+    
+    /// Queue a transfer and return its job id
+    pub fn push(&self, kind: TransferKind) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.jobs.lock().unwrap().push_back(TransferJob {
+            id,
+            kind,
+            resume: ResumeState::default(),
+            attempts: 0,
+            retry_at: None,
+        });
+        self.save_journal();
+        self.wake.notify_one();
+        id
+    }
+    
+    /// Start tracking a multi-file transfer of `total_files` files; returns its batch id
+    pub fn start_batch(&self, name: String, total_files: usize, total_bytes: u64) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.batches.lock().unwrap().insert(id, Batch {
+            name,
+            total_files,
+            done_files: 0,
+            total_bytes,
+            done_bytes: 0,
+        });
+        id
+    }
+    
+    /// Count one file of a batch as finished (whatever the outcome); the
+    /// batch is dropped once all of its files are accounted for
+    pub fn batch_files_done(&self, batch: u64, files: usize, bytes: u64) {
+        let mut batches = self.batches.lock().unwrap();
+        if let Some(progress) = batches.get_mut(&batch) {
+            progress.done_files += files;
+            progress.done_bytes += bytes;
+            if progress.done_files >= progress.total_files {
+                batches.remove(&batch);
+            }
+        }
+    }
+    
+    /// Batches still in progress
+    pub fn batches(&self) -> Vec<Batch> {
+        self.batches.lock().unwrap().values().cloned().collect()
+    }
+    
+    /// Put a job back at the head of the queue so it runs next
+    pub fn requeue_front(&self, job: TransferJob) {
+        self.jobs.lock().unwrap().push_front(job);
+        self.save_journal();
+        self.wake.notify_one();
+    }
+    
+    /// Put a failed job back at the end of the queue, to run again once
+    /// `delay` has passed
+    pub fn retry_later(&self, mut job: TransferJob, delay: Duration) {
+        job.attempts += 1;
+        job.retry_at = Some(Instant::now() + delay);
+        self.jobs.lock().unwrap().push_back(job);
+        self.save_journal();
+        self.wake.notify_one();
+    }
+    
+    /// Mark whether a connected client is available to run jobs
+    pub fn set_online(&self, online: bool) {
+        self.online.store(online, Ordering::SeqCst);
+        if online {
+            self.wake.notify_one();
+        }
+    }
+    
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+    
+    pub fn pending(&self) -> usize {
+        self.jobs.lock().unwrap().len()
+    }
+    
+    /// Active job (if any), then pending ones in run order, then paused ones
+    pub fn snapshot(&self) -> Vec<(TransferJob, TransferState)> {
+        let active = self.active.lock().unwrap()
+            .as_ref()
+            .map(|a| (a.job.clone(), TransferState::Active));
+        let pending = self.jobs.lock().unwrap()
+            .iter()
+            .map(|job| (job.clone(), TransferState::Queued))
+            .collect::<Vec<_>>();
+        let paused = self.paused.lock().unwrap()
+            .iter()
+            .map(|job| (job.clone(), TransferState::Paused))
+            .collect::<Vec<_>>();
+        
+        active.into_iter().chain(pending).chain(paused).collect()
+    }
+    
+    /// Mark `job` as running and return the signals that stop it
+    pub fn start(&self, job: &TransferJob) -> TransferControl {
+        let control = TransferControl::default();
+        *self.active.lock().unwrap() = Some(ActiveTransfer {
+            job: job.clone(),
+            control: control.clone(),
+        });
+        self.save_journal();
+        control
+    }
+    
+    /// Clear the running job once the worker is done with it
+    pub fn finish(&self, id: u64) {
+        let mut active = self.active.lock().unwrap();
+        if active.as_ref().is_some_and(|a| a.job.id == id) {
+            *active = None;
+        }
+        drop(active);
+        self.save_journal();
+    }
+    
+    /// Cancel a job: the running one is signalled to stop, a pending or paused
+    /// one is removed and returned so the caller can clean up after it.
+    pub fn cancel(&self, id: u64) -> Option<TransferJob> {
+        if let Some(active) = self.active.lock().unwrap().as_ref() {
+            if active.job.id == id {
+                active.control.cancel.cancel();
+                return None;
+            }
+        }
+        
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(pos) = jobs.iter().position(|job| job.id == id) {
+            let job = jobs.remove(pos);
+            drop(jobs);
+            self.save_journal();
+            return job;
+        }
+        drop(jobs);
+        
+        let mut paused = self.paused.lock().unwrap();
+        let pos = paused.iter().position(|job| job.id == id)?;
+        let job = paused.remove(pos);
+        self.save_paused(&paused);
+        Some(job)
+    }
+    
+    /// Pause a job: the running one stops at the next chunk boundary, a pending
+    /// one is moved aside straight away
+    pub fn pause(&self, id: u64) {
+        if let Some(active) = self.active.lock().unwrap().as_ref() {
+            if active.job.id == id {
+                active.control.pause.cancel();
+                return;
+            }
+        }
+        
+        let job = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let Some(pos) = jobs.iter().position(|job| job.id == id) else {
+                return;
+            };
+            jobs.remove(pos)
+        };
+        if let Some(job) = job {
+            self.park(job);
+            self.save_journal();
+        }
+    }
+    
+    /// Pause the running job and move every pending one aside
+    pub fn pause_all(&self) {
+        if let Some(active) = self.active.lock().unwrap().as_ref() {
+            active.control.pause.cancel();
+        }
+        
+        let pending: Vec<TransferJob> = self.jobs.lock().unwrap().drain(..).collect();
+        if !pending.is_empty() {
+            let mut paused = self.paused.lock().unwrap();
+            paused.extend(pending);
+            self.save_paused(&paused);
+            drop(paused);
+            self.save_journal();
+        }
+    }
+    
+    /// Store a job that stopped because it was paused, with its progress
+    pub fn park(&self, job: TransferJob) {
+        let mut paused = self.paused.lock().unwrap();
+        paused.push(job);
+        self.save_paused(&paused);
+    }
+    
+    /// Move a paused job back to the end of the queue
+    pub fn resume(&self, id: u64) {
+        let mut paused = self.paused.lock().unwrap();
+        let Some(pos) = paused.iter().position(|job| job.id == id) else {
+            return;
+        };
+        let mut job = paused.remove(pos);
+        self.save_paused(&paused);
+        drop(paused);
+        
+        // Resuming by hand skips the rest of a retry wait
+        job.retry_at = None;
+        self.jobs.lock().unwrap().push_back(job);
+        self.save_journal();
+        self.wake.notify_one();
+    }
+    
+    /// Number of jobs the previous session left unfinished
+    pub fn restored(&self) -> usize {
+        self.restored.lock().unwrap().len()
+    }
+    
+    /// Jobs the previous session left unfinished, still waiting
+    pub fn restored_jobs(&self) -> Vec<TransferJob> {
+        self.restored.lock().unwrap().clone()
+    }
+    
+    /// Queue the jobs the previous session left unfinished
+    pub fn resume_restored(&self) {
+        let restored: Vec<TransferJob> = self.restored.lock().unwrap().drain(..).collect();
+        self.jobs.lock().unwrap().extend(restored);
+        self.save_journal();
+        self.wake.notify_one();
+    }
+    
+    /// Drop the jobs the previous session left unfinished, returning them so
+    /// the caller can clean up after them
+    pub fn discard_restored(&self) -> Vec<TransferJob> {
+        let restored: Vec<TransferJob> = self.restored.lock().unwrap().drain(..).collect();
+        self.save_journal();
+        restored
+    }
+    
+    /// Write the unfinished jobs (restored, running and pending) to the journal
+    fn save_journal(&self) {
+        let Some(journal_file) = &self.journal_file else {
+            return;
+        };
+        let mut unfinished: Vec<TransferJob> = self.restored.lock().unwrap().clone();
+        unfinished.extend(self.active.lock().unwrap().as_ref().map(|a| a.job.clone()));
+        unfinished.extend(self.jobs.lock().unwrap().iter().cloned());
+        let result = serde_json::to_string_pretty(&unfinished)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(journal_file, json)?));
+        if let Err(e) = result {
+            warn!("Failed to save the transfer journal: {:?}", e);
+        }
+    }
+    
+    fn save_paused(&self, paused: &[TransferJob]) {
+        let Some(paused_file) = &self.paused_file else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(paused)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(paused_file, json)?));
+        if let Err(e) = result {
+            warn!("Failed to save paused transfers: {:?}", e);
+        }
+    }
+    
+    /// Hold the whole queue until `duration` has passed
+    pub fn pause_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut paused = self.paused_until.lock().unwrap();
+        if paused.map_or(true, |current| current < until) {
+            *paused = Some(until);
+        }
+    }
+    
+    /// Time left before the queue may run again, if it is paused
+    pub fn pause_remaining(&self) -> Option<Duration> {
+        let mut paused = self.paused_until.lock().unwrap();
+        match *paused {
+            Some(until) if until > Instant::now() => Some(until - Instant::now()),
+            _ => {
+                *paused = None;
+                None
+            }
+        }
+    }
+    
+    /// Wait for the next job while online
+    pub async fn next(&self) -> TransferJob {
+        loop {
+            // Jobs waiting to retry are passed over until their time comes
+            let mut next_retry = None;
+            if self.is_online() {
+                let mut jobs = self.jobs.lock().unwrap();
+                let now = Instant::now();
+                if let Some(pos) = jobs.iter().position(|job| job.retry_at.map_or(true, |at| at <= now)) {
+                    return jobs.remove(pos).unwrap();
+                }
+                next_retry = jobs.iter().filter_map(|job| job.retry_at).min();
+            }
+            match next_retry {
+                Some(at) => {
+                    tokio::select! {
+                        _ = self.wake.notified() => {}
+                        _ = tokio::time::sleep_until(at.into()) => {}
+                    }
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+}
+
+/// Find the Telegram invocation error behind `err`, if any
+pub fn invocation_error(err: &anyhow::Error) -> Option<&InvocationError> {
+    err.chain().find_map(|cause| {
+        // Upload errors reach us wrapped in `io::Error`
+        cause.downcast_ref::<InvocationError>().or_else(|| {
+            cause
+                .downcast_ref::<std::io::Error>()?
+                .get_ref()?
+                .downcast_ref::<InvocationError>()
+        })
+    })
+}
+
+/// Seconds Telegram asked us to back off for, if `err` is a FLOOD_WAIT
+pub fn flood_wait_seconds(err: &anyhow::Error) -> Option<u32> {
+    match invocation_error(err)? {
+        InvocationError::Rpc(rpc) if rpc.name == "FLOOD_WAIT" || rpc.name == "FLOOD_PREMIUM_WAIT" => {
+            Some(rpc.value.unwrap_or(DEFAULT_FLOOD_WAIT_SECS))
+        }
+        _ => None,
+    }
+}
+
+/// Whether Telegram forgot the parts of a resumed upload, so it must restart from zero
+pub fn is_missing_parts(err: &anyhow::Error) -> bool {
+    matches!(
+        invocation_error(err),
+        Some(InvocationError::Rpc(rpc)) if rpc.name.starts_with("FILE_PART") && rpc.name.ends_with("MISSING")
+    )
+}
+
+/// Whether `err` means the connection itself broke, rather than Telegram
+/// rejecting the request. Local I/O errors (a deleted source file, a full
+/// disk) are not: waiting for a reconnect would never fix them
+pub fn is_connection_error(err: &anyhow::Error) -> bool {
+    matches!(invocation_error(err), Some(invocation) if !matches!(invocation, InvocationError::Rpc(_)))
+}
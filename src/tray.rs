@@ -0,0 +1,106 @@
+use anyhow::Result;
+use tray_icon::menu::{Menu, MenuEvent, MenuId, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+
+const ICON_SIZE: u32 = 32;
+
+/// Something the user picked from the tray
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayAction {
+    OpenWindow,
+    PauseAll,
+    Quit,
+}
+
+/// System tray icon with its context menu.
+///
+/// Must live on the UI thread; events are collected with `poll`.
+pub struct Tray {
+    icon: TrayIcon,
+    open_id: MenuId,
+    pause_id: MenuId,
+    quit_id: MenuId,
+}
+
+impl Tray {
+    pub fn new() -> Result<Self> {
+        // The Linux backend runs on GTK, which must be initialised before use
+        #[cfg(target_os = "linux")]
+        gtk::init()?;
+        
+        let open = MenuItem::new(tr!("Open Window"), true, None);
+        let pause = MenuItem::new(tr!("Pause All Transfers"), true, None);
+        let quit = MenuItem::new(tr!("Quit"), true, None);
+        
+        let menu = Menu::new();
+        menu.append_items(&[&open, &pause, &PredefinedMenuItem::separator(), &quit])?;
+        
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip("Telegram Cloud Storage")
+            .with_icon(app_icon()?)
+            .build()?;
+        
+        Ok(Self {
+            icon,
+            open_id: open.id().clone(),
+            pause_id: pause.id().clone(),
+            quit_id: quit.id().clone(),
+        })
+    }
+    
+    /// Drain pending tray events, returning the most recent action
+    pub fn poll(&self) -> Option<TrayAction> {
+        #[cfg(target_os = "linux")]
+        while gtk::events_pending() {
+            gtk::main_iteration_do(false);
+        }
+        
+        let mut action = None;
+        
+        while let Ok(event) = MenuEvent::receiver().try_recv() {
+            if event.id == self.open_id {
+                action = Some(TrayAction::OpenWindow);
+            } else if event.id == self.pause_id {
+                action = Some(TrayAction::PauseAll);
+            } else if event.id == self.quit_id {
+                action = Some(TrayAction::Quit);
+            }
+        }
+        
+        while let Ok(event) = TrayIconEvent::receiver().try_recv() {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                action = Some(TrayAction::OpenWindow);
+            }
+        }
+        
+        action
+    }
+    
+    /// Show the current transfer state in the icon's tooltip
+    pub fn set_status(&self, status: &str) {
+        let _ = self.icon.set_tooltip(Some(format!("Telegram Cloud Storage\n{}", status)));
+    }
+}
+
+/// Telegram-blue disc drawn at runtime, so no image asset is needed
+fn app_icon() -> Result<Icon> {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0 - 1.0;
+    
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            let alpha = if distance <= radius { 255 } else { 0 };
+            rgba.extend_from_slice(&[0x2A, 0xAB, 0xEE, alpha]);
+        }
+    }
+    
+    Ok(Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)?)
+}
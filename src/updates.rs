@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::config::Settings;
+use crate::AppWindow;
+
+/// Latest published release of the project
+const RELEASE_FEED: &str = "https://api.github.com/repos/DarkFeed2005/TeleDrive-RS/releases/latest";
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A release newer than the running build
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub version: String,
+    /// Release page with the downloads
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    html_url: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+}
+
+/// Ask the release feed whether there is something newer than this build
+pub async fn check() -> Result<Option<Release>> {
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("TeleDrive/", env!("CARGO_PKG_VERSION")))
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let release: GithubRelease = http.get(RELEASE_FEED)
+        .send().await?
+        .error_for_status()?
+        .json().await
+        .context("Unexpected release feed")?;
+    if release.draft || release.prerelease {
+        return Ok(None);
+    }
+    
+    let latest = semver::Version::parse(release.tag_name.trim_start_matches('v'))
+        .with_context(|| format!("Unexpected release tag {:?}", release.tag_name))?;
+    let current = semver::Version::parse(env!("CARGO_PKG_VERSION"))?;
+    Ok((latest > current).then(|| Release { version: latest.to_string(), url: release.html_url }))
+}
+
+/// Check at startup and then daily while enabled, showing a banner for
+/// releases the user has not dismissed. `check_now` forces a check
+pub async fn run_update_checker(
+    settings: Arc<Mutex<Settings>>,
+    check_now: Arc<Notify>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    loop {
+        let (enabled, dismissed) = {
+            let settings = settings.lock().unwrap();
+            (settings.check_updates, settings.dismissed_update.clone())
+        };
+        if enabled {
+            match check().await {
+                Ok(Some(release)) if release.version != dismissed => {
+                    info!("TeleDrive {} is available", release.version);
+                    let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+                        ui.set_update_version(release.version.into());
+                        ui.set_update_url(release.url.into());
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Update check failed: {:?}", e),
+            }
+        }
+        
+        tokio::select! {
+            _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+            _ = check_now.notified() => {}
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use grammers_client::Client;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::api_usage;
+use crate::config::Settings;
+use crate::database::{Database, FileRecord};
+use crate::transfer::{ResumeState, TransferControl};
+use crate::{caption, chunked, download_file_from_telegram, errors, paths, record_chat, refresh_file_list, set_status, AppWindow};
+
+/// Left alone for a while after startup, so the first transfers get the connection
+const FIRST_CHECK_DELAY: Duration = Duration::from_secs(10 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Files re-checked each time, those checked longest ago first
+const SAMPLE_SIZE: usize = 5;
+/// Files up to this size are downloaded whole and hashed; larger ones only
+/// have the first chunk of each message fetched
+const FULL_CHECK_LIMIT: u64 = 64 * 1024 * 1024;
+/// Folder in the cache the downloads being hashed go to
+const VERIFY_DIR: &str = "verify";
+
+/// What re-checking a stored file found
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Downloaded and its hash matches
+    Intact,
+    /// Every message still starts with readable content; too large, or
+    /// without a hash, to compare in full
+    Reachable,
+    /// One of its messages was deleted
+    Gone,
+    /// Downloaded, but it isn't what was uploaded
+    Corrupt(String),
+}
+
+/// Fetch `record` back from Telegram and compare it with what was uploaded
+pub async fn verify(client: &Client, db: &Database, record: &FileRecord) -> Result<Outcome> {
+    let key = record.key().context("Record has no message")?;
+    let chat = record_chat(client, Some(record)).await?;
+    let ids = record.message_ids();
+    api_usage::request("messages.getMessages");
+    let messages = client.get_messages_by_id(chat, &ids).await?;
+    let mut medias = Vec::with_capacity(ids.len());
+    for message in messages {
+        let Some(media) = message.and_then(|m| m.media()) else {
+            return Ok(Outcome::Gone);
+        };
+        medias.push(media);
+    }
+    
+    // Photos are Telegram's JPEG, with nothing to compare against
+    let hashable = record.sha256.is_some() && !record.photo;
+    if !hashable || record.file_size > FULL_CHECK_LIMIT {
+        for media in &medias {
+            let location = chunked::file_location(media).context("Message has no downloadable file")?;
+            let (_, bytes) = chunked::fetch_chunk(client.clone(), location, 0).await?;
+            if bytes.is_empty() && chunked::media_size(media).unwrap_or(0) > 0 {
+                return Ok(Outcome::Corrupt("Its first chunk came back empty".to_string()));
+            }
+        }
+        return Ok(Outcome::Reachable);
+    }
+    
+    let dir = paths::cache_dir(VERIFY_DIR);
+    tokio::fs::create_dir_all(&dir).await?;
+    let dest = dir.join(key.to_string().replace(':', "-"));
+    let downloaded = download_file_from_telegram(
+        client,
+        db,
+        key,
+        &dest,
+        &mut ResumeState::default(),
+        &TransferControl::default(),
+        slint::Weak::default(),
+    ).await;
+    let hash = match downloaded {
+        Ok(()) => {
+            let path = dest.clone();
+            tokio::task::spawn_blocking(move || caption::hash_file(&path)).await?
+        }
+        Err(e) => Err(e),
+    };
+    let _ = tokio::fs::remove_file(&dest).await;
+    
+    let hash = match hash {
+        Ok(hash) => hash,
+        // Fewer bytes came back than Telegram says it holds
+        Err(e) if errors::classify(&e) == errors::ErrorKind::Integrity => return Ok(Outcome::Corrupt(format!("{:#}", e))),
+        Err(e) => return Err(e),
+    };
+    match record.sha256.as_deref() {
+        Some(expected) if expected != hash => Ok(Outcome::Corrupt(format!("SHA-256 is {}, expected {}", hash, expected))),
+        _ => Ok(Outcome::Intact),
+    }
+}
+
+/// While enabled, re-check a few stored files every few hours and flag the
+/// ones whose messages are gone or whose content no longer matches, so it's
+/// known before the file is needed
+pub async fn run_verifier(
+    client: Arc<Mutex<Option<Client>>>,
+    db: Arc<Database>,
+    settings: Arc<Mutex<Settings>>,
+    ui_handle: slint::Weak<AppWindow>,
+) {
+    tokio::time::sleep(FIRST_CHECK_DELAY).await;
+    loop {
+        let enabled = settings.lock().unwrap().verify_in_background;
+        let tg_client = client.lock().unwrap().clone();
+        if let (true, Some(tg_client)) = (enabled, tg_client) {
+            let flagged = check_sample(&tg_client, &db).await;
+            if flagged > 0 {
+                set_status(&ui_handle, tr!("{count} stored file(s) failed the integrity check", count = flagged));
+                refresh_file_list(&ui_handle, &db);
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Verify the files checked longest ago, returning how many were flagged
+async fn check_sample(client: &Client, db: &Database) -> usize {
+    let mut candidates: Vec<FileRecord> = db.all_records()
+        .into_iter()
+        .filter(|r| r.message_id.is_some() && !r.missing)
+        .collect();
+    candidates.sort_by_key(|r| r.verified);
+    
+    let mut flagged = 0;
+    for record in candidates.into_iter().take(SAMPLE_SIZE) {
+        let Some(key) = record.key() else { continue };
+        let outcome = match verify(client, db, &record).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                // Network trouble says nothing about the file; try it next time
+                warn!("Failed to verify {}: {:?}", record.filename, e);
+                continue;
+            }
+        };
+        info!("Verified {}: {:?}", record.path(), outcome);
+        if matches!(outcome, Outcome::Gone | Outcome::Corrupt(_)) {
+            flagged += 1;
+        }
+        let now = Local::now();
+        let result = db.update_records(&[key], |r| {
+            r.verified = Some(now);
+            match &outcome {
+                Outcome::Intact | Outcome::Reachable => r.corrupt = false,
+                Outcome::Gone => r.missing = true,
+                Outcome::Corrupt(_) => r.corrupt = true,
+            }
+        }).await;
+        if let Err(e) = result {
+            warn!("Failed to record the check of {}: {:?}", record.filename, e);
+        }
+    }
+    flagged
+}
@@ -0,0 +1,84 @@
+use anyhow::{bail, Context, Result};
+use grammers_client::types::Uploaded;
+use grammers_client::Client;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{debug, info, warn};
+
+use crate::{api_usage, paths};
+
+/// Folder in the cache frames are written to until they are uploaded
+const FRAME_DIR: &str = "video-thumbnails";
+/// Telegram keeps document thumbnails at most this wide or high
+const MAX_SIDE: u32 = 320;
+/// Frames the `thumbnail` filter picks the most typical one from
+const CANDIDATE_FRAMES: u32 = 60;
+
+/// Set once ffmpeg turned out to be missing, so it isn't looked for again
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// ffmpeg from `FFMPEG`, or the one on the PATH
+fn ffmpeg() -> String {
+    std::env::var("FFMPEG").unwrap_or_else(|_| "ffmpeg".to_string())
+}
+
+/// Write a representative frame of the video at `path`, `duration_secs`
+/// long if known, as a JPEG thumbnail. `None` when ffmpeg isn't installed or
+/// can't read the video. Blocking, run it off the async runtime
+pub fn generate(path: &Path, duration_secs: Option<f64>) -> Option<PathBuf> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    match extract_frame(path, duration_secs) {
+        Ok(frame) => Some(frame),
+        Err(e) => {
+            debug!("No thumbnail for {:?}: {:?}", path, e);
+            None
+        }
+    }
+}
+
+fn extract_frame(path: &Path, duration_secs: Option<f64>) -> Result<PathBuf> {
+    let dir = paths::cache_dir(FRAME_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let dest = dir.join(format!("{:032x}.jpg", rand::random::<u128>()));
+    // Past opening titles and fades to black, without going far into long videos
+    let start = duration_secs.map_or(0.0, |d| (d * 0.1).min(30.0));
+    let filter = format!(
+        "thumbnail={},scale='if(gt(iw,ih),{},-2)':'if(gt(iw,ih),-2,{})'",
+        CANDIDATE_FRAMES, MAX_SIDE, MAX_SIDE,
+    );
+    let status = Command::new(ffmpeg())
+        .args(["-hide_banner", "-loglevel", "error", "-ss", &format!("{:.2}", start), "-i"])
+        .arg(path)
+        .args(["-vf", &filter, "-frames:v", "1", "-q:v", "4", "-y"])
+        .arg(&dest)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let status = match status {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("ffmpeg not found; videos are uploaded without a thumbnail");
+            UNAVAILABLE.store(true, Ordering::Relaxed);
+            bail!("ffmpeg not found");
+        }
+        Err(e) => return Err(e).context("Failed to run ffmpeg"),
+    };
+    if !status.success() || !dest.exists() {
+        let _ = std::fs::remove_file(&dest);
+        bail!("ffmpeg exited with {}", status);
+    }
+    Ok(dest)
+}
+
+/// Upload the frame at `frame` for use as a thumbnail, deleting it after.
+/// A failure only costs the thumbnail
+pub async fn upload(client: &Client, frame: &Path) -> Option<Uploaded> {
+    api_usage::request("upload.saveFilePart");
+    let uploaded = client.upload_file(frame).await;
+    let _ = tokio::fs::remove_file(frame).await;
+    uploaded.map_err(|e| warn!("Failed to upload the thumbnail {:?}: {:?}", frame, e)).ok()
+}
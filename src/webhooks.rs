@@ -0,0 +1,61 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::hooks::HookFile;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Something that happened, sent as the JSON body of a POST
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    UploadComplete { file: HookFile },
+    DownloadComplete { file: HookFile },
+    SyncError { path: String, error: String },
+    /// Sent from the settings to check the URL
+    Test,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    app: &'static str,
+    version: &'static str,
+    time: String,
+}
+
+/// POST `event` to `url` and wait for the answer
+pub async fn send(url: &str, event: &WebhookEvent) -> Result<()> {
+    let payload = Payload {
+        event,
+        app: "teledrive",
+        version: env!("CARGO_PKG_VERSION"),
+        time: chrono::Local::now().to_rfc3339(),
+    };
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?
+        .post(url)
+        .json(&payload)
+        .send().await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Send `event` in the background when a webhook is configured. Failures are
+/// only logged so a broken endpoint never holds up transfers
+pub fn notify(url: &str, event: WebhookEvent) {
+    if url.trim().is_empty() {
+        return;
+    }
+    let url = url.trim().to_string();
+    tokio::spawn(async move {
+        match send(&url, &event).await {
+            Ok(()) => info!("Webhook delivered: {:?}", event),
+            Err(e) => warn!("Webhook to {} failed: {:?}", url, e),
+        }
+    });
+}